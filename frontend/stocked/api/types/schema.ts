@@ -3,9 +3,13 @@
 //! Each type is stripped from sensitive information like passwords, etc, each is
 //! designed to be used on the client-side.
 
+// NOTE: The money, the prices and the percent deltas are decimal strings with the scale of the column,
+// e.g. "150.50" or "12.3400", the floats would not round-trip them.
+
 export interface SessionUser {
   id: string;
   balance: string;
+  delta: string; // Percent change of the portfolio since the start of the UTC day.
   email: string;
 }
 
@@ -14,8 +18,386 @@ export interface Stock {
   abbreviation: string;
   company: string;
   since: Date; // Date in ISO format
-  price: number;
-  delta: number; // Percent change in price since the last data revalidation(ideally 1 minute)
+  price: string;
+  delta: string; // Percent change in price since the last data revalidation(ideally 1 minute)
   last_update: Date; // Date in ISO format
   created_at: Date; // Date in ISO format
 }
+
+// Response of the GET /stocks, paged with the opaque cursor.
+export interface StocksPage {
+  stocks: Stock[];
+  next_cursor: string | null; // Pass it back as ?cursor= to fetch the next page, null on the last page.
+  total: number; // Count of every stock matching the filters, not only the ones on that page.
+}
+
+export interface PricePoint {
+  price: string;
+  recorded_at: Date; // Date in ISO format, UTC
+}
+
+// Response of the GET /stocks/{id}/history?range=1d|1w|1m|1y|custom&from=&to=
+export interface StockHistory {
+  stock_id: number;
+  from: Date;
+  to: Date;
+  prices: PricePoint[]; // Ordered from the oldest to the newest, sampled down to at most 2000 points.
+}
+
+export interface Candle {
+  start: Date; // Start of the bucket, UTC
+  open: string;
+  high: string;
+  low: string;
+  close: string;
+  ticks: number; // 0 when the bucket had no ticks and the previous close was carried forward.
+}
+
+// Response of the GET /stocks/{id}/candles?interval=1m|5m|1h|1d&range=...
+export interface StockCandles {
+  stock_id: number;
+  from: Date;
+  to: Date;
+  candles: Candle[];
+}
+
+export interface Quote {
+  id: number; // Id of the point in the price history, also the id of the SSE event.
+  stock_id: number;
+  abbreviation: string;
+  price: string;
+  delta: string; // Percent change since the previous price.
+  at: Date; // Date in ISO format, UTC
+}
+
+// Messages of the WebSocket at /stream/quotes?tickers=AAPL,MSFT, tagged with the type.
+export type QuoteStreamClientMessage =
+  | { type: "subscribe"; tickers: string[] }
+  | { type: "unsubscribe"; tickers: string[] };
+
+export type QuoteStreamServerMessage =
+  | { type: "welcome"; user_id: number | null }
+  | { type: "subscribed"; tickers: string[]; unknown: string[] }
+  | ({ type: "quote" } & Quote)
+  | { type: "error"; message: string };
+
+// Events of the GET /stream/events?tickers=AAPL,MSFT (text/event-stream), named by the SSE event field.
+// Reconnecting with the Last-Event-ID header, or ?last_event_id=, replays the quotes missed in between.
+export interface SubscribedEvent {
+  tickers: string[];
+  unknown: string[];
+  user_id: number | null; // Only the signed in clients get the balance events.
+}
+
+export interface BalanceEvent {
+  balance: string;
+  delta: string;
+}
+
+export type OrderSide = "buy" | "sell";
+export type OrderType = "market" | "limit";
+export type OrderStatus = "open" | "filled" | "cancelled" | "rejected";
+// The book orders trade with the other users, those have to be the limit ones.
+export type OrderVenue = "market" | "book";
+
+// Body of the POST /orders, the limit_price is required for the limit orders only.
+export interface NewOrder {
+  stock_id: number;
+  side: OrderSide;
+  type: OrderType;
+  venue?: OrderVenue; // Defaults to the market.
+  quantity: number;
+  limit_price?: string | number;
+}
+
+// Response of the POST /orders, GET /orders?status=&limit= and DELETE /orders/{id}
+export interface Order {
+  id: number;
+  user_id: number;
+  stock_id: number;
+  side: OrderSide;
+  type: OrderType;
+  venue: OrderVenue;
+  quantity: number;
+  filled_quantity: number; // The book orders may fill in parts.
+  limit_price: string | null;
+  status: OrderStatus;
+  fill_price: string | null; // The average price of the fills.
+  created_at: Date; // Date in ISO format, UTC
+  closed_at: Date | null;
+}
+
+export interface BookLevel {
+  price: string;
+  quantity: number;
+  orders: number;
+}
+
+// Response of the GET /stocks/{id}/book?levels=, the best price first on both sides.
+export interface BookDepth {
+  bids: BookLevel[];
+  asks: BookLevel[];
+}
+
+export type OfferStatus = "pending" | "accepted" | "rejected" | "expired";
+
+// Body of the POST /offers, the side is the one of the user making the offer.
+export interface NewOffer {
+  to_user_id: number;
+  stock_id: number;
+  side: OrderSide;
+  quantity: number;
+  amount: string | number; // For all of the shares.
+  expires_in?: number; // Seconds, a day by default.
+}
+
+// Response of the POST /offers, GET /offers?status=&limit= and POST /offers/{id}/(accept|reject|expire),
+// also the data of the "offer" event of the /stream/events for both of the users.
+export interface Offer {
+  id: number;
+  from_user_id: number;
+  to_user_id: number;
+  stock_id: number;
+  side: OrderSide;
+  quantity: number;
+  amount: string;
+  status: OfferStatus;
+  expires_at: Date; // Date in ISO format, UTC
+  created_at: Date;
+  closed_at: Date | null;
+}
+
+// The typed AST of the trading rules, see the `rules::ast` module of the server.
+export type RuleTrigger =
+  | { type: "tick"; stock_id: number } // Every new price of the stock.
+  | { type: "schedule"; weekdays?: RuleWeekday[]; time: string }; // "09:30:00" UTC, every day without the weekdays.
+
+export type RuleWeekday = "Mon" | "Tue" | "Wed" | "Thu" | "Fri" | "Sat" | "Sun";
+
+export type RuleComparison = "lt" | "le" | "gt" | "ge" | "eq" | "ne";
+
+export type RuleValue =
+  | { type: "price"; stock_id: number }
+  | { type: "cost_basis"; stock_id: number } // Unknown without the shares.
+  | { type: "shares"; stock_id: number }
+  | { type: "balance" }
+  | { type: "constant"; value: string | number }
+  | { type: "scaled"; value: RuleValue; factor: string | number };
+
+export type RuleCondition =
+  | { type: "all"; conditions: RuleCondition[] }
+  | { type: "any"; conditions: RuleCondition[] }
+  | { type: "not"; condition: RuleCondition }
+  | { type: "compare"; left: RuleValue; op: RuleComparison; right: RuleValue };
+
+export type RuleAction = {
+  type: "order"; // The market order.
+  stock_id: number;
+  side: OrderSide;
+  quantity: { shares: number } | "all"; // All of the shares held, only for the sells.
+};
+
+export interface RuleDefinition {
+  trigger: RuleTrigger;
+  condition?: RuleCondition | null; // Required for the tick rules.
+  action: RuleAction;
+}
+
+// Body of the POST /rules.
+export interface NewRule {
+  name: string;
+  definition: RuleDefinition;
+}
+
+// Response of the POST /rules, GET /rules and PATCH /rules/{id} with the { enabled: boolean } body.
+export interface Rule {
+  id: number;
+  user_id: number;
+  name: string;
+  definition: RuleDefinition;
+  enabled: boolean;
+  matched: boolean; // Whether the condition held at the last evaluation.
+  next_run_at: Date | null; // For the scheduled rules, date in ISO format, UTC
+  created_at: Date;
+  updated_at: Date;
+}
+
+export type RuleOutcome = "unmatched" | "held" | "fired" | "failed";
+
+// Response of the GET /rules/{id}/evaluations?limit=, newest first.
+export interface RuleEvaluation {
+  id: number;
+  rule_id: number;
+  outcome: RuleOutcome;
+  order_id: number | null; // The order placed, when it fired.
+  detail: string | null; // Why it failed.
+  evaluated_at: Date;
+}
+
+// Body of the POST /rules/{id}/backtest, the range is at most 366 days.
+export interface BacktestRequest {
+  from: Date; // Date in ISO format, UTC
+  to: Date;
+  initial_balance?: string | number; // 10000 by default.
+  initial_shares?: Record<number, number>; // Shares held from the start by the stock id, at the price at the start.
+}
+
+// Response of the POST /rules/{id}/backtest, nothing is traded.
+export interface Backtest {
+  rule_id: number;
+  from: Date;
+  to: Date;
+  initial_value: string;
+  final_balance: string; // The cash at the end.
+  final_value: string; // The cash and the shares at the last prices.
+  total_return: number; // Fraction, 0.05 is 5%.
+  max_drawdown: number; // Fraction as well.
+  evaluations: Record<RuleOutcome, number>;
+  trades: {
+    at: Date;
+    stock_id: number;
+    side: OrderSide;
+    quantity: number;
+    price: string;
+    value: string;
+  }[];
+  failures: { at: Date; detail: string }[]; // The first 100 of those.
+  equity_curve: { at: Date; value: string }[]; // At most 500 points.
+}
+
+// Response of the GET /me/positions, the closed positions (quantity 0) are kept for the realized P&L.
+export interface Position {
+  stock_id: number;
+  abbreviation: string;
+  company: string;
+  quantity: number;
+  average_cost: string; // Average price paid per share still held.
+  realized_pnl: string;
+  price: string; // Current price of the stock.
+  updated_at: Date; // Date in ISO format, UTC
+}
+
+// Response of the GET /me/portfolio, the shares held are valued at the current prices.
+export interface Portfolio {
+  cash: string;
+  total_value: string; // The cash and the market value.
+  market_value: string;
+  cost_basis: string;
+  unrealized_pnl: string;
+  realized_pnl: string;
+  reference_value: string; // The shares held now at the prices of the reference.
+  delta: string; // Percent change since the reference, the same as SessionUser.delta.
+  reference_at: Date; // Start of the UTC day, in ISO format
+}
+
+export interface PortfolioSnapshot {
+  day: Date; // UTC day, YYYY-MM-DD
+  total_value: string; // The cash and the market value at the close of the day.
+  market_value: string;
+  net_flow: string; // Deposits and adjustments of the day, not counted as the returns.
+}
+
+export type PerformanceWindow = "1m" | "3m" | "6m" | "1y" | "ytd" | "all";
+
+// Response of the GET /me/performance?window=1m|3m|6m|1y|ytd|all, the ratios are fractions, 0.05 is 5%.
+export interface Performance {
+  window: PerformanceWindow;
+  time_weighted_return: number;
+  max_drawdown: number;
+  volatility: number | null; // Annualized, null with less than 3 snapshots.
+  sharpe_ratio: number | null;
+  snapshots: PortfolioSnapshot[];
+}
+
+export type LedgerTransactionType = "fill" | "deposit" | "fee" | "adjustment" | "offer";
+export type LedgerAccount = "market" | "fees" | "external" | "peer";
+
+export interface LedgerTransaction {
+  id: number;
+  type: LedgerTransactionType;
+  order_id: number | null; // The filled order, for the fills.
+  description: string;
+  amount: string; // Change of the balance, negative when the money left.
+  account: LedgerAccount; // The other side of the transaction.
+  created_at: Date; // Date in ISO format, UTC
+}
+
+// Response of the GET /me/transactions?limit=&cursor=, newest first.
+export interface TransactionsPage {
+  transactions: LedgerTransaction[];
+  next_cursor: string | null;
+}
+
+export interface WatchedStock {
+  stock_id: number;
+  abbreviation: string;
+  company: string;
+  price: string; // Current price of the stock.
+  delta: string; // Percent change since the previous price.
+  last_update: Date; // Date in ISO format, UTC
+  added_at: Date;
+}
+
+// Response of the /me/watchlists endpoints, the watchlists and their stocks are in the order of the user.
+export interface Watchlist {
+  id: number;
+  name: string;
+  position: number; // From 0.
+  stocks: WatchedStock[];
+  created_at: Date; // Date in ISO format, UTC
+  updated_at: Date;
+}
+
+// Body of the POST /me/watchlists.
+export interface NewWatchlist {
+  name: string;
+  tickers?: string[]; // The stocks to start with, in that order.
+}
+
+export type AlertMetric = "price" | "delta";
+
+export type AlertDirection = "above" | "below";
+
+// Response of the /me/alerts endpoints.
+export interface Alert {
+  id: number;
+  user_id: number;
+  stock_id: number;
+  abbreviation: string;
+  metric: AlertMetric;
+  direction: AlertDirection;
+  threshold: string; // The price, or the percent delta.
+  rearm: boolean; // Fires again after its condition stopped holding, instead of only once.
+  cooldown_seconds: number;
+  enabled: boolean;
+  armed: boolean;
+  last_triggered_at: Date | null; // Date in ISO format, UTC
+  created_at: Date;
+}
+
+// Body of the POST /me/alerts.
+export interface NewAlert {
+  ticker: string;
+  metric: AlertMetric;
+  direction: AlertDirection;
+  threshold: string;
+  rearm?: boolean;
+  cooldown_seconds?: number; // 5 minutes when not given, at least a minute for the re-arming ones.
+}
+
+// Also the data of the `notification` event of the /stream/events.
+export interface Notification {
+  id: number;
+  user_id: number;
+  alert_id: number | null; // Null once the alert is deleted.
+  message: string;
+  created_at: Date; // Date in ISO format, UTC
+  read_at: Date | null;
+}
+
+// Response of the GET /me/notifications, `unread` counts the whole inbox.
+export interface NotificationsPage {
+  notifications: Notification[];
+  unread: number;
+  next_cursor: string | null;
+}
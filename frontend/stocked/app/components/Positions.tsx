@@ -0,0 +1,45 @@
+import Link from "next/link";
+import { useFetch } from "../../api/hooks/useFetch";
+import { Position } from "../../api/types/schema";
+
+export function Positions() {
+  const { data: positions, error, isLoading } =
+    useFetch<Position[]>("/me/positions");
+
+  if (error) return <div>Error loading positions</div>;
+
+  if (isLoading) return <div>Loading...</div>;
+
+  const held = positions?.filter((position) => position.quantity > 0) ?? [];
+
+  if (held.length === 0) {
+    return <div>You do not hold any stocks</div>;
+  }
+
+  return (
+    <main className="w-full">
+      <h2 className="text-xl font-bold mb-4">Your stocks ({held.length})</h2>
+      <div className="grid grid-cols-2 md:grid-cols-3 lg:grid-cols-4 gap-4">
+        {held.map((position) => (
+          <Link
+            href={`/stocks/${position.stock_id}`}
+            key={position.stock_id}
+            className="rounded-2xl bg-gray-800 p-4 text-white shadow-md transition hover:scale-105"
+          >
+            <h3 className="text-lg font-semibold">{position.abbreviation}</h3>
+            <p className="text-sm">Shares: {position.quantity}</p>
+            <p className="text-sm">
+              Average cost: ${position.average_cost}
+            </p>
+            <p className="text-sm">Price: ${position.price}</p>
+            <p className="text-sm">
+              Realized P&L: ${position.realized_pnl}
+            </p>
+          </Link>
+        ))}
+      </div>
+    </main>
+  );
+}
+
+export default Positions;
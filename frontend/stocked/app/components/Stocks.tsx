@@ -1,26 +1,54 @@
+import { useState } from "react";
 import { useFetch } from "../../api/hooks/useFetch";
-import { Stock } from "../../api/types/schema";
+import { StocksPage } from "../../api/types/schema";
 import StockEntry from "./StockEntry";
 
+const PAGE_SIZE = 20;
+
 export function Stocks() {
-  const { data: stocks, error, isLoading } = useFetch<Stock[]>("/stocks");
+  // Cursors of the pages we have visited, the last one is the current page, undefined is the first page.
+  const [cursors, setCursors] = useState<(string | undefined)[]>([undefined]);
+  const cursor = cursors[cursors.length - 1];
+
+  const query = new URLSearchParams({ limit: String(PAGE_SIZE) });
+  if (cursor) query.set("cursor", cursor);
+
+  const { data: page, error, isLoading } = useFetch<StocksPage>(
+    `/stocks?${query}`,
+  );
 
   if (error) return <div>Error loading stocks</div>;
 
   if (isLoading) return <div>Loading...</div>;
 
-  if (stocks === undefined || stocks.length === 0) {
+  if (page === undefined || page.stocks.length === 0) {
     return <div>No stocks available</div>;
   }
 
   return (
     <main className="w-full">
-      <h2 className="text-xl font-bold mb-4">Stocks</h2>
+      <h2 className="text-xl font-bold mb-4">Stocks ({page.total})</h2>
       <div className="grid grid-cols-2 md:grid-cols-3 lg:grid-cols-4 gap-4">
-        {stocks.map((stock) => (
+        {page.stocks.map((stock) => (
           <StockEntry stock={stock} key={stock.id} />
         ))}
       </div>
+      <div className="mt-4 flex justify-between">
+        <button
+          disabled={cursors.length === 1}
+          onClick={() => setCursors(cursors.slice(0, -1))}
+        >
+          Previous
+        </button>
+        <button
+          disabled={page.next_cursor === null}
+          onClick={() =>
+            page.next_cursor && setCursors([...cursors, page.next_cursor])
+          }
+        >
+          Next
+        </button>
+      </div>
     </main>
   );
 }
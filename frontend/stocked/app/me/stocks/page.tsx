@@ -1,7 +1,7 @@
 "use client";
 
-import { Stocks } from "../../components/Stocks";
+import { Positions } from "../../components/Positions";
 
 export default function Page() {
-  return <Stocks />;
+  return <Positions />;
 }
@@ -1,16 +1,18 @@
 "use client";
 
 import { useFetch } from "../../api/hooks/useFetch";
-import { Stock } from "../../api/types/schema";
+import { StocksPage } from "../../api/types/schema";
 import StockEntry from "../components/StockEntry";
 
 export default function Page() {
-  const { data: stocks, error, isLoading } = useFetch<Stock[]>("/stocks");
+  const { data: page, error, isLoading } = useFetch<StocksPage>("/stocks");
 
   if (isLoading && !error) return <div>Loading...</div>;
 
   if (error) return <div>Error loading stocks</div>;
 
+  const stocks = page?.stocks;
+
   if (stocks === undefined || stocks.length === 0) {
     return <div>No stocks available</div>;
   }
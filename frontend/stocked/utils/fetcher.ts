@@ -1,5 +1,20 @@
 import { ApiFetchError } from "../api/hooks/useFetch";
 
+const CSRF_COOKIE = "CSRF";
+const CSRF_HEADER = "X-CSRF-Token";
+
+/// The CSRF token of the session, the API sets it next to the SSID cookie, undefined outside of the browser.
+function csrfToken(): string | undefined {
+  if (typeof document === "undefined") {
+    return undefined;
+  }
+
+  return document.cookie
+    .split("; ")
+    .find((cookie) => cookie.startsWith(`${CSRF_COOKIE}=`))
+    ?.slice(CSRF_COOKIE.length + 1);
+}
+
 export async function fetcher<Data>(
   path: string,
   options: RequestInit = {},
@@ -13,6 +28,17 @@ export async function fetcher<Data>(
 
   const url = new URL(endpoint, process.env.NEXT_PUBLIC_CLIENT_URL);
 
+  // The API rejects the mutating requests of the session without its token.
+  const method = (options.method || "GET").toUpperCase();
+  const token = csrfToken();
+
+  if (token && !["GET", "HEAD", "OPTIONS"].includes(method)) {
+    options = {
+      ...options,
+      headers: { [CSRF_HEADER]: token, ...(options.headers || {}) },
+    };
+  }
+
   console.log(`Fetching url: ${url} with options:`, {
     headers: {
       "Content-Type": "application/json",
@@ -0,0 +1,55 @@
+-- Timestamped price history of the stocks, replaces the stocks_history table.
+-- The stocks_history kept the prices in the REAL[] array without the timestamps, so there was no way
+-- to tell when the price happened, or to query some range of time without reading the whole array.
+CREATE TABLE stock_prices (
+    -- BIGSERIAL as that table grows with every price update of every stock.
+    id BIGSERIAL PRIMARY KEY,
+    stock_id INTEGER NOT NULL REFERENCES stocks(id) ON DELETE CASCADE,
+    price REAL NOT NULL CHECK (price > 0),
+    recorded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+
+-- Every read of the history is a range of time for a single stock.
+CREATE INDEX stock_prices_stock_id_recorded_at_idx ON stock_prices (stock_id, recorded_at);
+
+
+-- Keep the existing history. We do not know when those prices happened, the best guess is that they were
+-- taken every minute (that is what we are aiming for), where the last element is the price at the last_update of the stock.
+INSERT INTO
+    stock_prices (stock_id, price, recorded_at)
+SELECT
+    stocks_history.stock_id,
+    history.price,
+    stocks.last_update - (
+        (array_length(stocks_history.prices, 1) - history.position) * INTERVAL '1 minute'
+    )
+FROM
+    stocks_history
+    JOIN stocks ON stocks.id = stocks_history.stock_id
+    CROSS JOIN LATERAL unnest(stocks_history.prices) WITH ORDINALITY AS history(price, position)
+WHERE
+    history.price > 0;
+
+
+-- Stocks without any history get their current price as the first point, so the history is never empty.
+INSERT INTO
+    stock_prices (stock_id, price, recorded_at)
+SELECT
+    stocks.id,
+    stocks.price,
+    stocks.last_update
+FROM
+    stocks
+WHERE
+    NOT EXISTS (
+        SELECT
+            1
+        FROM
+            stock_prices
+        WHERE
+            stock_prices.stock_id = stocks.id
+    );
+
+
+DROP TABLE stocks_history;
@@ -0,0 +1,41 @@
+-- Orders of the users, the user_stocks table only says that the user "has" the stock, with no quantity or price.
+-- The market orders are filled right away at the current price of the stock, the limit ones stay open
+-- until the price crosses their limit.
+CREATE TYPE order_side AS ENUM ('buy', 'sell');
+
+
+CREATE TYPE order_kind AS ENUM ('market', 'limit');
+
+
+-- The rejected orders are the limit orders that crossed, but the user could not afford them by then.
+CREATE TYPE order_status AS ENUM ('open', 'filled', 'cancelled', 'rejected');
+
+
+CREATE TABLE orders (
+    -- BIGSERIAL as every user places many of those.
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    stock_id INTEGER NOT NULL REFERENCES stocks(id) ON DELETE CASCADE,
+    side order_side NOT NULL,
+    kind order_kind NOT NULL,
+    -- Only the whole shares for now.
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    limit_price REAL CHECK (limit_price > 0),
+    status order_status NOT NULL DEFAULT 'open',
+    -- The price the order was filled at, which for the limit orders is the limit or better.
+    fill_price REAL CHECK (fill_price > 0),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- When the order left the open status, whichever way.
+    closed_at TIMESTAMP,
+    CONSTRAINT orders_kind_limit_price_check CHECK ((kind = 'limit') = (limit_price IS NOT NULL)),
+    CONSTRAINT orders_fill_price_status_check CHECK ((status = 'filled') = (fill_price IS NOT NULL)),
+    CONSTRAINT orders_status_closed_at_check CHECK ((status = 'open') = (closed_at IS NULL))
+);
+
+
+-- Listing the orders of the user, newest first.
+CREATE INDEX orders_user_id_id_idx ON orders (user_id, id DESC);
+
+
+-- The price engine only ever looks for the open orders of the stocks that moved.
+CREATE INDEX orders_open_stock_id_idx ON orders (stock_id) WHERE status = 'open';
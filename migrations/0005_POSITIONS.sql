@@ -0,0 +1,65 @@
+-- Positions of the users, replaces the user_stocks junction table, which could only say that the user "has" the stock.
+-- Updated in the same transaction as the order fills, with the average cost method:
+-- the buys move the average cost, the sells realize the difference between the fill price and the average cost.
+CREATE TABLE positions (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    stock_id INTEGER NOT NULL REFERENCES stocks(id) ON DELETE CASCADE,
+    quantity INTEGER NOT NULL DEFAULT 0 CHECK (quantity >= 0),
+    -- Average price paid per share of the shares still held.
+    average_cost REAL NOT NULL DEFAULT 0 CHECK (average_cost >= 0),
+    realized_pnl REAL NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, stock_id)
+);
+
+
+-- The positions out of the orders filled so far.
+-- NOTE: That is the approximation, the average cost is taken over all the buys, where the exact one depends
+-- on the order the buys and sells happened in. Good enough for the orders placed before that migration.
+INSERT INTO
+    positions (user_id, stock_id, quantity, average_cost, realized_pnl)
+SELECT
+    user_id,
+    stock_id,
+    bought - sold,
+    CASE WHEN bought - sold > 0 THEN cost ELSE 0 END,
+    proceeds - sold * cost
+FROM
+    (
+        SELECT
+            user_id,
+            stock_id,
+            COALESCE(SUM(quantity) FILTER (WHERE side = 'buy'), 0) AS bought,
+            COALESCE(SUM(quantity) FILTER (WHERE side = 'sell'), 0) AS sold,
+            COALESCE(
+                SUM(fill_price * quantity) FILTER (WHERE side = 'buy') / NULLIF(SUM(quantity) FILTER (WHERE side = 'buy'), 0),
+                0
+            ) AS cost,
+            COALESCE(SUM(fill_price * quantity) FILTER (WHERE side = 'sell'), 0) AS proceeds
+        FROM
+            orders
+        WHERE
+            status = 'filled'
+        GROUP BY
+            user_id,
+            stock_id
+    ) AS filled;
+
+
+-- The user_stocks rows do not say how many shares, or at what price, so those become a single share
+-- bought at the current price of the stock, with nothing realized yet.
+-- The rows the orders already made the position for keep that position, which may well be empty.
+INSERT INTO
+    positions (user_id, stock_id, quantity, average_cost)
+SELECT
+    user_stocks.user_id,
+    user_stocks.stock_id,
+    1,
+    stocks.price
+FROM
+    user_stocks
+    JOIN stocks ON stocks.id = user_stocks.stock_id
+ON CONFLICT (user_id, stock_id) DO NOTHING;
+
+
+DROP TABLE user_stocks;
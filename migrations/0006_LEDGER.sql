@@ -0,0 +1,106 @@
+-- Double-entry ledger of the balances, the users.balance is only the running total of the cash entries of the user.
+-- Every change of the balance is the transaction with the entries that sum up to zero, the positive amount is the debit,
+-- the negative one is the credit. The cash account is the money of the user, the other accounts are the other side:
+-- the market the shares are bought from and sold to, the fees we charge, and the money coming from or leaving to outside.
+CREATE TYPE ledger_kind AS ENUM ('fill', 'deposit', 'fee', 'adjustment');
+
+
+CREATE TYPE ledger_account AS ENUM ('cash', 'market', 'fees', 'external');
+
+
+CREATE TABLE ledger_transactions (
+    id BIGSERIAL PRIMARY KEY,
+    -- The user whose cash the transaction moves.
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind ledger_kind NOT NULL,
+    -- The order that was filled, for the fills.
+    -- NOTE: No cascade, the stock with the filled orders cannot be deleted without losing the history of the balances.
+    order_id BIGINT REFERENCES orders(id),
+    description TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+
+CREATE TABLE ledger_entries (
+    id BIGSERIAL PRIMARY KEY,
+    transaction_id BIGINT NOT NULL REFERENCES ledger_transactions(id) ON DELETE CASCADE,
+    account ledger_account NOT NULL,
+    amount REAL NOT NULL CHECK (amount <> 0)
+);
+
+
+-- Listing the transactions of the user, newest first.
+CREATE INDEX ledger_transactions_user_id_id_idx ON ledger_transactions (user_id, id DESC);
+
+
+CREATE INDEX ledger_entries_transaction_id_idx ON ledger_entries (transaction_id);
+
+
+-- The entries of the transaction have to sum up to zero by the time it commits, that is deferred
+-- as the entries are inserted one by one.
+CREATE FUNCTION ledger_check_balanced() RETURNS TRIGGER AS $$
+BEGIN
+    IF (SELECT SUM(amount) FROM ledger_entries WHERE transaction_id = NEW.transaction_id) <> 0 THEN
+        RAISE EXCEPTION 'Ledger transaction % is not balanced', NEW.transaction_id;
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+
+CREATE CONSTRAINT TRIGGER ledger_entries_balanced
+AFTER INSERT ON ledger_entries
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW EXECUTE FUNCTION ledger_check_balanced();
+
+
+-- The ledger is append-only, the mistakes are fixed with the adjustments, not by editing the history.
+-- NOTE: The rows are still deleted together with the user.
+CREATE FUNCTION ledger_reject_update() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'The ledger is append-only, % cannot be updated', TG_TABLE_NAME;
+END;
+$$ LANGUAGE plpgsql;
+
+
+CREATE TRIGGER ledger_transactions_append_only
+BEFORE UPDATE ON ledger_transactions
+FOR EACH ROW EXECUTE FUNCTION ledger_reject_update();
+
+
+CREATE TRIGGER ledger_entries_append_only
+BEFORE UPDATE ON ledger_entries
+FOR EACH ROW EXECUTE FUNCTION ledger_reject_update();
+
+
+-- We do not know where the existing balances came from, those are opened with the adjustment from outside.
+WITH opened AS (
+    INSERT INTO
+        ledger_transactions (user_id, kind, description)
+    SELECT
+        id,
+        'adjustment',
+        'Opening balance'
+    FROM
+        users
+    WHERE
+        balance <> 0
+    RETURNING
+        id,
+        user_id
+)
+INSERT INTO
+    ledger_entries (transaction_id, account, amount)
+SELECT
+    opened.id,
+    entry.account,
+    entry.amount
+FROM
+    opened
+    JOIN users ON users.id = opened.user_id
+    CROSS JOIN LATERAL (
+        VALUES
+            ('cash'::ledger_account, users.balance),
+            ('external'::ledger_account, -users.balance)
+    ) AS entry(account, amount);
@@ -0,0 +1,54 @@
+-- The money and the prices move to NUMERIC, the REAL drifts after a few trades as it cannot represent most of the cents.
+-- The prices keep 4 decimal places, as the cheap stocks trade in the fractions of the cent,
+-- the cash keeps 2, and the percent deltas 2 as well.
+ALTER TABLE stocks
+    ALTER COLUMN price TYPE NUMERIC(20, 4) USING round(price::numeric, 4),
+    ALTER COLUMN delta TYPE NUMERIC(6, 2) USING round(delta::numeric, 2);
+
+
+ALTER TABLE stock_prices
+    ALTER COLUMN price TYPE NUMERIC(20, 4) USING round(price::numeric, 4);
+
+
+ALTER TABLE users
+    ALTER COLUMN balance TYPE NUMERIC(20, 2) USING round(balance::numeric, 2),
+    ALTER COLUMN delta TYPE NUMERIC(6, 2) USING round(delta::numeric, 2);
+
+
+ALTER TABLE orders
+    ALTER COLUMN limit_price TYPE NUMERIC(20, 4) USING round(limit_price::numeric, 4),
+    ALTER COLUMN fill_price TYPE NUMERIC(20, 4) USING round(fill_price::numeric, 4);
+
+
+ALTER TABLE positions
+    ALTER COLUMN average_cost TYPE NUMERIC(20, 4) USING round(average_cost::numeric, 4),
+    ALTER COLUMN realized_pnl TYPE NUMERIC(20, 2) USING round(realized_pnl::numeric, 2);
+
+
+-- Both sides of the entry round the same way, so the transactions still balance.
+ALTER TABLE ledger_entries
+    ALTER COLUMN amount TYPE NUMERIC(20, 2) USING round(amount::numeric, 2);
+
+
+-- The balances were only as close to the ledger as the REAL could get, now those can be exact.
+UPDATE
+    users
+SET
+    balance = COALESCE(ledger.balance, 0)
+FROM
+    users AS target
+    LEFT JOIN (
+        SELECT
+            ledger_transactions.user_id,
+            SUM(ledger_entries.amount) AS balance
+        FROM
+            ledger_entries
+            JOIN ledger_transactions ON ledger_transactions.id = ledger_entries.transaction_id
+        WHERE
+            ledger_entries.account = 'cash'
+        GROUP BY
+            ledger_transactions.user_id
+    ) AS ledger ON ledger.user_id = target.id
+WHERE
+    users.id = target.id
+    AND users.balance <> COALESCE(ledger.balance, 0);
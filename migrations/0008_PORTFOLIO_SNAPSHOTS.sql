@@ -0,0 +1,14 @@
+-- Value of the portfolio of every user per UTC day, the series the performance analytics run on.
+-- The snapshot job rewrites the row of the current day as it goes, so once the day is over the row holds its closing value.
+CREATE TABLE portfolio_snapshots (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    -- The cash and the market value of the positions.
+    total_value NUMERIC(20, 2) NOT NULL,
+    market_value NUMERIC(20, 2) NOT NULL,
+    -- The money that came in (or left) from the outside during the day, the deposits and the adjustments.
+    -- That is not the performance, the returns are computed without it.
+    net_flow NUMERIC(20, 2) NOT NULL DEFAULT 0,
+    taken_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, day)
+);
@@ -0,0 +1,46 @@
+-- The order book, where the users trade the stocks with each other instead of at the prices of the market.
+-- The book orders are the limit orders that rest in the book of the stock until the order of another user
+-- crosses them, with the price-time priority. Those may fill in parts, by any number of trades.
+CREATE TYPE order_venue AS ENUM ('market', 'book');
+
+
+ALTER TABLE orders
+    ADD COLUMN venue order_venue NOT NULL DEFAULT 'market',
+    -- The shares filled so far, the order is filled once that reaches the quantity.
+    ADD COLUMN filled_quantity INTEGER NOT NULL DEFAULT 0;
+
+
+UPDATE
+    orders
+SET
+    filled_quantity = quantity
+WHERE
+    status = 'filled';
+
+
+-- The fill price is now the average price of the fills, which the cancelled order keeps for its filled part.
+ALTER TABLE orders
+    DROP CONSTRAINT orders_fill_price_status_check,
+    ADD CONSTRAINT orders_filled_quantity_check CHECK (filled_quantity BETWEEN 0 AND quantity),
+    ADD CONSTRAINT orders_fill_price_filled_check CHECK ((filled_quantity > 0) = (fill_price IS NOT NULL)),
+    ADD CONSTRAINT orders_status_filled_check CHECK ((status = 'filled') = (filled_quantity = quantity)),
+    ADD CONSTRAINT orders_venue_kind_check CHECK (venue = 'market' OR kind = 'limit');
+
+
+-- The books are rebuilt out of those on startup.
+CREATE INDEX orders_open_book_idx ON orders (stock_id, id) WHERE status = 'open' AND venue = 'book';
+
+
+-- The matches of the book, the price is always the one of the order that was resting in the book.
+CREATE TABLE trades (
+    id BIGSERIAL PRIMARY KEY,
+    stock_id INTEGER NOT NULL REFERENCES stocks(id) ON DELETE CASCADE,
+    buy_order_id BIGINT NOT NULL REFERENCES orders(id),
+    sell_order_id BIGINT NOT NULL REFERENCES orders(id),
+    price NUMERIC(20, 4) NOT NULL CHECK (price > 0),
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+
+CREATE INDEX trades_stock_id_id_idx ON trades (stock_id, id DESC);
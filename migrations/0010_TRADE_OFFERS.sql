@@ -0,0 +1,41 @@
+-- The trade offers one user proposes directly to another, e.g. 100 shares of the stock for 5000 of the balance.
+-- Nothing is reserved while the offer is pending, both sides are checked once it is accepted, and settled
+-- together in the same transaction.
+CREATE TYPE offer_status AS ENUM ('pending', 'accepted', 'rejected', 'expired');
+
+
+CREATE TABLE trade_offers (
+    id BIGSERIAL PRIMARY KEY,
+    from_user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    to_user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    stock_id INTEGER NOT NULL REFERENCES stocks(id) ON DELETE CASCADE,
+    -- The side of the user that proposes it, the sell gives the shares for the money.
+    side order_side NOT NULL,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    -- The money paid for all of the shares.
+    amount NUMERIC(20, 2) NOT NULL CHECK (amount > 0),
+    status offer_status NOT NULL DEFAULT 'pending',
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    closed_at TIMESTAMP,
+    CHECK (from_user_id <> to_user_id),
+    CHECK ((status = 'pending') = (closed_at IS NULL))
+);
+
+
+CREATE INDEX trade_offers_from_user_id_id_idx ON trade_offers (from_user_id, id DESC);
+
+
+CREATE INDEX trade_offers_to_user_id_id_idx ON trade_offers (to_user_id, id DESC);
+
+
+-- The offers the expiry job goes through.
+CREATE INDEX trade_offers_pending_expires_at_idx ON trade_offers (expires_at) WHERE status = 'pending';
+
+
+-- The money of the accepted offer moves from the cash of one user to the other, the peer account is the other
+-- side of both of those transactions, so it sums up to zero over every accepted offer.
+ALTER TYPE ledger_kind ADD VALUE 'offer';
+
+
+ALTER TYPE ledger_account ADD VALUE 'peer';
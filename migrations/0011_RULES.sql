@@ -0,0 +1,55 @@
+-- The automated trading rules of the users, e.g. sell the shares once the price drops 5% below the cost basis,
+-- or buy the shares every Monday. The rule itself is the typed AST of the `rules` module, stored as JSONB,
+-- the columns next to it are derived from it on write, so the engine finds the rules to evaluate.
+CREATE TYPE rule_trigger AS ENUM ('tick', 'schedule');
+
+
+CREATE TABLE rules (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL CHECK (LENGTH(name) BETWEEN 1 AND 100),
+    definition JSONB NOT NULL CHECK (JSONB_TYPEOF(definition) = 'object'),
+    trigger rule_trigger NOT NULL,
+    -- The stock whose ticks evaluate the rule, for the tick rules.
+    stock_id INTEGER REFERENCES stocks(id) ON DELETE CASCADE,
+    -- When the scheduled rule runs next, for the schedule rules.
+    next_run_at TIMESTAMP,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Whether the condition held at the last evaluation, the tick rule only fires once it starts to hold.
+    matched BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((trigger = 'tick') = (stock_id IS NOT NULL)),
+    CHECK ((trigger = 'schedule') = (next_run_at IS NOT NULL))
+);
+
+
+CREATE INDEX rules_user_id_id_idx ON rules (user_id, id DESC);
+
+
+CREATE INDEX rules_tick_stock_id_idx ON rules (stock_id) WHERE enabled AND trigger = 'tick';
+
+
+CREATE INDEX rules_schedule_next_run_at_idx ON rules (next_run_at) WHERE enabled AND trigger = 'schedule';
+
+
+-- Every evaluation of the rule, for the audit. The held one is the tick rule whose condition still holds
+-- since it fired, so it does not fire again.
+CREATE TYPE rule_outcome AS ENUM ('unmatched', 'held', 'fired', 'failed');
+
+
+CREATE TABLE rule_evaluations (
+    id BIGSERIAL PRIMARY KEY,
+    rule_id BIGINT NOT NULL REFERENCES rules(id) ON DELETE CASCADE,
+    outcome rule_outcome NOT NULL,
+    -- The order the rule placed, when it fired.
+    order_id BIGINT REFERENCES orders(id),
+    -- Why it failed.
+    detail TEXT,
+    evaluated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((outcome = 'fired') = (order_id IS NOT NULL)),
+    CHECK ((outcome = 'failed') = (detail IS NOT NULL))
+);
+
+
+CREATE INDEX rule_evaluations_rule_id_id_idx ON rule_evaluations (rule_id, id DESC);
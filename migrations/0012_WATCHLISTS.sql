@@ -0,0 +1,25 @@
+-- The named watchlists of the users, the stocks those track without owning them. Both the watchlists of the user
+-- and the stocks of the watchlist are kept in the order the user put them in.
+CREATE TABLE watchlists (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL CHECK (LENGTH(name) BETWEEN 1 AND 100),
+    -- The place of the watchlist among the ones of the user, from 0.
+    position INTEGER NOT NULL CHECK (position >= 0),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, name),
+    -- Deferred, so the reorder can swap the positions within the transaction.
+    UNIQUE (user_id, position) DEFERRABLE INITIALLY DEFERRED
+);
+
+
+CREATE TABLE watchlist_stocks (
+    watchlist_id BIGINT NOT NULL REFERENCES watchlists(id) ON DELETE CASCADE,
+    stock_id INTEGER NOT NULL REFERENCES stocks(id) ON DELETE CASCADE,
+    -- The place of the stock in the watchlist, from 0.
+    position INTEGER NOT NULL CHECK (position >= 0),
+    added_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (watchlist_id, stock_id),
+    UNIQUE (watchlist_id, position) DEFERRABLE INITIALLY DEFERRED
+);
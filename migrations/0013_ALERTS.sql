@@ -0,0 +1,48 @@
+-- The price alerts of the users, e.g. AAPL above 200, or the delta of AAPL below -3%. Those are checked against
+-- every quote of the stock, the one-shot alert is disabled once it fires, the re-arming one fires again only after
+-- its condition stopped holding in between, and not sooner than its cooldown after the last time.
+CREATE TYPE alert_metric AS ENUM ('price', 'delta');
+
+
+CREATE TYPE alert_direction AS ENUM ('above', 'below');
+
+
+CREATE TABLE alerts (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    stock_id INTEGER NOT NULL REFERENCES stocks(id) ON DELETE CASCADE,
+    metric alert_metric NOT NULL,
+    direction alert_direction NOT NULL,
+    threshold NUMERIC(20, 4) NOT NULL,
+    rearm BOOLEAN NOT NULL,
+    cooldown_seconds INTEGER NOT NULL CHECK (cooldown_seconds >= 0),
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Whether the alert fires once its condition holds, the re-arming one is disarmed until the condition stops holding.
+    armed BOOLEAN NOT NULL DEFAULT TRUE,
+    last_triggered_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+
+CREATE INDEX alerts_user_id_id_idx ON alerts (user_id, id DESC);
+
+
+CREATE INDEX alerts_stock_id_idx ON alerts (stock_id) WHERE enabled;
+
+
+-- The inbox of the user, the notification is unread until the user marks it.
+CREATE TABLE notifications (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- The alert that triggered it, the notification outlives the deleted alert.
+    alert_id BIGINT REFERENCES alerts(id) ON DELETE SET NULL,
+    message TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at TIMESTAMP
+);
+
+
+CREATE INDEX notifications_user_id_id_idx ON notifications (user_id, id DESC);
+
+
+CREATE INDEX notifications_unread_idx ON notifications (user_id) WHERE read_at IS NULL;
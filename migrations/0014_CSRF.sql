@@ -0,0 +1,3 @@
+-- The synchronizer token of the session, every mutating request of the session has to send it back in the
+-- X-CSRF-Token header. The existing sessions get theirs here, the client picks it up from the GET /auth/session.
+ALTER TABLE sessions ADD COLUMN csrf_token UUID NOT NULL DEFAULT uuid_generate_v4();
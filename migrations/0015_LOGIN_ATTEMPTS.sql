@@ -0,0 +1,15 @@
+-- The failed logins per email and per client address, the login limiter backs off and locks out by those. Kept in
+-- the database, so the limits hold across the restarts and the instances of the server.
+CREATE TYPE login_attempt_scope AS ENUM ('email', 'ip');
+
+
+CREATE TABLE login_attempts (
+    scope login_attempt_scope NOT NULL,
+    -- The lowercased email, or the address of the client.
+    key TEXT NOT NULL,
+    -- Since the last lockout, or the last success of the email.
+    failures INTEGER NOT NULL DEFAULT 0 CHECK (failures >= 0),
+    last_failure_at TIMESTAMP NOT NULL,
+    locked_until TIMESTAMP,
+    PRIMARY KEY (scope, key)
+);
@@ -0,0 +1,2 @@
+-- The stale attempts are pruned by the time of their last failure, the unknown emails would pile up otherwise.
+CREATE INDEX login_attempts_last_failure_at_idx ON login_attempts (last_failure_at);
@@ -1,6 +1,16 @@
+"""Downloads the daily history of the tickers into `<TICKER>.csv` files.
+
+Those can be loaded into the database with `cargo run --bin import -- AAPL.csv MSFT.csv`.
+"""
+
+import sys
+
 import yfinance as yf
-from pprint import pprint
 
-ticker = yf.Ticker("AAPL")
+tickers = sys.argv[1:] or ["AAPL"]
 
-pprint(ticker.history(period="1y"))
+for symbol in tickers:
+    history = yf.Ticker(symbol).history(period="1y")
+    # The index is named Date, so the header is Date,Open,High,Low,Close,Volume,Dividends,Stock Splits.
+    history.to_csv(f"{symbol}.csv")
+    print(f"Saved {len(history)} rows to {symbol}.csv")
@@ -0,0 +1,20 @@
+#[derive(thiserror::Error, Debug, Clone)]
+// NOTE: Those are shown to the client by the controllers, except the database one, so keep them free of anything sensitive.
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    #[error("Invalid alert: {0}")]
+    InvalidAlert(String),
+    #[error("Alert not found: {0}")]
+    AlertNotFound(i64),
+    #[error("Stock not found: {0}")]
+    StockNotFound(String),
+    #[error("Notification not found: {0}")]
+    NotificationNotFound(i64),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
@@ -0,0 +1,628 @@
+//! Price alerts of the users, e.g. AAPL above 200, or the delta of AAPL below -3%.
+//!
+//! The alerts are checked against every quote the market publishes, on their own task, see `spawn`. The alert that
+//! fires writes the notification into the inbox of the user, which is then pushed to the user's event streams.
+//!
+//! The one-shot alert is disabled once it fires. The re-arming one is disarmed instead, it arms again once its
+//! condition stops holding and fires again once it holds after that, but not sooner than its cooldown after the
+//! last time, so the price flapping around the threshold does not flood the inbox.
+
+mod error;
+pub mod notifications;
+
+pub use error::Error;
+pub use notifications::{Notification, NotificationFeed};
+
+use std::collections::BTreeMap;
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    database::{DatabaseConnection, types::Money},
+    market::{Quote, QuoteFeed},
+};
+
+pub(crate) type Result<T> = std::result::Result<T, self::Error>;
+
+pub const MAX_ALERTS: i64 = 100;
+
+pub const DEFAULT_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// The re-arming alerts cannot fire more often than that.
+pub const MIN_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(60);
+
+pub const MAX_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The deltas are clamped to that by the market, the alert beyond it would never fire.
+pub const MAX_DELTA: i64 = 99;
+
+/// The most quotes evaluated at once, the batch the market publishes on every tick is split into those.
+const BATCH_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "alert_metric", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AlertMetric {
+    Price,
+    /// Percent change since the previous price.
+    Delta,
+}
+
+impl AlertMetric {
+    pub fn of(&self, quote: &Quote) -> Money {
+        match self {
+            AlertMetric::Price => quote.price,
+            AlertMetric::Delta => quote.delta,
+        }
+    }
+
+    fn scale(&self) -> u32 {
+        match self {
+            AlertMetric::Price => Money::PRICE_SCALE,
+            AlertMetric::Delta => Money::PERCENT_SCALE,
+        }
+    }
+
+    fn format(&self, value: Money) -> String {
+        match self {
+            AlertMetric::Price => value.round(self.scale()).to_string(),
+            AlertMetric::Delta => format!("{}%", value.round(self.scale())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "alert_direction", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AlertDirection {
+    Above,
+    Below,
+}
+
+impl AlertDirection {
+    /// Reaching the threshold counts.
+    pub fn holds(&self, value: Money, threshold: Money) -> bool {
+        match self {
+            AlertDirection::Above => value >= threshold,
+            AlertDirection::Below => value <= threshold,
+        }
+    }
+}
+
+impl std::fmt::Display for AlertDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertDirection::Above => f.write_str("above"),
+            AlertDirection::Below => f.write_str("below"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Alert {
+    pub id: i64,
+    pub user_id: i32,
+    pub stock_id: i32,
+    pub abbreviation: String,
+    pub metric: AlertMetric,
+    pub direction: AlertDirection,
+    pub threshold: Money,
+    /// Whether it fires again after its condition stopped holding, or only once.
+    pub rearm: bool,
+    pub cooldown_seconds: i32,
+    pub enabled: bool,
+    pub armed: bool,
+    pub last_triggered_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NewAlert {
+    pub ticker: String,
+    pub metric: AlertMetric,
+    pub direction: AlertDirection,
+    /// The price, or the percent delta, e.g. `-3` for -3%.
+    pub threshold: Money,
+    #[serde(default)]
+    pub rearm: bool,
+    /// `DEFAULT_COOLDOWN` when not given.
+    #[serde(default)]
+    pub cooldown_seconds: Option<i32>,
+}
+
+impl NewAlert {
+    /// The threshold rounded to the scale of the metric and the cooldown.
+    pub fn validate(&self) -> self::Result<(Money, i32)> {
+        let threshold = self.threshold.round(self.metric.scale());
+
+        match self.metric {
+            AlertMetric::Price if !threshold.is_positive() => {
+                return Err(Error::InvalidAlert(
+                    "the price threshold must be positive".into(),
+                ));
+            }
+            AlertMetric::Delta if threshold.decimal().abs() > MAX_DELTA.into() => {
+                return Err(Error::InvalidAlert(format!(
+                    "the delta threshold must be between -{MAX_DELTA}% and {MAX_DELTA}%"
+                )));
+            }
+            _ => {}
+        }
+
+        let cooldown = self
+            .cooldown_seconds
+            .unwrap_or(DEFAULT_COOLDOWN.as_secs() as i32);
+        let min = if self.rearm {
+            MIN_COOLDOWN.as_secs()
+        } else {
+            0
+        };
+
+        if cooldown < 0 || !(min..=MAX_COOLDOWN.as_secs()).contains(&(cooldown as u64)) {
+            return Err(Error::InvalidAlert(format!(
+                "the cooldown must be between {min} and {} seconds",
+                MAX_COOLDOWN.as_secs()
+            )));
+        }
+
+        Ok((threshold, cooldown))
+    }
+}
+
+/// What the quote does to the alert.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Step {
+    Fire,
+    Rearm,
+    Nothing,
+}
+
+impl Alert {
+    fn step(&self, quote: &Quote, at: chrono::NaiveDateTime) -> Step {
+        let holds = self.direction.holds(self.metric.of(quote), self.threshold);
+        let cooled_down = self.last_triggered_at.is_none_or(|last| {
+            at - last >= chrono::Duration::seconds(self.cooldown_seconds.into())
+        });
+
+        match (holds, self.armed) {
+            // NOTE: Within the cooldown the alert stays armed, so it fires once it is over, if it still holds.
+            (true, true) if cooled_down => Step::Fire,
+            (false, false) if self.rearm => Step::Rearm,
+            _ => Step::Nothing,
+        }
+    }
+
+    fn message(&self, quote: &Quote) -> String {
+        let subject = match self.metric {
+            AlertMetric::Price => format!("{} price", self.abbreviation),
+            AlertMetric::Delta => format!("{} delta", self.abbreviation),
+        };
+
+        format!(
+            "{subject} is {} {}, now at {}",
+            self.direction,
+            self.metric.format(self.threshold),
+            self.metric.format(self.metric.of(quote))
+        )
+    }
+}
+
+fn normalize(ticker: &str) -> String {
+    ticker.trim().to_uppercase()
+}
+
+async fn find_alert<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    user_id: i32,
+    id: i64,
+) -> self::Result<Alert> {
+    sqlx::query_as!(
+        Alert,
+        r#"SELECT
+            alerts.id, alerts.user_id, alerts.stock_id, stocks.abbreviation,
+            alerts.metric AS "metric: AlertMetric", alerts.direction AS "direction: AlertDirection",
+            alerts.threshold, alerts.rearm, alerts.cooldown_seconds, alerts.enabled, alerts.armed,
+            alerts.last_triggered_at, alerts.created_at
+        FROM alerts JOIN stocks ON stocks.id = alerts.stock_id
+        WHERE alerts.id = $1 AND alerts.user_id = $2"#,
+        id,
+        user_id
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or(Error::AlertNotFound(id))
+}
+
+/// Newest first.
+pub async fn list_alerts(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+) -> self::Result<Vec<Alert>> {
+    Ok(sqlx::query_as!(
+        Alert,
+        r#"SELECT
+            alerts.id, alerts.user_id, alerts.stock_id, stocks.abbreviation,
+            alerts.metric AS "metric: AlertMetric", alerts.direction AS "direction: AlertDirection",
+            alerts.threshold, alerts.rearm, alerts.cooldown_seconds, alerts.enabled, alerts.armed,
+            alerts.last_triggered_at, alerts.created_at
+        FROM alerts JOIN stocks ON stocks.id = alerts.stock_id
+        WHERE alerts.user_id = $1
+        ORDER BY alerts.id DESC"#,
+        user_id
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+pub async fn get_alert(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+) -> self::Result<Alert> {
+    self::find_alert(conn, user_id, id).await
+}
+
+pub async fn create_alert(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    alert: &NewAlert,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Alert> {
+    let (threshold, cooldown) = alert.validate()?;
+    let ticker = self::normalize(&alert.ticker);
+
+    let mut tx = conn.begin().await?;
+
+    // Locks the user row, so the concurrent creates cannot go over the limit.
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM alerts WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if count >= MAX_ALERTS {
+        return Err(Error::InvalidAlert(format!(
+            "cannot have more than {MAX_ALERTS} alerts"
+        )));
+    }
+
+    let stock_id = sqlx::query_scalar!("SELECT id FROM stocks WHERE abbreviation = $1", ticker)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::StockNotFound(ticker.clone()))?;
+
+    let id = sqlx::query_scalar!(
+        "INSERT INTO alerts (user_id, stock_id, metric, direction, threshold, rearm, cooldown_seconds, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id",
+        user_id,
+        stock_id,
+        alert.metric as AlertMetric,
+        alert.direction as AlertDirection,
+        threshold as Money,
+        alert.rearm,
+        cooldown,
+        at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let alert = self::find_alert(&mut *tx, user_id, id).await?;
+
+    tx.commit().await?;
+
+    Ok(alert)
+}
+
+/// The alert that is enabled again is armed as well, whether it fired before or not.
+pub async fn set_enabled(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    enabled: bool,
+) -> self::Result<Alert> {
+    let mut tx = conn.begin().await?;
+
+    sqlx::query!(
+        "UPDATE alerts SET armed = CASE WHEN $3 AND NOT enabled THEN TRUE ELSE armed END, enabled = $3
+        WHERE id = $1 AND user_id = $2",
+        id,
+        user_id,
+        enabled
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let alert = self::find_alert(&mut *tx, user_id, id).await?;
+
+    tx.commit().await?;
+
+    Ok(alert)
+}
+
+/// The notifications of the alert stay in the inbox.
+pub async fn delete_alert(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+) -> self::Result<()> {
+    let deleted = sqlx::query!(
+        "DELETE FROM alerts WHERE id = $1 AND user_id = $2",
+        id,
+        user_id
+    )
+    .execute(conn)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(Error::AlertNotFound(id));
+    }
+
+    Ok(())
+}
+
+/// Checks the enabled alerts of the quoted stocks against the latest quote of each, writes the notifications of
+/// the ones that fired and returns those, to be published.
+pub async fn evaluate_quotes(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    quotes: &[Quote],
+    at: chrono::NaiveDateTime,
+) -> self::Result<Vec<Notification>> {
+    let mut latest = BTreeMap::<i32, &Quote>::new();
+
+    for quote in quotes {
+        let entry = latest.entry(quote.stock_id).or_insert(quote);
+
+        if quote.id > entry.id {
+            *entry = quote;
+        }
+    }
+
+    let stock_ids = latest.keys().copied().collect::<Vec<_>>();
+
+    let mut tx = conn.begin().await?;
+
+    let alerts = sqlx::query_as!(
+        Alert,
+        r#"SELECT
+            alerts.id, alerts.user_id, alerts.stock_id, stocks.abbreviation,
+            alerts.metric AS "metric: AlertMetric", alerts.direction AS "direction: AlertDirection",
+            alerts.threshold, alerts.rearm, alerts.cooldown_seconds, alerts.enabled, alerts.armed,
+            alerts.last_triggered_at, alerts.created_at
+        FROM alerts JOIN stocks ON stocks.id = alerts.stock_id
+        WHERE alerts.stock_id = ANY($1) AND alerts.enabled
+        ORDER BY alerts.id
+        FOR UPDATE OF alerts"#,
+        &stock_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut fired = vec![];
+    let mut user_ids = vec![];
+    let mut messages = vec![];
+    let mut rearmed = vec![];
+
+    for alert in &alerts {
+        let quote = latest[&alert.stock_id];
+
+        match alert.step(quote, at) {
+            Step::Fire => {
+                fired.push(alert.id);
+                user_ids.push(alert.user_id);
+                messages.push(alert.message(quote));
+            }
+            Step::Rearm => rearmed.push(alert.id),
+            Step::Nothing => {}
+        }
+    }
+
+    if fired.is_empty() && rearmed.is_empty() {
+        return Ok(vec![]);
+    }
+
+    sqlx::query!(
+        "UPDATE alerts SET enabled = rearm, armed = FALSE, last_triggered_at = $2 WHERE id = ANY($1)",
+        &fired,
+        at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE alerts SET armed = TRUE WHERE id = ANY($1)",
+        &rearmed
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let notifications = sqlx::query_as!(
+        Notification,
+        "INSERT INTO notifications (user_id, alert_id, message, created_at)
+        SELECT user_id, alert_id, message, $4
+        FROM UNNEST($1::int4[], $2::int8[], $3::text[]) AS fired(user_id, alert_id, message)
+        RETURNING id, user_id, alert_id, message, created_at, read_at",
+        &user_ids,
+        &fired,
+        &messages,
+        at
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(notifications)
+}
+
+/// Evaluates the alerts against the quotes of the feed on its own task, for as long as the feed lives, and
+/// publishes the notifications of the ones that fired.
+///
+/// NOTE: The feed is subscribed before this returns, so nothing published after is missed, unless the task falls
+/// behind. The skipped quotes are not evaluated then, the next quote of the stock is, so the crossings that came
+/// and went within those are missed.
+pub fn spawn(
+    DatabaseConnection(conn): DatabaseConnection,
+    quotes: &QuoteFeed,
+    notifications: NotificationFeed,
+) -> tokio::task::JoinHandle<()> {
+    let mut quotes = quotes.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let batch = match quotes.recv().await {
+                Ok(batch) => batch,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "Alerts fell behind the quotes");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            for chunk in batch.chunks(BATCH_SIZE) {
+                match self::evaluate_quotes(&conn, chunk, chrono::Utc::now().naive_utc()).await {
+                    Ok(fired) if !fired.is_empty() => {
+                        tracing::debug!("{} alerts fired", fired.len());
+                        notifications.publish(&fired);
+                    }
+                    Ok(_) => {}
+                    Err(err) => tracing::error!(?err, "Evaluating the alerts failed"),
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(direction: AlertDirection, threshold: i64, rearm: bool) -> Alert {
+        Alert {
+            id: 1,
+            user_id: 1,
+            stock_id: 1,
+            abbreviation: "AAPL".into(),
+            metric: AlertMetric::Price,
+            direction,
+            threshold: Money::from(threshold),
+            rearm,
+            cooldown_seconds: 60,
+            enabled: true,
+            armed: true,
+            last_triggered_at: None,
+            created_at: chrono::NaiveDateTime::default(),
+        }
+    }
+
+    fn quote(price: i64, delta: &str) -> Quote {
+        Quote {
+            id: 1,
+            stock_id: 1,
+            abbreviation: "AAPL".into(),
+            price: Money::from(price).round(Money::PRICE_SCALE),
+            delta: delta.parse().unwrap(),
+            at: chrono::NaiveDateTime::default(),
+        }
+    }
+
+    #[test]
+    fn test_step() {
+        let at = chrono::NaiveDateTime::default();
+        let mut above = alert(AlertDirection::Above, 200, true);
+
+        assert_eq!(above.step(&quote(199, "0"), at), Step::Nothing);
+        assert_eq!(above.step(&quote(200, "0"), at), Step::Fire);
+
+        above.armed = false;
+        above.last_triggered_at = Some(at);
+
+        assert_eq!(above.step(&quote(201, "0"), at), Step::Nothing);
+        assert_eq!(above.step(&quote(199, "0"), at), Step::Rearm);
+
+        // Flapping back within the cooldown waits for it.
+        above.armed = true;
+
+        assert_eq!(
+            above.step(&quote(201, "0"), at + chrono::Duration::seconds(59)),
+            Step::Nothing
+        );
+        assert_eq!(
+            above.step(&quote(201, "0"), at + chrono::Duration::seconds(60)),
+            Step::Fire
+        );
+
+        let mut once = alert(AlertDirection::Below, 100, false);
+        once.armed = false;
+
+        assert_eq!(once.step(&quote(101, "0"), at), Step::Nothing);
+
+        let mut delta = alert(AlertDirection::Below, -3, true);
+        delta.metric = AlertMetric::Delta;
+
+        assert_eq!(delta.step(&quote(1, "-2.50"), at), Step::Nothing);
+        assert_eq!(delta.step(&quote(1, "-3.50"), at), Step::Fire);
+        assert_eq!(
+            delta.message(&quote(1, "-3.50")),
+            "AAPL delta is below -3.00%, now at -3.50%"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let new = |metric, threshold: &str, rearm, cooldown_seconds| NewAlert {
+            ticker: "AAPL".into(),
+            metric,
+            direction: AlertDirection::Above,
+            threshold: threshold.parse().unwrap(),
+            rearm,
+            cooldown_seconds,
+        };
+
+        assert_eq!(
+            new(AlertMetric::Price, "200.123456", false, None)
+                .validate()
+                .unwrap(),
+            (
+                "200.1235".parse().unwrap(),
+                DEFAULT_COOLDOWN.as_secs() as i32
+            )
+        );
+        assert!(
+            new(AlertMetric::Price, "0", false, None)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            new(AlertMetric::Delta, "-3", false, None)
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            new(AlertMetric::Delta, "150", false, None)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            new(AlertMetric::Price, "1", false, Some(0))
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            new(AlertMetric::Price, "1", true, Some(0))
+                .validate()
+                .is_err()
+        );
+        assert!(
+            new(AlertMetric::Price, "1", true, Some(-1))
+                .validate()
+                .is_err()
+        );
+    }
+}
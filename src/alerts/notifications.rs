@@ -0,0 +1,120 @@
+//! The inbox of the user, the alerts deliver their notifications there.
+//!
+//! Every notification is written first and published to the `NotificationFeed` after, so the client that missed
+//! the live one, or was not connected at all, finds it in the inbox. It stays unread until the user marks it.
+
+use super::Error;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Notification {
+    pub id: i64,
+    pub user_id: i32,
+    /// The alert that triggered it, None once the alert is deleted.
+    pub alert_id: Option<i64>,
+    pub message: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub read_at: Option<chrono::NaiveDateTime>,
+}
+
+/// The new notifications, the clients of the user they are for push those live.
+#[derive(Clone, Debug)]
+pub struct NotificationFeed(pub tokio::sync::broadcast::Sender<Notification>);
+
+impl NotificationFeed {
+    pub const CAPACITY: usize = 256;
+
+    pub fn new(capacity: usize) -> Self {
+        Self(tokio::sync::broadcast::channel(capacity).0)
+    }
+
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Notification> {
+        self.0.subscribe()
+    }
+
+    pub fn publish(&self, notifications: &[Notification]) {
+        for notification in notifications {
+            // NOTE: That only fails when nobody is listening, which is fine.
+            let _ = self.0.send(notification.clone());
+        }
+    }
+}
+
+impl Default for NotificationFeed {
+    fn default() -> Self {
+        Self::new(Self::CAPACITY)
+    }
+}
+
+/// Newest first, only the ones older than `before` when given, the cursor of the page.
+pub async fn list_notifications(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    unread_only: bool,
+    before: Option<i64>,
+    limit: i64,
+) -> super::Result<Vec<Notification>> {
+    Ok(sqlx::query_as!(
+        Notification,
+        "SELECT id, user_id, alert_id, message, created_at, read_at
+        FROM notifications
+        WHERE user_id = $1 AND (NOT $2 OR read_at IS NULL) AND ($3::int8 IS NULL OR id < $3)
+        ORDER BY id DESC
+        LIMIT $4",
+        user_id,
+        unread_only,
+        before,
+        limit
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+pub async fn count_unread(conn: &sqlx::Pool<sqlx::Postgres>, user_id: i32) -> super::Result<i64> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM notifications WHERE user_id = $1 AND read_at IS NULL"#,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?)
+}
+
+/// Marking the read notification again keeps the time it was read first.
+pub async fn mark_read(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Notification> {
+    sqlx::query_as!(
+        Notification,
+        "UPDATE notifications SET read_at = COALESCE(read_at, $3)
+        WHERE id = $1 AND user_id = $2
+        RETURNING id, user_id, alert_id, message, created_at, read_at",
+        id,
+        user_id,
+        at
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotificationNotFound(id))
+}
+
+/// Marks every unread notification of the user, up to the id when given, so the ones that arrived after the
+/// client looked at the inbox stay unread. Returns how many were marked.
+pub async fn mark_all_read(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    up_to: Option<i64>,
+    at: chrono::NaiveDateTime,
+) -> super::Result<u64> {
+    Ok(sqlx::query!(
+        "UPDATE notifications SET read_at = $3
+        WHERE user_id = $1 AND read_at IS NULL AND ($2::int8 IS NULL OR id <= $2)",
+        user_id,
+        up_to,
+        at
+    )
+    .execute(conn)
+    .await?
+    .rows_affected())
+}
@@ -0,0 +1,204 @@
+//! Performance analytics over the series of the daily portfolio values.
+//!
+//! Those are pure functions, so they run on any series, the snapshots of the users or the synthetic ones.
+//! The returns are daily and time-weighted: the money that came in during the day is taken out of its closing
+//! value, so only the change of the prices counts. The ratios are fractions, `0.05` is 5%.
+
+/// Used to annualize the daily returns.
+pub const TRADING_DAYS: f64 = 252.0;
+
+/// Below that the volatility is only the noise of the floats, e.g. of the steady growth.
+const MIN_VOLATILITY: f64 = 1e-9;
+
+/// The value of the portfolio at the close of the day.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Point {
+    pub value: f64,
+    /// The money that came in during the day, negative when it left.
+    pub net_flow: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Performance {
+    pub time_weighted_return: f64,
+    pub max_drawdown: f64,
+    /// None with less than two returns, there is nothing to deviate from.
+    pub volatility: Option<f64>,
+    /// None without the volatility, or when there is none to speak of.
+    pub sharpe_ratio: Option<f64>,
+}
+
+impl Performance {
+    /// The performance over the points, the first one is only the base the second one returns against.
+    pub fn of(points: &[Point], risk_free_rate: f64) -> Self {
+        let returns = self::daily_returns(points);
+
+        Self {
+            time_weighted_return: self::time_weighted_return(&returns),
+            max_drawdown: self::max_drawdown(&returns),
+            volatility: self::volatility(&returns),
+            sharpe_ratio: self::sharpe_ratio(&returns, risk_free_rate),
+        }
+    }
+}
+
+/// The return of every day against the previous one, without the flows of the day.
+///
+/// The days following the empty portfolio are skipped, there is nothing to return on, the first deposit only starts the series.
+pub fn daily_returns(points: &[Point]) -> Vec<f64> {
+    points
+        .windows(2)
+        .filter(|days| days[0].value > 0.0)
+        .map(|days| (days[1].value - days[1].net_flow) / days[0].value - 1.0)
+        .collect()
+}
+
+/// The returns chained together, what the single unit invested at the start would have returned.
+pub fn time_weighted_return(returns: &[f64]) -> f64 {
+    returns.iter().map(|r| 1.0 + r).product::<f64>() - 1.0
+}
+
+/// The largest fall from the peak of the chained returns, `0.2` is 20% below the peak.
+///
+/// NOTE: That runs on the returns rather than the values, otherwise a withdrawal would look like a drawdown.
+pub fn max_drawdown(returns: &[f64]) -> f64 {
+    let mut growth = 1.0;
+    let mut peak = 1.0;
+    let mut drawdown = 0.0_f64;
+
+    for r in returns {
+        growth *= 1.0 + r;
+        peak = f64::max(peak, growth);
+        drawdown = drawdown.max((peak - growth) / peak);
+    }
+
+    drawdown
+}
+
+fn mean(returns: &[f64]) -> f64 {
+    returns.iter().sum::<f64>() / returns.len() as f64
+}
+
+/// Annualized sample standard deviation of the daily returns.
+pub fn volatility(returns: &[f64]) -> Option<f64> {
+    if returns.len() < 2 {
+        return None;
+    }
+
+    let mean = self::mean(returns);
+    let variance =
+        returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+
+    Some(variance.sqrt() * TRADING_DAYS.sqrt())
+}
+
+/// Annualized return over the risk-free rate, per the unit of the volatility.
+pub fn sharpe_ratio(returns: &[f64], risk_free_rate: f64) -> Option<f64> {
+    let volatility = self::volatility(returns).filter(|volatility| *volatility > MIN_VOLATILITY)?;
+
+    Some((self::mean(returns) * TRADING_DAYS - risk_free_rate) / volatility)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(values: &[f64]) -> Vec<Point> {
+        values
+            .iter()
+            .map(|&value| Point {
+                value,
+                net_flow: 0.0,
+            })
+            .collect()
+    }
+
+    fn assert_close(left: f64, right: f64) {
+        assert!((left - right).abs() < 1e-9, "{left} != {right}");
+    }
+
+    #[test]
+    fn test_steady_growth() {
+        let values = (0..=10)
+            .map(|day| 100.0 * 1.01_f64.powi(day))
+            .collect::<Vec<_>>();
+        let performance = Performance::of(&points(&values), 0.0);
+
+        assert_close(performance.time_weighted_return, 1.01_f64.powi(10) - 1.0);
+        assert_close(performance.max_drawdown, 0.0);
+        assert_close(performance.volatility.unwrap(), 0.0);
+        // Nothing to divide by.
+        assert_eq!(performance.sharpe_ratio, None);
+    }
+
+    #[test]
+    fn test_flows_are_not_returns() {
+        let series = [
+            Point {
+                value: 0.0,
+                net_flow: 0.0,
+            },
+            // The first deposit.
+            Point {
+                value: 100.0,
+                net_flow: 100.0,
+            },
+            // Doubled by the deposit, up 10% by the prices.
+            Point {
+                value: 210.0,
+                net_flow: 100.0,
+            },
+            // Half of it withdrawn.
+            Point {
+                value: 105.0,
+                net_flow: -105.0,
+            },
+        ];
+
+        let returns = daily_returns(&series);
+        assert_eq!(returns.len(), 2);
+        assert_close(returns[0], 0.1);
+        assert_close(returns[1], 0.0);
+
+        let performance = Performance::of(&series, 0.0);
+        assert_close(performance.time_weighted_return, 0.1);
+        assert_close(performance.max_drawdown, 0.0);
+    }
+
+    #[test]
+    fn test_drawdown_and_ratios() {
+        let performance = Performance::of(&points(&[100.0, 120.0, 90.0, 130.0]), 0.0);
+
+        assert_close(performance.time_weighted_return, 0.3);
+        // From 120 down to 90.
+        assert_close(performance.max_drawdown, 0.25);
+
+        let returns = [0.01, -0.01];
+        assert_close(
+            volatility(&returns).unwrap(),
+            0.0002_f64.sqrt() * TRADING_DAYS.sqrt(),
+        );
+        assert_close(sharpe_ratio(&returns, 0.0).unwrap(), 0.0);
+
+        let returns = [0.02, 0.0];
+        let volatility = volatility(&returns).unwrap();
+        assert_close(
+            sharpe_ratio(&returns, 0.05).unwrap(),
+            (0.01 * TRADING_DAYS - 0.05) / volatility,
+        );
+    }
+
+    #[test]
+    fn test_short_series() {
+        assert_eq!(Performance::of(&[], 0.0), Performance::default());
+        assert_eq!(
+            Performance::of(&points(&[100.0]), 0.0),
+            Performance::default()
+        );
+
+        let performance = Performance::of(&points(&[100.0, 90.0]), 0.0);
+        assert_close(performance.time_weighted_return, -0.1);
+        assert_close(performance.max_drawdown, 0.1);
+        assert_eq!(performance.volatility, None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Imports the yfinance CSV files into the `stocks` and the `stock_prices` tables.
+//!
+//! ```text
+//! cargo run --bin import -- [--ticker AAPL] [--company "Apple Inc."] AAPL.csv [MSFT.csv ...]
+//! ```
+//!
+//! The ticker defaults to the file name without the extension, so `--ticker` only makes sense with a single file.
+//! Each file is imported in its own transaction, the failure of one does not roll back the others.
+
+use rust_web_app::{config, database::DatabaseConnection, logger, market::import, prelude::*};
+
+const USAGE: &str = "Usage: import [--ticker TICKER] [--company NAME] FILE...";
+
+struct Args {
+    ticker: Option<String>,
+    company: Option<String>,
+    files: Vec<std::path::PathBuf>,
+}
+
+fn parse_args() -> std::result::Result<Args, String> {
+    let mut args = std::env::args().skip(1);
+    let mut parsed = Args {
+        ticker: None,
+        company: None,
+        files: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ticker" => parsed.ticker = Some(args.next().ok_or("--ticker requires a value")?),
+            "--company" => parsed.company = Some(args.next().ok_or("--company requires a value")?),
+            "-h" | "--help" => return Err(USAGE.into()),
+            flag if flag.starts_with("--") => return Err(format!("unknown flag {flag}")),
+            file => parsed.files.push(file.into()),
+        }
+    }
+
+    if parsed.files.is_empty() {
+        return Err("no files to import".into());
+    }
+
+    if parsed.files.len() > 1 && (parsed.ticker.is_some() || parsed.company.is_some()) {
+        return Err("--ticker and --company can only be used with a single file".into());
+    }
+
+    Ok(parsed)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    config::Config::new()?;
+    logger::init()?;
+
+    let DatabaseConnection(conn) = DatabaseConnection::new().await?;
+    let mut failed = 0;
+
+    for path in &args.files {
+        let ticker = match args.ticker.clone().or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        }) {
+            Some(ticker) => ticker,
+            None => {
+                error!("Cannot infer the ticker from {}", path.display());
+                failed += 1;
+                continue;
+            }
+        };
+
+        let result = match std::fs::File::open(path) {
+            Ok(file) => import::import_history(&conn, &ticker, args.company.as_deref(), file)
+                .await
+                .map_err(Error::from),
+            Err(err) => Err(err.into()),
+        };
+
+        match result {
+            Ok(report) => {
+                println!(
+                    "{}: {} {} (id {}), {} points imported, {} rows rejected",
+                    path.display(),
+                    if report.created { "created" } else { "updated" },
+                    report.abbreviation,
+                    report.stock_id,
+                    report.imported,
+                    report.errors.len()
+                );
+
+                for row in report.errors {
+                    println!("  {row}");
+                }
+            }
+            Err(err) => {
+                error!("{}: import failed: {err}", path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        error!("{failed} of {} files failed to import", args.files.len());
+        std::process::exit(1);
+    }
+
+    return Ok(());
+}
@@ -51,6 +51,12 @@ pub enum EnvError {
         variant: super::Env,
         translation: String,
     },
+    #[error("Environment variable {env} has invalid value {value:?}: {reason}")]
+    InvalidValue {
+        env: super::Env,
+        value: String,
+        reason: String,
+    },
     #[error("I/O error occurred: {0}")]
     Io(#[from] Arc<std::io::Error>),
     #[error("Catch all variant: {0}")]
@@ -4,9 +4,21 @@ pub use self::error::{EnvError, Error};
 use std::{collections::HashSet, fmt::Debug, hash::Hash, str::FromStr};
 use strum::IntoEnumIterator;
 
+use crate::{
+    controller::auth::{hashing::HashingConfig, limiter::LoginPolicy},
+    market::{MarketConfig, PriceModel, ReplayConfig, SimulationConfig},
+};
+
 pub(in crate::config) type Result<T> = std::result::Result<T, self::Error>;
 
-pub struct Config;
+pub struct Config {
+    /// The source of the prices that move in the background, simulated by default.
+    pub market: crate::market::MarketConfig,
+    /// How many failed logins are let through and how long the client waits after those.
+    pub login: crate::controller::auth::limiter::LoginPolicy,
+    /// How many passwords are hashed at once, the logins beyond that are answered with 503.
+    pub hashing: crate::controller::auth::hashing::HashingConfig,
+}
 
 /// # This code should not happen. Written to practice unit testing.
 ///
@@ -48,12 +60,59 @@ pub enum Env {
     DbPostgresAdambPassword,
     DatabaseUrl,
     RustLog,
+    // The optional ones below fall back to the defaults in the code when left out of the .env file.
+    /// `simulation`, `replay` or `disabled`.
+    MarketProvider,
+    MarketTickSeconds,
+    /// `gbm` or `random_walk`.
+    MarketModel,
+    /// The percent the random walk moves the price by per tick.
+    MarketStep,
+    MarketDrift,
+    MarketVolatility,
+    MarketSeed,
+    MarketReplayFile,
+    MarketReplaySpeed,
+    MarketReplayRepeat,
+    HashingMaxConcurrent,
+    HashingMaxWaitMs,
+    LoginMaxFailuresPerEmail,
+    LoginMaxFailuresPerIp,
+    LoginBaseDelayMs,
+    LoginMaxDelaySeconds,
+    LoginLockoutSeconds,
+    LoginResetAfterSeconds,
 }
 
 impl Env {
     // The same .env is shared across frontend which is a shenanigan.
     pub const ENV_PATH: &str = ".env";
 
+    /// The envs with the defaults in the code, those are not required in the .env file.
+    pub fn is_optional(&self) -> bool {
+        matches!(
+            self,
+            Env::MarketProvider
+                | Env::MarketTickSeconds
+                | Env::MarketModel
+                | Env::MarketStep
+                | Env::MarketDrift
+                | Env::MarketVolatility
+                | Env::MarketSeed
+                | Env::MarketReplayFile
+                | Env::MarketReplaySpeed
+                | Env::MarketReplayRepeat
+                | Env::HashingMaxConcurrent
+                | Env::HashingMaxWaitMs
+                | Env::LoginMaxFailuresPerEmail
+                | Env::LoginMaxFailuresPerIp
+                | Env::LoginBaseDelayMs
+                | Env::LoginMaxDelaySeconds
+                | Env::LoginLockoutSeconds
+                | Env::LoginResetAfterSeconds
+        )
+    }
+
     /// Loads the envs from the `.env` file and checks for 1-1 mapping between the envs defined in the .env file and the enum variants.
     /// Tries to be detailed about the error messages converting the mismatches between the two.
     fn load_envs() -> self::Result<()> {
@@ -65,7 +124,7 @@ impl Env {
     // The isolation between two function compare_envs and check_mapping is to make the
     // unit testing easier. Normally I would just call the check_mapping directly
     fn compare_envs(other: HashSet<String>) -> self::Result<()> {
-        Self::check_mapping(Self::get_enum_envs()?, other)
+        Self::check_mapping(Self::get_required_envs()?, other)
     }
 
     /// Checks if there is 1-1 mapping between the envs declared in the .env file and the envs declared in the Env enum.
@@ -141,6 +200,14 @@ impl Env {
             })
             .map_err(Error::from)
     }
+
+    /// The envs declared in the Env enum that have to be in the .env file, i.e. all but the optional ones.
+    pub fn get_required_envs() -> self::Result<HashSet<String>> {
+        let mut enum_envs = Self::get_enum_envs()?;
+        enum_envs.retain(|env| Env::from_str(env).is_ok_and(|env| !env.is_optional()));
+
+        Ok(enum_envs)
+    }
 }
 
 impl Config {
@@ -151,7 +218,140 @@ impl Config {
     pub fn new() -> self::Result<Self> {
         Env::load_envs()?;
 
-        Ok(Self)
+        let lookup = |env: &Env| dotenvy::var(env.as_ref()).ok();
+
+        Ok(Self {
+            market: Self::market(&lookup)?,
+            login: Self::login(&lookup)?,
+            hashing: Self::hashing(&lookup)?,
+        })
+    }
+
+    /// Parses the optional env, `None` when it is not set or left empty.
+    ///
+    /// The envs are read through the `lookup`, so the tests can pass their own without touching the process.
+    fn parse<T: FromStr>(
+        lookup: &impl Fn(&Env) -> Option<String>,
+        env: Env,
+    ) -> self::Result<Option<T>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        let Some(value) = lookup(&env).filter(|value| !value.trim().is_empty()) else {
+            return Ok(None);
+        };
+
+        value.trim().parse().map(Some).map_err(|e: T::Err| {
+            EnvError::InvalidValue {
+                env,
+                reason: e.to_string(),
+                value,
+            }
+            .into()
+        })
+    }
+
+    /// The simulation with the default model, unless the envs pick something else.
+    fn market(lookup: &impl Fn(&Env) -> Option<String>) -> self::Result<MarketConfig> {
+        let provider = lookup(&Env::MarketProvider).unwrap_or_default();
+
+        Ok(match provider.trim() {
+            "" | "simulation" => {
+                let default = SimulationConfig::default();
+
+                MarketConfig::Simulation(SimulationConfig {
+                    tick: Self::parse(lookup, Env::MarketTickSeconds)?
+                        .map_or(default.tick, std::time::Duration::from_secs),
+                    model: Self::price_model(lookup)?,
+                    seed: Self::parse(lookup, Env::MarketSeed)?,
+                })
+            }
+            "replay" => {
+                let default = ReplayConfig::default();
+
+                MarketConfig::Replay(ReplayConfig {
+                    path: Self::parse(lookup, Env::MarketReplayFile)?.unwrap_or(default.path),
+                    speed: Self::parse(lookup, Env::MarketReplaySpeed)?.unwrap_or(default.speed),
+                    repeat: Self::parse(lookup, Env::MarketReplayRepeat)?.unwrap_or(default.repeat),
+                })
+            }
+            "disabled" => MarketConfig::Disabled,
+            _ => {
+                return Err(EnvError::InvalidValue {
+                    env: Env::MarketProvider,
+                    value: provider,
+                    reason: "expected simulation, replay or disabled".into(),
+                }
+                .into());
+            }
+        })
+    }
+
+    fn login(lookup: &impl Fn(&Env) -> Option<String>) -> self::Result<LoginPolicy> {
+        let default = LoginPolicy::default();
+        let seconds = |env, default| -> self::Result<std::time::Duration> {
+            Ok(Self::parse(lookup, env)?.map_or(default, std::time::Duration::from_secs))
+        };
+        // Zero failures would lock out every email before its first try.
+        let failures = |env, default| -> self::Result<i32> {
+            Ok(Self::parse::<std::num::NonZeroU16>(lookup, env)?
+                .map_or(default, |max| max.get().into()))
+        };
+
+        Ok(LoginPolicy {
+            max_failures_per_email: failures(
+                Env::LoginMaxFailuresPerEmail,
+                default.max_failures_per_email,
+            )?,
+            max_failures_per_ip: failures(Env::LoginMaxFailuresPerIp, default.max_failures_per_ip)?,
+            base_delay: Self::parse(lookup, Env::LoginBaseDelayMs)?
+                .map_or(default.base_delay, std::time::Duration::from_millis),
+            max_delay: seconds(Env::LoginMaxDelaySeconds, default.max_delay)?,
+            lockout: seconds(Env::LoginLockoutSeconds, default.lockout)?,
+            reset_after: seconds(Env::LoginResetAfterSeconds, default.reset_after)?,
+        })
+    }
+
+    fn hashing(lookup: &impl Fn(&Env) -> Option<String>) -> self::Result<HashingConfig> {
+        let default = HashingConfig::default();
+
+        Ok(HashingConfig {
+            max_concurrent: Self::parse::<std::num::NonZeroUsize>(
+                lookup,
+                Env::HashingMaxConcurrent,
+            )?
+            .map_or(default.max_concurrent, |max| max.get()),
+            max_wait: Self::parse(lookup, Env::HashingMaxWaitMs)?
+                .map_or(default.max_wait, std::time::Duration::from_millis),
+        })
+    }
+
+    fn price_model(lookup: &impl Fn(&Env) -> Option<String>) -> self::Result<PriceModel> {
+        let model = lookup(&Env::MarketModel).unwrap_or_default();
+
+        Ok(match model.trim() {
+            "" | "gbm" => match PriceModel::default() {
+                PriceModel::GeometricBrownianMotion { drift, volatility } => {
+                    PriceModel::GeometricBrownianMotion {
+                        drift: Self::parse(lookup, Env::MarketDrift)?.unwrap_or(drift),
+                        volatility: Self::parse(lookup, Env::MarketVolatility)?
+                            .unwrap_or(volatility),
+                    }
+                }
+                model => model,
+            },
+            "random_walk" => PriceModel::RandomWalk {
+                step: Self::parse(lookup, Env::MarketStep)?.unwrap_or(PriceModel::DEFAULT_STEP),
+            },
+            _ => {
+                return Err(EnvError::InvalidValue {
+                    env: Env::MarketModel,
+                    value: model,
+                    reason: "expected gbm or random_walk".into(),
+                }
+                .into());
+            }
+        })
     }
 }
 
@@ -172,6 +372,9 @@ mod tests {
     #[derive(Debug)]
     struct TempCwd {
         old: std::path::PathBuf,
+        // Held so the directory outlives the cwd change, otherwise it is removed while we are still inside it
+        // which only works on Windows where the cwd cannot be deleted.
+        _dir: Option<tempfile::TempDir>,
     }
 
     impl TempCwd {
@@ -189,7 +392,7 @@ mod tests {
             let current = std::env::current_dir().context("Failed to get current dir")?;
             assert_eq!(current, new);
 
-            Ok(Self { old })
+            Ok(Self { old, _dir: None })
         }
     }
 
@@ -243,7 +446,7 @@ mod tests {
 
         // Store previous cwd and restore it after the test.
         // If into bound to the variable, the Drop will be called immediately and the cwd will be restored before the test completes.
-        let _guard = TempCwd::push(tempdir.path())?;
+        let mut _guard = TempCwd::push(tempdir.path())?;
 
         // Write every single var to the env file, we do not care about the values.
         for var in vars.iter() {
@@ -270,6 +473,8 @@ mod tests {
         }
 
         // Hold the guard to restore the cwd later.
+        _guard._dir = Some(tempdir);
+
         Ok((file_envs, _guard))
     }
 
@@ -458,6 +663,171 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_optional_envs_not_required_in_file() {
+        let required = <Env as strum::IntoEnumIterator>::iter()
+            .filter(|env| !env.is_optional())
+            .map(|env| env.to_string())
+            .collect::<HashSet<String>>();
+
+        assert!(Env::compare_envs(required).is_ok());
+    }
+
+    fn lookup(vars: &[(Env, &str)]) -> impl Fn(&Env) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(env, value)| (env.clone(), value.to_string()))
+            .collect::<std::collections::HashMap<_, _>>();
+
+        move |env| vars.get(env).cloned()
+    }
+
+    #[test]
+    fn test_market_config_from_envs() {
+        use crate::market::{MarketConfig, PriceModel, ReplayConfig, SimulationConfig};
+
+        assert_eq!(
+            super::Config::market(&lookup(&[])).unwrap(),
+            MarketConfig::default()
+        );
+
+        let config = super::Config::market(&lookup(&[
+            (Env::MarketTickSeconds, "5"),
+            (Env::MarketModel, "random_walk"),
+            (Env::MarketStep, "0.02"),
+            (Env::MarketSeed, "42"),
+            // Not used by the simulation.
+            (Env::MarketReplaySpeed, "not a number"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config,
+            MarketConfig::Simulation(SimulationConfig {
+                tick: std::time::Duration::from_secs(5),
+                model: PriceModel::RandomWalk { step: 0.02 },
+                seed: Some(42),
+            })
+        );
+
+        // A percent per tick when the step is not given.
+        assert_eq!(
+            super::Config::market(&lookup(&[(Env::MarketModel, "random_walk")])).unwrap(),
+            MarketConfig::Simulation(SimulationConfig {
+                model: PriceModel::RandomWalk { step: 1.0 },
+                ..Default::default()
+            })
+        );
+
+        let config = super::Config::market(&lookup(&[
+            (Env::MarketProvider, "replay"),
+            (Env::MarketReplayFile, "data/quotes.json"),
+            (Env::MarketReplaySpeed, "60"),
+            (Env::MarketReplayRepeat, "true"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config,
+            MarketConfig::Replay(ReplayConfig {
+                path: "data/quotes.json".into(),
+                speed: 60.0,
+                repeat: true,
+            })
+        );
+
+        assert_eq!(
+            super::Config::market(&lookup(&[(Env::MarketProvider, "disabled")])).unwrap(),
+            MarketConfig::Disabled
+        );
+    }
+
+    #[test]
+    fn test_market_config_invalid_envs() {
+        for vars in [
+            [(Env::MarketProvider, "live")],
+            [(Env::MarketModel, "heston")],
+            [(Env::MarketSeed, "-1")],
+        ] {
+            let result = super::Config::market(&lookup(&vars));
+
+            assert!(
+                matches!(
+                    result,
+                    Err(crate::config::Error::Env(crate::config::EnvError::InvalidValue { ref env, .. }))
+                    if *env == vars[0].0
+                ),
+                "Expected InvalidValue error, but got {:?}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_login_policy_from_envs() {
+        use crate::controller::auth::limiter::LoginPolicy;
+
+        assert_eq!(
+            super::Config::login(&lookup(&[])).unwrap(),
+            LoginPolicy::default()
+        );
+
+        assert_eq!(
+            super::Config::login(&lookup(&[
+                (Env::LoginMaxFailuresPerEmail, "3"),
+                (Env::LoginBaseDelayMs, "0"),
+                (Env::LoginLockoutSeconds, "60"),
+            ]))
+            .unwrap(),
+            LoginPolicy {
+                max_failures_per_email: 3,
+                base_delay: std::time::Duration::ZERO,
+                lockout: std::time::Duration::from_secs(60),
+                ..LoginPolicy::default()
+            }
+        );
+
+        for vars in [
+            [(Env::LoginMaxFailuresPerIp, "0")],
+            [(Env::LoginResetAfterSeconds, "1h")],
+        ] {
+            assert!(matches!(
+                super::Config::login(&lookup(&vars)),
+                Err(crate::config::Error::Env(
+                    crate::config::EnvError::InvalidValue { .. }
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_hashing_config_from_envs() {
+        use crate::controller::auth::hashing::HashingConfig;
+
+        assert_eq!(
+            super::Config::hashing(&lookup(&[])).unwrap(),
+            HashingConfig::default()
+        );
+
+        assert_eq!(
+            super::Config::hashing(&lookup(&[
+                (Env::HashingMaxConcurrent, "2"),
+                (Env::HashingMaxWaitMs, "100"),
+            ]))
+            .unwrap(),
+            HashingConfig {
+                max_concurrent: 2,
+                max_wait: std::time::Duration::from_millis(100),
+            }
+        );
+
+        // The pool without any permit would turn every login away.
+        assert!(matches!(
+            super::Config::hashing(&lookup(&[(Env::HashingMaxConcurrent, "0")])),
+            Err(crate::config::Error::Env(
+                crate::config::EnvError::InvalidValue { .. }
+            ))
+        ));
+    }
+
     #[test]
     #[serial_test::serial]
     fn test_check_missing_env_from_file() {
@@ -0,0 +1,88 @@
+//! CSRF protection of the cookie-authenticated endpoints, with the synchronizer token bound to the session row.
+//!
+//! The token is given to the client in the `CSRF` cookie next to the `SSID` one. That cookie is not `HttpOnly`,
+//! so the frontend reads it and sends it back in the `X-CSRF-Token` header of every mutating request. The other
+//! origins cannot read it, so they cannot forge the header even though the browser attaches the cookies.
+//!
+//! The requests without the `SSID` cookie are not checked, there is no session to ride on, nor are the ones whose
+//! session does not exist, the handlers answer those with 401 anyway.
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use sqlx::types::Uuid;
+use subtle::ConstantTimeEq;
+use tower_cookies::{Cookie, Cookies};
+
+use crate::{
+    controller::{auth::Error, cookies},
+    database::DatabaseConnection,
+};
+
+pub const HEADER: &str = "X-CSRF-Token";
+
+/// Readable by the frontend, otherwise the same as the `SSID` cookie, so both go away together.
+pub fn create_csrf_cookie(token: Uuid) -> Cookie<'static> {
+    Cookie::build((cookies::CSRF, token.to_string()))
+        .http_only(false)
+        .path("/")
+        .same_site(tower_cookies::cookie::SameSite::Strict)
+        .max_age(time::Duration::days(7))
+        .into()
+}
+
+pub async fn find_csrf_token(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    ssid: Uuid,
+) -> super::Result<Option<Uuid>> {
+    Ok(
+        sqlx::query_scalar!("SELECT csrf_token FROM sessions WHERE id = $1::uuid", ssid)
+            .fetch_optional(conn)
+            .await?,
+    )
+}
+
+/// Middleware rejecting the POST, PUT, PATCH and DELETE requests of the session without its token with 403.
+pub async fn verify_csrf_token(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    request: Request,
+    next: Next,
+) -> super::Result<Response> {
+    if !matches!(
+        *request.method(),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    ) {
+        return Ok(next.run(request).await);
+    }
+
+    // The malformed cookie is the handler's to reject.
+    let Some(ssid) = cookies
+        .get(cookies::SSID)
+        .and_then(|ssid| Uuid::parse_str(ssid.value()).ok())
+    else {
+        return Ok(next.run(request).await);
+    };
+
+    let Some(expected) = self::find_csrf_token(&conn, ssid).await? else {
+        return Ok(next.run(request).await);
+    };
+
+    let token = request
+        .headers()
+        .get(HEADER)
+        .and_then(|token| token.to_str().ok())
+        .and_then(|token| Uuid::parse_str(token).ok());
+
+    // The time of the comparison does not tell how much of the guessed token matched.
+    let valid = token.is_some_and(|token| bool::from(token.as_bytes().ct_eq(expected.as_bytes())));
+
+    if !valid {
+        return Err(Error::InvalidCsrfToken);
+    }
+
+    Ok(next.run(request).await)
+}
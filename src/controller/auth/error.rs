@@ -33,6 +33,14 @@ pub enum Error {
     EmailTaken(String),
     #[error("Already authenticated")]
     AlreadyAuthenticated,
+    #[error("Invalid or missing CSRF token")]
+    InvalidCsrfToken,
+    #[error("Too many login attempts, retry in {retry_after} seconds")]
+    TooManyLoginAttempts { retry_after: u64 },
+    #[error("Account locked after too many failed logins, retry in {retry_after} seconds")]
+    AccountLocked { retry_after: u64 },
+    #[error("Service Unavailable, too many logins at once, retry later")]
+    HashingPoolSaturated,
     #[error("Invalid email or password")]
     InvalidCredentials {
         #[source]
@@ -53,6 +61,14 @@ impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         let message = Cow::Owned(self.to_string());
 
+        let retry_after = match self {
+            Error::TooManyLoginAttempts { retry_after } | Error::AccountLocked { retry_after } => {
+                Some(retry_after)
+            }
+            Error::HashingPoolSaturated => Some(1),
+            _ => None,
+        };
+
         // This should not leak sensitive information.
         let representation = match self {
             Error::MissingSessionCookie => ErrorResponse {
@@ -94,6 +110,22 @@ impl IntoResponse for Error {
                 status: axum::http::StatusCode::BAD_REQUEST,
                 message,
             },
+            Error::InvalidCsrfToken => ErrorResponse {
+                status: axum::http::StatusCode::FORBIDDEN,
+                message,
+            },
+            Error::TooManyLoginAttempts { .. } => ErrorResponse {
+                status: axum::http::StatusCode::TOO_MANY_REQUESTS,
+                message,
+            },
+            Error::AccountLocked { .. } => ErrorResponse {
+                status: axum::http::StatusCode::LOCKED,
+                message,
+            },
+            Error::HashingPoolSaturated => ErrorResponse {
+                status: axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                message,
+            },
             Error::InvalidCredentials { .. } => ErrorResponse {
                 status: axum::http::StatusCode::BAD_REQUEST,
                 message,
@@ -108,7 +140,15 @@ impl IntoResponse for Error {
             },
         };
 
-        return self.to_response(representation);
+        let mut response = self.to_response(representation);
+
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(axum::http::header::RETRY_AFTER, retry_after.into());
+        }
+
+        return response;
     }
 }
 
@@ -0,0 +1,102 @@
+//! The bounded pool the Argon2 hashing and verification runs on, off the async executor.
+//!
+//! Every hash takes a permit first and runs on the blocking threads of tokio while holding it, so at most
+//! `max_concurrent` of them run at once. The request that does not get the permit within `max_wait` is answered
+//! with 503, instead of queueing behind the others while the logins pile up.
+
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+use crate::controller::auth::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashingConfig {
+    /// Argon2 uses the whole core while hashing, so more than the cores only makes each of them slower.
+    pub max_concurrent: usize,
+    pub max_wait: std::time::Duration,
+}
+
+impl Default for HashingConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: std::thread::available_parallelism().map_or(4, |cores| cores.get()),
+            max_wait: std::time::Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HashingPool {
+    permits: Arc<Semaphore>,
+    max_wait: std::time::Duration,
+}
+
+impl Default for HashingPool {
+    fn default() -> Self {
+        Self::new(HashingConfig::default())
+    }
+}
+
+impl HashingPool {
+    pub fn new(config: HashingConfig) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(config.max_concurrent)),
+            max_wait: config.max_wait,
+        }
+    }
+
+    /// Runs the hashing on the blocking thread once there is the permit for it.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        hashing: impl FnOnce() -> T + Send + 'static,
+    ) -> super::Result<T> {
+        let permit = tokio::time::timeout(self.max_wait, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| Error::HashingPoolSaturated)?
+            // NOTE: The semaphore is never closed.
+            .map_err(|_| Error::HashingPoolSaturated)?;
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+
+            hashing()
+        })
+        .await
+        .map_err(|e| Error::Other(Arc::new(anyhow::Error::new(e))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_saturated_pool_rejects() {
+        let pool = HashingPool::new(HashingConfig {
+            max_concurrent: 1,
+            max_wait: std::time::Duration::from_millis(50),
+        });
+
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let busy = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || released.recv().is_ok()).await }
+        });
+
+        // Lets the busy one take the permit.
+        while pool.permits.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(matches!(
+            pool.run(|| ()).await,
+            Err(Error::HashingPoolSaturated)
+        ));
+
+        release.send(()).unwrap();
+        assert!(matches!(busy.await.unwrap(), Ok(true)));
+
+        assert_eq!(pool.run(|| 1 + 1).await.ok(), Some(2));
+    }
+}
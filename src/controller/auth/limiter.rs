@@ -0,0 +1,738 @@
+//! Brute-force protection of the login, by the failed attempts per email and per client address.
+//!
+//! Every failure makes the next attempt of the key wait longer, from `base_delay` doubling up to `max_delay`, and
+//! the key that failed too many times in a row is locked out for `lockout`. The locked out email is answered with
+//! 423, the backoff and the locked out address with 429, both with the `Retry-After`.
+//!
+//! The failures older than `reset_after` are forgotten. The successful login clears the failures of the email, but
+//! not the ones of the address, so the attacker knowing one account cannot reset the guessing of the others.
+//!
+//! Every attempt is counted as failed up front, in the same atomic step as the check, and taken back once it
+//! succeeds. Otherwise the burst of concurrent attempts would all get past the check before any of them failed.
+//!
+//! The state lives in the `AttemptStore`, in the memory of the process for the tests, or in the `login_attempts`
+//! table, so the limits hold across the restarts and the instances of the server. The keys that are neither locked
+//! out nor failed within `reset_after` are pruned, see `spawn`, otherwise guessing the random emails would grow
+//! the store without limit.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{controller::auth::Error, database::DatabaseConnection};
+
+/// The memory store forgets the stale keys once it holds that many.
+const MEMORY_PRUNE_THRESHOLD: usize = 10_000;
+
+/// How often the stale keys are pruned from the store.
+pub const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LoginKey {
+    /// Lowercased, as the login gets it.
+    Email(String),
+    Ip(IpAddr),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "login_attempt_scope", rename_all = "lowercase")]
+enum LoginScope {
+    Email,
+    Ip,
+}
+
+impl LoginKey {
+    fn scope(&self) -> LoginScope {
+        match self {
+            LoginKey::Email(_) => LoginScope::Email,
+            LoginKey::Ip(_) => LoginScope::Ip,
+        }
+    }
+
+    fn key(&self) -> String {
+        match self {
+            LoginKey::Email(email) => email.clone(),
+            LoginKey::Ip(ip) => ip.to_string(),
+        }
+    }
+}
+
+/// The failed attempts of the key, since its last lockout or its last success.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attempts {
+    pub failures: i32,
+    pub last_failure_at: chrono::NaiveDateTime,
+    pub locked_until: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    /// Too soon after the last failure, the attempt can be made at that time.
+    Backoff(chrono::NaiveDateTime),
+    Locked(chrono::NaiveDateTime),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoginPolicy {
+    /// The failures in a row that lock the email out.
+    pub max_failures_per_email: i32,
+    /// Higher than the one of the email, many users can share the address.
+    pub max_failures_per_ip: i32,
+    /// The wait after the first failure, doubled by every next one.
+    pub base_delay: std::time::Duration,
+    pub max_delay: std::time::Duration,
+    pub lockout: std::time::Duration,
+    pub reset_after: std::time::Duration,
+}
+
+impl Default for LoginPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures_per_email: 5,
+            max_failures_per_ip: 20,
+            base_delay: std::time::Duration::from_secs(1),
+            max_delay: std::time::Duration::from_secs(60),
+            lockout: std::time::Duration::from_secs(15 * 60),
+            reset_after: std::time::Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+fn duration(duration: std::time::Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX)
+}
+
+impl LoginPolicy {
+    fn max_failures(&self, key: &LoginKey) -> i32 {
+        match key {
+            LoginKey::Email(_) => self.max_failures_per_email,
+            LoginKey::Ip(_) => self.max_failures_per_ip,
+        }
+    }
+
+    /// The wait after that many failures.
+    fn delay(&self, failures: i32) -> chrono::Duration {
+        if failures <= 0 {
+            return chrono::Duration::zero();
+        }
+
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << (failures - 1).min(31) as u32);
+
+        self::duration(delay.min(self.max_delay))
+    }
+
+    fn is_stale(&self, attempts: &Attempts, now: chrono::NaiveDateTime) -> bool {
+        now - attempts.last_failure_at >= self::duration(self.reset_after)
+    }
+
+    /// Neither locked out nor failed recently, forgetting the key changes nothing about its next attempt.
+    fn is_forgotten(&self, attempts: &Attempts, now: chrono::NaiveDateTime) -> bool {
+        self.is_stale(attempts, now) && attempts.locked_until.is_none_or(|until| until <= now)
+    }
+
+    pub fn verdict(&self, attempts: &Attempts, now: chrono::NaiveDateTime) -> Verdict {
+        if let Some(until) = attempts.locked_until
+            && until > now
+        {
+            return Verdict::Locked(until);
+        }
+
+        if self.is_stale(attempts, now) {
+            return Verdict::Allowed;
+        }
+
+        let delay = self.delay(attempts.failures);
+        let retry_at = attempts.last_failure_at + delay;
+
+        // NOTE: The concurrent attempts may come with the `now` a bit before the failure recorded by the other one,
+        // that is no reason to turn them away when there is no delay to wait for.
+        if delay > chrono::Duration::zero() && retry_at > now {
+            Verdict::Backoff(retry_at)
+        } else {
+            Verdict::Allowed
+        }
+    }
+
+    /// The attempts of the key once the failure reserved by the attempt that succeeded is taken back.
+    pub fn release(
+        &self,
+        key: &LoginKey,
+        attempts: Attempts,
+        now: chrono::NaiveDateTime,
+    ) -> Attempts {
+        match attempts.locked_until {
+            // The reservation got past the check before the lockout, so it was among the failures that caused it,
+            // the attempts after the lockout are rejected without counting. One less does not lock the key out.
+            Some(until) if until > now => Attempts {
+                failures: self.max_failures(key) - 1,
+                locked_until: None,
+                ..attempts
+            },
+            _ => Attempts {
+                failures: (attempts.failures - 1).max(0),
+                ..attempts
+            },
+        }
+    }
+
+    /// The attempts of the key after it failed once more, the count starts over once it locks the key out.
+    pub fn fail(
+        &self,
+        key: &LoginKey,
+        attempts: Option<Attempts>,
+        now: chrono::NaiveDateTime,
+    ) -> Attempts {
+        let failures = match attempts {
+            // NOTE: The concurrent attempt that got past the check before the lockout does not extend it.
+            Some(attempts) if attempts.locked_until.is_some_and(|until| until > now) => {
+                return Attempts {
+                    last_failure_at: now,
+                    ..attempts
+                };
+            }
+            Some(attempts) if !self.is_stale(&attempts, now) && attempts.locked_until.is_none() => {
+                attempts.failures + 1
+            }
+            _ => 1,
+        };
+
+        if failures >= self.max_failures(key) {
+            return Attempts {
+                failures: 0,
+                last_failure_at: now,
+                locked_until: Some(now + self::duration(self.lockout)),
+            };
+        }
+
+        Attempts {
+            failures,
+            last_failure_at: now,
+            locked_until: None,
+        }
+    }
+}
+
+/// Where the attempts of the keys are kept.
+pub trait AttemptStore: Send + Sync {
+    /// Counts the attempt as failed unless the key has to wait, returns the verdict it was made by. Atomic, so none
+    /// of the concurrent attempts of the key gets past the check without being counted.
+    fn reserve(
+        &self,
+        key: &LoginKey,
+        policy: &LoginPolicy,
+        now: chrono::NaiveDateTime,
+    ) -> impl Future<Output = super::Result<Verdict>> + Send;
+
+    /// Takes back the failure reserved by the attempt that succeeded after all.
+    fn release(
+        &self,
+        key: &LoginKey,
+        policy: &LoginPolicy,
+        now: chrono::NaiveDateTime,
+    ) -> impl Future<Output = super::Result<()>> + Send;
+
+    fn clear(&self, key: &LoginKey) -> impl Future<Output = super::Result<()>> + Send;
+
+    /// Removes the keys the policy has forgotten, returns how many.
+    fn prune(
+        &self,
+        policy: &LoginPolicy,
+        now: chrono::NaiveDateTime,
+    ) -> impl Future<Output = super::Result<u64>> + Send;
+}
+
+/// Only limits the one process and forgets everything on the restart, meant for the tests.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore(Arc<Mutex<HashMap<LoginKey, Attempts>>>);
+
+impl AttemptStore for MemoryStore {
+    async fn reserve(
+        &self,
+        key: &LoginKey,
+        policy: &LoginPolicy,
+        now: chrono::NaiveDateTime,
+    ) -> super::Result<Verdict> {
+        let mut attempts = self.0.lock().unwrap();
+
+        if attempts.len() >= MEMORY_PRUNE_THRESHOLD {
+            attempts.retain(|_, attempts| !policy.is_forgotten(attempts, now));
+        }
+
+        let current = attempts.get(key).copied();
+        let verdict = current.map_or(Verdict::Allowed, |current| policy.verdict(&current, now));
+
+        if verdict == Verdict::Allowed {
+            attempts.insert(key.clone(), policy.fail(key, current, now));
+        }
+
+        Ok(verdict)
+    }
+
+    async fn release(
+        &self,
+        key: &LoginKey,
+        policy: &LoginPolicy,
+        now: chrono::NaiveDateTime,
+    ) -> super::Result<()> {
+        if let Some(attempts) = self.0.lock().unwrap().get_mut(key) {
+            *attempts = policy.release(key, *attempts, now);
+        }
+
+        Ok(())
+    }
+
+    async fn clear(&self, key: &LoginKey) -> super::Result<()> {
+        self.0.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+
+    async fn prune(&self, policy: &LoginPolicy, now: chrono::NaiveDateTime) -> super::Result<u64> {
+        let mut attempts = self.0.lock().unwrap();
+        let before = attempts.len();
+
+        attempts.retain(|_, attempts| !policy.is_forgotten(attempts, now));
+
+        Ok((before - attempts.len()) as u64)
+    }
+}
+
+/// Shared by every instance of the server on the same database.
+#[derive(Clone)]
+pub struct PostgresStore(pub DatabaseConnection);
+
+impl PostgresStore {
+    async fn update(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        key: &LoginKey,
+        attempts: Attempts,
+    ) -> super::Result<()> {
+        sqlx::query!(
+            "UPDATE login_attempts SET failures = $3, last_failure_at = $4, locked_until = $5
+            WHERE scope = $1 AND key = $2",
+            key.scope() as LoginScope,
+            key.key(),
+            attempts.failures,
+            attempts.last_failure_at,
+            attempts.locked_until
+        )
+        .execute(tx.as_mut())
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl AttemptStore for PostgresStore {
+    async fn reserve(
+        &self,
+        key: &LoginKey,
+        policy: &LoginPolicy,
+        now: chrono::NaiveDateTime,
+    ) -> super::Result<Verdict> {
+        let mut tx = self.0.0.begin().await?;
+
+        // The row of the first attempt is made first, so it can be locked like every other.
+        sqlx::query!(
+            "INSERT INTO login_attempts (scope, key, last_failure_at) VALUES ($1, $2, $3)
+            ON CONFLICT (scope, key) DO NOTHING",
+            key.scope() as LoginScope,
+            key.key(),
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let attempts = sqlx::query_as!(
+            Attempts,
+            "SELECT failures, last_failure_at, locked_until FROM login_attempts
+            WHERE scope = $1 AND key = $2
+            FOR UPDATE",
+            key.scope() as LoginScope,
+            key.key()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let verdict = policy.verdict(&attempts, now);
+
+        if verdict == Verdict::Allowed {
+            Self::update(&mut tx, key, policy.fail(key, Some(attempts), now)).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(verdict)
+    }
+
+    async fn release(
+        &self,
+        key: &LoginKey,
+        policy: &LoginPolicy,
+        now: chrono::NaiveDateTime,
+    ) -> super::Result<()> {
+        let mut tx = self.0.0.begin().await?;
+
+        let attempts = sqlx::query_as!(
+            Attempts,
+            "SELECT failures, last_failure_at, locked_until FROM login_attempts
+            WHERE scope = $1 AND key = $2
+            FOR UPDATE",
+            key.scope() as LoginScope,
+            key.key()
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(attempts) = attempts {
+            Self::update(&mut tx, key, policy.release(key, attempts, now)).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn clear(&self, key: &LoginKey) -> super::Result<()> {
+        sqlx::query!(
+            "DELETE FROM login_attempts WHERE scope = $1 AND key = $2",
+            key.scope() as LoginScope,
+            key.key()
+        )
+        .execute(&self.0.0)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn prune(&self, policy: &LoginPolicy, now: chrono::NaiveDateTime) -> super::Result<u64> {
+        let pruned = sqlx::query!(
+            "DELETE FROM login_attempts
+            WHERE last_failure_at <= $1 AND (locked_until IS NULL OR locked_until <= $2)",
+            now - self::duration(policy.reset_after),
+            now
+        )
+        .execute(&self.0.0)
+        .await?;
+
+        Ok(pruned.rows_affected())
+    }
+}
+
+#[derive(Clone)]
+enum Store {
+    Memory(MemoryStore),
+    Postgres(PostgresStore),
+}
+
+/// The policy with the store it keeps the attempts in, shared by the state of the app.
+#[derive(Clone)]
+pub struct LoginLimiter {
+    pub policy: LoginPolicy,
+    store: Store,
+}
+
+impl LoginLimiter {
+    pub fn memory(policy: LoginPolicy) -> Self {
+        Self {
+            policy,
+            store: Store::Memory(MemoryStore::default()),
+        }
+    }
+
+    pub fn postgres(database: DatabaseConnection, policy: LoginPolicy) -> Self {
+        Self {
+            policy,
+            store: Store::Postgres(PostgresStore(database)),
+        }
+    }
+
+    /// Reserves the attempt for every key, it is rejected by the first key that has to wait. The locked out email is
+    /// first in the keys, so it takes precedence. The keys reserved before the rejection are taken back, so the
+    /// rejected attempt does not count.
+    pub async fn reserve(
+        &self,
+        keys: &[LoginKey],
+        now: chrono::NaiveDateTime,
+    ) -> super::Result<()> {
+        for (reserved, key) in keys.iter().enumerate() {
+            let verdict = match &self.store {
+                Store::Memory(store) => store.reserve(key, &self.policy, now).await?,
+                Store::Postgres(store) => store.reserve(key, &self.policy, now).await?,
+            };
+
+            let error = match (verdict, key) {
+                (Verdict::Allowed, _) => continue,
+                (Verdict::Locked(until), LoginKey::Email(_)) => Error::AccountLocked {
+                    retry_after: self::retry_after(until, now),
+                },
+                (Verdict::Locked(at) | Verdict::Backoff(at), _) => Error::TooManyLoginAttempts {
+                    retry_after: self::retry_after(at, now),
+                },
+            };
+
+            self.release(&keys[..reserved], now).await?;
+
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// The reserved attempt succeeded, the failures of the email are forgotten and the rest is taken back.
+    pub async fn succeed(
+        &self,
+        keys: &[LoginKey],
+        now: chrono::NaiveDateTime,
+    ) -> super::Result<()> {
+        for key in keys {
+            match key {
+                LoginKey::Email(_) => self.clear(key).await?,
+                LoginKey::Ip(_) => self.release_key(key, now).await?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Takes back the reserved attempt that did not get to the verification.
+    pub async fn release(
+        &self,
+        keys: &[LoginKey],
+        now: chrono::NaiveDateTime,
+    ) -> super::Result<()> {
+        for key in keys {
+            self.release_key(key, now).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn release_key(&self, key: &LoginKey, now: chrono::NaiveDateTime) -> super::Result<()> {
+        match &self.store {
+            Store::Memory(store) => store.release(key, &self.policy, now).await,
+            Store::Postgres(store) => store.release(key, &self.policy, now).await,
+        }
+    }
+
+    async fn clear(&self, key: &LoginKey) -> super::Result<()> {
+        match &self.store {
+            Store::Memory(store) => store.clear(key).await,
+            Store::Postgres(store) => store.clear(key).await,
+        }
+    }
+
+    pub async fn prune(&self, now: chrono::NaiveDateTime) -> super::Result<u64> {
+        match &self.store {
+            Store::Memory(store) => store.prune(&self.policy, now).await,
+            Store::Postgres(store) => store.prune(&self.policy, now).await,
+        }
+    }
+}
+
+/// Prunes the stale keys of the limiter every `interval` on its own task, for as long as the runtime lives.
+pub fn spawn(limiter: LoginLimiter, interval: std::time::Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticks.tick().await;
+
+            match limiter.prune(chrono::Utc::now().naive_utc()).await {
+                Ok(pruned) => tracing::debug!(pruned, "Pruned the stale login attempts"),
+                Err(err) => tracing::error!(?err, "Pruning the login attempts failed"),
+            }
+        }
+    })
+}
+
+/// Whole seconds, rounded up, so the client retrying right then is not too early.
+fn retry_after(at: chrono::NaiveDateTime, now: chrono::NaiveDateTime) -> u64 {
+    let millis = (at - now).num_milliseconds().max(0) as u64;
+
+    millis.div_ceil(1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_backoff_without_delay() {
+        let policy = LoginPolicy {
+            base_delay: std::time::Duration::ZERO,
+            ..LoginPolicy::default()
+        };
+        let key = LoginKey::Email("limited@email.com".into());
+        let now = chrono::NaiveDateTime::default();
+
+        // The attempt that started before the failure of the other one was recorded.
+        let attempts = policy.fail(&key, None, now);
+        assert_eq!(
+            policy.verdict(&attempts, now - chrono::Duration::milliseconds(1)),
+            Verdict::Allowed
+        );
+    }
+
+    #[test]
+    fn test_backoff_and_lockout() {
+        let policy = LoginPolicy::default();
+        let key = LoginKey::Email("limited@email.com".into());
+        let now = chrono::NaiveDateTime::default();
+        let seconds = chrono::Duration::seconds;
+
+        let mut attempts = policy.fail(&key, None, now);
+        assert_eq!(attempts.failures, 1);
+        assert_eq!(
+            policy.verdict(&attempts, now),
+            Verdict::Backoff(now + seconds(1))
+        );
+        assert_eq!(
+            policy.verdict(&attempts, now + seconds(1)),
+            Verdict::Allowed
+        );
+
+        for failures in 2..5 {
+            attempts = policy.fail(&key, Some(attempts), now);
+            assert_eq!(attempts.failures, failures);
+        }
+
+        // 1, 2, 4 and 8 seconds.
+        assert_eq!(
+            policy.verdict(&attempts, now),
+            Verdict::Backoff(now + seconds(8))
+        );
+
+        attempts = policy.fail(&key, Some(attempts), now);
+        let until = now + seconds(15 * 60);
+        assert_eq!(attempts.locked_until, Some(until));
+        assert_eq!(policy.verdict(&attempts, now), Verdict::Locked(until));
+
+        // The failure racing the lockout does not extend it.
+        assert_eq!(
+            policy
+                .fail(&key, Some(attempts), now + seconds(1))
+                .locked_until,
+            Some(until)
+        );
+
+        // Starts over once the lockout is over.
+        assert_eq!(policy.verdict(&attempts, until), Verdict::Allowed);
+        assert_eq!(policy.fail(&key, Some(attempts), until).failures, 1);
+    }
+
+    #[test]
+    fn test_stale_failures_are_forgotten() {
+        let policy = LoginPolicy::default();
+        let key = LoginKey::Ip(IpAddr::from([127, 0, 0, 1]));
+        let now = chrono::NaiveDateTime::default();
+
+        let mut attempts = None;
+        for _ in 0..10 {
+            attempts = Some(policy.fail(&key, attempts, now));
+        }
+
+        // Capped at the max delay.
+        assert_eq!(
+            policy.verdict(&attempts.unwrap(), now),
+            Verdict::Backoff(now + chrono::Duration::seconds(60))
+        );
+
+        let later = now + chrono::Duration::hours(1);
+        assert_eq!(policy.verdict(&attempts.unwrap(), later), Verdict::Allowed);
+        assert_eq!(policy.fail(&key, attempts, later).failures, 1);
+    }
+
+    #[test]
+    fn test_release_takes_back_the_reservation() {
+        let policy = LoginPolicy {
+            max_failures_per_ip: 3,
+            ..LoginPolicy::default()
+        };
+        let key = LoginKey::Ip(IpAddr::from([127, 0, 0, 1]));
+        let now = chrono::NaiveDateTime::default();
+
+        let attempts = policy.fail(&key, None, now);
+        assert_eq!(policy.release(&key, attempts, now).failures, 0);
+
+        // The succeeded one was among the failures that locked the key out.
+        let mut attempts = None;
+        for _ in 0..3 {
+            attempts = Some(policy.fail(&key, attempts, now));
+        }
+        assert!(attempts.unwrap().locked_until.is_some());
+
+        let released = policy.release(&key, attempts.unwrap(), now);
+        assert_eq!(released.failures, 2);
+        assert_eq!(released.locked_until, None);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_reservations_are_counted() {
+        let limiter = LoginLimiter::memory(LoginPolicy {
+            max_failures_per_email: 3,
+            base_delay: std::time::Duration::ZERO,
+            ..LoginPolicy::default()
+        });
+        let keys = [LoginKey::Email("burst@email.com".into())];
+        let now = chrono::NaiveDateTime::default();
+
+        // None of them has failed yet, still only the first three get through.
+        for _ in 0..3 {
+            limiter.reserve(&keys, now).await.unwrap();
+        }
+        assert!(matches!(
+            limiter.reserve(&keys, now).await,
+            Err(Error::AccountLocked { .. })
+        ));
+
+        limiter.succeed(&keys, now).await.unwrap();
+        limiter.reserve(&keys, now).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejected_attempt_is_not_counted() {
+        let limiter = LoginLimiter::memory(LoginPolicy {
+            max_failures_per_email: 2,
+            max_failures_per_ip: 1,
+            base_delay: std::time::Duration::ZERO,
+            ..LoginPolicy::default()
+        });
+        let email = LoginKey::Email("victim@email.com".into());
+        let blocked = [email.clone(), LoginKey::Ip(IpAddr::from([10, 0, 0, 1]))];
+        let now = chrono::NaiveDateTime::default();
+
+        limiter.reserve(&blocked, now).await.unwrap();
+
+        // The blocked address cannot lock the email out by retrying.
+        for _ in 0..5 {
+            assert!(matches!(
+                limiter.reserve(&blocked, now).await,
+                Err(Error::TooManyLoginAttempts { .. })
+            ));
+        }
+
+        limiter
+            .reserve(&[email, LoginKey::Ip(IpAddr::from([10, 0, 0, 2]))], now)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_retry_after_rounds_up() {
+        let now = chrono::NaiveDateTime::default();
+
+        assert_eq!(
+            retry_after(now + chrono::Duration::milliseconds(1500), now),
+            2
+        );
+        assert_eq!(retry_after(now + chrono::Duration::seconds(3), now), 3);
+        assert_eq!(retry_after(now, now), 0);
+    }
+}
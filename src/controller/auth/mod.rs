@@ -1,9 +1,15 @@
 //! NOTE: The authentication controller module could probably be consider an abomination of this project.
 
+pub mod csrf;
 mod error;
+pub mod hashing;
+pub mod limiter;
 mod password_policy;
 
-use std::sync::Arc;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, LazyLock},
+};
 
 use argon2::{
     Argon2,
@@ -22,13 +28,22 @@ use crate::{
     },
 };
 use axum::{
-    Json, Router,
-    extract::{FromRef, FromRequest, State, rejection::JsonRejection},
+    Extension, Json, Router,
+    extract::{ConnectInfo, FromRef, FromRequest, State, rejection::JsonRejection},
     routing::{get, post},
 };
+use hashing::HashingPool;
+use limiter::{LoginKey, LoginLimiter};
 
 pub(in crate::controller::auth) type Result<T> = std::result::Result<T, self::Error>;
 
+/// Hash of the random password, with the same parameters as the ones of the users. The login verifies against it
+/// when the email is unknown, so it takes as long as with the registered email and does not tell which ones are.
+static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    self::hash_password(SaltString::generate(&mut OsRng).as_str())
+        .expect("hashing the dummy password cannot fail")
+});
+
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct ClientAuthenticationCredentials {
     pub email: String,
@@ -64,7 +79,12 @@ where
 pub fn router<S: Clone + Send + Sync + 'static>() -> axum::Router<S>
 where
     DatabaseConnection: FromRef<S>,
+    LoginLimiter: FromRef<S>,
+    HashingPool: FromRef<S>,
 {
+    // Hashed up front, otherwise the first login of the unknown email would take twice as long.
+    LazyLock::force(&DUMMY_PASSWORD_HASH);
+
     Router::new()
         .route("/auth/session", get(get_auth_session))
         .route("/auth/register", post(register_user))
@@ -137,7 +157,20 @@ pub async fn get_auth_session(
     State(DatabaseConnection(conn)): State<DatabaseConnection>,
     cookies: Cookies,
 ) -> self::Result<Json<ClientUser>> {
-    return Ok(Json(self::get_server_side_session(&conn, &cookies).await?));
+    let user = self::get_server_side_session(&conn, &cookies).await?;
+
+    // Hands the CSRF token again, for the sessions made before there was one, or the client that lost the cookie.
+    let ssid = cookies
+        .get(cookies::SSID)
+        .and_then(|ssid| Uuid::parse_str(ssid.value()).ok());
+
+    if let Some(ssid) = ssid
+        && let Some(token) = csrf::find_csrf_token(&conn, ssid).await?
+    {
+        cookies.add(csrf::create_csrf_cookie(token));
+    }
+
+    return Ok(Json(user));
 }
 
 /// NOTE: I am not sure if I want to isolate such logic into separate functions as it's not very flexible.
@@ -177,9 +210,8 @@ where
         .into())
 }
 
+/// Blocks the thread for as long as the hashing takes, the handlers run it on the `HashingPool`.
 pub fn hash_password(password: &str) -> self::Result<String> {
-    // NOTE: As per documentation OsRng use may block the OS, maybe that should be put inside the tokio::task::spawn_blocking
-
     let salt = SaltString::generate(&mut OsRng);
     let hash = Argon2::default()
         .hash_password(password.as_bytes(), &salt)?
@@ -188,9 +220,10 @@ pub fn hash_password(password: &str) -> self::Result<String> {
     Ok(hash)
 }
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = crate::AppState)]
 pub async fn register_user(
     State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(hashing): State<HashingPool>,
     cookies: Cookies,
     ExtractClientAuthenticationCredentials(credentials): ExtractClientAuthenticationCredentials<
         ClientAuthenticationCredentials,
@@ -215,7 +248,7 @@ pub async fn register_user(
     // Then we would just take the hashed password and email and insert it into the database.
     // alongside with the unique salt for that user. The salt will be probably prefixing the password
     // for the login logic.
-    // 4. Then we would create a session for that user, note: we still do not have CSRF protection.
+    // 4. Then we would create a session for that user, with the CSRF token bound to it, see `csrf`.
     // 5. We would save that session in the database and set the session cookie in the response.
     // 6. Next we would save the session cookie and the user_id generate into the junction table
     // as there could be multiple sessions for a single user.
@@ -237,14 +270,12 @@ pub async fn register_user(
         return Err(self::Error::PasswordRequirementsNotMet(password));
     }
 
-    let mut tx: sqlx::Transaction<'_, sqlx::Postgres> = conn.begin().await?;
-
     // Check if email is already taken.
     let is_email_taken = sqlx::query!(
         "SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)",
         email
     )
-    .fetch_one(tx.as_mut())
+    .fetch_one(&conn)
     .await?
     .exists;
 
@@ -254,12 +285,17 @@ pub async fn register_user(
         return Err(self::Error::EmailTaken(email));
     }
 
+    // Hashed before the transaction, so the connection is not held while waiting for the pool and hashing.
+    let password_hash = hashing
+        .run(move || self::hash_password(&password))
+        .await??;
+
+    let mut tx: sqlx::Transaction<'_, sqlx::Postgres> = conn.begin().await?;
+
     let account = sqlx::query!("INSERT INTO accounts (created_at) VALUES (DEFAULT) RETURNING id")
         .fetch_one(tx.as_mut())
         .await?;
 
-    let password_hash = self::hash_password(&password)?;
-
     let user = sqlx::query_as!(
         DatabaseUser,
         "INSERT INTO users (email, password_hash, account_id) 
@@ -272,20 +308,28 @@ pub async fn register_user(
     .fetch_one(tx.as_mut())
     .await?;
 
-    let DatabaseSession { id: ssid, .. } =
-        self::create_database_session(tx.as_mut(), user.id).await?;
+    let DatabaseSession {
+        id: ssid,
+        csrf_token,
+        ..
+    } = self::create_database_session(tx.as_mut(), user.id).await?;
 
     let cookie = self::create_ssid_cookie(ssid)?;
     cookies.add(cookie);
+    cookies.add(csrf::create_csrf_cookie(csrf_token));
 
     tx.commit().await?;
 
     return Ok(Json(user));
 }
 
-#[axum::debug_handler]
+/// The client address is there when served with the connect info, the failures are only limited per email otherwise.
+#[axum::debug_handler(state = crate::AppState)]
 pub async fn login_user(
     State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(limiter): State<LoginLimiter>,
+    State(hashing): State<HashingPool>,
+    client: Option<Extension<ConnectInfo<SocketAddr>>>,
     cookies: Cookies,
     ExtractClientAuthenticationCredentials(credentials): ExtractClientAuthenticationCredentials<
         ClientAuthenticationCredentials,
@@ -294,10 +338,11 @@ pub async fn login_user(
     // Logging the user we need to do:
     // 1. Check if the user is already authenticated, if so, return an error.
     // 2. Take the email and password from the user, send it over HTTP, ideally that would be HTTPS
-    // 3. We would have to take that email and query the database for the user, I have heard that is not ideal
-    // as it exposes timing attacks, but I don't see the way we would hash the password without the salt.
-    // We have to take the email, match the user, take the salt and password, hash it and compare the hashes against the one in database.
+    // 3. We would have to take that email and query the database for the user, take the salt and password, hash it
+    // and compare the hashes against the one in database. When there is no such user the password is verified against
+    // the dummy hash, so both take the same time and the timing does not tell which emails are registered.
     // 4. Then we would save the ssid cookie and create a session for that user in the database.
+    // The failed attempts are limited per email and per client address, see `limiter`.
 
     // We cannot just propagate the error here, as they are not relevant to that endpoint.
     // We could log it though.
@@ -307,35 +352,88 @@ pub async fn login_user(
 
     let ClientAuthenticationCredentials { email, password } = credentials;
 
-    let mut tx = conn.begin().await?;
+    let mut keys = vec![LoginKey::Email(email.clone())];
+    if let Some(Extension(ConnectInfo(addr))) = client {
+        keys.push(LoginKey::Ip(addr.ip()));
+    }
 
-    let Some(user) = sqlx::query_as!(DatabaseUser, "SELECT * FROM users WHERE email = $1", email)
-        .fetch_optional(tx.as_mut())
-        .await?
-    else {
-        return Err(self::Error::InvalidCredentials { source: None });
+    // Counted as failed until verified, see `limiter`.
+    limiter
+        .reserve(&keys, chrono::Utc::now().naive_utc())
+        .await?;
+
+    // The attempt that did not get to the verification, e.g. turned away by the saturated pool, does not count.
+    let (user, verified) = match self::verify_credentials(&conn, &hashing, &email, password).await {
+        Ok(verified) => verified,
+        Err(e) => {
+            limiter
+                .release(&keys, chrono::Utc::now().naive_utc())
+                .await?;
+
+            return Err(e);
+        }
     };
 
-    Argon2::default()
-        .verify_password(
-            password.as_bytes(),
-            &PasswordHash::new(&user.password_hash)?,
-        )
-        .map_err(|e| self::Error::InvalidCredentials {
-            source: Some(Arc::new(anyhow::Error::new(e))),
-        })?;
+    // The unknown email counts as well, otherwise the lockout would tell which ones exist.
+    let (Some(user), Ok(())) = (user, verified) else {
+        return Err(self::Error::InvalidCredentials {
+            source: verified.err().map(|e| Arc::new(anyhow::Error::new(e))),
+        });
+    };
 
-    let DatabaseSession { id: ssid, .. } =
-        self::create_database_session(tx.as_mut(), user.id).await?;
+    limiter
+        .succeed(&keys, chrono::Utc::now().naive_utc())
+        .await?;
+
+    // Only begun once verified, so the connection is not held while waiting for the pool and hashing.
+    let mut tx = conn.begin().await?;
+
+    let DatabaseSession {
+        id: ssid,
+        csrf_token,
+        ..
+    } = self::create_database_session(tx.as_mut(), user.id).await?;
 
     let cookie = self::create_ssid_cookie(ssid)?;
     cookies.add(cookie);
+    cookies.add(csrf::create_csrf_cookie(csrf_token));
 
     tx.commit().await?;
 
     return Ok(Json(ClientUser::from(user)));
 }
 
+/// The user of the email, if any, and whether the password matches its hash, or the dummy one when there is none.
+///
+/// The malformed hash is the error of the server, only the mismatch is the one of the client.
+async fn verify_credentials(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    hashing: &HashingPool,
+    email: &str,
+    password: String,
+) -> self::Result<(
+    Option<DatabaseUser>,
+    std::result::Result<(), argon2::password_hash::Error>,
+)> {
+    let user = sqlx::query_as!(DatabaseUser, "SELECT * FROM users WHERE email = $1", email)
+        .fetch_optional(conn)
+        .await?;
+
+    let password_hash = user.as_ref().map_or_else(
+        || DUMMY_PASSWORD_HASH.clone(),
+        |user| user.password_hash.clone(),
+    );
+
+    let verified = hashing
+        .run(move || {
+            PasswordHash::new(&password_hash)
+                .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        })
+        .await??;
+
+    Ok((user, verified))
+}
+
 #[axum::debug_handler]
 pub async fn logout_user(
     State(DatabaseConnection(conn)): State<DatabaseConnection>,
@@ -389,6 +487,7 @@ pub async fn logout_user(
                 source: Some(Arc::new(anyhow::Error::new(e))),
             })?;
             cookies.remove(cookie);
+            cookies.remove(csrf::create_csrf_cookie(Uuid::nil()));
         }
         None => {
             // NOTE: This should not happen as call for server side session already validates that.
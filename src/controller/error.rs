@@ -1,4 +1,4 @@
-use crate::controller::{auth, stocks};
+use crate::controller::{auth, me, offers, orders, rules, stocks, stream};
 
 // That error seem useless, if we have a separate errors for each module, why would we need that.
 // We could consider using that if some controllers would have common errors, but that seem unlikely.
@@ -13,6 +13,11 @@ pub enum Error {
     Stocks(#[from] stocks::Error),
     // #[error("Authentication controller error: {0}")]
     Auth(#[from] auth::Error),
+    Stream(#[from] stream::Error),
+    Orders(#[from] orders::Error),
+    Offers(#[from] offers::Error),
+    Rules(#[from] rules::Error),
+    Me(#[from] me::Error),
     GenericControllerError(#[from] GenericControllerError),
 }
 
@@ -0,0 +1,87 @@
+//! Price alerts of the signed in user, those live in the `alerts` module.
+
+use axum::{
+    Json,
+    extract::{Path, State, rejection::JsonRejection},
+    http::StatusCode,
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    alerts::{self, Alert, NewAlert},
+    controller::{auth, me::Error},
+    database::DatabaseConnection,
+};
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AlertPatch {
+    pub enabled: bool,
+}
+
+fn parse_alert_id(id: String) -> super::Result<i64> {
+    id.parse::<i64>().map_err(|_| Error::InvalidAlertId(id))
+}
+
+/// Newest first.
+pub async fn get_alerts(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> super::Result<Json<Vec<Alert>>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+
+    Ok(Json(alerts::list_alerts(&conn, user.id).await?))
+}
+
+pub async fn post_alert(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    alert: std::result::Result<Json<NewAlert>, JsonRejection>,
+) -> super::Result<(StatusCode, Json<Alert>)> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Json(alert) = alert.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    let alert =
+        alerts::create_alert(&conn, user.id, &alert, chrono::Utc::now().naive_utc()).await?;
+
+    Ok((StatusCode::CREATED, Json(alert)))
+}
+
+pub async fn get_alert(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> super::Result<Json<Alert>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_alert_id(id)?;
+
+    Ok(Json(alerts::get_alert(&conn, user.id, id).await?))
+}
+
+/// Enables or disables the alert, the enabled one is armed again.
+pub async fn patch_alert(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    patch: std::result::Result<Json<AlertPatch>, JsonRejection>,
+) -> super::Result<Json<Alert>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_alert_id(id)?;
+    let Json(patch) = patch.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    Ok(Json(
+        alerts::set_enabled(&conn, user.id, id, patch.enabled).await?,
+    ))
+}
+
+pub async fn delete_alert(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> super::Result<StatusCode> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_alert_id(id)?;
+
+    alerts::delete_alert(&conn, user.id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
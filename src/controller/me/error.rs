@@ -0,0 +1,95 @@
+use std::borrow::Cow;
+
+use axum::response::IntoResponse;
+
+use crate::{
+    alerts,
+    controller::auth,
+    error::{ErrorExt, ErrorResponse},
+    ledger, portfolio, trading, watchlists,
+};
+
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Me error")]
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    #[error(transparent)]
+    Ledger(#[from] ledger::Error),
+    #[error(transparent)]
+    Portfolio(#[from] portfolio::Error),
+    #[error(transparent)]
+    Auth(#[from] auth::Error),
+    #[error(transparent)]
+    Trading(#[from] trading::Error),
+    #[error(transparent)]
+    Watchlists(#[from] watchlists::Error),
+    #[error(transparent)]
+    Alerts(#[from] alerts::Error),
+    #[error("Invalid query parameters: {0}")]
+    InvalidQuery(String),
+    #[error("Invalid body: {0}")]
+    InvalidBody(String),
+    #[error("Invalid watchlist id: {0}")]
+    InvalidWatchlistId(String),
+    #[error("Invalid alert id: {0}")]
+    InvalidAlertId(String),
+    #[error("Invalid notification id: {0}")]
+    InvalidNotificationId(String),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let message = Cow::Owned(self.to_string());
+
+        let status = match self {
+            Error::Auth(err) => return err.into_response(),
+            Error::InvalidQuery(_)
+            | Error::InvalidBody(_)
+            | Error::InvalidWatchlistId(_)
+            | Error::InvalidAlertId(_)
+            | Error::InvalidNotificationId(_) => axum::http::StatusCode::BAD_REQUEST,
+            Error::Watchlists(ref err) => match err {
+                watchlists::Error::InvalidWatchlist(_) => axum::http::StatusCode::BAD_REQUEST,
+                watchlists::Error::WatchlistNotFound(_)
+                | watchlists::Error::StockNotFound(_)
+                | watchlists::Error::NotWatched(_) => axum::http::StatusCode::NOT_FOUND,
+                watchlists::Error::DuplicateName(_) | watchlists::Error::AlreadyWatched(_) => {
+                    axum::http::StatusCode::CONFLICT
+                }
+                watchlists::Error::DatabaseError(_) => {
+                    return self.to_response(ErrorResponse::default());
+                }
+            },
+            Error::Alerts(ref err) => match err {
+                alerts::Error::InvalidAlert(_) => axum::http::StatusCode::BAD_REQUEST,
+                alerts::Error::AlertNotFound(_)
+                | alerts::Error::StockNotFound(_)
+                | alerts::Error::NotificationNotFound(_) => axum::http::StatusCode::NOT_FOUND,
+                alerts::Error::DatabaseError(_) => {
+                    return self.to_response(ErrorResponse::default());
+                }
+            },
+            Error::DatabaseError(_)
+            | Error::Ledger(_)
+            | Error::Portfolio(_)
+            | Error::Trading(_) => {
+                return self.to_response(ErrorResponse::default());
+            }
+        };
+
+        return self.to_response(ErrorResponse { status, message });
+    }
+}
+
+impl ErrorExt for Error {
+    fn to(self) -> crate::Error {
+        return crate::controller::Error::from(self).into();
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
@@ -0,0 +1,247 @@
+//! Resources of the signed in user, which are always resolved from the session, never from the path.
+//!
+//! The ids in the path, e.g. of the watchlist, only pick among the resources of that user.
+
+pub mod alerts;
+mod error;
+pub mod notifications;
+pub mod watchlists;
+
+pub use error::Error;
+
+use axum::{
+    Json,
+    extract::{FromRef, Query, State, rejection::QueryRejection},
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    analytics::{self, Performance},
+    controller::auth,
+    database::DatabaseConnection,
+    ledger::{self, LedgerTransaction},
+    portfolio::{self, Portfolio, Snapshot},
+    trading::{self, Position},
+};
+
+pub(in crate::controller::me) type Result<T> = std::result::Result<T, self::Error>;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+/// NOTE: There is no source of the risk-free rate yet, so the Sharpe ratio is measured against 0.
+pub const RISK_FREE_RATE: f64 = 0.0;
+
+pub fn router<S: Clone + Send + Sync + 'static>() -> axum::Router<S>
+where
+    DatabaseConnection: FromRef<S>,
+{
+    axum::Router::new()
+        .route("/me/positions", axum::routing::get(get_positions))
+        .route("/me/portfolio", axum::routing::get(get_portfolio))
+        .route("/me/performance", axum::routing::get(get_performance))
+        .route("/me/transactions", axum::routing::get(get_transactions))
+        .route(
+            "/me/watchlists",
+            axum::routing::get(watchlists::get_watchlists).post(watchlists::post_watchlist),
+        )
+        .route(
+            "/me/watchlists/order",
+            axum::routing::put(watchlists::put_watchlists_order),
+        )
+        .route(
+            "/me/watchlists/{id}",
+            axum::routing::get(watchlists::get_watchlist)
+                .patch(watchlists::patch_watchlist)
+                .delete(watchlists::delete_watchlist),
+        )
+        .route(
+            "/me/watchlists/{id}/tickers",
+            axum::routing::post(watchlists::post_ticker).put(watchlists::put_tickers_order),
+        )
+        .route(
+            "/me/watchlists/{id}/tickers/{ticker}",
+            axum::routing::delete(watchlists::delete_ticker),
+        )
+        .route(
+            "/me/alerts",
+            axum::routing::get(alerts::get_alerts).post(alerts::post_alert),
+        )
+        .route(
+            "/me/alerts/{id}",
+            axum::routing::get(alerts::get_alert)
+                .patch(alerts::patch_alert)
+                .delete(alerts::delete_alert),
+        )
+        .route(
+            "/me/notifications",
+            axum::routing::get(notifications::get_notifications),
+        )
+        .route(
+            "/me/notifications/read",
+            axum::routing::post(notifications::post_read_all),
+        )
+        .route(
+            "/me/notifications/{id}/read",
+            axum::routing::post(notifications::post_read),
+        )
+}
+
+/// The cursor is the id of the last transaction on the page, the next page has the older ones.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct TransactionsQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+/// Single page of the transactions, `next_cursor` is None when there is nothing more to fetch.
+#[derive(serde::Serialize)]
+pub struct TransactionsPage {
+    pub transactions: Vec<LedgerTransaction>,
+    pub next_cursor: Option<String>,
+}
+
+/// How far back the performance goes, relative to today, except `all` which takes every snapshot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PerformanceWindow {
+    #[serde(rename = "1m")]
+    Month,
+    #[serde(rename = "3m")]
+    Quarter,
+    #[serde(rename = "6m")]
+    HalfYear,
+    #[default]
+    #[serde(rename = "1y")]
+    Year,
+    #[serde(rename = "ytd")]
+    YearToDate,
+    #[serde(rename = "all")]
+    All,
+}
+
+impl PerformanceWindow {
+    /// The first day of the window, None when it is not bounded.
+    pub fn start(&self, today: chrono::NaiveDate) -> Option<chrono::NaiveDate> {
+        match self {
+            PerformanceWindow::Month => Some(today - chrono::Duration::days(30)),
+            PerformanceWindow::Quarter => Some(today - chrono::Duration::days(91)),
+            PerformanceWindow::HalfYear => Some(today - chrono::Duration::days(182)),
+            PerformanceWindow::Year => Some(today - chrono::Duration::days(365)),
+            PerformanceWindow::YearToDate => {
+                chrono::NaiveDate::from_ymd_opt(chrono::Datelike::year(&today), 1, 1)
+            }
+            PerformanceWindow::All => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct PerformanceQuery {
+    pub window: Option<PerformanceWindow>,
+}
+
+/// The performance over the window, with the daily snapshots it was computed from for the charts.
+#[derive(serde::Serialize)]
+pub struct PerformanceReport {
+    pub window: PerformanceWindow,
+    #[serde(flatten)]
+    pub performance: Performance,
+    pub snapshots: Vec<Snapshot>,
+}
+
+/// The limit of the page and the id it starts before, for the pages that are keyed by the id, newest first.
+fn parse_page(limit: Option<i64>, cursor: Option<String>) -> self::Result<(i64, Option<i64>)> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(Error::InvalidQuery(format!(
+            "limit must be between 1 and {MAX_LIMIT}, got {limit}"
+        )));
+    }
+
+    let before = cursor
+        .map(|cursor| {
+            cursor
+                .parse::<i64>()
+                .map_err(|_| Error::InvalidQuery(format!("invalid cursor: {cursor}")))
+        })
+        .transpose()?;
+
+    Ok((limit, before))
+}
+
+pub async fn get_positions(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> self::Result<Json<Vec<Position>>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+
+    Ok(Json(trading::list_positions(&conn, user.id).await?))
+}
+
+/// The valuation at the current prices, the delta is the same one the session user has.
+pub async fn get_portfolio(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> self::Result<Json<Portfolio>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+
+    Ok(Json(
+        portfolio::valuate(&conn, user.id, chrono::Utc::now().naive_utc()).await?,
+    ))
+}
+
+pub async fn get_performance(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    query: std::result::Result<Query<PerformanceQuery>, QueryRejection>,
+) -> self::Result<Json<PerformanceReport>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let window = query.window.unwrap_or_default();
+    let today = chrono::Utc::now().date_naive();
+
+    let snapshots =
+        portfolio::snapshots::list_snapshots(&conn, user.id, window.start(today)).await?;
+    let points = snapshots
+        .iter()
+        .map(analytics::Point::from)
+        .collect::<Vec<_>>();
+
+    Ok(Json(PerformanceReport {
+        window,
+        performance: Performance::of(&points, RISK_FREE_RATE),
+        snapshots,
+    }))
+}
+
+pub async fn get_transactions(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    query: std::result::Result<Query<TransactionsQuery>, QueryRejection>,
+) -> self::Result<Json<TransactionsPage>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let (limit, before) = self::parse_page(query.limit, query.cursor)?;
+
+    // One more than the page, to know if there is the next one.
+    let mut transactions = ledger::list_transactions(&conn, user.id, before, limit + 1).await?;
+
+    let next_cursor = if transactions.len() as i64 > limit {
+        transactions.truncate(limit as usize);
+        transactions
+            .last()
+            .map(|transaction| transaction.id.to_string())
+    } else {
+        None
+    };
+
+    Ok(Json(TransactionsPage {
+        transactions,
+        next_cursor,
+    }))
+}
@@ -0,0 +1,105 @@
+//! The inbox of the signed in user, the new notifications are pushed live by the event stream as well.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State, rejection::QueryRejection},
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    alerts::{Notification, notifications},
+    controller::{auth, me::Error},
+    database::DatabaseConnection,
+};
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct NotificationsQuery {
+    /// Only the unread ones.
+    pub unread: bool,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+/// Single page of the inbox, newest first, `unread` counts the whole inbox, not just the page.
+#[derive(serde::Serialize)]
+pub struct NotificationsPage {
+    pub notifications: Vec<Notification>,
+    pub unread: i64,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ReadAllQuery {
+    /// The newest notification the client has seen, the ones after it stay unread.
+    pub up_to: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ReadAll {
+    pub marked: u64,
+}
+
+fn parse_notification_id(id: String) -> super::Result<i64> {
+    id.parse::<i64>()
+        .map_err(|_| Error::InvalidNotificationId(id))
+}
+
+pub async fn get_notifications(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    query: std::result::Result<Query<NotificationsQuery>, QueryRejection>,
+) -> super::Result<Json<NotificationsPage>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let (limit, before) = super::parse_page(query.limit, query.cursor)?;
+
+    // One more than the page, to know if there is the next one.
+    let mut notifications =
+        notifications::list_notifications(&conn, user.id, query.unread, before, limit + 1).await?;
+
+    let next_cursor = if notifications.len() as i64 > limit {
+        notifications.truncate(limit as usize);
+        notifications
+            .last()
+            .map(|notification| notification.id.to_string())
+    } else {
+        None
+    };
+
+    Ok(Json(NotificationsPage {
+        notifications,
+        unread: notifications::count_unread(&conn, user.id).await?,
+        next_cursor,
+    }))
+}
+
+pub async fn post_read(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> super::Result<Json<Notification>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_notification_id(id)?;
+
+    Ok(Json(
+        notifications::mark_read(&conn, user.id, id, chrono::Utc::now().naive_utc()).await?,
+    ))
+}
+
+pub async fn post_read_all(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    query: std::result::Result<Query<ReadAllQuery>, QueryRejection>,
+) -> super::Result<Json<ReadAll>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let marked =
+        notifications::mark_all_read(&conn, user.id, query.up_to, chrono::Utc::now().naive_utc())
+            .await?;
+
+    Ok(Json(ReadAll { marked }))
+}
@@ -0,0 +1,183 @@
+//! Watchlists of the signed in user, those live in the `watchlists` module.
+
+use axum::{
+    Json,
+    extract::{Path, State, rejection::JsonRejection},
+    http::StatusCode,
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    controller::{auth, me::Error},
+    database::DatabaseConnection,
+    watchlists::{self, NewWatchlist, Watchlist},
+};
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct WatchlistPatch {
+    pub name: String,
+}
+
+/// Every watchlist of the user, in the new order.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct WatchlistsOrder {
+    pub ids: Vec<i64>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct NewTicker {
+    pub ticker: String,
+}
+
+/// Every stock of the watchlist, in the new order.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct TickersOrder {
+    pub tickers: Vec<String>,
+}
+
+fn parse_watchlist_id(id: String) -> super::Result<i64> {
+    id.parse::<i64>().map_err(|_| Error::InvalidWatchlistId(id))
+}
+
+pub async fn get_watchlists(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> super::Result<Json<Vec<Watchlist>>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+
+    Ok(Json(watchlists::list_watchlists(&conn, user.id).await?))
+}
+
+pub async fn post_watchlist(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    watchlist: std::result::Result<Json<NewWatchlist>, JsonRejection>,
+) -> super::Result<(StatusCode, Json<Watchlist>)> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Json(watchlist) = watchlist.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    let watchlist =
+        watchlists::create_watchlist(&conn, user.id, &watchlist, chrono::Utc::now().naive_utc())
+            .await?;
+
+    Ok((StatusCode::CREATED, Json(watchlist)))
+}
+
+pub async fn put_watchlists_order(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    order: std::result::Result<Json<WatchlistsOrder>, JsonRejection>,
+) -> super::Result<Json<Vec<Watchlist>>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Json(order) = order.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    Ok(Json(
+        watchlists::reorder_watchlists(&conn, user.id, &order.ids).await?,
+    ))
+}
+
+pub async fn get_watchlist(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> super::Result<Json<Watchlist>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_watchlist_id(id)?;
+
+    Ok(Json(watchlists::get_watchlist(&conn, user.id, id).await?))
+}
+
+/// Renames the watchlist.
+pub async fn patch_watchlist(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    patch: std::result::Result<Json<WatchlistPatch>, JsonRejection>,
+) -> super::Result<Json<Watchlist>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_watchlist_id(id)?;
+    let Json(patch) = patch.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    Ok(Json(
+        watchlists::rename_watchlist(
+            &conn,
+            user.id,
+            id,
+            &patch.name,
+            chrono::Utc::now().naive_utc(),
+        )
+        .await?,
+    ))
+}
+
+pub async fn delete_watchlist(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> super::Result<StatusCode> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_watchlist_id(id)?;
+
+    watchlists::delete_watchlist(&conn, user.id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Adds the stock at the end of the watchlist.
+pub async fn post_ticker(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    ticker: std::result::Result<Json<NewTicker>, JsonRejection>,
+) -> super::Result<Json<Watchlist>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_watchlist_id(id)?;
+    let Json(ticker) = ticker.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    Ok(Json(
+        watchlists::add_ticker(
+            &conn,
+            user.id,
+            id,
+            &ticker.ticker,
+            chrono::Utc::now().naive_utc(),
+        )
+        .await?,
+    ))
+}
+
+pub async fn put_tickers_order(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    order: std::result::Result<Json<TickersOrder>, JsonRejection>,
+) -> super::Result<Json<Watchlist>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_watchlist_id(id)?;
+    let Json(order) = order.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    Ok(Json(
+        watchlists::reorder_tickers(
+            &conn,
+            user.id,
+            id,
+            &order.tickers,
+            chrono::Utc::now().naive_utc(),
+        )
+        .await?,
+    ))
+}
+
+pub async fn delete_ticker(
+    Path((id, ticker)): Path<(String, String)>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> super::Result<Json<Watchlist>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = self::parse_watchlist_id(id)?;
+
+    Ok(Json(
+        watchlists::remove_ticker(&conn, user.id, id, &ticker, chrono::Utc::now().naive_utc())
+            .await?,
+    ))
+}
@@ -1,6 +1,11 @@
 pub mod auth;
 mod error;
+pub mod me;
+pub mod offers;
+pub mod orders;
+pub mod rules;
 pub mod stocks;
+pub mod stream;
 
 pub use error::Error;
 
@@ -21,6 +26,8 @@ pub mod types {
 /// Cookie names used across the application, maybe they should also map to a value type.
 pub mod cookies {
     pub const SSID: &str = "SSID";
+    /// The CSRF token of the session, readable by the frontend, see `auth::csrf`.
+    pub const CSRF: &str = "CSRF";
 }
 
 // I do not see the use of Result from the controller module itself.
@@ -0,0 +1,131 @@
+//! Trade offers between the signed in user and the other users, the settlement lives in the `trading` module.
+//!
+//! Every change of the offer is published to the `OfferFeed`, the `/stream/events` of both users sends it on.
+
+mod error;
+
+pub use error::Error;
+
+use axum::{
+    Json,
+    extract::{
+        FromRef, Path, Query, State,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    http::StatusCode,
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    controller::auth,
+    database::DatabaseConnection,
+    trading::{NewOffer, Offer, OfferFeed, OfferStatus, offers},
+};
+
+pub(in crate::controller::offers) type Result<T> = std::result::Result<T, self::Error>;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+pub fn router<S: Clone + Send + Sync + 'static>() -> axum::Router<S>
+where
+    DatabaseConnection: FromRef<S>,
+    OfferFeed: FromRef<S>,
+{
+    axum::Router::new()
+        .route("/offers", axum::routing::get(get_offers).post(post_offer))
+        .route("/offers/{id}/accept", axum::routing::post(accept_offer))
+        .route("/offers/{id}/reject", axum::routing::post(reject_offer))
+        .route("/offers/{id}/expire", axum::routing::post(expire_offer))
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct OffersQuery {
+    pub status: Option<OfferStatus>,
+    pub limit: Option<i64>,
+}
+
+pub async fn post_offer(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(feed): State<OfferFeed>,
+    cookies: Cookies,
+    offer: std::result::Result<Json<NewOffer>, JsonRejection>,
+) -> self::Result<(StatusCode, Json<Offer>)> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Json(offer) = offer.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    let offer =
+        offers::create_offer(&conn, user.id, &offer, chrono::Utc::now().naive_utc()).await?;
+    feed.publish(std::slice::from_ref(&offer));
+
+    Ok((StatusCode::CREATED, Json(offer)))
+}
+
+/// The offers the user made and the ones made to it.
+pub async fn get_offers(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    query: std::result::Result<Query<OffersQuery>, QueryRejection>,
+) -> self::Result<Json<Vec<Offer>>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(Error::InvalidQuery(format!(
+            "limit must be between 1 and {MAX_LIMIT}, got {limit}"
+        )));
+    }
+
+    Ok(Json(
+        offers::list_offers(&conn, user.id, query.status, limit).await?,
+    ))
+}
+
+pub async fn accept_offer(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(feed): State<OfferFeed>,
+    cookies: Cookies,
+) -> self::Result<Json<Offer>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = id.parse::<i64>().map_err(|_| Error::InvalidOfferId(id))?;
+
+    let offer = offers::accept_offer(&conn, user.id, id, chrono::Utc::now().naive_utc()).await?;
+    feed.publish(std::slice::from_ref(&offer));
+
+    Ok(Json(offer))
+}
+
+pub async fn reject_offer(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(feed): State<OfferFeed>,
+    cookies: Cookies,
+) -> self::Result<Json<Offer>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = id.parse::<i64>().map_err(|_| Error::InvalidOfferId(id))?;
+
+    let offer = offers::reject_offer(&conn, user.id, id, chrono::Utc::now().naive_utc()).await?;
+    feed.publish(std::slice::from_ref(&offer));
+
+    Ok(Json(offer))
+}
+
+/// Takes back the offer the user made, before the other user gets to it.
+pub async fn expire_offer(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(feed): State<OfferFeed>,
+    cookies: Cookies,
+) -> self::Result<Json<Offer>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = id.parse::<i64>().map_err(|_| Error::InvalidOfferId(id))?;
+
+    let offer = offers::expire_offer(&conn, user.id, id, chrono::Utc::now().naive_utc()).await?;
+    feed.publish(std::slice::from_ref(&offer));
+
+    Ok(Json(offer))
+}
@@ -0,0 +1,76 @@
+use std::borrow::Cow;
+
+use axum::response::IntoResponse;
+
+use crate::{
+    controller::auth,
+    error::{ErrorExt, ErrorResponse},
+    trading,
+};
+
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Orders error")]
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    #[error(transparent)]
+    Auth(#[from] auth::Error),
+    #[error(transparent)]
+    Trading(#[from] trading::Error),
+    #[error("Invalid query parameters: {0}")]
+    InvalidQuery(String),
+    #[error("Invalid order: {0}")]
+    InvalidBody(String),
+    #[error("Invalid order id: {0}")]
+    InvalidOrderId(String),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let message = Cow::Owned(self.to_string());
+
+        let status = match self {
+            Error::Auth(err) => return err.into_response(),
+            Error::InvalidQuery(_) | Error::InvalidBody(_) | Error::InvalidOrderId(_) => {
+                axum::http::StatusCode::BAD_REQUEST
+            }
+            Error::Trading(ref err) => match err {
+                trading::Error::InvalidOrder(_) | trading::Error::InvalidOffer(_) => {
+                    axum::http::StatusCode::BAD_REQUEST
+                }
+                trading::Error::StockNotFound(_)
+                | trading::Error::OrderNotFound(_)
+                | trading::Error::UserNotFound(_)
+                | trading::Error::OfferNotFound(_) => axum::http::StatusCode::NOT_FOUND,
+                trading::Error::OrderNotOpen(_)
+                | trading::Error::OfferNotPending(_)
+                | trading::Error::OfferExpired(_) => axum::http::StatusCode::CONFLICT,
+                trading::Error::OfferNotCovered(_)
+                | trading::Error::InsufficientFunds { .. }
+                | trading::Error::InsufficientShares { .. } => {
+                    axum::http::StatusCode::UNPROCESSABLE_ENTITY
+                }
+                trading::Error::DatabaseError(_)
+                | trading::Error::Ledger(_)
+                | trading::Error::Other(_) => {
+                    return self.to_response(ErrorResponse::default());
+                }
+            },
+            Error::DatabaseError(_) => return self.to_response(ErrorResponse::default()),
+        };
+
+        return self.to_response(ErrorResponse { status, message });
+    }
+}
+
+impl ErrorExt for Error {
+    fn to(self) -> crate::Error {
+        return crate::controller::Error::from(self).into();
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
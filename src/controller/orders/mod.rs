@@ -0,0 +1,95 @@
+//! Orders of the signed in user, the execution itself lives in the `trading` module.
+
+mod error;
+
+pub use error::Error;
+
+use axum::{
+    Json,
+    extract::{
+        FromRef, Path, Query, State,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    http::StatusCode,
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    controller::auth,
+    database::DatabaseConnection,
+    trading::{self, NewOrder, Order, OrderBooks, OrderStatus, OrderVenue},
+};
+
+pub(in crate::controller::orders) type Result<T> = std::result::Result<T, self::Error>;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+pub fn router<S: Clone + Send + Sync + 'static>() -> axum::Router<S>
+where
+    DatabaseConnection: FromRef<S>,
+    OrderBooks: FromRef<S>,
+{
+    axum::Router::new()
+        .route("/orders", axum::routing::get(get_orders).post(post_order))
+        .route("/orders/{id}", axum::routing::delete(delete_order))
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct OrdersQuery {
+    pub status: Option<OrderStatus>,
+    pub limit: Option<i64>,
+}
+
+pub async fn post_order(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(books): State<OrderBooks>,
+    cookies: Cookies,
+    order: std::result::Result<Json<NewOrder>, JsonRejection>,
+) -> self::Result<(StatusCode, Json<Order>)> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Json(order) = order.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    let order = match order.venue {
+        OrderVenue::Market => trading::place_order(&conn, user.id, &order).await?,
+        OrderVenue::Book => trading::place_book_order(&conn, &books, user.id, &order).await?,
+    };
+
+    Ok((StatusCode::CREATED, Json(order)))
+}
+
+pub async fn get_orders(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    query: std::result::Result<Query<OrdersQuery>, QueryRejection>,
+) -> self::Result<Json<Vec<Order>>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(Error::InvalidQuery(format!(
+            "limit must be between 1 and {MAX_LIMIT}, got {limit}"
+        )));
+    }
+
+    Ok(Json(
+        trading::list_orders(&conn, user.id, query.status, limit).await?,
+    ))
+}
+
+pub async fn delete_order(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(books): State<OrderBooks>,
+    cookies: Cookies,
+) -> self::Result<Json<Order>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = id.parse::<i64>().map_err(|_| Error::InvalidOrderId(id))?;
+
+    Ok(Json(
+        trading::cancel_order(&conn, &books, user.id, id).await?,
+    ))
+}
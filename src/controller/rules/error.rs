@@ -0,0 +1,65 @@
+use std::borrow::Cow;
+
+use axum::response::IntoResponse;
+
+use crate::{
+    controller::auth,
+    error::{ErrorExt, ErrorResponse},
+    rules,
+};
+
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Rules error")]
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    #[error(transparent)]
+    Auth(#[from] auth::Error),
+    #[error(transparent)]
+    Rules(#[from] rules::Error),
+    #[error("Invalid query parameters: {0}")]
+    InvalidQuery(String),
+    #[error("Invalid rule: {0}")]
+    InvalidBody(String),
+    #[error("Invalid rule id: {0}")]
+    InvalidRuleId(String),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let message = Cow::Owned(self.to_string());
+
+        let status = match self {
+            Error::Auth(err) => return err.into_response(),
+            Error::InvalidQuery(_) | Error::InvalidBody(_) | Error::InvalidRuleId(_) => {
+                axum::http::StatusCode::BAD_REQUEST
+            }
+            Error::Rules(ref err) => match err {
+                rules::Error::InvalidRule(_) | rules::Error::InvalidBacktest(_) => {
+                    axum::http::StatusCode::BAD_REQUEST
+                }
+                rules::Error::RuleNotFound(_) | rules::Error::StockNotFound(_) => {
+                    axum::http::StatusCode::NOT_FOUND
+                }
+                rules::Error::DatabaseError(_) | rules::Error::Other(_) => {
+                    return self.to_response(ErrorResponse::default());
+                }
+            },
+            Error::DatabaseError(_) => return self.to_response(ErrorResponse::default()),
+        };
+
+        return self.to_response(ErrorResponse { status, message });
+    }
+}
+
+impl ErrorExt for Error {
+    fn to(self) -> crate::Error {
+        return crate::controller::Error::from(self).into();
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
@@ -0,0 +1,138 @@
+//! Trading rules of the signed in user, the evaluation lives in the `rules` module.
+//!
+//! The rules are not deleted, only disabled, so their evaluations stay around for the audit.
+
+mod error;
+
+pub use error::Error;
+
+use axum::{
+    Json,
+    extract::{
+        FromRef, Path, Query, State,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    http::StatusCode,
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    controller::auth,
+    database::DatabaseConnection,
+    rules::{self, Backtest, BacktestRequest, Evaluation, NewRule, Rule},
+};
+
+pub(in crate::controller::rules) type Result<T> = std::result::Result<T, self::Error>;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+pub fn router<S: Clone + Send + Sync + 'static>() -> axum::Router<S>
+where
+    DatabaseConnection: FromRef<S>,
+{
+    axum::Router::new()
+        .route("/rules", axum::routing::get(get_rules).post(post_rule))
+        .route("/rules/{id}", axum::routing::patch(patch_rule))
+        .route(
+            "/rules/{id}/evaluations",
+            axum::routing::get(get_evaluations),
+        )
+        .route("/rules/{id}/backtest", axum::routing::post(post_backtest))
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct RulePatch {
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct EvaluationsQuery {
+    pub limit: Option<i64>,
+}
+
+pub async fn post_rule(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    rule: std::result::Result<Json<NewRule>, JsonRejection>,
+) -> self::Result<(StatusCode, Json<Rule>)> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let Json(rule) = rule.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    let rule = rules::create_rule(&conn, user.id, &rule, chrono::Utc::now().naive_utc()).await?;
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub async fn get_rules(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+) -> self::Result<Json<Vec<Rule>>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+
+    Ok(Json(rules::list_rules(&conn, user.id).await?))
+}
+
+/// Enables or disables the rule.
+pub async fn patch_rule(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    patch: std::result::Result<Json<RulePatch>, JsonRejection>,
+) -> self::Result<Json<Rule>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = id.parse::<i64>().map_err(|_| Error::InvalidRuleId(id))?;
+    let Json(patch) = patch.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    Ok(Json(
+        rules::set_enabled(
+            &conn,
+            user.id,
+            id,
+            patch.enabled,
+            chrono::Utc::now().naive_utc(),
+        )
+        .await?,
+    ))
+}
+
+/// The audit trail of the rule, newest first.
+pub async fn get_evaluations(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    query: std::result::Result<Query<EvaluationsQuery>, QueryRejection>,
+) -> self::Result<Json<Vec<Evaluation>>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = id.parse::<i64>().map_err(|_| Error::InvalidRuleId(id))?;
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(Error::InvalidQuery(format!(
+            "limit must be between 1 and {MAX_LIMIT}, got {limit}"
+        )));
+    }
+
+    Ok(Json(
+        rules::list_evaluations(&conn, user.id, id, limit).await?,
+    ))
+}
+
+/// How the rule would have done over the price history of the range, nothing is traded.
+pub async fn post_backtest(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    cookies: Cookies,
+    request: std::result::Result<Json<BacktestRequest>, JsonRejection>,
+) -> self::Result<Json<Backtest>> {
+    let user = auth::get_server_side_session(&conn, &cookies).await?;
+    let id = id.parse::<i64>().map_err(|_| Error::InvalidRuleId(id))?;
+    let Json(request) = request.map_err(|e| Error::InvalidBody(e.body_text()))?;
+
+    Ok(Json(
+        rules::backtest::backtest(&conn, user.id, id, &request).await?,
+    ))
+}
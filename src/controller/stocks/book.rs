@@ -0,0 +1,47 @@
+//! Depth of the order book of the stock, the book itself lives in the `trading` module.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State, rejection::QueryRejection},
+};
+
+use crate::{
+    controller::stocks::Error,
+    database::DatabaseConnection,
+    trading::{Depth, OrderBooks},
+};
+
+pub const DEFAULT_LEVELS: usize = 10;
+pub const MAX_LEVELS: usize = 100;
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct BookQuery {
+    pub levels: Option<usize>,
+}
+
+pub async fn get_stock_book(
+    Path(id): Path<String>,
+    State(conn): State<DatabaseConnection>,
+    State(books): State<OrderBooks>,
+    query: std::result::Result<Query<BookQuery>, QueryRejection>,
+) -> super::Result<Json<Depth>> {
+    let id = super::parse_stock_id(id)?;
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let levels = query.levels.unwrap_or(DEFAULT_LEVELS);
+
+    if !(1..=MAX_LEVELS).contains(&levels) {
+        return Err(Error::InvalidQuery(format!(
+            "levels must be between 1 and {MAX_LEVELS}, got {levels}"
+        )));
+    }
+
+    if super::find_stock_by_id(conn.clone(), id).await?.is_none() {
+        return Err(Error::StockNotFound(id.to_string()));
+    }
+
+    let book = books.lock(&conn.0, id).await?;
+
+    Ok(Json(book.depth(levels)))
+}
@@ -0,0 +1,332 @@
+//! OHLC candles aggregated from the raw price ticks of the `stock_prices` table.
+//!
+//! The aggregation is done in Rust rather than in Postgres, as filling the buckets without any tick
+//! with the previous close is awkward to express in SQL, and it keeps the function usable by anything
+//! that already holds the ticks in memory.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State, rejection::QueryRejection},
+};
+
+use crate::{
+    controller::stocks::{
+        Error,
+        history::{self, HistoryQuery, HistoryRange, PricePoint},
+    },
+    database::{DatabaseConnection, types::Money},
+};
+
+/// Upper bound of the candles in a single response, e.g. a year of 1m candles would be over half a million.
+pub const MAX_CANDLES: i64 = 5000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub enum CandleInterval {
+    #[default]
+    #[serde(rename = "1m")]
+    Minute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "1d")]
+    Day,
+}
+
+impl CandleInterval {
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            CandleInterval::Minute => chrono::Duration::minutes(1),
+            CandleInterval::FiveMinutes => chrono::Duration::minutes(5),
+            CandleInterval::Hour => chrono::Duration::hours(1),
+            CandleInterval::Day => chrono::Duration::days(1),
+        }
+    }
+
+    /// Start of the bucket the timestamp falls into, buckets are aligned to the unix epoch.
+    pub fn bucket_start(&self, timestamp: chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+        let seconds = self.duration().num_seconds();
+        let since_epoch = timestamp.and_utc().timestamp();
+
+        chrono::DateTime::from_timestamp(since_epoch - since_epoch.rem_euclid(seconds), 0)
+            .expect("bucket start is within the range of the timestamp")
+            .naive_utc()
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct CandlesQuery {
+    pub interval: CandleInterval,
+    pub range: Option<HistoryRange>,
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Candle {
+    /// Start of the bucket, the bucket spans to the start of the next one.
+    pub start: chrono::NaiveDateTime,
+    pub open: Money,
+    pub high: Money,
+    pub low: Money,
+    pub close: Money,
+    /// Zero when the bucket had no ticks and the previous close was carried forward.
+    pub ticks: i64,
+}
+
+impl Candle {
+    fn flat(start: chrono::NaiveDateTime, price: Money) -> Self {
+        Self {
+            start,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            ticks: 0,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct StockCandles {
+    pub stock_id: i32,
+    pub from: chrono::NaiveDateTime,
+    pub to: chrono::NaiveDateTime,
+    pub candles: Vec<Candle>,
+}
+
+/// Aggregates the ticks, ordered by time, into the candles covering every bucket from `from` to `to`.
+///
+/// `previous` is the last price before `from`, it is carried into the leading buckets without ticks.
+/// Without it, the candles start from the bucket of the first tick, as there is nothing to carry.
+pub fn aggregate(
+    ticks: &[PricePoint],
+    previous: Option<Money>,
+    interval: CandleInterval,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+) -> Vec<Candle> {
+    let step = interval.duration();
+    let last = interval.bucket_start(to);
+
+    let mut candles: Vec<Candle> = Vec::new();
+    let mut close = previous;
+    let mut ticks = ticks.iter().peekable();
+    let mut start = interval.bucket_start(from);
+
+    while start <= last {
+        let end = start + step;
+        let mut candle: Option<Candle> = None;
+
+        while let Some(tick) = ticks.next_if(|tick| tick.recorded_at < end) {
+            // Ticks before the range only move the close, as the caller may pass a wider slice.
+            if tick.recorded_at < start {
+                close = Some(tick.price);
+                continue;
+            }
+
+            let candle = candle.get_or_insert_with(|| Candle {
+                open: tick.price,
+                ..Candle::flat(start, tick.price)
+            });
+
+            candle.high = candle.high.max(tick.price);
+            candle.low = candle.low.min(tick.price);
+            candle.close = tick.price;
+            candle.ticks += 1;
+        }
+
+        match (candle, close) {
+            (Some(candle), _) => {
+                close = Some(candle.close);
+                candles.push(candle);
+            }
+            (None, Some(close)) => candles.push(Candle::flat(start, close)),
+            (None, None) => {}
+        }
+
+        start = end;
+    }
+
+    candles
+}
+
+pub async fn get_stock_candles(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    query: std::result::Result<Query<CandlesQuery>, QueryRejection>,
+) -> crate::controller::stocks::Result<Json<StockCandles>> {
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let id = super::parse_stock_id(id)?;
+    let (from, to) = HistoryQuery {
+        range: query.range,
+        from: query.from,
+        to: query.to,
+    }
+    .interval(chrono::Utc::now().naive_utc())?;
+
+    let buckets = (to - from).num_seconds() / query.interval.duration().num_seconds() + 1;
+
+    if buckets > MAX_CANDLES {
+        return Err(Error::InvalidQuery(format!(
+            "too many candles requested ({buckets}), use the wider interval or the shorter range, the limit is {MAX_CANDLES}"
+        )));
+    }
+
+    let exists = sqlx::query_scalar!("SELECT EXISTS (SELECT 1 FROM stocks WHERE id = $1)", id)
+        .fetch_one(&conn)
+        .await?;
+
+    if exists != Some(true) {
+        return Err(Error::StockNotFound(id.to_string()));
+    }
+
+    // The candle of the first bucket may start before `from`, so we have to include the whole bucket.
+    let first = query.interval.bucket_start(from);
+    let ticks = history::list_prices(&conn, id, first, to).await?;
+
+    let previous = sqlx::query_scalar!(
+        r#"SELECT price AS "price: Money" FROM stock_prices WHERE stock_id = $1 AND recorded_at < $2
+        ORDER BY recorded_at DESC, id DESC LIMIT 1"#,
+        id,
+        first
+    )
+    .fetch_optional(&conn)
+    .await?;
+
+    Ok(Json(StockCandles {
+        stock_id: id,
+        from,
+        to,
+        candles: self::aggregate(&ticks, previous, query.interval, from, to),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32, second: u32) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2025, 6, 15)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    fn price(value: f64) -> Money {
+        Money::from_f64(value, Money::PRICE_SCALE).unwrap()
+    }
+
+    fn tick(value: f64, recorded_at: chrono::NaiveDateTime) -> PricePoint {
+        PricePoint {
+            price: price(value),
+            recorded_at,
+        }
+    }
+
+    #[test]
+    fn test_bucket_start() {
+        assert_eq!(
+            CandleInterval::FiveMinutes.bucket_start(at(10, 7, 59)),
+            at(10, 5, 0)
+        );
+        assert_eq!(
+            CandleInterval::Hour.bucket_start(at(10, 59, 59)),
+            at(10, 0, 0)
+        );
+        assert_eq!(CandleInterval::Day.bucket_start(at(10, 0, 0)), at(0, 0, 0));
+    }
+
+    #[test]
+    fn test_aggregate_ohlc() {
+        let ticks = [
+            tick(10.0, at(10, 0, 5)),
+            tick(12.0, at(10, 0, 20)),
+            tick(9.0, at(10, 0, 40)),
+            tick(11.0, at(10, 0, 59)),
+            tick(11.5, at(10, 1, 0)),
+        ];
+
+        let candles = aggregate(
+            &ticks,
+            None,
+            CandleInterval::Minute,
+            at(10, 0, 0),
+            at(10, 1, 30),
+        );
+
+        assert_eq!(
+            candles,
+            [
+                Candle {
+                    start: at(10, 0, 0),
+                    open: price(10.0),
+                    high: price(12.0),
+                    low: price(9.0),
+                    close: price(11.0),
+                    ticks: 4,
+                },
+                Candle {
+                    start: at(10, 1, 0),
+                    ticks: 1,
+                    ..Candle::flat(at(10, 1, 0), price(11.5))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_carries_previous_close() {
+        let ticks = [tick(10.0, at(10, 1, 10)), tick(8.0, at(10, 1, 50))];
+
+        let candles = aggregate(
+            &ticks,
+            Some(price(7.0)),
+            CandleInterval::Minute,
+            at(10, 0, 0),
+            at(10, 3, 0),
+        );
+
+        assert_eq!(
+            candles,
+            [
+                // Leading bucket without the ticks carries the price from before the range.
+                Candle::flat(at(10, 0, 0), price(7.0)),
+                Candle {
+                    start: at(10, 1, 0),
+                    open: price(10.0),
+                    high: price(10.0),
+                    low: price(8.0),
+                    close: price(8.0),
+                    ticks: 2,
+                },
+                Candle::flat(at(10, 2, 0), price(8.0)),
+                Candle::flat(at(10, 3, 0), price(8.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_without_previous_skips_leading_buckets() {
+        let ticks = [tick(10.0, at(10, 2, 0))];
+
+        let candles = aggregate(
+            &ticks,
+            None,
+            CandleInterval::Minute,
+            at(10, 0, 0),
+            at(10, 2, 30),
+        );
+
+        assert_eq!(
+            candles,
+            [Candle {
+                ticks: 1,
+                ..Candle::flat(at(10, 2, 0), price(10.0))
+            }]
+        );
+        assert!(aggregate(&[], None, CandleInterval::Hour, at(0, 0, 0), at(23, 0, 0)).is_empty());
+    }
+}
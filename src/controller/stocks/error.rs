@@ -1,17 +1,63 @@
+use std::borrow::Cow;
+
 use axum::response::IntoResponse;
 
+use crate::{
+    controller::error::GenericControllerError,
+    error::{ErrorExt, ErrorResponse},
+};
+
 #[derive(thiserror::Error, Debug, Clone)]
 #[error("Stocks error")]
 pub enum Error {
     #[error(transparent)]
     DatabaseError(#[from] crate::database::Error),
-    GenericControllerError(#[from] crate::controller::error::GenericControllerError),
+    GenericControllerError(#[from] GenericControllerError),
+    #[error("Invalid query parameters: {0}")]
+    InvalidQuery(String),
+    #[error("Invalid pagination cursor: {0}")]
+    InvalidCursor(String),
+    #[error("Stock not found: {0}")]
+    StockNotFound(String),
+    #[error(transparent)]
+    Trading(#[from] crate::trading::Error),
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        // TODO: Do the error handling when the application matures.
+        let message = Cow::Owned(self.to_string());
+
+        let representation = match self {
+            Error::InvalidQuery(_) | Error::InvalidCursor(_) => ErrorResponse {
+                status: axum::http::StatusCode::BAD_REQUEST,
+                message,
+            },
+            // The display of that one is the generic "Internal Server Error", it does not tell the client anything.
+            Error::GenericControllerError(GenericControllerError::IdNotInPostgresSerialRange {
+                ref id,
+            }) => ErrorResponse {
+                status: axum::http::StatusCode::BAD_REQUEST,
+                message: Cow::Owned(format!("Invalid stock id: {id}")),
+            },
+            Error::StockNotFound(_) => ErrorResponse {
+                status: axum::http::StatusCode::NOT_FOUND,
+                message,
+            },
+            Error::DatabaseError(_) | Error::Trading(_) => ErrorResponse::default(),
+        };
+
+        return self.to_response(representation);
+    }
+}
+
+impl ErrorExt for Error {
+    fn to(self) -> crate::Error {
+        return crate::controller::Error::from(self).into();
+    }
+}
 
-        axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
     }
 }
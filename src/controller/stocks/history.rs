@@ -0,0 +1,271 @@
+//! Price history of a single stock, backed by the `stock_prices` table.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State, rejection::QueryRejection},
+};
+
+use crate::{
+    controller::stocks::Error,
+    database::{DatabaseConnection, types::Money},
+};
+
+/// Upper bound of the custom range, longer than any of the relative ones.
+pub const MAX_RANGE_DAYS: i64 = 5 * 365;
+
+/// Upper bound of the prices in a single response, the longer ranges are sampled down to that.
+pub const MAX_PRICE_POINTS: i32 = 2000;
+
+/// The range of the history, relative to now, except the custom one which takes `from` and `to`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub enum HistoryRange {
+    #[default]
+    #[serde(rename = "1d")]
+    Day,
+    #[serde(rename = "1w")]
+    Week,
+    #[serde(rename = "1m")]
+    Month,
+    #[serde(rename = "1y")]
+    Year,
+    #[serde(rename = "custom")]
+    Custom,
+}
+
+impl HistoryRange {
+    fn duration(&self) -> Option<chrono::Duration> {
+        match self {
+            HistoryRange::Day => Some(chrono::Duration::days(1)),
+            HistoryRange::Week => Some(chrono::Duration::weeks(1)),
+            HistoryRange::Month => Some(chrono::Duration::days(30)),
+            HistoryRange::Year => Some(chrono::Duration::days(365)),
+            HistoryRange::Custom => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub range: Option<HistoryRange>,
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>,
+}
+
+impl HistoryQuery {
+    /// Resolves the query into the `[from, to]` interval, timestamps are in UTC as the rest of the database.
+    pub fn interval(
+        &self,
+        now: chrono::NaiveDateTime,
+    ) -> crate::controller::stocks::Result<(chrono::NaiveDateTime, chrono::NaiveDateTime)> {
+        // Passing `from` or `to` alone implies the custom range.
+        let is_custom = self.from.is_some() || self.to.is_some();
+
+        let (from, to) = match self.range.unwrap_or_default().duration() {
+            Some(_) if is_custom && self.range.is_some() => {
+                return Err(Error::InvalidQuery(
+                    "from and to can only be used with the custom range".into(),
+                ));
+            }
+            Some(duration) if !is_custom => (now - duration, now),
+            _ => {
+                let Some(from) = self.from else {
+                    return Err(Error::InvalidQuery(
+                        "custom range requires the from parameter".into(),
+                    ));
+                };
+
+                (from, self.to.unwrap_or(now))
+            }
+        };
+
+        if from > to {
+            return Err(Error::InvalidQuery("from cannot be after to".into()));
+        }
+
+        if to - from > chrono::Duration::days(MAX_RANGE_DAYS) {
+            return Err(Error::InvalidQuery(format!(
+                "the range cannot be longer than {MAX_RANGE_DAYS} days"
+            )));
+        }
+
+        Ok((from, to))
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PricePoint {
+    pub price: Money,
+    pub recorded_at: chrono::NaiveDateTime,
+}
+
+#[derive(serde::Serialize)]
+pub struct StockHistory {
+    pub stock_id: i32,
+    pub from: chrono::NaiveDateTime,
+    pub to: chrono::NaiveDateTime,
+    /// Ordered from the oldest to the newest.
+    pub prices: Vec<PricePoint>,
+}
+
+// Not a handler.
+pub async fn list_prices(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    stock_id: i32,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+) -> crate::controller::stocks::Result<Vec<PricePoint>> {
+    Ok(sqlx::query_as!(
+        PricePoint,
+        "SELECT price, recorded_at FROM stock_prices
+        WHERE stock_id = $1 AND recorded_at BETWEEN $2 AND $3
+        ORDER BY recorded_at, id",
+        stock_id,
+        from,
+        to
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Splits the interval into `MAX_PRICE_POINTS` equal buckets and keeps the last price of each, so the response
+/// stays bounded no matter how often the prices ticked. The ranges with fewer ticks than that are returned whole.
+pub async fn sample_prices(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    stock_id: i32,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+) -> crate::controller::stocks::Result<Vec<PricePoint>> {
+    // NOTE: GREATEST guards the division of the empty interval, every tick falls into the first bucket then.
+    Ok(sqlx::query_as!(
+        PricePoint,
+        r#"SELECT DISTINCT ON (bucket) price AS "price!", recorded_at AS "recorded_at!"
+        FROM (
+            SELECT id, price, recorded_at,
+                LEAST(
+                    FLOOR(EXTRACT(EPOCH FROM recorded_at - $2) * $4::INT / GREATEST(EXTRACT(EPOCH FROM $3 - $2), 1)),
+                    $4::INT - 1
+                ) AS bucket
+            FROM stock_prices
+            WHERE stock_id = $1 AND recorded_at BETWEEN $2 AND $3
+        ) AS ticks
+        ORDER BY bucket, recorded_at DESC, id DESC"#,
+        stock_id,
+        from,
+        to,
+        MAX_PRICE_POINTS
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+pub async fn get_stock_history(
+    Path(id): Path<String>,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    query: std::result::Result<Query<HistoryQuery>, QueryRejection>,
+) -> crate::controller::stocks::Result<Json<StockHistory>> {
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let id = super::parse_stock_id(id)?;
+    let (from, to) = query.interval(chrono::Utc::now().naive_utc())?;
+
+    // Empty history of an existing stock is fine, but the history of the non-existing stock is not.
+    let exists = sqlx::query_scalar!("SELECT EXISTS (SELECT 1 FROM stocks WHERE id = $1)", id)
+        .fetch_one(&conn)
+        .await?;
+
+    if exists != Some(true) {
+        return Err(Error::StockNotFound(id.to_string()));
+    }
+
+    let prices = self::sample_prices(&conn, id, from, to).await?;
+
+    Ok(Json(StockHistory {
+        stock_id: id,
+        from,
+        to,
+        prices,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2025, 6, 15)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_relative_ranges() {
+        for (range, duration) in [
+            (None, chrono::Duration::days(1)),
+            (Some(HistoryRange::Week), chrono::Duration::weeks(1)),
+            (Some(HistoryRange::Year), chrono::Duration::days(365)),
+        ] {
+            let query = HistoryQuery {
+                range,
+                ..Default::default()
+            };
+
+            assert_eq!(query.interval(now()).unwrap(), (now() - duration, now()));
+        }
+    }
+
+    #[test]
+    fn test_custom_range() {
+        let from = now() - chrono::Duration::hours(3);
+
+        let query = HistoryQuery {
+            from: Some(from),
+            ..Default::default()
+        };
+        assert_eq!(query.interval(now()).unwrap(), (from, now()));
+
+        let query = HistoryQuery {
+            range: Some(HistoryRange::Custom),
+            from: Some(from),
+            to: Some(from + chrono::Duration::hours(1)),
+        };
+        assert_eq!(
+            query.interval(now()).unwrap(),
+            (from, from + chrono::Duration::hours(1))
+        );
+    }
+
+    #[test]
+    fn test_invalid_ranges() {
+        let queries = [
+            // Custom without the from.
+            HistoryQuery {
+                range: Some(HistoryRange::Custom),
+                ..Default::default()
+            },
+            // Relative range mixed with the bounds.
+            HistoryQuery {
+                range: Some(HistoryRange::Week),
+                from: Some(now()),
+                to: None,
+            },
+            // Reversed bounds.
+            HistoryQuery {
+                range: None,
+                from: Some(now()),
+                to: Some(now() - chrono::Duration::seconds(1)),
+            },
+            // Longer than the limit.
+            HistoryQuery {
+                range: None,
+                from: Some(now() - chrono::Duration::days(MAX_RANGE_DAYS + 1)),
+                to: None,
+            },
+        ];
+
+        for query in queries {
+            assert!(matches!(query.interval(now()), Err(Error::InvalidQuery(_))));
+        }
+    }
+}
@@ -1,11 +1,23 @@
+pub mod book;
+pub mod candles;
 mod error;
+pub mod history;
+pub mod pagination;
+
 pub use error::Error;
 use sqlx::types::chrono;
 use tracing::info;
 
-use crate::{controller::error::GenericControllerError, database::DatabaseConnection};
+use crate::{
+    controller::{
+        error::GenericControllerError,
+        stocks::pagination::{Cursor, StocksPage, StocksQuery},
+    },
+    database::{DatabaseConnection, types::Money},
+    trading::OrderBooks,
+};
 use axum::{
-    extract::{FromRef, Json, Path, State},
+    extract::{FromRef, Json, Path, Query, State, rejection::QueryRejection},
     response::IntoResponse,
 };
 
@@ -14,14 +26,31 @@ pub(in crate::controller::stocks) type Result<T> = std::result::Result<T, self::
 pub fn router<S: Clone + Send + Sync + 'static>() -> axum::Router<S>
 where
     DatabaseConnection: FromRef<S>,
+    OrderBooks: FromRef<S>,
 {
     axum::Router::new()
         .route("/stocks", axum::routing::get(get_stocks))
         .route("/stocks/{id}", axum::routing::get(get_stock))
+        .route(
+            "/stocks/{id}/history",
+            axum::routing::get(history::get_stock_history),
+        )
+        .route(
+            "/stocks/{id}/candles",
+            axum::routing::get(candles::get_stock_candles),
+        )
+        .route(
+            "/stocks/{id}/book",
+            axum::routing::get(book::get_stock_book),
+        )
+        .route(
+            "/stocks/by-symbol/{abbreviation}",
+            axum::routing::get(get_stock_by_abbreviation),
+        )
 }
 
 // https://docs.rs/sqlx/latest/sqlx/postgres/types/index.html#types
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, sqlx::FromRow)]
 
 // TODO: Delegate the database schemas to separate module/file.
 pub struct Stock {
@@ -29,37 +58,105 @@ pub struct Stock {
     abbreviation: String,
     company: String,
     since: chrono::NaiveDate, // DATE
-    price: f32,
-    delta: f32,
-    last_update: chrono::NaiveDate, // TIMESTAMP
-    created_at: chrono::NaiveDate,  // TIMESTAMP
+    price: Money,
+    delta: Money,
+    last_update: chrono::NaiveDateTime, // TIMESTAMP
+    created_at: chrono::NaiveDateTime,  // TIMESTAMP
 }
 
 // Not a handler.
-// <T: DeserializeOwned + Send + Sync>(
-async fn list_stocks(DatabaseConnection(conn): DatabaseConnection) -> self::Result<Vec<Stock>> {
+async fn find_stock_by_id(
+    DatabaseConnection(conn): DatabaseConnection,
+    id: i32,
+) -> self::Result<Option<Stock>> {
     // TODO: Consider reading queries from file
     // let account = sqlx::query_file!("tests/test-query-account-by-id.sql", 1i32)
     //     .fetch_one(&mut conn)
     //     .await?;
 
     // That maps the query result to the struct Stock.
-    Ok(sqlx::query_as!(Stock, "SELECT * FROM stocks")
-        .fetch_all(&conn)
-        .await
-        // Propagation casts to self::Error using #[from] crate::database::Error on self::Error
-        .map_err(crate::database::Error::from)?)
+    Ok(
+        sqlx::query_as!(Stock, "SELECT * FROM stocks WHERE id = $1", id)
+            .fetch_optional(&conn)
+            .await?,
+    )
+}
+
+// Not a handler.
+async fn find_stock_by_abbreviation(
+    DatabaseConnection(conn): DatabaseConnection,
+    abbreviation: &str,
+) -> self::Result<Option<Stock>> {
+    Ok(sqlx::query_as!(
+        Stock,
+        "SELECT * FROM stocks WHERE abbreviation = $1",
+        abbreviation
+    )
+    .fetch_optional(&conn)
+    .await?)
+}
+
+/// Not a handler, fetches a single page of the stocks matching the query.
+async fn list_stocks_page(
+    DatabaseConnection(conn): DatabaseConnection,
+    query: &StocksQuery,
+) -> self::Result<StocksPage> {
+    let (limit, cursor) = query.validate()?;
+
+    // The sorting column and the filters are dynamic, so we cannot use the query_as! macro here.
+    let mut builder = sqlx::QueryBuilder::new("SELECT * FROM stocks");
+    query.push_filters(&mut builder);
+    query.push_keyset(&mut builder, cursor);
+
+    // Fetch one more to know if there is the next page without running another query.
+    builder.push(" LIMIT ").push_bind(limit + 1);
+
+    let mut stocks = builder.build_query_as::<Stock>().fetch_all(&conn).await?;
+
+    let next_cursor = match stocks.len() as i64 > limit {
+        true => {
+            stocks.truncate(limit as usize);
+            stocks
+                .last()
+                .map(|stock| Cursor::from_stock(stock, query.sort, query.order).to_string())
+        }
+        false => None,
+    };
+
+    let mut builder = sqlx::QueryBuilder::new("SELECT COUNT(*) FROM stocks");
+    query.push_filters(&mut builder);
+
+    let total = builder.build_query_scalar::<i64>().fetch_one(&conn).await?;
+
+    Ok(StocksPage {
+        stocks,
+        next_cursor,
+        total,
+    })
 }
 
 #[axum::debug_handler]
 pub async fn get_stocks(
     State(conn): State<DatabaseConnection>,
     // axum::extract::State(AppState { database }): axum::extract::State<AppState>,
-    axum::extract::Path(()): axum::extract::Path<()>,
+    query: std::result::Result<Query<StocksQuery>, QueryRejection>,
 ) -> self::Result<impl IntoResponse> {
-    let stocks = self::list_stocks(conn).await?;
+    let Query(query) = query.map_err(|e| self::Error::InvalidQuery(e.body_text()))?;
+
+    let page = self::list_stocks_page(conn, &query).await?;
+
+    Ok(Json(page))
+}
 
-    Ok(Json(stocks))
+/// Parses the id from the path, it has to fit into the postgres SERIAL range, that is [1, 2^31 - 1].
+fn parse_stock_id(id: String) -> self::Result<i32> {
+    Ok(id
+        .parse::<i32>()
+        .map_err(|_| GenericControllerError::IdNotInPostgresSerialRange { id })
+        .and_then(|id| match id < 1 {
+            true => Err(GenericControllerError::IdNotInPostgresSerialRange { id: id.to_string() }),
+            false => Ok(id),
+        })?)
 }
 
 pub async fn get_stock(
@@ -70,25 +167,24 @@ pub async fn get_stock(
     // }): State<AppState>,
     State(conn): State<DatabaseConnection>,
 ) -> self::Result<impl IntoResponse> {
-    let id = id
-        .parse::<i32>()
-        .map_err(|_| GenericControllerError::IdNotInPostgresSerialRange { id })
-        .and_then(|id| match id < 1 {
-            true => Err(GenericControllerError::IdNotInPostgresSerialRange { id: id.to_string() }),
-            false => Ok(id),
-        })?;
-
-    // TODO: I do not remember what happened here, but that is clearly some temporary shenanigans of the mind.
-    // We should just SELECT from the database here.
+    let id = self::parse_stock_id(id)?;
 
-    let stocks = self::list_stocks(conn).await?;
     info!("Looking for stock with id: {}", id);
 
-    Ok(Json(
-        stocks
-            .into_iter()
-            // That would fail if id > i32::MAX
-            .find(|stock| stock.id == id)
-            .unwrap(),
-    ))
+    let Some(stock) = self::find_stock_by_id(conn, id).await? else {
+        return Err(self::Error::StockNotFound(id.to_string()));
+    };
+
+    Ok(Json(stock))
+}
+
+pub async fn get_stock_by_abbreviation(
+    Path(abbreviation): Path<String>,
+    State(conn): State<DatabaseConnection>,
+) -> self::Result<impl IntoResponse> {
+    let Some(stock) = self::find_stock_by_abbreviation(conn, &abbreviation).await? else {
+        return Err(self::Error::StockNotFound(abbreviation));
+    };
+
+    Ok(Json(stock))
 }
@@ -0,0 +1,372 @@
+//! Query parameters and the keyset cursor used to page through `GET /stocks`.
+//!
+//! We are using the keyset pagination instead of the OFFSET, as the OFFSET has to scan every skipped row
+//! and the pages would shift when the stocks are inserted in between the requests.
+//! The cursor holds the sorting, the value of the sorted column and the id of the last stock on the page,
+//! the id breaks the ties as most of the columns are not unique.
+
+use std::{fmt::Display, str::FromStr};
+
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::{
+    controller::stocks::{Error, Stock},
+    database::types::Money,
+};
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, strum_macros::AsRefStr,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SortBy {
+    Price,
+    Delta,
+    #[default]
+    Abbreviation,
+    LastUpdate,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, strum_macros::AsRefStr,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query parameters of the `GET /stocks`, every one of them is optional.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct StocksQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: SortBy,
+    pub order: SortOrder,
+    pub min_price: Option<Money>,
+    pub max_price: Option<Money>,
+    pub min_delta: Option<Money>,
+    pub max_delta: Option<Money>,
+    /// Case-insensitive prefix of the company name.
+    pub company: Option<String>,
+}
+
+/// Single page of the stocks, `next_cursor` is None when there is nothing more to fetch.
+#[derive(serde::Serialize)]
+pub struct StocksPage {
+    pub stocks: Vec<Stock>,
+    pub next_cursor: Option<String>,
+    /// Count of all the stocks matching the filters, not only the ones on that page.
+    pub total: i64,
+}
+
+/// Value of the sorted column of the last stock on the page.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CursorValue {
+    Price(Money),
+    Delta(Money),
+    Abbreviation(String),
+    LastUpdate(chrono::NaiveDateTime),
+}
+
+/// Serialized as `{sort}:{order}:{id}:{value}`, we are not trying to hide anything there, the client should just
+/// send it back as it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    pub order: SortOrder,
+    pub id: i32,
+    pub value: CursorValue,
+}
+
+impl Cursor {
+    pub fn from_stock(stock: &Stock, sort: SortBy, order: SortOrder) -> Self {
+        let value = match sort {
+            SortBy::Price => CursorValue::Price(stock.price),
+            SortBy::Delta => CursorValue::Delta(stock.delta),
+            SortBy::Abbreviation => CursorValue::Abbreviation(stock.abbreviation.clone()),
+            SortBy::LastUpdate => CursorValue::LastUpdate(stock.last_update),
+        };
+
+        Self {
+            order,
+            id: stock.id,
+            value,
+        }
+    }
+
+    pub fn sort(&self) -> SortBy {
+        match self.value {
+            CursorValue::Price(_) => SortBy::Price,
+            CursorValue::Delta(_) => SortBy::Delta,
+            CursorValue::Abbreviation(_) => SortBy::Abbreviation,
+            CursorValue::LastUpdate(_) => SortBy::LastUpdate,
+        }
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:",
+            self.sort().as_ref(),
+            self.order.as_ref(),
+            self.id
+        )?;
+
+        match &self.value {
+            CursorValue::Price(value) | CursorValue::Delta(value) => write!(f, "{value}"),
+            CursorValue::Abbreviation(value) => write!(f, "{value}"),
+            CursorValue::LastUpdate(value) => write!(f, "{}", value.format(CURSOR_DATETIME_FORMAT)),
+        }
+    }
+}
+
+// Keeps the fractional seconds, otherwise we would skip the stocks updated within the same second.
+const CURSOR_DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(cursor: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidCursor(cursor.to_string());
+
+        // The value is last, so the abbreviation or the timestamp may contain the separator.
+        let mut parts = cursor.splitn(4, ':');
+        let (Some(sort), Some(order), Some(id), Some(value)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        let order = match order {
+            "asc" => SortOrder::Asc,
+            "desc" => SortOrder::Desc,
+            _ => return Err(invalid()),
+        };
+        let id = id.parse::<i32>().map_err(|_| invalid())?;
+
+        let value = match sort {
+            "price" => CursorValue::Price(value.parse().map_err(|_| invalid())?),
+            "delta" => CursorValue::Delta(value.parse().map_err(|_| invalid())?),
+            "abbreviation" => CursorValue::Abbreviation(value.to_string()),
+            "last_update" => CursorValue::LastUpdate(
+                chrono::NaiveDateTime::parse_from_str(value, CURSOR_DATETIME_FORMAT)
+                    .map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self { order, id, value })
+    }
+}
+
+impl StocksQuery {
+    /// Validates the parameters and returns the page size alongside with the decoded cursor.
+    pub fn validate(&self) -> crate::controller::stocks::Result<(i64, Option<Cursor>)> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(Error::InvalidQuery(format!(
+                "limit must be between 1 and {MAX_LIMIT}"
+            )));
+        }
+
+        if let (Some(min), Some(max)) = (self.min_price, self.max_price)
+            && min > max
+        {
+            return Err(Error::InvalidQuery(
+                "min_price cannot be greater than max_price".into(),
+            ));
+        }
+
+        if let (Some(min), Some(max)) = (self.min_delta, self.max_delta)
+            && min > max
+        {
+            return Err(Error::InvalidQuery(
+                "min_delta cannot be greater than max_delta".into(),
+            ));
+        }
+
+        let cursor = self.cursor.as_deref().map(Cursor::from_str).transpose()?;
+
+        // Cursor produced for the different sorting would point to some random place in the result set.
+        if let Some(cursor) = &cursor
+            && (cursor.sort() != self.sort || cursor.order != self.order)
+        {
+            return Err(Error::InvalidCursor(cursor.to_string()));
+        }
+
+        Ok((limit, cursor))
+    }
+
+    /// Pushes the `WHERE` clause of the filters, those are shared between the page and the total count query.
+    pub fn push_filters(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        builder.push(" WHERE TRUE");
+
+        if let Some(min_price) = self.min_price {
+            builder.push(" AND price >= ").push_bind(min_price);
+        }
+
+        if let Some(max_price) = self.max_price {
+            builder.push(" AND price <= ").push_bind(max_price);
+        }
+
+        if let Some(min_delta) = self.min_delta {
+            builder.push(" AND delta >= ").push_bind(min_delta);
+        }
+
+        if let Some(max_delta) = self.max_delta {
+            builder.push(" AND delta <= ").push_bind(max_delta);
+        }
+
+        if let Some(company) = self.company.as_deref().filter(|c| !c.is_empty()) {
+            builder
+                .push(" AND company ILIKE ")
+                .push_bind(format!("{}%", escape_like(company)));
+        }
+    }
+
+    /// Pushes the keyset condition and the ordering, the id is always the tie breaker.
+    pub fn push_keyset(&self, builder: &mut QueryBuilder<'_, Postgres>, cursor: Option<Cursor>) {
+        let column = self.sort.as_ref();
+        let (comparison, direction) = match self.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+
+        if let Some(Cursor { id, value, .. }) = cursor {
+            builder.push(format!(" AND ({column}, id) {comparison} ("));
+
+            match value {
+                CursorValue::Price(value) | CursorValue::Delta(value) => builder.push_bind(value),
+                CursorValue::Abbreviation(value) => builder.push_bind(value),
+                CursorValue::LastUpdate(value) => builder.push_bind(value),
+            };
+
+            builder.push(", ").push_bind(id).push(")");
+        }
+
+        builder.push(format!(" ORDER BY {column} {direction}, id {direction}"));
+    }
+}
+
+/// Escapes the wildcards of the LIKE pattern so the user input is matched literally.
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursors = [
+            Cursor {
+                order: SortOrder::Asc,
+                id: 1,
+                value: CursorValue::Price("2800.2500".parse().unwrap()),
+            },
+            Cursor {
+                order: SortOrder::Desc,
+                id: 2,
+                value: CursorValue::Delta("-0.30".parse().unwrap()),
+            },
+            Cursor {
+                order: SortOrder::Asc,
+                id: 3,
+                value: CursorValue::Abbreviation("WITH:COLON".into()),
+            },
+            Cursor {
+                order: SortOrder::Desc,
+                id: 4,
+                value: CursorValue::LastUpdate(
+                    chrono::NaiveDate::from_ymd_opt(2025, 1, 2)
+                        .unwrap()
+                        .and_hms_micro_opt(3, 4, 5, 678)
+                        .unwrap(),
+                ),
+            },
+        ];
+
+        for cursor in cursors {
+            assert_eq!(Cursor::from_str(&cursor.to_string()).unwrap(), cursor);
+        }
+    }
+
+    #[test]
+    fn test_cursor_invalid() {
+        for cursor in [
+            "",
+            "price",
+            "price:asc:1",
+            "price:asc:x:1",
+            "price:asc:1:x",
+            "price:up:1:1",
+            "price:1:1",
+            "volume:asc:1:1",
+        ] {
+            assert!(
+                matches!(Cursor::from_str(cursor), Err(Error::InvalidCursor(_))),
+                "Expected InvalidCursor for {cursor}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_query_validate() {
+        assert_eq!(
+            StocksQuery::default().validate().unwrap(),
+            (DEFAULT_LIMIT, None)
+        );
+
+        for limit in [0, MAX_LIMIT + 1] {
+            let query = StocksQuery {
+                limit: Some(limit),
+                ..Default::default()
+            };
+
+            assert!(matches!(query.validate(), Err(Error::InvalidQuery(_))));
+        }
+
+        let query = StocksQuery {
+            min_price: Some(Money::from(10)),
+            max_price: Some(Money::from(5)),
+            ..Default::default()
+        };
+
+        assert!(matches!(query.validate(), Err(Error::InvalidQuery(_))));
+
+        // Cursor of the price sorting used while sorting by abbreviation.
+        let query = StocksQuery {
+            cursor: Some("price:asc:1:10".into()),
+            ..Default::default()
+        };
+
+        assert!(matches!(query.validate(), Err(Error::InvalidCursor(_))));
+
+        // Cursor of the ascending order used while sorting in the descending one.
+        let query = StocksQuery {
+            cursor: Some("abbreviation:asc:1:AAPL".into()),
+            order: SortOrder::Desc,
+            ..Default::default()
+        };
+
+        assert!(matches!(query.validate(), Err(Error::InvalidCursor(_))));
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("100%_a\\b"), "100\\%\\_a\\\\b");
+    }
+}
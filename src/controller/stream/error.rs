@@ -0,0 +1,49 @@
+use std::borrow::Cow;
+
+use axum::response::IntoResponse;
+
+use crate::{
+    controller::auth,
+    error::{ErrorExt, ErrorResponse},
+};
+
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Stream error")]
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    // The invalid or expired session is rejected the same way the auth controller does it.
+    #[error(transparent)]
+    Auth(#[from] auth::Error),
+    #[error("Invalid query parameters: {0}")]
+    InvalidQuery(String),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let message = Cow::Owned(self.to_string());
+
+        let representation = match self {
+            Error::Auth(err) => return err.into_response(),
+            Error::InvalidQuery(_) => ErrorResponse {
+                status: axum::http::StatusCode::BAD_REQUEST,
+                message,
+            },
+            Error::DatabaseError(_) => ErrorResponse::default(),
+        };
+
+        return self.to_response(representation);
+    }
+}
+
+impl ErrorExt for Error {
+    fn to(self) -> crate::Error {
+        return crate::controller::Error::from(self).into();
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
@@ -0,0 +1,284 @@
+//! Server-Sent Events at `/stream/events`, the same quotes as the WebSocket, for the clients without one.
+//!
+//! The events are `subscribed` once at the start, `quote` for every quote of the followed tickers and
+//! `balance` with the balance and the delta of the signed in user, whenever those change, and `offer` for every
+//! change of the trade offers the user made or got, and `notification` for every new notification in the inbox of
+//! the user. The missed offers and notifications are not replayed, the client that reconnects should list those
+//! again.
+//!
+//! The quotes carry their history id as the event id, so the client that reconnects with the
+//! `Last-Event-ID` header gets the quotes it missed from the `stock_prices` table before the live ones.
+//! That also covers the slow clients, the stream that falls behind is ended and the client resumes
+//! from the history once it reconnects, which the `EventSource` does on its own.
+
+use std::{collections::BTreeSet, convert::Infallible, time::Duration};
+
+use axum::{
+    extract::{Query, State, rejection::QueryRejection},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::Stream;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tower_cookies::Cookies;
+
+use crate::{
+    alerts::{Notification, NotificationFeed},
+    controller::stream::{self, Error},
+    database::{DatabaseConnection, types::Money},
+    market::{self, Quote, QuoteFeed},
+    trading::{Offer, OfferFeed},
+};
+
+/// The most quotes sent to the reconnected client, it should refetch the history if it missed more.
+pub const REPLAY_LIMIT: i64 = 1000;
+
+/// There is no notification when the balance changes, so we just check it that often.
+pub const BALANCE_POLL: Duration = Duration::from_secs(2);
+
+/// Events buffered for the client before the stream stops reading the feed and eventually falls behind.
+const EVENT_BUFFER: usize = 64;
+
+pub const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct EventsQuery {
+    /// Comma separated tickers to follow, e.g. `AAPL,MSFT`.
+    pub tickers: Option<String>,
+    /// The `EventSource` only sends the header when reconnecting, that lets the client resume a stream
+    /// it had before the page reload. The header wins when both are present.
+    pub last_event_id: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SubscribedEvent {
+    pub tickers: BTreeSet<String>,
+    pub unknown: BTreeSet<String>,
+    /// None for the anonymous clients, those do not get the balance events.
+    pub user_id: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BalanceEvent {
+    pub balance: Money,
+    pub delta: Money,
+}
+
+pub async fn stream_events(
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(feed): State<QuoteFeed>,
+    State(offers): State<OfferFeed>,
+    State(notifications): State<NotificationFeed>,
+    cookies: Cookies,
+    headers: HeaderMap,
+    query: std::result::Result<Query<EventsQuery>, QueryRejection>,
+) -> stream::Result<Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>> {
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let last_event_id = match headers.get(LAST_EVENT_ID) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<i64>().ok())
+                .ok_or_else(|| {
+                    Error::InvalidQuery(format!("{LAST_EVENT_ID} must be the id of the event"))
+                })?,
+        ),
+        None => query.last_event_id,
+    };
+
+    let tickers = stream::parse_tickers(query.tickers.as_deref().unwrap_or_default())?;
+    let (tickers, unknown) = stream::split_known_tickers(&conn, tickers).await?;
+    let user = stream::optional_session(&conn, &cookies).await?;
+
+    // Subscribing before reading the history, the overlap is skipped by the ids.
+    let quotes = feed.subscribe();
+    let offers = offers.subscribe();
+    let notifications = notifications.subscribe();
+    let (tx, rx) = mpsc::channel(EVENT_BUFFER);
+
+    let events = Events {
+        tx,
+        conn,
+        tickers,
+        user_id: user.map(|user| user.id),
+    };
+
+    tokio::spawn(events.run(quotes, offers, notifications, unknown, last_event_id));
+
+    Ok(Sse::new(ReceiverStream::new(rx).map(Ok)).keep_alive(KeepAlive::default()))
+}
+
+struct Events {
+    tx: mpsc::Sender<Event>,
+    conn: sqlx::Pool<sqlx::Postgres>,
+    tickers: BTreeSet<String>,
+    user_id: Option<i32>,
+}
+
+impl Events {
+    /// Runs until the client disconnects, or falls behind.
+    async fn run(
+        self,
+        mut quotes: broadcast::Receiver<std::sync::Arc<[Quote]>>,
+        mut offers: broadcast::Receiver<Offer>,
+        mut notifications: broadcast::Receiver<Notification>,
+        unknown: BTreeSet<String>,
+        last_event_id: Option<i64>,
+    ) {
+        let subscribed = SubscribedEvent {
+            tickers: self.tickers.clone(),
+            unknown,
+            user_id: self.user_id,
+        };
+
+        if self.send("subscribed", None, &subscribed).await.is_err() {
+            return;
+        }
+
+        // Everything up to that id was already sent to the client.
+        let mut last = last_event_id.unwrap_or(0);
+
+        if let Some(after) = last_event_id {
+            let tickers = self.tickers.iter().cloned().collect::<Vec<_>>();
+
+            let missed = match market::quotes_since(&self.conn, after, &tickers, REPLAY_LIMIT).await
+            {
+                Ok(missed) => missed,
+                Err(err) => {
+                    tracing::error!(?err, "Failed to read the missed quotes");
+                    return;
+                }
+            };
+
+            for quote in missed {
+                last = quote.id;
+
+                if self.send_quote(&quote).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        let mut poll = tokio::time::interval(BALANCE_POLL);
+        let mut balance: Option<BalanceEvent> = None;
+
+        loop {
+            tokio::select! {
+                batch = quotes.recv() => match batch {
+                    Ok(batch) => {
+                        for quote in batch.iter() {
+                            if quote.id <= last || !self.tickers.contains(&quote.abbreviation) {
+                                continue;
+                            }
+
+                            last = quote.id;
+
+                            if self.send_quote(quote).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "Ending the slow event stream, the client resumes from the history");
+                        return;
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                offer = offers.recv(), if self.user_id.is_some() => match offer {
+                    Ok(offer) if self.user_id.is_some_and(|id| id == offer.from_user_id || id == offer.to_user_id) => {
+                        if self.send("offer", None, &offer).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "Ending the slow event stream, the client lists the offers again");
+                        return;
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                notification = notifications.recv(), if self.user_id.is_some() => match notification {
+                    Ok(notification) if self.user_id == Some(notification.user_id) => {
+                        if self.send("notification", None, &notification).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "Ending the slow event stream, the client lists the notifications again");
+                        return;
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                _ = poll.tick(), if self.user_id.is_some() => {
+                    let Some(user_id) = self.user_id else { continue };
+
+                    let current = match self.balance(user_id).await {
+                        Ok(current) => current,
+                        Err(err) => {
+                            tracing::error!(?err, "Failed to read the balance for the event stream");
+                            continue;
+                        }
+                    };
+
+                    if balance != current {
+                        balance = current;
+
+                        // The user is gone, the session with it.
+                        let Some(current) = current else { return };
+
+                        if self.send("balance", None, &current).await.is_err() {
+                            return;
+                        }
+                    }
+                },
+                _ = self.tx.closed() => return,
+            }
+        }
+    }
+
+    async fn balance(
+        &self,
+        user_id: i32,
+    ) -> std::result::Result<Option<BalanceEvent>, sqlx::Error> {
+        sqlx::query_as!(
+            BalanceEvent,
+            "SELECT balance, delta FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_optional(&self.conn)
+        .await
+    }
+
+    async fn send_quote(&self, quote: &Quote) -> std::result::Result<(), ()> {
+        self.send("quote", Some(quote.id), quote).await
+    }
+
+    /// Errors when the client is gone.
+    async fn send<T: serde::Serialize>(
+        &self,
+        name: &str,
+        id: Option<i64>,
+        data: &T,
+    ) -> std::result::Result<(), ()> {
+        let mut event = Event::default().event(name);
+
+        // NOTE: Only the quotes have the id, the events without one do not reset the Last-Event-ID of the client.
+        if let Some(id) = id {
+            event = event.id(id.to_string());
+        }
+
+        let event = event.json_data(data).map_err(|err| {
+            tracing::error!(?err, "Failed to serialize the event");
+        })?;
+
+        self.tx.send(event).await.map_err(|_| ())
+    }
+}
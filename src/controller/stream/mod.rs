@@ -0,0 +1,123 @@
+//! Live updates pushed to the clients, instead of them polling `GET /stocks`.
+//!
+//! The quotes come from the `QuoteFeed` in the state, the market publishes there whatever it writes.
+//! There is the WebSocket at `/stream/quotes` and the Server-Sent Events at `/stream/events`, for the
+//! clients that only want to listen.
+
+mod error;
+pub mod events;
+pub mod websocket;
+
+use std::collections::BTreeSet;
+
+pub use error::Error;
+
+use axum::extract::FromRef;
+use tower_cookies::Cookies;
+
+use crate::{
+    alerts::NotificationFeed, controller::auth, database::DatabaseConnection,
+    database::types::ClientUser, market::QuoteFeed, trading::OfferFeed,
+};
+
+pub(in crate::controller::stream) type Result<T> = std::result::Result<T, self::Error>;
+
+/// Upper bound of the tickers a single connection follows, so one client cannot ask for everything many times over.
+pub const MAX_TICKERS: usize = 100;
+
+pub fn router<S: Clone + Send + Sync + 'static>() -> axum::Router<S>
+where
+    DatabaseConnection: FromRef<S>,
+    QuoteFeed: FromRef<S>,
+    OfferFeed: FromRef<S>,
+    NotificationFeed: FromRef<S>,
+{
+    axum::Router::new()
+        .route(
+            "/stream/quotes",
+            axum::routing::get(websocket::stream_quotes),
+        )
+        .route("/stream/events", axum::routing::get(events::stream_events))
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct StreamQuery {
+    /// Comma separated tickers to subscribe to right away, e.g. `AAPL,MSFT`.
+    pub tickers: Option<String>,
+}
+
+/// Normalizes the comma separated tickers, those are stored uppercase.
+pub fn parse_tickers(value: &str) -> Result<BTreeSet<String>> {
+    let tickers = value
+        .split(',')
+        .map(|ticker| ticker.trim().to_uppercase())
+        .filter(|ticker| !ticker.is_empty())
+        .collect::<BTreeSet<_>>();
+
+    if tickers.len() > MAX_TICKERS {
+        return Err(Error::InvalidQuery(format!(
+            "too many tickers ({}), the limit is {MAX_TICKERS}",
+            tickers.len()
+        )));
+    }
+
+    Ok(tickers)
+}
+
+/// Keeps only the tickers of the stocks that exist, returns the known and the unknown ones.
+pub async fn split_known_tickers(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    tickers: BTreeSet<String>,
+) -> Result<(BTreeSet<String>, BTreeSet<String>)> {
+    let requested = tickers.iter().cloned().collect::<Vec<_>>();
+
+    let known = sqlx::query_scalar!(
+        "SELECT abbreviation FROM stocks WHERE abbreviation = ANY($1)",
+        &requested
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .collect::<BTreeSet<_>>();
+
+    let unknown = tickers.difference(&known).cloned().collect();
+
+    Ok((known, unknown))
+}
+
+/// The streams are open for everyone, the session is optional.
+///
+/// No cookie means the anonymous client, but the invalid or expired one is still rejected, as that
+/// is most likely the client that thinks it is signed in.
+pub async fn optional_session(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    cookies: &Cookies,
+) -> Result<Option<ClientUser>> {
+    match auth::get_server_side_session(conn, cookies).await {
+        Ok(user) => Ok(Some(user)),
+        Err(auth::Error::MissingSessionCookie) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tickers() {
+        assert_eq!(
+            parse_tickers(" aapl,MSFT,, aapl ").unwrap(),
+            BTreeSet::from(["AAPL".to_string(), "MSFT".to_string()])
+        );
+        assert!(parse_tickers("").unwrap().is_empty());
+
+        let many = (0..=MAX_TICKERS)
+            .map(|i| format!("T{i}"))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        assert!(matches!(parse_tickers(&many), Err(Error::InvalidQuery(_))));
+    }
+}
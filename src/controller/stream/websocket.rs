@@ -0,0 +1,286 @@
+//! WebSocket stream of the quotes at `/stream/quotes`.
+//!
+//! The client picks the tickers with the `tickers` query parameter and changes them later with the
+//! `subscribe` and `unsubscribe` messages. Every message is a JSON object tagged with the `type`.
+//!
+//! NOTE: The slow clients are dropped rather than buffered for. Each connection reads from the bounded
+//! broadcast channel, and when it falls behind by more than its capacity, or a single send stalls for
+//! longer than `SEND_TIMEOUT`, the connection is closed and the client has to reconnect.
+
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{
+        Query, State,
+        rejection::QueryRejection,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    response::Response,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tower_cookies::Cookies;
+
+use crate::{
+    controller::stream::{self, Error, MAX_TICKERS, StreamQuery},
+    database::DatabaseConnection,
+    market::{Quote, QuoteFeed},
+};
+
+/// How long a single message may take to be written to the client before we give up on it.
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The client messages are tiny, there is no reason to accept the default 64MiB.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe { tickers: Vec<String> },
+    Unsubscribe { tickers: Vec<String> },
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The first message of the connection, `user_id` is None for the anonymous clients.
+    Welcome {
+        user_id: Option<i32>,
+    },
+    /// Sent after every change of the subscription, with the whole current set of the tickers.
+    Subscribed {
+        tickers: BTreeSet<String>,
+        /// Requested tickers that do not exist, those were not subscribed.
+        unknown: BTreeSet<String>,
+    },
+    Quote(Quote),
+    Error {
+        message: String,
+    },
+}
+
+pub async fn stream_quotes(
+    ws: WebSocketUpgrade,
+    State(DatabaseConnection(conn)): State<DatabaseConnection>,
+    State(feed): State<QuoteFeed>,
+    cookies: Cookies,
+    query: std::result::Result<Query<StreamQuery>, QueryRejection>,
+) -> stream::Result<Response> {
+    let Query(query) = query.map_err(|e| Error::InvalidQuery(e.body_text()))?;
+
+    let tickers = stream::parse_tickers(query.tickers.as_deref().unwrap_or_default())?;
+    let user = stream::optional_session(&conn, &cookies).await?;
+
+    // Subscribing before the upgrade, so nothing published during the handshake is missed.
+    let quotes = feed.subscribe();
+
+    Ok(ws
+        .max_message_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| async move {
+            let connection = Connection {
+                socket,
+                conn,
+                tickers: BTreeSet::new(),
+            };
+
+            connection
+                .run(quotes, user.map(|user| user.id), tickers)
+                .await;
+        }))
+}
+
+struct Connection {
+    socket: WebSocket,
+    conn: sqlx::Pool<sqlx::Postgres>,
+    tickers: BTreeSet<String>,
+}
+
+impl Connection {
+    async fn run(
+        mut self,
+        mut quotes: broadcast::Receiver<Arc<[Quote]>>,
+        user_id: Option<i32>,
+        initial: BTreeSet<String>,
+    ) {
+        if self
+            .send(&ServerMessage::Welcome { user_id })
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        if !initial.is_empty() && self.subscribe(initial).await.is_err() {
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                batch = quotes.recv() => match batch {
+                    Ok(batch) => {
+                        let subscribed = batch
+                            .iter()
+                            .filter(|quote| self.tickers.contains(&quote.abbreviation))
+                            .cloned()
+                            .collect::<Vec<_>>();
+
+                        for quote in subscribed {
+                            if self.send(&ServerMessage::Quote(quote)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "Dropping the slow quote stream client");
+
+                        return self
+                            .close(close_code::AGAIN, "Too slow, reconnect to resume")
+                            .await;
+                    }
+                    Err(RecvError::Closed) => {
+                        return self.close(close_code::AWAY, "Server is shutting down").await;
+                    }
+                },
+                message = self.socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        if self.handle(text.as_str()).await.is_err() {
+                            return;
+                        }
+                    }
+                    // Pings are answered by axum itself, we do not have any use for the binary messages.
+                    Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => {}
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                },
+            }
+        }
+    }
+
+    /// Errors only when the connection is gone, the invalid messages are answered with the error message.
+    async fn handle(&mut self, text: &str) -> std::result::Result<(), ()> {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                return self
+                    .send(&ServerMessage::Error {
+                        message: format!("Invalid message: {e}"),
+                    })
+                    .await;
+            }
+        };
+
+        let (subscribe, tickers) = match message {
+            ClientMessage::Subscribe { tickers } => (true, tickers),
+            ClientMessage::Unsubscribe { tickers } => (false, tickers),
+        };
+
+        let tickers = match stream::parse_tickers(&tickers.join(",")) {
+            Ok(tickers) => tickers,
+            Err(e) => {
+                return self
+                    .send(&ServerMessage::Error {
+                        message: e.to_string(),
+                    })
+                    .await;
+            }
+        };
+
+        if subscribe {
+            return self.subscribe(tickers).await;
+        }
+
+        self.tickers.retain(|ticker| !tickers.contains(ticker));
+
+        self.send(&ServerMessage::Subscribed {
+            tickers: self.tickers.clone(),
+            unknown: BTreeSet::new(),
+        })
+        .await
+    }
+
+    async fn subscribe(&mut self, tickers: BTreeSet<String>) -> std::result::Result<(), ()> {
+        let (known, unknown) = match stream::split_known_tickers(&self.conn, tickers).await {
+            Ok(split) => split,
+            Err(err) => {
+                tracing::error!(?err, "Failed to resolve the subscribed tickers");
+
+                return self
+                    .send(&ServerMessage::Error {
+                        message: "Internal Server Error".into(),
+                    })
+                    .await;
+            }
+        };
+
+        if self.tickers.union(&known).count() > MAX_TICKERS {
+            return self
+                .send(&ServerMessage::Error {
+                    message: format!("Too many tickers, the limit is {MAX_TICKERS}"),
+                })
+                .await;
+        }
+
+        self.tickers.extend(known);
+
+        self.send(&ServerMessage::Subscribed {
+            tickers: self.tickers.clone(),
+            unknown,
+        })
+        .await
+    }
+
+    async fn send(&mut self, message: &ServerMessage) -> std::result::Result<(), ()> {
+        let text = serde_json::to_string(message).map_err(|err| {
+            tracing::error!(?err, "Failed to serialize the stream message");
+        })?;
+
+        match tokio::time::timeout(SEND_TIMEOUT, self.socket.send(Message::text(text))).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(()),
+            Err(_) => {
+                tracing::warn!("Dropping the quote stream client that stopped reading");
+                Err(())
+            }
+        }
+    }
+
+    async fn close(mut self, code: u16, reason: &'static str) {
+        let frame = CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+
+        let _ =
+            tokio::time::timeout(SEND_TIMEOUT, self.socket.send(Message::Close(Some(frame)))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_format() {
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(r#"{"type": "subscribe", "tickers": ["AAPL"]}"#)
+                .unwrap(),
+            ClientMessage::Subscribe {
+                tickers: vec!["AAPL".into()]
+            }
+        );
+
+        let quote = Quote {
+            id: 1,
+            stock_id: 1,
+            abbreviation: "AAPL".into(),
+            price: "10.0000".parse().unwrap(),
+            delta: "1.50".parse().unwrap(),
+            at: chrono::NaiveDateTime::default(),
+        };
+
+        let json = serde_json::to_value(ServerMessage::Quote(quote)).unwrap();
+
+        assert_eq!(json["type"], "quote");
+        assert_eq!(json["abbreviation"], "AAPL");
+        assert_eq!(json["price"], "10.0000");
+        assert_eq!(json["delta"], "1.50");
+    }
+}
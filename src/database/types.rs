@@ -1,3 +1,135 @@
+use std::{fmt::Display, str::FromStr};
+
+use rust_decimal::{Decimal, RoundingStrategy, prelude::FromPrimitive, prelude::ToPrimitive};
+
+/// Fixed-point decimal of the money, the prices and the percent deltas, backed by the NUMERIC columns.
+///
+/// There is no `Add` or `Mul` on purpose, the arithmetic is checked and the rounding is explicit, see `round`.
+/// Serialized as the string with the scale of the column it came from, e.g. `"150.50"`, as the JSON numbers
+/// are floats on the other side. Deserialized from either the string or the number.
+///
+/// NOTE: sqlx decodes the NUMERIC zero without its scale, so the zero is always serialized as `"0"`.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    sqlx::Type,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct Money(Decimal);
+
+impl Money {
+    pub const ZERO: Money = Money(Decimal::ZERO);
+    /// Scale of the cash: the balances, the ledger amounts and the realized P&L, NUMERIC(20, 2).
+    pub const CASH_SCALE: u32 = 2;
+    /// Scale of the prices, NUMERIC(20, 4).
+    pub const PRICE_SCALE: u32 = 4;
+    /// Scale of the percent deltas, NUMERIC(6, 2).
+    pub const PERCENT_SCALE: u32 = 2;
+
+    pub const fn new(value: Decimal) -> Self {
+        Self(value)
+    }
+
+    /// None for the NaN, the infinities and whatever does not fit the decimal.
+    pub fn from_f64(value: f64, scale: u32) -> Option<Self> {
+        Decimal::from_f64(value).map(|value| Self(value).round(scale))
+    }
+
+    /// NOTE: Lossy, only for the things that are floats anyway, like the simulation of the prices.
+    pub fn to_f64(self) -> f64 {
+        self.0.to_f64().unwrap_or_default()
+    }
+
+    pub const fn decimal(self) -> Decimal {
+        self.0
+    }
+
+    /// Rounds half away from zero to the scale, and pads to it, so `1.5` at the scale 2 is `1.50`.
+    pub fn round(self, scale: u32) -> Self {
+        let mut value = self
+            .0
+            .round_dp_with_strategy(scale, RoundingStrategy::MidpointAwayFromZero);
+        value.rescale(scale);
+
+        Self(value)
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    /// The price times the quantity, e.g. the value of the order, not rounded.
+    pub fn checked_mul(self, quantity: i64) -> Option<Money> {
+        self.0.checked_mul(Decimal::from(quantity)).map(Self)
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0.is_sign_positive() && !self.0.is_zero()
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0.is_sign_negative() && !self.0.is_zero()
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl std::ops::Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Self::Output {
+        Self(-self.0)
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Money {
+    type Err = rust_decimal::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Decimal::from_str(s).map(Self)
+    }
+}
+
+impl From<Decimal> for Money {
+    fn from(value: Decimal) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Money> for Decimal {
+    fn from(value: Money) -> Self {
+        value.0
+    }
+}
+
+impl From<i64> for Money {
+    fn from(value: i64) -> Self {
+        Self(Decimal::from(value))
+    }
+}
+
 // TODO: Delegate the database schemas to separate module/file.
 #[derive(Debug)]
 pub struct DatabaseSession {
@@ -5,6 +137,7 @@ pub struct DatabaseSession {
     pub user_id: i32,
     pub created_at: chrono::NaiveDate,
     pub expires_at: chrono::NaiveDate,
+    pub csrf_token: sqlx::types::uuid::Uuid,
 }
 
 #[derive(Debug)]
@@ -14,8 +147,8 @@ pub struct DatabaseUser {
     pub id: i32,
     pub created_at: chrono::NaiveDate,
     pub account_id: i32,
-    pub balance: f32,
-    pub delta: f32,
+    pub balance: Money,
+    pub delta: Money,
     pub email: String,
     pub password_hash: String,
     // pub password_salt: String,
@@ -42,8 +175,9 @@ pub struct DatabaseAccount {
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct ClientUser {
     pub id: i32,
-    pub balance: f32,
-    pub delta: f32,
+    pub balance: Money,
+    /// Percent change of the portfolio since the start of the UTC day, see `crate::portfolio`.
+    pub delta: Money,
     pub email: String,
     pub created_at: chrono::NaiveDate,
 }
@@ -59,3 +193,53 @@ impl From<DatabaseUser> for ClientUser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(value: &str) -> Money {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_money_round() {
+        assert_eq!(money("1.005").round(2).to_string(), "1.01");
+        assert_eq!(money("-1.005").round(2).to_string(), "-1.01");
+        assert_eq!(money("1.5").round(2).to_string(), "1.50");
+        assert_eq!(money("100").round(4).to_string(), "100.0000");
+        assert_eq!(Money::from_f64(0.1 + 0.2, 2), Some(money("0.30")));
+        assert_eq!(Money::from_f64(f64::NAN, 2), None);
+    }
+
+    #[test]
+    fn test_money_checked_arithmetic() {
+        // That is the classic one the floats get wrong.
+        assert_eq!(money("0.1").checked_add(money("0.2")), Some(money("0.3")));
+        assert_eq!(money("10.25").checked_mul(3), Some(money("30.75")));
+        assert_eq!(Money::new(Decimal::MAX).checked_add(money("1")), None);
+        assert_eq!(Money::new(Decimal::MIN).checked_sub(money("1")), None);
+        assert_eq!(Money::new(Decimal::MAX).checked_mul(2), None);
+
+        assert!(money("0.01").is_positive());
+        assert!(!Money::ZERO.is_positive() && !Money::ZERO.is_negative());
+        assert!((-money("0.01")).is_negative());
+    }
+
+    #[test]
+    fn test_money_serde() {
+        assert_eq!(
+            serde_json::to_string(&money("150.50")).unwrap(),
+            "\"150.50\""
+        );
+        assert_eq!(
+            serde_json::from_str::<Money>("\"150.5\"").unwrap(),
+            money("150.5")
+        );
+        assert_eq!(
+            serde_json::from_str::<Money>("90.1").unwrap(),
+            money("90.1")
+        );
+        assert!(serde_json::from_str::<Money>("\"abc\"").is_err());
+    }
+}
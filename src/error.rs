@@ -18,6 +18,7 @@ pub enum Error {
     // There is not module called database. Think about making one.
     Database(#[from] crate::database::Error),
     Controller(#[from] crate::controller::Error),
+    Market(#[from] crate::market::Error),
     // When we use the value interpolation here, we must not leak any sensitive information.
     // We would be using that as a "message" for the client error, of course, if data is transparent
     // that it may be included. Of course that only applies to error implementing IntoResponse.
@@ -0,0 +1,307 @@
+//! Double-entry ledger of the balances of the users.
+//!
+//! Every change of `users.balance` goes through `record`, which writes the transaction with two entries:
+//! the cash of the user and the other side of the money, which sum up to zero. The database refuses
+//! the transactions that do not balance and any update of the written ones, see the `0006_LEDGER` migration.
+//! So the balance is always the sum of the cash entries of the user, `check_consistency` proves that,
+//! `rebuild_balances` restores it.
+
+mod error;
+
+pub use error::Error;
+
+use crate::database::types::Money;
+
+pub(crate) type Result<T> = std::result::Result<T, self::Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "ledger_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Fill,
+    Deposit,
+    Fee,
+    Adjustment,
+    /// The accepted trade offer between two users.
+    Offer,
+}
+
+/// The accounts the money moves between, the cash is the one of the user of the transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "ledger_account", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Account {
+    Cash,
+    Market,
+    Fees,
+    External,
+    /// The other user of the accepted trade offer.
+    Peer,
+}
+
+/// The transaction as seen by the user, with the change of the cash and where the money went or came from.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LedgerTransaction {
+    pub id: i64,
+    #[serde(rename = "type")]
+    pub kind: TransactionKind,
+    pub order_id: Option<i64>,
+    pub description: String,
+    /// Change of the balance, negative when the money left.
+    pub amount: Money,
+    /// The other side of the transaction.
+    pub account: Account,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Balance of the user that does not match the sum of the cash entries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BalanceMismatch {
+    pub user_id: i32,
+    pub balance: Money,
+    pub ledger: Money,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsistencyReport {
+    pub mismatches: Vec<BalanceMismatch>,
+    /// Transactions whose entries do not sum up to zero, or that have less than two entries.
+    pub unbalanced: Vec<i64>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty() && self.unbalanced.is_empty()
+    }
+}
+
+/// Moves the `amount` in or out of the cash of the user, against the `account`, and updates the balance with it.
+/// That is the only place the balance should ever change, returns the id of the transaction.
+///
+/// The amount has to be in the whole cents already, the caller decides which way to round it.
+///
+/// NOTE: Takes the connection, so it joins whatever transaction of the database the caller is in,
+/// the balance and the ledger have to commit together.
+pub async fn record(
+    conn: &mut sqlx::PgConnection,
+    user_id: i32,
+    kind: TransactionKind,
+    order_id: Option<i64>,
+    amount: Money,
+    account: Account,
+    description: &str,
+) -> self::Result<i64> {
+    if amount.is_zero() || amount.round(Money::CASH_SCALE) != amount || account == Account::Cash {
+        return Err(Error::InvalidAmount(amount));
+    }
+
+    let id = sqlx::query_scalar!(
+        "INSERT INTO ledger_transactions (user_id, kind, order_id, description)
+        VALUES ($1, $2, $3, $4) RETURNING id",
+        user_id,
+        kind as TransactionKind,
+        order_id,
+        description
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO ledger_entries (transaction_id, account, amount)
+        VALUES ($1, 'cash', $2), ($1, $3, -$2::numeric)",
+        id,
+        amount as Money,
+        account as Account
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE users SET balance = balance + $2 WHERE id = $1",
+        user_id,
+        amount as Money
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(id)
+}
+
+/// Money coming from outside, e.g. the user topping up the account.
+pub async fn deposit(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    amount: Money,
+) -> self::Result<i64> {
+    if !amount.is_positive() {
+        return Err(Error::InvalidAmount(amount));
+    }
+
+    let mut tx = conn.begin().await?;
+
+    let id = self::record(
+        &mut tx,
+        user_id,
+        TransactionKind::Deposit,
+        None,
+        amount,
+        Account::External,
+        "Deposit",
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(id)
+}
+
+/// Charges the positive `fee` from the cash of the user into the fees, of the order when given.
+///
+/// NOTE: Takes the connection like `record`, so the fee commits together with the fill it is charged for.
+pub async fn charge_fee(
+    conn: &mut sqlx::PgConnection,
+    user_id: i32,
+    order_id: Option<i64>,
+    fee: Money,
+) -> self::Result<i64> {
+    if !fee.is_positive() {
+        return Err(Error::InvalidAmount(fee));
+    }
+
+    self::record(
+        conn,
+        user_id,
+        TransactionKind::Fee,
+        order_id,
+        -fee,
+        Account::Fees,
+        "Fee",
+    )
+    .await
+}
+
+/// Correction of the balance, either way, with the reason in the description.
+pub async fn adjust(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    amount: Money,
+    description: &str,
+) -> self::Result<i64> {
+    let mut tx = conn.begin().await?;
+
+    let id = self::record(
+        &mut tx,
+        user_id,
+        TransactionKind::Adjustment,
+        None,
+        amount,
+        Account::External,
+        description,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(id)
+}
+
+/// Transactions of the user, newest first, only the ones before the id when given.
+pub async fn list_transactions(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    before: Option<i64>,
+    limit: i64,
+) -> self::Result<Vec<LedgerTransaction>> {
+    Ok(sqlx::query_as!(
+        LedgerTransaction,
+        r#"SELECT ledger_transactions.id, kind AS "kind: TransactionKind", order_id, description,
+            cash.amount, other.account AS "account: Account", created_at
+        FROM ledger_transactions
+        JOIN ledger_entries AS cash
+            ON cash.transaction_id = ledger_transactions.id AND cash.account = 'cash'
+        JOIN ledger_entries AS other
+            ON other.transaction_id = ledger_transactions.id AND other.account <> 'cash'
+        WHERE user_id = $1 AND ($2::int8 IS NULL OR ledger_transactions.id < $2)
+        ORDER BY ledger_transactions.id DESC
+        LIMIT $3"#,
+        user_id,
+        before,
+        limit
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Compares the balances of the users with the ledger and looks for the transactions that do not balance.
+pub async fn check_consistency(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+) -> self::Result<ConsistencyReport> {
+    let mismatches = sqlx::query_as!(
+        BalanceMismatch,
+        r#"SELECT users.id AS user_id, users.balance, COALESCE(ledger.balance, 0) AS "ledger!"
+        FROM users
+        LEFT JOIN (
+            SELECT ledger_transactions.user_id, SUM(ledger_entries.amount) AS balance
+            FROM ledger_entries
+            JOIN ledger_transactions ON ledger_transactions.id = ledger_entries.transaction_id
+            WHERE ledger_entries.account = 'cash'
+            GROUP BY ledger_transactions.user_id
+        ) AS ledger ON ledger.user_id = users.id
+        WHERE users.balance <> COALESCE(ledger.balance, 0)
+        ORDER BY users.id"#
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let unbalanced = sqlx::query_scalar!(
+        "SELECT ledger_transactions.id
+        FROM ledger_transactions
+        LEFT JOIN ledger_entries ON ledger_entries.transaction_id = ledger_transactions.id
+        GROUP BY ledger_transactions.id
+        HAVING COUNT(ledger_entries.id) < 2 OR COALESCE(SUM(ledger_entries.amount), 0) <> 0
+        ORDER BY ledger_transactions.id"
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(ConsistencyReport {
+        mismatches,
+        unbalanced,
+    })
+}
+
+/// Sets the balance of every user to the sum of the cash entries, returns how many of those changed.
+pub async fn rebuild_balances(conn: &sqlx::Pool<sqlx::Postgres>) -> self::Result<u64> {
+    Ok(sqlx::query!(
+        "UPDATE users SET balance = COALESCE(ledger.balance, 0)
+        FROM users AS target
+        LEFT JOIN (
+            SELECT ledger_transactions.user_id, SUM(ledger_entries.amount) AS balance
+            FROM ledger_entries
+            JOIN ledger_transactions ON ledger_transactions.id = ledger_entries.transaction_id
+            WHERE ledger_entries.account = 'cash'
+            GROUP BY ledger_transactions.user_id
+        ) AS ledger ON ledger.user_id = target.id
+        WHERE users.id = target.id AND users.balance <> COALESCE(ledger.balance, 0)"
+    )
+    .execute(conn)
+    .await?
+    .rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consistency_report() {
+        assert!(ConsistencyReport::default().is_consistent());
+        assert!(
+            !ConsistencyReport {
+                unbalanced: vec![1],
+                ..Default::default()
+            }
+            .is_consistent()
+        );
+    }
+}
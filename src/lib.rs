@@ -4,55 +4,132 @@ use std::sync::Arc;
 
 pub use prelude::*;
 
+pub mod alerts;
+pub mod analytics;
 pub mod config;
 pub mod controller;
 pub mod database;
 mod error;
+pub mod ledger;
 pub mod logger;
+pub mod market;
+pub mod portfolio;
 pub mod prelude;
+pub mod rules;
+pub mod trading;
+pub mod watchlists;
 
 use axum::{
     Router,
     extract::{FromRef, MatchedPath},
     http::Request,
-    middleware::{Next, from_fn},
+    middleware::{Next, from_fn, from_fn_with_state},
 };
 
-use crate::{config::Config, database::DatabaseConnection};
+use crate::{
+    alerts::NotificationFeed,
+    config::Config,
+    controller::auth::{
+        hashing::HashingPool,
+        limiter::{LoginLimiter, LoginPolicy},
+    },
+    database::DatabaseConnection,
+    market::QuoteFeed,
+    trading::{OfferFeed, OrderBooks},
+};
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
     pub database: DatabaseConnection,
+    /// Quotes written by the market, streamed to the clients subscribed to them.
+    pub quotes: QuoteFeed,
+    /// The order books of the stocks, matched in the memory of the process.
+    pub books: OrderBooks,
+    /// Changes of the trade offers, streamed to both users of the offer.
+    pub offers: OfferFeed,
+    /// The new notifications of the inbox, streamed to the user they are for.
+    pub notifications: NotificationFeed,
+    /// The failed logins per email and per client address.
+    pub login_limiter: LoginLimiter,
+    /// Where the passwords are hashed and verified, off the async executor.
+    pub hashing: HashingPool,
     // caches: std::sync::Arc<tokio::sync::Mutex<std::collections::HashMap<String, String>>>,
 }
 
 impl AppState {
+    /// Keeps the failed logins in the memory, so every state of the tests starts without any.
     pub fn new(database: impl Into<DatabaseConnection>) -> Self {
         Self {
             database: database.into(),
+            quotes: QuoteFeed::default(),
+            // Loaded lazily, each book the first time its stock is traded.
+            books: OrderBooks::default(),
+            offers: OfferFeed::default(),
+            notifications: NotificationFeed::default(),
+            login_limiter: LoginLimiter::memory(LoginPolicy::default()),
+            hashing: HashingPool::default(),
         }
     }
 
     /// Create a default AppState by initializing the database connection.
     /// This is useful for production use where we want to create the state
     pub async fn default() -> crate::Result<Self> {
+        let database = DatabaseConnection::new().await?;
+        let books = OrderBooks::load(&database.0)
+            .await
+            .map_err(anyhow::Error::from)?;
+
         Ok(Self {
-            database: DatabaseConnection::new().await?,
+            login_limiter: LoginLimiter::postgres(database.clone(), LoginPolicy::default()),
+            database,
+            quotes: QuoteFeed::default(),
+            books,
+            offers: OfferFeed::default(),
+            notifications: NotificationFeed::default(),
+            hashing: HashingPool::default(),
         })
     }
 }
 
-pub async fn run(_config: config::Config) -> crate::Result<()> {
+pub async fn run(config: config::Config) -> crate::Result<()> {
     let listener = tokio::net::TcpListener::bind(Config::APP_SOCKET_ADDR).await?;
 
     tracing::debug!("Listening on {}", Config::APP_SOCKET_ADDR);
 
     // None, because it defaults to creating database already in the app function, it is easier this way to test using `app`.
-    let state = AppState::default().await?;
+    let mut state = AppState::default().await?;
+    state.login_limiter.policy = config.login;
+    state.hashing = HashingPool::new(config.hashing);
+
+    // Started here and not in the `app`, so the tests do not get the prices moving under them,
+    // nor the snapshots taken and the rules evaluated behind their back.
+    // The alerts subscribe to the quotes before the market starts publishing those.
+    alerts::spawn(
+        state.database.clone(),
+        &state.quotes,
+        state.notifications.clone(),
+    );
+    market::start(state.database.clone(), state.quotes.clone(), config.market)?;
+    portfolio::snapshots::spawn(state.database.clone(), portfolio::snapshots::INTERVAL);
+    trading::offers::spawn(
+        state.database.clone(),
+        state.offers.clone(),
+        trading::offers::EXPIRY_INTERVAL,
+    );
+    rules::spawn(state.database.clone(), rules::SCHEDULE_INTERVAL);
+    controller::auth::limiter::spawn(
+        state.login_limiter.clone(),
+        controller::auth::limiter::PRUNE_INTERVAL,
+    );
 
     let app = app(state).await?;
 
-    axum::serve(listener, app).await?;
+    // The login limiter needs the address of the client.
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -61,14 +138,24 @@ pub async fn routes<S: Clone + Send + Sync + 'static>(state: AppState) -> self::
     let router = Router::new()
         .merge(controller::stocks::router())
         .merge(controller::auth::router())
+        .merge(controller::stream::router())
+        .merge(controller::orders::router())
+        .merge(controller::offers::router())
+        .merge(controller::rules::router())
+        .merge(controller::me::router())
         .with_state(state);
 
     Ok(router)
 }
 
 pub async fn app(state: AppState) -> self::Result<Router> {
+    let csrf = from_fn_with_state(
+        state.database.clone(),
+        controller::auth::csrf::verify_csrf_token,
+    );
+
     Ok(Router::new()
-        .nest("/api/v1", routes(state).await?)
+        .nest("/api/v1", routes(state).await?.layer(csrf))
         .layer(
             tower_http::trace::TraceLayer::new_for_http()
                 .make_span_with(|req: &Request<axum::body::Body>| {
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+#[derive(thiserror::Error, Debug, Clone)]
+// NOTE: The market is not exposed to the client directly, so we do not care about leaking anything here,
+// those are only logged by the background tasks.
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    #[error("Invalid market configuration: {0}")]
+    InvalidConfiguration(String),
+    #[error("Invalid market replay file: {0}")]
+    InvalidReplayFile(String),
+    #[error("Invalid import: {0}")]
+    InvalidImport(String),
+    #[error(transparent)]
+    Other(#[from] Arc<anyhow::Error>),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
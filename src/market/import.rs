@@ -0,0 +1,366 @@
+//! Bulk import of the daily history in the yfinance format, as written by `scripts/download_stocks.py`.
+//!
+//! The file holds a single ticker, with the `Date,Open,High,Low,Close,Volume` header, the extra columns
+//! like `Dividends` are ignored. The closes go into the `stock_prices` history and the last one becomes
+//! the current price of the stock, which is created when it does not exist yet.
+//!
+//! Invalid rows do not abort the import, those are skipped and reported back with their line.
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    database::types::Money,
+    market::{self, Error, replay},
+};
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct HistoryRow {
+    #[serde(rename = "Date")]
+    pub date: String,
+    #[serde(rename = "Open")]
+    pub open: Option<f64>,
+    #[serde(rename = "High")]
+    pub high: Option<f64>,
+    #[serde(rename = "Low")]
+    pub low: Option<f64>,
+    #[serde(rename = "Close")]
+    pub close: Option<f64>,
+    #[serde(rename = "Volume")]
+    pub volume: Option<f64>,
+}
+
+/// The single point of the history that passed the validation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryPoint {
+    pub recorded_at: chrono::NaiveDateTime,
+    pub price: Money,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowError {
+    /// Line in the file, counting the header as the first one.
+    pub line: u64,
+    pub message: String,
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportReport {
+    pub stock_id: i32,
+    pub abbreviation: String,
+    /// Whether the stock was inserted, rather than updated.
+    pub created: bool,
+    /// Points written into the history, the rows with the errors are not counted.
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// yfinance writes the dates with the exchange offset, e.g. `2025-01-02 00:00:00-05:00`, those are
+/// converted to UTC like everything else we store. The plain timestamps are taken as they are.
+pub fn parse_date(value: &str) -> Option<chrono::NaiveDateTime> {
+    let value = value.trim();
+
+    chrono::DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%:z")
+        .or_else(|_| chrono::DateTime::parse_from_rfc3339(value))
+        .map(|date| date.naive_utc())
+        .ok()
+        .or_else(|| replay::parse_timestamp(value))
+}
+
+fn positive(name: &str, value: Option<f64>) -> std::result::Result<Option<f64>, String> {
+    match value {
+        Some(value) if !(value.is_finite() && value > 0.0) => {
+            Err(format!("{name} must be a positive number, got {value}"))
+        }
+        value => Ok(value),
+    }
+}
+
+/// Checks the row against what the `stocks` and the `stock_prices` tables accept.
+pub fn validate_row(row: &HistoryRow) -> std::result::Result<HistoryPoint, String> {
+    let recorded_at =
+        parse_date(&row.date).ok_or_else(|| format!("invalid date: {:?}", row.date))?;
+
+    let close = positive("Close", row.close)?.ok_or("missing Close")?;
+    positive("Open", row.open)?;
+
+    if let (Some(high), Some(low)) = (positive("High", row.high)?, positive("Low", row.low)?)
+        && high < low
+    {
+        return Err(format!("High {high} is below Low {low}"));
+    }
+
+    if let Some(volume) = row.volume
+        && !(volume.is_finite() && volume >= 0.0)
+    {
+        return Err(format!("Volume must not be negative, got {volume}"));
+    }
+
+    // NOTE: The column keeps 4 decimal places, tiny prices would be rounded to zero and break the CHECK.
+    let Some(price) =
+        Money::from_f64(close, Money::PRICE_SCALE).filter(|price| price.is_positive())
+    else {
+        return Err(format!("Close {close} does not fit the price column"));
+    };
+
+    Ok(HistoryPoint { recorded_at, price })
+}
+
+/// Reads and validates every row, returns the valid points ordered by time and the errors of the rest.
+pub fn parse_history<R: std::io::Read>(reader: R) -> (Vec<HistoryPoint>, Vec<RowError>) {
+    let mut reader = csv::Reader::from_reader(reader);
+
+    let mut points = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen: HashMap<chrono::NaiveDateTime, u64> = HashMap::new();
+
+    for (index, row) in reader.deserialize::<HistoryRow>().enumerate() {
+        // The header is the first line, the rows start from the second.
+        let fallback = index as u64 + 2;
+
+        let result = row
+            .map_err(|e| {
+                let line = e.position().map(|p| p.line()).unwrap_or(fallback);
+                (line, e.to_string())
+            })
+            .and_then(|row| validate_row(&row).map_err(|message| (fallback, message)));
+
+        match result {
+            Ok(point) => match seen.get(&point.recorded_at) {
+                Some(first) => errors.push(RowError {
+                    line: fallback,
+                    message: format!("duplicate date, already imported from line {first}"),
+                }),
+                None => {
+                    seen.insert(point.recorded_at, fallback);
+                    points.push(point);
+                }
+            },
+            Err((line, message)) => errors.push(RowError { line, message }),
+        }
+    }
+
+    points.sort_by_key(|point| point.recorded_at);
+
+    (points, errors)
+}
+
+/// Percent change between the last two closes, clamped into the CHECK constraint of the `delta` column.
+pub fn last_delta(points: &[HistoryPoint]) -> Money {
+    let [.., previous, last] = points else {
+        return Money::ZERO;
+    };
+
+    let (previous, last) = (previous.price.decimal(), last.price.decimal());
+    let bound = Decimal::new(9999, 2);
+
+    let delta = last
+        .checked_sub(previous)
+        .and_then(|change| change.checked_div(previous))
+        .and_then(|ratio| ratio.checked_mul(Decimal::ONE_HUNDRED))
+        .unwrap_or_default();
+
+    Money::new(delta.clamp(-bound, bound)).round(Money::PERCENT_SCALE)
+}
+
+/// Imports the history of the single ticker, upserting the stock on its abbreviation.
+///
+/// The points already in the history at the same timestamps are replaced, so importing the same file
+/// twice does not duplicate anything. The current price is only overwritten when the imported history
+/// is newer than the last update of the stock, so importing the old data does not move the live price back.
+pub async fn import_history<R: std::io::Read>(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    abbreviation: &str,
+    company: Option<&str>,
+    reader: R,
+) -> market::Result<ImportReport> {
+    let abbreviation = abbreviation.trim().to_uppercase();
+
+    if abbreviation.is_empty() || abbreviation.chars().any(char::is_whitespace) {
+        return Err(Error::InvalidImport(format!(
+            "invalid ticker: {abbreviation:?}"
+        )));
+    }
+
+    let (points, errors) = self::parse_history(reader);
+
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Err(Error::InvalidImport(format!(
+            "no valid rows to import for {abbreviation}, {} rows were rejected",
+            errors.len()
+        )));
+    };
+
+    let mut tx = conn.begin().await?;
+
+    let stock = sqlx::query!(
+        r#"INSERT INTO stocks (abbreviation, company, since, price, delta, last_update)
+        VALUES ($1, COALESCE($2, $1), $3, $4, $5, $6)
+        ON CONFLICT (abbreviation) DO UPDATE SET
+            company = COALESCE($2, stocks.company),
+            since = LEAST(stocks.since, EXCLUDED.since),
+            price = CASE WHEN EXCLUDED.last_update >= stocks.last_update
+                THEN EXCLUDED.price ELSE stocks.price END,
+            delta = CASE WHEN EXCLUDED.last_update >= stocks.last_update
+                THEN EXCLUDED.delta ELSE stocks.delta END,
+            last_update = GREATEST(stocks.last_update, EXCLUDED.last_update)
+        RETURNING id, (xmax = 0) AS "created!""#,
+        abbreviation,
+        company as Option<&str>,
+        first.recorded_at.date(),
+        last.price as Money,
+        self::last_delta(&points) as Money,
+        last.recorded_at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let (timestamps, prices): (Vec<chrono::NaiveDateTime>, Vec<Money>) = points
+        .iter()
+        .map(|point| (point.recorded_at, point.price))
+        .unzip();
+
+    sqlx::query!(
+        "DELETE FROM stock_prices WHERE stock_id = $1 AND recorded_at = ANY($2::timestamp[])",
+        stock.id,
+        &timestamps
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO stock_prices (stock_id, price, recorded_at)
+        SELECT $1, price, recorded_at FROM UNNEST($2::numeric[], $3::timestamp[]) AS points(price, recorded_at)",
+        stock.id,
+        &prices as &[Money],
+        &timestamps
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(ImportReport {
+        stock_id: stock.id,
+        abbreviation,
+        created: stock.created,
+        imported: points.len(),
+        errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(value: &str) -> Money {
+        value.parse().unwrap()
+    }
+
+    fn row(date: &str, close: Option<f64>) -> HistoryRow {
+        HistoryRow {
+            date: date.into(),
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: Some(1000.0),
+        }
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let midnight = |day| {
+            chrono::NaiveDate::from_ymd_opt(2025, 1, day)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        };
+
+        assert_eq!(
+            parse_date("2025-01-02 00:00:00-05:00"),
+            Some(midnight(2) + chrono::Duration::hours(5))
+        );
+        assert_eq!(parse_date("2025-01-02"), Some(midnight(2)));
+        assert_eq!(parse_date("02/01/2025"), None);
+    }
+
+    #[test]
+    fn test_validate_row() {
+        assert_eq!(
+            validate_row(&row("2025-01-02", Some(10.5))).map(|point| point.price),
+            Ok(money("10.5"))
+        );
+        // The float noise of the yfinance closes is rounded off.
+        assert_eq!(
+            validate_row(&row("2025-01-02", Some(243.0399932861328)))
+                .map(|point| point.price.to_string()),
+            Ok("243.0400".to_string())
+        );
+
+        assert!(validate_row(&row("2025-01-02", None)).is_err());
+        assert!(validate_row(&row("2025-01-02", Some(0.0))).is_err());
+        assert!(validate_row(&row("2025-01-02", Some(f64::NAN))).is_err());
+        assert!(validate_row(&row("2025-01-02", Some(1e-60))).is_err());
+        assert!(validate_row(&row("not a date", Some(10.0))).is_err());
+
+        assert!(
+            validate_row(&HistoryRow {
+                high: Some(9.0),
+                ..row("2025-01-02", Some(10.0))
+            })
+            .is_err()
+        );
+        assert!(
+            validate_row(&HistoryRow {
+                volume: Some(-1.0),
+                ..row("2025-01-02", Some(10.0))
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_history_reports_rows() {
+        let csv = "\
+Date,Open,High,Low,Close,Volume,Dividends,Stock Splits
+2025-01-03 00:00:00-05:00,11,12,10,11.5,100,0,0
+2025-01-02 00:00:00-05:00,10,11,9,10.5,100,0,0
+2025-01-06 00:00:00-05:00,11,12,10,abc,100,0,0
+2025-01-07 00:00:00-05:00,11,12,10,-3,100,0,0
+2025-01-03 00:00:00-05:00,11,12,10,11.5,100,0,0
+";
+
+        let (points, errors) = parse_history(csv.as_bytes());
+
+        assert_eq!(
+            points.iter().map(|point| point.price).collect::<Vec<_>>(),
+            [money("10.5"), money("11.5")]
+        );
+        assert_eq!(
+            errors.iter().map(|error| error.line).collect::<Vec<_>>(),
+            [4, 5, 6]
+        );
+        assert!(errors[2].message.contains("line 2"));
+    }
+
+    #[test]
+    fn test_last_delta_is_clamped() {
+        let point = |price| HistoryPoint {
+            recorded_at: chrono::NaiveDateTime::default(),
+            price: money(price),
+        };
+
+        assert_eq!(last_delta(&[point("10")]), Money::ZERO);
+        assert_eq!(last_delta(&[point("10"), point("11")]), money("10"));
+        assert_eq!(last_delta(&[point("3"), point("4")]), money("33.33"));
+        assert_eq!(last_delta(&[point("1"), point("500")]), money("99.99"));
+    }
+}
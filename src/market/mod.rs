@@ -0,0 +1,262 @@
+//! Everything that moves the prices of the stocks, it runs in the background next to the HTTP server.
+
+mod error;
+pub mod import;
+pub mod provider;
+pub mod replay;
+pub mod simulation;
+
+pub use error::Error;
+pub use provider::{MarketDataProvider, PriceUpdate};
+pub use replay::{ReplayConfig, ReplayProvider};
+pub use simulation::{PriceModel, SimulationConfig, Simulator};
+
+use std::sync::Arc;
+
+use crate::database::{DatabaseConnection, types::Money};
+
+pub(crate) type Result<T> = std::result::Result<T, self::Error>;
+
+/// The price of the stock after the update.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Quote {
+    /// Id of the point in the `stock_prices` history, it only grows, so it orders the quotes of every stock.
+    pub id: i64,
+    pub stock_id: i32,
+    pub abbreviation: String,
+    pub price: Money,
+    /// Percent change since the previous price.
+    pub delta: Money,
+    pub at: chrono::NaiveDateTime,
+}
+
+/// Fans the written quotes out to the streaming clients, every subscriber gets its own copy.
+///
+/// The quotes written together are published as a single batch, so the capacity counts the ticks of the market
+/// rather than the quotes, and no number of stocks makes the receivers lag within one tick.
+///
+/// The channel is bounded, the receivers that fall more than the capacity behind are lagging
+/// and it is up to them to decide what to do about it, the sender never waits.
+#[derive(Clone, Debug)]
+pub struct QuoteFeed(pub tokio::sync::broadcast::Sender<Arc<[Quote]>>);
+
+impl QuoteFeed {
+    pub const CAPACITY: usize = 64;
+
+    pub fn new(capacity: usize) -> Self {
+        Self(tokio::sync::broadcast::channel(capacity).0)
+    }
+
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Arc<[Quote]>> {
+        self.0.subscribe()
+    }
+
+    pub fn publish(&self, quotes: &[Quote]) {
+        if quotes.is_empty() {
+            return;
+        }
+
+        // NOTE: That only fails when nobody is listening, which is fine.
+        let _ = self.0.send(Arc::from(quotes));
+    }
+}
+
+impl Default for QuoteFeed {
+    fn default() -> Self {
+        Self::new(Self::CAPACITY)
+    }
+}
+
+/// Where the prices come from, the market is not started at all when disabled.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MarketConfig {
+    Disabled,
+    Simulation(SimulationConfig),
+    Replay(ReplayConfig),
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        Self::Simulation(SimulationConfig::default())
+    }
+}
+
+/// Writes the new prices into the `stocks` and appends them to the `stock_prices` history in a single statement.
+///
+/// The delta is computed against the price that was stored before, clamped to the CHECK constraint of the column.
+/// Unknown tickers and non-positive prices are skipped, so the returned quotes may be fewer than the updates.
+pub async fn apply_prices(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    updates: &[PriceUpdate],
+    at: chrono::NaiveDateTime,
+) -> self::Result<Vec<Quote>> {
+    let (abbreviations, prices): (Vec<String>, Vec<Money>) = updates
+        .iter()
+        .map(|update| (update.abbreviation.clone(), update.price))
+        .unzip();
+
+    // NOTE: The SET expressions see the row before the update, so stocks.price in the delta is the old price.
+    Ok(sqlx::query_as!(
+        Quote,
+        r#"WITH updated AS (
+            UPDATE stocks SET
+                delta = GREATEST(-99.99, LEAST(99.99, (updates.price - stocks.price) / stocks.price * 100)),
+                price = updates.price,
+                last_update = $3
+            FROM UNNEST($1::text[], $2::numeric[]) AS updates(abbreviation, price)
+            WHERE stocks.abbreviation = updates.abbreviation AND updates.price > 0
+            RETURNING stocks.id, stocks.abbreviation, stocks.price, stocks.delta, stocks.last_update
+        ), history AS (
+            INSERT INTO stock_prices (stock_id, price, recorded_at)
+            SELECT id, price, last_update FROM updated
+            RETURNING id, stock_id
+        )
+        SELECT
+            history.id AS "id!",
+            updated.id AS "stock_id!",
+            updated.abbreviation AS "abbreviation!",
+            updated.price AS "price!",
+            updated.delta AS "delta!",
+            updated.last_update AS "at!"
+        FROM updated JOIN history ON history.stock_id = updated.id
+        ORDER BY history.id"#,
+        &abbreviations,
+        &prices as &[Money],
+        at
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Quotes of the tickers written after the history point `after`, at most the newest `limit` of them, oldest first.
+///
+/// That is what the clients missed while disconnected. The delta is recomputed against the previous point of
+/// the history, as only the latest one is stored on the stock.
+pub async fn quotes_since(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    after: i64,
+    tickers: &[String],
+    limit: i64,
+) -> self::Result<Vec<Quote>> {
+    let mut quotes = sqlx::query_as!(
+        Quote,
+        r#"SELECT
+            points.id AS "id!",
+            points.stock_id AS "stock_id!",
+            stocks.abbreviation AS "abbreviation!",
+            points.price AS "price!",
+            COALESCE(
+                GREATEST(-99.99, LEAST(99.99, (points.price - previous.price) / previous.price * 100)),
+                0
+            )::NUMERIC(6, 2) AS "delta!",
+            points.recorded_at AS "at!"
+        FROM stock_prices points
+        JOIN stocks ON stocks.id = points.stock_id
+        LEFT JOIN LATERAL (
+            SELECT price FROM stock_prices
+            WHERE stock_id = points.stock_id AND id < points.id
+            ORDER BY id DESC LIMIT 1
+        ) previous ON TRUE
+        WHERE points.id > $1 AND stocks.abbreviation = ANY($2)
+        ORDER BY points.id DESC
+        LIMIT $3"#,
+        after,
+        tickers,
+        limit
+    )
+    .fetch_all(conn)
+    .await?;
+
+    quotes.reverse();
+
+    Ok(quotes)
+}
+
+/// Starts the configured provider, returns None when the market is disabled.
+pub fn start(
+    database: DatabaseConnection,
+    feed: QuoteFeed,
+    config: MarketConfig,
+) -> self::Result<Option<tokio::task::JoinHandle<()>>> {
+    Ok(match config {
+        MarketConfig::Disabled => None,
+        MarketConfig::Simulation(config) => {
+            Some(self::spawn(database, feed, Simulator::new(config)?))
+        }
+        MarketConfig::Replay(config) => Some(self::spawn(
+            database,
+            feed,
+            ReplayProvider::from_file(config)?,
+        )),
+    })
+}
+
+/// Runs the provider on its own task and writes every batch it emits, until it is exhausted or the runtime shuts down.
+/// The quotes that were written are then published to the feed, the limit orders they cross are filled,
+/// the tick rules of the stocks are evaluated and the deltas of the users holding the stocks are refreshed.
+///
+/// Failed batches are logged and skipped, the provider is asked for the next one after a short pause.
+pub fn spawn<P>(
+    DatabaseConnection(conn): DatabaseConnection,
+    feed: QuoteFeed,
+    mut provider: P,
+) -> tokio::task::JoinHandle<()>
+where
+    P: MarketDataProvider + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let result = match provider.next_updates(&conn).await {
+                Ok(Some(updates)) => {
+                    self::apply_prices(&conn, &updates, chrono::Utc::now().naive_utc()).await
+                }
+                Ok(None) => {
+                    tracing::info!("Market data provider is exhausted, no more price updates");
+                    return;
+                }
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(quotes) => {
+                    tracing::debug!("Market updated {} stocks", quotes.len());
+                    feed.publish(&quotes);
+
+                    match crate::trading::fill_crossed_orders(&conn, &quotes).await {
+                        Ok(orders) if !orders.is_empty() => {
+                            tracing::debug!("Market closed {} crossed limit orders", orders.len())
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            tracing::error!(?err, "Filling the crossed limit orders failed")
+                        }
+                    }
+
+                    let stock_ids = quotes
+                        .iter()
+                        .map(|quote| quote.stock_id)
+                        .collect::<Vec<_>>();
+                    let at = chrono::Utc::now().naive_utc();
+
+                    match crate::rules::evaluate_ticks(&conn, &stock_ids, at).await {
+                        Ok(evaluations) if !evaluations.is_empty() => {
+                            tracing::debug!("Market evaluated {} tick rules", evaluations.len())
+                        }
+                        Ok(_) => {}
+                        Err(err) => tracing::error!(?err, "Evaluating the tick rules failed"),
+                    }
+
+                    if let Err(err) = crate::portfolio::refresh_deltas(&conn, &stock_ids, at).await
+                    {
+                        tracing::error!(?err, "Refreshing the deltas of the users failed")
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(?err, "Market update failed");
+                    // NOTE: So the provider that fails right away does not spin the loop.
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,25 @@
+//! The source of the price updates, the update loop in `market::spawn` does not care where those come from.
+//!
+//! We only have the simulated market and the file replay for now, a live feed would be another implementation.
+
+use std::future::Future;
+
+use crate::{database::types::Money, market};
+
+/// The new price of the stock, identified by the ticker as that is what every feed speaks.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PriceUpdate {
+    pub abbreviation: String,
+    pub price: Money,
+}
+
+pub trait MarketDataProvider: Send {
+    /// Waits until the next batch of the updates is due and returns it.
+    ///
+    /// Returns `None` when the provider is exhausted and there will be no more updates, the loop stops then.
+    /// The provider is responsible for the pacing, the loop calls that again right after writing the batch.
+    fn next_updates(
+        &mut self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+    ) -> impl Future<Output = market::Result<Option<Vec<PriceUpdate>>>> + Send;
+}
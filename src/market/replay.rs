@@ -0,0 +1,315 @@
+//! Replays the historical quotes from a CSV or JSON file as if they were coming from a live feed.
+//!
+//! Each record is `timestamp, abbreviation, price`. The records sharing the timestamp are emitted together,
+//! and the gaps between the timestamps are slept through, divided by the speed.
+
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    database::types::Money,
+    market::{
+        self, Error,
+        provider::{MarketDataProvider, PriceUpdate},
+    },
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayConfig {
+    /// `.csv` with the `timestamp,abbreviation,price` header or `.json` with the array of the same objects.
+    pub path: PathBuf,
+    /// How many times faster than the recorded time, 60.0 replays a minute of quotes every second.
+    pub speed: f64,
+    /// Starts over from the first record when the file is exhausted.
+    pub repeat: bool,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("quotes.csv"),
+            speed: 1.0,
+            repeat: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct ReplayRecord {
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub timestamp: chrono::NaiveDateTime,
+    pub abbreviation: String,
+    pub price: Money,
+}
+
+/// Accepts the ISO timestamps, with the space instead of the `T` and the plain dates, as exported by pandas.
+fn deserialize_timestamp<'de, D>(
+    deserializer: D,
+) -> std::result::Result<chrono::NaiveDateTime, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+
+    parse_timestamp(&value)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp: {value}")))
+}
+
+pub fn parse_timestamp(value: &str) -> Option<chrono::NaiveDateTime> {
+    let value = value.trim();
+
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| chrono::NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+}
+
+pub struct ReplayProvider {
+    /// Batches of the updates, with the delay to wait before emitting each of them.
+    batches: Vec<(Duration, Vec<PriceUpdate>)>,
+    position: usize,
+    repeat: bool,
+}
+
+impl ReplayProvider {
+    pub fn from_file(config: ReplayConfig) -> market::Result<Self> {
+        let records = match config.path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => csv::Reader::from_path(&config.path)
+                .and_then(|mut reader| reader.deserialize().collect::<Result<Vec<_>, _>>())
+                .map_err(|e| Error::InvalidReplayFile(format!("{}: {e}", config.path.display())))?,
+            Some("json") => std::fs::read(&config.path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                .map_err(|e| Error::InvalidReplayFile(format!("{}: {e}", config.path.display())))?,
+            _ => {
+                return Err(Error::InvalidReplayFile(format!(
+                    "{}: expected .csv or .json extension",
+                    config.path.display()
+                )));
+            }
+        };
+
+        Self::new(records, config.speed, config.repeat)
+    }
+
+    pub fn new(mut records: Vec<ReplayRecord>, speed: f64, repeat: bool) -> market::Result<Self> {
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(Error::InvalidConfiguration(format!(
+                "replay speed must be positive, got {speed}"
+            )));
+        }
+
+        if records.is_empty() {
+            return Err(Error::InvalidReplayFile("no records to replay".into()));
+        }
+
+        // Stable, so the records with the same timestamp keep the order of the file.
+        records.sort_by_key(|record| record.timestamp);
+
+        let mut batches: Vec<(Duration, Vec<PriceUpdate>)> = Vec::new();
+        let mut previous: Option<chrono::NaiveDateTime> = None;
+
+        for ReplayRecord {
+            timestamp,
+            abbreviation,
+            price,
+        } in records
+        {
+            let update = PriceUpdate {
+                abbreviation,
+                price,
+            };
+
+            match previous {
+                Some(previous) if previous == timestamp => {
+                    if let Some((_, batch)) = batches.last_mut() {
+                        batch.push(update);
+                    }
+                }
+                _ => {
+                    let gap = previous
+                        .map(|previous| (timestamp - previous).to_std().unwrap_or_default())
+                        .unwrap_or_default();
+
+                    batches.push((gap.div_f64(speed), vec![update]));
+                    previous = Some(timestamp);
+                }
+            }
+        }
+
+        Ok(Self {
+            batches,
+            position: 0,
+            repeat,
+        })
+    }
+
+    /// Returns the next batch with its delay without waiting, None once exhausted.
+    pub fn next_batch(&mut self) -> Option<(Duration, Vec<PriceUpdate>)> {
+        if self.position == self.batches.len() {
+            if !self.repeat {
+                return None;
+            }
+
+            self.position = 0;
+        }
+
+        let (delay, batch) = self.batches[self.position].clone();
+        self.position += 1;
+
+        Some((delay, batch))
+    }
+}
+
+impl MarketDataProvider for ReplayProvider {
+    async fn next_updates(
+        &mut self,
+        _conn: &sqlx::Pool<sqlx::Postgres>,
+    ) -> market::Result<Option<Vec<PriceUpdate>>> {
+        let Some((delay, batch)) = self.next_batch() else {
+            return Ok(None);
+        };
+
+        tokio::time::sleep(delay).await;
+
+        Ok(Some(batch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn record(timestamp: &str, abbreviation: &str, price: f64) -> ReplayRecord {
+        ReplayRecord {
+            timestamp: parse_timestamp(timestamp).unwrap(),
+            abbreviation: abbreviation.into(),
+            price: Money::from_f64(price, Money::PRICE_SCALE).unwrap(),
+        }
+    }
+
+    fn update(abbreviation: &str, price: f64) -> PriceUpdate {
+        PriceUpdate {
+            abbreviation: abbreviation.into(),
+            price: Money::from_f64(price, Money::PRICE_SCALE).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = chrono::NaiveDate::from_ymd_opt(2025, 1, 2)
+            .unwrap()
+            .and_hms_opt(10, 30, 0)
+            .unwrap();
+
+        assert_eq!(parse_timestamp("2025-01-02T10:30:00"), Some(expected));
+        assert_eq!(parse_timestamp("2025-01-02 10:30:00.000"), Some(expected));
+        assert_eq!(
+            parse_timestamp("2025-01-02"),
+            Some(expected.date().and_hms_opt(0, 0, 0).unwrap())
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_batches_and_speed() {
+        let mut provider = ReplayProvider::new(
+            vec![
+                record("2025-01-02T10:01:00", "AAPL", 2.0),
+                record("2025-01-02T10:00:00", "AAPL", 1.0),
+                record("2025-01-02T10:00:00", "MSFT", 10.0),
+            ],
+            60.0,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            provider.next_batch(),
+            Some((
+                Duration::ZERO,
+                vec![update("AAPL", 1.0), update("MSFT", 10.0)]
+            ))
+        );
+        // A minute of recorded time at 60x speed.
+        assert_eq!(
+            provider.next_batch(),
+            Some((Duration::from_secs(1), vec![update("AAPL", 2.0)]))
+        );
+        assert_eq!(provider.next_batch(), None);
+    }
+
+    #[test]
+    fn test_repeat() {
+        let mut provider =
+            ReplayProvider::new(vec![record("2025-01-02", "AAPL", 1.0)], 1.0, true).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(
+                provider.next_batch(),
+                Some((Duration::ZERO, vec![update("AAPL", 1.0)]))
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_replay() {
+        assert!(matches!(
+            ReplayProvider::new(vec![], 1.0, false),
+            Err(Error::InvalidReplayFile(_))
+        ));
+        assert!(matches!(
+            ReplayProvider::new(vec![record("2025-01-02", "AAPL", 1.0)], 0.0, false),
+            Err(Error::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let csv = dir.path().join("quotes.csv");
+        let mut file = std::fs::File::create(&csv)?;
+        writeln!(file, "timestamp,abbreviation,price")?;
+        writeln!(file, "2025-01-02 10:00:00,AAPL,1.5")?;
+        writeln!(file, "2025-01-02 10:00:01,AAPL,1.6")?;
+
+        let json = dir.path().join("quotes.json");
+        std::fs::write(
+            &json,
+            r#"[{"timestamp": "2025-01-02T10:00:00", "abbreviation": "AAPL", "price": 1.5},
+                {"timestamp": "2025-01-02T10:00:01", "abbreviation": "AAPL", "price": 1.6}]"#,
+        )?;
+
+        for path in [csv, json] {
+            let mut provider = ReplayProvider::from_file(ReplayConfig {
+                path,
+                ..Default::default()
+            })?;
+
+            assert_eq!(provider.batches.len(), 2);
+            assert_eq!(
+                provider.next_batch(),
+                Some((Duration::ZERO, vec![update("AAPL", 1.5)]))
+            );
+        }
+
+        let txt = dir.path().join("quotes.txt");
+        std::fs::write(&txt, "")?;
+
+        assert!(matches!(
+            ReplayProvider::from_file(ReplayConfig {
+                path: txt,
+                ..Default::default()
+            }),
+            Err(Error::InvalidReplayFile(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+//! Simulated market, moves the price of every stock with a random model on each tick.
+//!
+//! That lets us develop without the network access to a real market feed, the seed makes the moves
+//! reproducible, which is what the tests are using.
+
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    database::types::Money,
+    market::{
+        self, Error,
+        provider::{MarketDataProvider, PriceUpdate},
+    },
+};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+const MIN_PRICE: Money = Money::new(rust_decimal::Decimal::from_parts(1, 0, 0, false, 2));
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PriceModel {
+    /// Each tick the price moves by the normally distributed percent with the `step` standard deviation.
+    RandomWalk { step: f64 },
+    /// Geometric Brownian motion, the `drift` and the `volatility` are annualized, like the ones you see quoted.
+    GeometricBrownianMotion { drift: f64, volatility: f64 },
+}
+
+impl PriceModel {
+    /// The `step` of the random walk when none is given, a percent per tick.
+    pub const DEFAULT_STEP: f64 = 1.0;
+}
+
+impl Default for PriceModel {
+    fn default() -> Self {
+        Self::GeometricBrownianMotion {
+            drift: 0.05,
+            volatility: 0.2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationConfig {
+    /// How often the prices move, DESIGN.md asks for at least once a minute.
+    pub tick: Duration,
+    pub model: PriceModel,
+    /// Fixed seed for reproducible moves, random one is drawn from the OS otherwise.
+    pub seed: Option<u64>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            tick: Duration::from_secs(60),
+            model: PriceModel::default(),
+            seed: None,
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn validate(&self) -> market::Result<()> {
+        if self.tick.is_zero() {
+            return Err(Error::InvalidConfiguration("tick cannot be zero".into()));
+        }
+
+        match self.model {
+            PriceModel::RandomWalk { step } if !(step.is_finite() && step >= 0.0) => Err(
+                Error::InvalidConfiguration(format!("random walk step must be >= 0, got {step}")),
+            ),
+            PriceModel::GeometricBrownianMotion { drift, volatility }
+                if !(drift.is_finite() && volatility.is_finite() && volatility >= 0.0) =>
+            {
+                Err(Error::InvalidConfiguration(format!(
+                    "invalid geometric Brownian motion parameters, drift: {drift}, volatility: {volatility}"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct Simulator {
+    rng: StdRng,
+    config: SimulationConfig,
+    /// Created on the first update, as the interval needs the runtime that may not exist yet in `new`.
+    interval: Option<tokio::time::Interval>,
+}
+
+impl Simulator {
+    pub fn new(config: SimulationConfig) -> market::Result<Self> {
+        config.validate()?;
+
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+
+        Ok(Self {
+            rng,
+            config,
+            interval: None,
+        })
+    }
+
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    /// Draws the next price, rounded to cents and never below a cent as the stocks table requires `price > 0`.
+    ///
+    /// NOTE: The model itself is in the floats, that is fine as the drawn price is rounded right away.
+    pub fn next_price(&mut self, price: Money) -> Money {
+        let price = price.to_f64();
+        let z = standard_normal(&mut self.rng);
+
+        let next = match self.config.model {
+            PriceModel::RandomWalk { step } => price * (1.0 + step / 100.0 * z),
+            PriceModel::GeometricBrownianMotion { drift, volatility } => {
+                let dt = self.config.tick.as_secs_f64() / SECONDS_PER_YEAR;
+
+                price * ((drift - volatility.powi(2) / 2.0) * dt + volatility * dt.sqrt() * z).exp()
+            }
+        };
+
+        Money::from_f64(next, Money::CASH_SCALE)
+            .filter(|next| *next >= MIN_PRICE)
+            .unwrap_or(MIN_PRICE)
+    }
+
+    /// Draws the next price of every stock, without waiting for the tick.
+    pub async fn next_prices(
+        &mut self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+    ) -> market::Result<Vec<PriceUpdate>> {
+        // Ordered, so the same seed moves the same stocks the same way.
+        let stocks =
+            sqlx::query!(r#"SELECT abbreviation, price AS "price: Money" FROM stocks ORDER BY id"#)
+                .fetch_all(conn)
+                .await?;
+
+        Ok(stocks
+            .into_iter()
+            .map(|stock| PriceUpdate {
+                price: self.next_price(stock.price),
+                abbreviation: stock.abbreviation,
+            })
+            .collect())
+    }
+}
+
+impl MarketDataProvider for Simulator {
+    /// The simulation never runs out, the first batch is emitted right away and then every tick.
+    async fn next_updates(
+        &mut self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+    ) -> market::Result<Option<Vec<PriceUpdate>>> {
+        let tick = self.config.tick;
+        let interval = self.interval.get_or_insert_with(|| {
+            let mut interval = tokio::time::interval(tick);
+            // If the database stalls we do not want a burst of ticks afterwards.
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            interval
+        });
+
+        interval.tick().await;
+
+        self.next_prices(conn).await.map(Some)
+    }
+}
+
+/// Box-Muller transform, the normal distribution itself lives in the rand_distr crate that we do not depend on.
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    // Excluding zero as the logarithm of it is not finite.
+    let u1: f64 = rng.random_range(f64::EPSILON..1.0);
+    let u2: f64 = rng.random();
+
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seeded(model: PriceModel) -> Simulator {
+        Simulator::new(SimulationConfig {
+            model,
+            seed: Some(42),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn money(value: &str) -> Money {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let (mut first, mut second) =
+            (seeded(PriceModel::default()), seeded(PriceModel::default()));
+
+        let mut price = (Money::from(100), Money::from(100));
+        for _ in 0..100 {
+            price = (first.next_price(price.0), second.next_price(price.1));
+            assert_eq!(price.0, price.1);
+        }
+    }
+
+    #[test]
+    fn test_price_stays_positive() {
+        // Absurd volatility, the price would jump around zero without the floor.
+        let mut simulator = seeded(PriceModel::RandomWalk { step: 500.0 });
+
+        let mut price = Money::from(1);
+        for _ in 0..1000 {
+            price = simulator.next_price(price);
+            assert!(price >= MIN_PRICE);
+        }
+    }
+
+    #[test]
+    fn test_zero_volatility_is_flat() {
+        let mut simulator = seeded(PriceModel::RandomWalk { step: 0.0 });
+        assert_eq!(simulator.next_price(money("123.45")), money("123.45"));
+
+        let mut simulator = seeded(PriceModel::GeometricBrownianMotion {
+            drift: 0.0,
+            volatility: 0.0,
+        });
+        assert_eq!(simulator.next_price(money("123.45")), money("123.45"));
+    }
+
+    #[test]
+    fn test_standard_normal_moments() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let samples = (0..20_000)
+            .map(|_| standard_normal(&mut rng))
+            .collect::<Vec<_>>();
+
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+
+        assert!(mean.abs() < 0.05, "mean: {mean}");
+        assert!((variance - 1.0).abs() < 0.05, "variance: {variance}");
+    }
+
+    #[test]
+    fn test_invalid_configuration() {
+        for config in [
+            SimulationConfig {
+                tick: Duration::ZERO,
+                ..Default::default()
+            },
+            SimulationConfig {
+                model: PriceModel::RandomWalk { step: -1.0 },
+                ..Default::default()
+            },
+            SimulationConfig {
+                model: PriceModel::GeometricBrownianMotion {
+                    drift: f64::NAN,
+                    volatility: 0.2,
+                },
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                Simulator::new(config),
+                Err(Error::InvalidConfiguration(_))
+            ));
+        }
+    }
+}
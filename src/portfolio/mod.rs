@@ -0,0 +1,291 @@
+//! Valuation of the portfolios of the users, what their positions are worth at the current prices.
+//!
+//! The percent change is measured against the reference snapshot: the prices of the stocks at the start
+//! of the UTC day, applied to the shares held now. So the deposits and the trades do not move it, only the prices do.
+//! That is what `users.delta` holds, `refresh_deltas` recomputes it after every tick of the market.
+
+mod error;
+pub mod snapshots;
+
+pub use error::Error;
+pub use snapshots::Snapshot;
+
+use rust_decimal::Decimal;
+
+use crate::database::types::Money;
+
+pub(crate) type Result<T> = std::result::Result<T, self::Error>;
+
+/// The delta of the user is clamped into the CHECK constraint of the column, the same way the stocks are.
+const MAX_DELTA: Decimal = Decimal::from_parts(9999, 0, 0, false, 2);
+
+/// The position of the user with the prices it is valued at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Holding {
+    pub user_id: i32,
+    pub quantity: i32,
+    pub average_cost: Money,
+    pub realized_pnl: Money,
+    pub price: Money,
+    /// The price of the stock at the reference, the current price when the stock has no history before it.
+    pub reference_price: Money,
+}
+
+/// Totals of the positions, the sums are exact and rounded to the cents only at the end.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Valuation {
+    /// The shares held at the current prices.
+    pub market_value: Money,
+    /// What was paid for the shares held, at their average cost.
+    pub cost_basis: Money,
+    pub unrealized_pnl: Money,
+    pub realized_pnl: Money,
+    /// The shares held at the reference prices.
+    pub reference_value: Money,
+    /// Percent change of the market value since the reference, 0 without any shares.
+    pub delta: Money,
+}
+
+impl Valuation {
+    pub fn of(holdings: &[Holding]) -> self::Result<Self> {
+        let mut market_value = Money::ZERO;
+        let mut cost_basis = Money::ZERO;
+        let mut realized_pnl = Money::ZERO;
+        let mut reference_value = Money::ZERO;
+
+        let add = |total: Money, price: Money, quantity: i32| {
+            price
+                .checked_mul(quantity as i64)
+                .and_then(|value| total.checked_add(value))
+                .ok_or(Error::Overflow)
+        };
+
+        for holding in holdings {
+            market_value = add(market_value, holding.price, holding.quantity)?;
+            cost_basis = add(cost_basis, holding.average_cost, holding.quantity)?;
+            reference_value = add(reference_value, holding.reference_price, holding.quantity)?;
+            realized_pnl = add(realized_pnl, holding.realized_pnl, 1)?;
+        }
+
+        let delta = self::percent_change(reference_value, market_value).ok_or(Error::Overflow)?;
+
+        let market_value = market_value.round(Money::CASH_SCALE);
+        let cost_basis = cost_basis.round(Money::CASH_SCALE);
+
+        Ok(Self {
+            market_value,
+            cost_basis,
+            unrealized_pnl: market_value
+                .checked_sub(cost_basis)
+                .ok_or(Error::Overflow)?,
+            realized_pnl: realized_pnl.round(Money::CASH_SCALE),
+            reference_value: reference_value.round(Money::CASH_SCALE),
+            delta,
+        })
+    }
+}
+
+/// The valuation of the portfolio of the user, with the cash next to it.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Portfolio {
+    /// The balance of the user.
+    pub cash: Money,
+    /// The cash and the market value.
+    pub total_value: Money,
+    #[serde(flatten)]
+    pub valuation: Valuation,
+    pub reference_at: chrono::NaiveDateTime,
+}
+
+/// The reference snapshot of the valuation at the time, the start of its UTC day.
+pub fn reference_at(at: chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+    at.date().and_time(chrono::NaiveTime::MIN)
+}
+
+/// Percent change from the value to the other, clamped to the delta column and rounded to its scale.
+fn percent_change(from: Money, to: Money) -> Option<Money> {
+    if from.is_zero() {
+        return Some(Money::ZERO.round(Money::PERCENT_SCALE));
+    }
+
+    let change = to
+        .decimal()
+        .checked_sub(from.decimal())?
+        .checked_div(from.decimal())?
+        .checked_mul(Decimal::ONE_HUNDRED)?;
+
+    Some(Money::from(change.clamp(-MAX_DELTA, MAX_DELTA)).round(Money::PERCENT_SCALE))
+}
+
+/// Positions of the users with the current prices and the prices at the reference, grouped by the user.
+async fn holdings(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_ids: &[i32],
+    reference_at: chrono::NaiveDateTime,
+) -> self::Result<Vec<Holding>> {
+    Ok(sqlx::query_as!(
+        Holding,
+        r#"SELECT
+            positions.user_id,
+            positions.quantity,
+            positions.average_cost,
+            positions.realized_pnl,
+            stocks.price,
+            COALESCE(reference.price, stocks.price) AS "reference_price!: Money"
+        FROM positions
+        JOIN stocks ON stocks.id = positions.stock_id
+        LEFT JOIN LATERAL (
+            SELECT price FROM stock_prices
+            WHERE stock_id = positions.stock_id AND recorded_at <= $2
+            ORDER BY recorded_at DESC, id DESC LIMIT 1
+        ) reference ON TRUE
+        WHERE positions.user_id = ANY($1)
+        ORDER BY positions.user_id"#,
+        user_ids,
+        reference_at
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Values the portfolio of the user at the time, which picks the reference snapshot.
+pub async fn valuate(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Portfolio> {
+    let reference_at = self::reference_at(at);
+
+    let cash = sqlx::query_scalar!(
+        r#"SELECT balance AS "balance: Money" FROM users WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    let valuation = Valuation::of(&self::holdings(conn, &[user_id], reference_at).await?)?;
+
+    Ok(Portfolio {
+        cash,
+        total_value: cash
+            .checked_add(valuation.market_value)
+            .ok_or(Error::Overflow)?,
+        valuation,
+        reference_at,
+    })
+}
+
+/// Recomputes `users.delta` of the users holding any of the stocks, returns how many of those changed.
+///
+/// NOTE: The users whose valuation overflows are skipped with the warning, so they do not hold back everybody else.
+pub async fn refresh_deltas(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    stock_ids: &[i32],
+    at: chrono::NaiveDateTime,
+) -> self::Result<u64> {
+    let user_ids = sqlx::query_scalar!(
+        "SELECT DISTINCT user_id FROM positions WHERE stock_id = ANY($1)",
+        stock_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    if user_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let holdings = self::holdings(conn, &user_ids, self::reference_at(at)).await?;
+
+    let (ids, deltas): (Vec<i32>, Vec<Money>) = holdings
+        .chunk_by(|a, b| a.user_id == b.user_id)
+        .filter_map(|holdings| match Valuation::of(holdings) {
+            Ok(valuation) => Some((holdings[0].user_id, valuation.delta)),
+            Err(err) => {
+                tracing::warn!(
+                    user_id = holdings[0].user_id,
+                    ?err,
+                    "Skipping the delta of the user"
+                );
+                None
+            }
+        })
+        .unzip();
+
+    Ok(sqlx::query!(
+        "UPDATE users SET delta = updates.delta
+        FROM UNNEST($1::int4[], $2::numeric[]) AS updates(id, delta)
+        WHERE users.id = updates.id AND users.delta <> updates.delta",
+        &ids,
+        &deltas as &[Money]
+    )
+    .execute(conn)
+    .await?
+    .rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holding(quantity: i32, average_cost: &str, price: &str, reference_price: &str) -> Holding {
+        Holding {
+            user_id: 1,
+            quantity,
+            average_cost: average_cost.parse().unwrap(),
+            realized_pnl: Money::ZERO,
+            price: price.parse().unwrap(),
+            reference_price: reference_price.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_valuation() {
+        let valuation = Valuation::of(&[
+            holding(10, "100", "110", "100"),
+            holding(3, "33.3333", "20.005", "25"),
+            Holding {
+                realized_pnl: "-12.5".parse().unwrap(),
+                ..holding(0, "0", "500", "400")
+            },
+        ])
+        .unwrap();
+
+        // 1100 + 60.015, rounded only once.
+        assert_eq!(valuation.market_value.to_string(), "1160.02");
+        assert_eq!(valuation.cost_basis.to_string(), "1100.00");
+        assert_eq!(valuation.unrealized_pnl.to_string(), "60.02");
+        assert_eq!(valuation.realized_pnl.to_string(), "-12.50");
+        assert_eq!(valuation.reference_value.to_string(), "1075.00");
+        // 85.015 / 1075
+        assert_eq!(valuation.delta.to_string(), "7.91");
+    }
+
+    #[test]
+    fn test_valuation_edge_cases() {
+        assert_eq!(Valuation::of(&[]).unwrap().delta, Money::ZERO);
+
+        // The closed positions are not worth anything, so there is nothing to change.
+        assert_eq!(
+            Valuation::of(&[holding(0, "0", "10", "5")]).unwrap().delta,
+            Money::ZERO
+        );
+
+        assert_eq!(
+            Valuation::of(&[holding(1, "1", "1000", "1")])
+                .unwrap()
+                .delta
+                .to_string(),
+            "99.99"
+        );
+
+        assert!(matches!(
+            Valuation::of(&[holding(i32::MAX, "1", &Decimal::MAX.to_string(), "1")]),
+            Err(Error::Overflow)
+        ));
+
+        assert_eq!(
+            reference_at("2025-06-15T13:45:00".parse().unwrap()).to_string(),
+            "2025-06-15 00:00:00"
+        );
+    }
+}
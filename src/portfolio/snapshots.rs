@@ -0,0 +1,148 @@
+//! Daily snapshots of the portfolios, the series the performance analytics run on.
+//!
+//! The job values every portfolio each `INTERVAL` and rewrites the snapshot of the current UTC day,
+//! so the row of the day that is over holds the last value taken before the midnight.
+
+use std::collections::HashMap;
+
+use super::Valuation;
+use crate::database::{DatabaseConnection, types::Money};
+
+/// How often the snapshot of the day is rewritten.
+pub const INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    pub day: chrono::NaiveDate,
+    /// The cash and the market value of the positions.
+    pub total_value: Money,
+    pub market_value: Money,
+    /// The deposits and the adjustments of the day, the money that is not the performance.
+    pub net_flow: Money,
+}
+
+impl From<&Snapshot> for crate::analytics::Point {
+    fn from(snapshot: &Snapshot) -> Self {
+        Self {
+            value: snapshot.total_value.to_f64(),
+            net_flow: snapshot.net_flow.to_f64(),
+        }
+    }
+}
+
+/// Values the portfolio of every user and writes it as the snapshot of the day of `at`, returns how many were written.
+///
+/// NOTE: The users whose valuation overflows are skipped with the warning, the same as in `refresh_deltas`.
+pub async fn take_snapshots(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    at: chrono::NaiveDateTime,
+) -> super::Result<u64> {
+    let users = sqlx::query!(r#"SELECT id, balance AS "balance: Money" FROM users ORDER BY id"#)
+        .fetch_all(conn)
+        .await?;
+
+    let user_ids = users.iter().map(|user| user.id).collect::<Vec<_>>();
+    let holdings = super::holdings(conn, &user_ids, super::reference_at(at)).await?;
+
+    let valuations = holdings
+        .chunk_by(|a, b| a.user_id == b.user_id)
+        .map(|holdings| (holdings[0].user_id, Valuation::of(holdings)))
+        .collect::<HashMap<_, _>>();
+
+    let mut ids = Vec::with_capacity(users.len());
+    let mut total_values = Vec::with_capacity(users.len());
+    let mut market_values = Vec::with_capacity(users.len());
+
+    for user in users {
+        let valuation = match valuations.get(&user.id).cloned() {
+            Some(Ok(valuation)) => valuation,
+            None => Valuation::default(),
+            Some(Err(err)) => {
+                tracing::warn!(user_id = user.id, ?err, "Skipping the snapshot of the user");
+                continue;
+            }
+        };
+
+        let Some(total_value) = user.balance.checked_add(valuation.market_value) else {
+            tracing::warn!(
+                user_id = user.id,
+                "Skipping the snapshot of the user, its value is out of range"
+            );
+            continue;
+        };
+
+        ids.push(user.id);
+        total_values.push(total_value);
+        market_values.push(valuation.market_value);
+    }
+
+    Ok(sqlx::query!(
+        "INSERT INTO portfolio_snapshots (user_id, day, total_value, market_value, net_flow, taken_at)
+        SELECT snapshots.user_id, $4::date, snapshots.total_value, snapshots.market_value, COALESCE(flows.amount, 0), $5
+        FROM UNNEST($1::int4[], $2::numeric[], $3::numeric[]) AS snapshots(user_id, total_value, market_value)
+        LEFT JOIN (
+            SELECT ledger_transactions.user_id, SUM(cash.amount) AS amount
+            FROM ledger_transactions
+            JOIN ledger_entries cash ON cash.transaction_id = ledger_transactions.id AND cash.account = 'cash'
+            WHERE ledger_transactions.created_at >= $4::date
+                AND ledger_transactions.created_at < $4::date + 1
+                AND EXISTS (
+                    SELECT 1 FROM ledger_entries
+                    WHERE transaction_id = ledger_transactions.id AND account = 'external'
+                )
+            GROUP BY ledger_transactions.user_id
+        ) flows ON flows.user_id = snapshots.user_id
+        ON CONFLICT (user_id, day) DO UPDATE SET
+            total_value = EXCLUDED.total_value,
+            market_value = EXCLUDED.market_value,
+            net_flow = EXCLUDED.net_flow,
+            taken_at = EXCLUDED.taken_at",
+        &ids,
+        &total_values as &[Money],
+        &market_values as &[Money],
+        at.date(),
+        at
+    )
+    .execute(conn)
+    .await?
+    .rows_affected())
+}
+
+/// Snapshots of the user from the day on, oldest first, all of them without the day.
+pub async fn list_snapshots(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    from: Option<chrono::NaiveDate>,
+) -> super::Result<Vec<Snapshot>> {
+    Ok(sqlx::query_as!(
+        Snapshot,
+        "SELECT day, total_value, market_value, net_flow FROM portfolio_snapshots
+        WHERE user_id = $1 AND ($2::date IS NULL OR day >= $2)
+        ORDER BY day",
+        user_id,
+        from
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Takes the snapshots every `interval` on its own task, for as long as the runtime lives.
+/// The failed runs are logged, the next one is tried at the next interval.
+pub fn spawn(
+    DatabaseConnection(conn): DatabaseConnection,
+    interval: std::time::Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticks.tick().await;
+
+            match self::take_snapshots(&conn, chrono::Utc::now().naive_utc()).await {
+                Ok(count) => tracing::debug!("Snapshotted {count} portfolios"),
+                Err(err) => tracing::error!(?err, "Taking the portfolio snapshots failed"),
+            }
+        }
+    })
+}
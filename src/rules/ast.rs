@@ -0,0 +1,626 @@
+//! The typed AST of the rules, what the users write and what the `definition` column of the `rules` table holds.
+//!
+//! The evaluation is pure, whatever the rule looks at comes from the `Facts` given by the caller, so the same
+//! evaluator runs the live rules and anything replaying the history.
+//!
+//! For example, sell all of the shares once the price drops 5% below the cost basis:
+//!
+//! ```json
+//! {
+//!     "trigger": {"type": "tick", "stock_id": 1},
+//!     "condition": {
+//!         "type": "compare",
+//!         "left": {"type": "price", "stock_id": 1},
+//!         "op": "le",
+//!         "right": {"type": "scaled", "value": {"type": "cost_basis", "stock_id": 1}, "factor": "0.95"}
+//!     },
+//!     "action": {"type": "order", "stock_id": 1, "side": "sell", "quantity": "all"}
+//! }
+//! ```
+
+use std::collections::BTreeSet;
+
+use chrono::Datelike;
+
+use crate::{
+    database::types::Money,
+    trading::{NewOrder, OrderKind, OrderSide, OrderVenue},
+};
+
+/// Bounds of the conditions, so a single rule cannot take the engine down.
+pub const MAX_DEPTH: usize = 8;
+pub const MAX_NODES: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Definition {
+    pub trigger: Trigger,
+    /// Always holds when there is none, only the scheduled rules may go without it.
+    #[serde(default)]
+    pub condition: Option<Condition>,
+    pub action: Action,
+}
+
+impl From<sqlx::types::Json<Definition>> for Definition {
+    fn from(json: sqlx::types::Json<Definition>) -> Self {
+        json.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    /// Every new price of the stock.
+    Tick { stock_id: i32 },
+    /// At the time of the day, UTC, on the weekdays, or every day without those.
+    Schedule {
+        #[serde(default)]
+        weekdays: Vec<chrono::Weekday>,
+        time: chrono::NaiveTime,
+    },
+}
+
+impl Trigger {
+    /// The first time the schedule comes after `after`, None for the tick trigger.
+    pub fn next_run(&self, after: chrono::NaiveDateTime) -> Option<chrono::NaiveDateTime> {
+        let Trigger::Schedule { weekdays, time } = self else {
+            return None;
+        };
+
+        (0..=7)
+            .map(|days| (after.date() + chrono::Days::new(days)).and_time(*time))
+            .find(|run| {
+                *run > after && (weekdays.is_empty() || weekdays.contains(&run.date().weekday()))
+            })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    All {
+        conditions: Vec<Condition>,
+    },
+    Any {
+        conditions: Vec<Condition>,
+    },
+    Not {
+        condition: Box<Condition>,
+    },
+    /// NOTE: The comparison with the unknown value does not hold, e.g. the cost basis without the shares.
+    Compare {
+        left: Value,
+        op: Comparison,
+        right: Value,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Value {
+    /// The current price of the stock.
+    Price {
+        stock_id: i32,
+    },
+    /// The average cost of the shares of the stock held, unknown without the shares.
+    CostBasis {
+        stock_id: i32,
+    },
+    /// The shares of the stock held.
+    Shares {
+        stock_id: i32,
+    },
+    Balance,
+    Constant {
+        value: Money,
+    },
+    /// The value times the factor, e.g. 0.95 of the cost basis is 5% below it.
+    Scaled {
+        value: Box<Value>,
+        factor: Money,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// The market order, placed the same as the user would place it.
+    Order {
+        stock_id: i32,
+        side: OrderSide,
+        quantity: Quantity,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantity {
+    Shares(i32),
+    /// Every share held, only for the sells.
+    All,
+}
+
+/// What the rule of the user knows about the market and the portfolio at the time it is evaluated.
+pub trait Facts {
+    fn price(&self, stock_id: i32) -> Option<Money>;
+
+    /// The shares of the stock held and their average cost, None without the position.
+    fn position(&self, stock_id: i32) -> Option<(i64, Money)>;
+
+    fn balance(&self) -> Money;
+}
+
+impl Definition {
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.trigger, &self.condition) {
+            (Trigger::Tick { .. }, None) => {
+                return Err("the tick rule needs the condition".into());
+            }
+            (_, Some(condition)) => {
+                let mut nodes = 0;
+                condition.validate(1, &mut nodes)?;
+            }
+            (Trigger::Schedule { .. }, None) => {}
+        }
+
+        match self.action {
+            Action::Order {
+                quantity: Quantity::Shares(shares),
+                ..
+            } if shares <= 0 => Err("the quantity must be positive".into()),
+            Action::Order {
+                side: OrderSide::Buy,
+                quantity: Quantity::All,
+                ..
+            } => Err("only the sell can be of all the shares".into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every stock the rule refers to.
+    pub fn stock_ids(&self) -> BTreeSet<i32> {
+        let mut stock_ids = BTreeSet::new();
+
+        if let Trigger::Tick { stock_id } = self.trigger {
+            stock_ids.insert(stock_id);
+        }
+
+        let Action::Order { stock_id, .. } = self.action;
+        stock_ids.insert(stock_id);
+
+        if let Some(condition) = &self.condition {
+            condition.stock_ids(&mut stock_ids);
+        }
+
+        stock_ids
+    }
+
+    /// Whether the rule should act now, the rule without the condition always does.
+    pub fn matches(&self, facts: &impl Facts) -> bool {
+        self.condition
+            .as_ref()
+            .is_none_or(|condition| condition.evaluate(facts))
+    }
+
+    /// What the rule does at the evaluation, `matched` is whether its condition held at the previous one.
+    ///
+    /// NOTE: The tick rule fires once its condition starts to hold, the scheduled one every time it holds.
+    pub fn decide(&self, facts: &impl Facts, matched: bool) -> Decision {
+        if !self.matches(facts) {
+            return Decision::Unmatched;
+        }
+
+        if matched && matches!(self.trigger, Trigger::Tick { .. }) {
+            return Decision::Held;
+        }
+
+        match self.action.order(facts) {
+            Ok(order) => Decision::Place(order),
+            Err(detail) => Decision::Fail(detail),
+        }
+    }
+}
+
+/// The outcome of the single evaluation of the rule, before its order is placed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    Unmatched,
+    Held,
+    Place(NewOrder),
+    /// The rule should act, but there is no order it could place, e.g. no shares to sell.
+    Fail(String),
+}
+
+impl Decision {
+    /// Whether the condition held, what the next evaluation gets as `matched`.
+    pub fn matched(&self) -> bool {
+        !matches!(self, Decision::Unmatched)
+    }
+}
+
+impl Condition {
+    fn validate(&self, depth: usize, nodes: &mut usize) -> Result<(), String> {
+        *nodes += 1;
+
+        if depth > MAX_DEPTH || *nodes > MAX_NODES {
+            return Err(format!(
+                "the condition is limited to the depth of {MAX_DEPTH} and {MAX_NODES} nodes"
+            ));
+        }
+
+        match self {
+            Condition::All { conditions } | Condition::Any { conditions } => {
+                if conditions.is_empty() {
+                    return Err("the all and any conditions cannot be empty".into());
+                }
+
+                conditions
+                    .iter()
+                    .try_for_each(|condition| condition.validate(depth + 1, nodes))
+            }
+            Condition::Not { condition } => condition.validate(depth + 1, nodes),
+            Condition::Compare { left, right, .. } => {
+                left.validate(depth + 1, nodes)?;
+                right.validate(depth + 1, nodes)
+            }
+        }
+    }
+
+    fn stock_ids(&self, stock_ids: &mut BTreeSet<i32>) {
+        match self {
+            Condition::All { conditions } | Condition::Any { conditions } => conditions
+                .iter()
+                .for_each(|condition| condition.stock_ids(stock_ids)),
+            Condition::Not { condition } => condition.stock_ids(stock_ids),
+            Condition::Compare { left, right, .. } => {
+                left.stock_ids(stock_ids);
+                right.stock_ids(stock_ids);
+            }
+        }
+    }
+
+    pub fn evaluate(&self, facts: &impl Facts) -> bool {
+        match self {
+            Condition::All { conditions } => conditions.iter().all(|c| c.evaluate(facts)),
+            Condition::Any { conditions } => conditions.iter().any(|c| c.evaluate(facts)),
+            Condition::Not { condition } => !condition.evaluate(facts),
+            Condition::Compare { left, op, right } => {
+                let (Some(left), Some(right)) = (left.evaluate(facts), right.evaluate(facts))
+                else {
+                    return false;
+                };
+
+                match op {
+                    Comparison::Lt => left < right,
+                    Comparison::Le => left <= right,
+                    Comparison::Gt => left > right,
+                    Comparison::Ge => left >= right,
+                    Comparison::Eq => left == right,
+                    Comparison::Ne => left != right,
+                }
+            }
+        }
+    }
+}
+
+impl Value {
+    fn validate(&self, depth: usize, nodes: &mut usize) -> Result<(), String> {
+        *nodes += 1;
+
+        if depth > MAX_DEPTH || *nodes > MAX_NODES {
+            return Err(format!(
+                "the condition is limited to the depth of {MAX_DEPTH} and {MAX_NODES} nodes"
+            ));
+        }
+
+        match self {
+            Value::Scaled { factor, .. } if !factor.is_positive() => {
+                Err("the factor must be positive".into())
+            }
+            Value::Scaled { value, .. } => value.validate(depth + 1, nodes),
+            _ => Ok(()),
+        }
+    }
+
+    fn stock_ids(&self, stock_ids: &mut BTreeSet<i32>) {
+        match self {
+            Value::Price { stock_id }
+            | Value::CostBasis { stock_id }
+            | Value::Shares { stock_id } => {
+                stock_ids.insert(*stock_id);
+            }
+            Value::Scaled { value, .. } => value.stock_ids(stock_ids),
+            Value::Balance | Value::Constant { .. } => {}
+        }
+    }
+
+    /// None when it is not known, or out of the range of the decimal.
+    pub fn evaluate(&self, facts: &impl Facts) -> Option<Money> {
+        match self {
+            Value::Price { stock_id } => facts.price(*stock_id),
+            Value::CostBasis { stock_id } => facts
+                .position(*stock_id)
+                .filter(|(shares, _)| *shares > 0)
+                .map(|(_, average_cost)| average_cost),
+            Value::Shares { stock_id } => Some(Money::from(
+                facts.position(*stock_id).map_or(0, |(shares, _)| shares),
+            )),
+            Value::Balance => Some(facts.balance()),
+            Value::Constant { value } => Some(*value),
+            Value::Scaled { value, factor } => value
+                .evaluate(facts)?
+                .decimal()
+                .checked_mul(factor.decimal())
+                .map(Money::from),
+        }
+    }
+}
+
+impl Action {
+    /// The order the action places now, the error tells why there is none.
+    pub fn order(&self, facts: &impl Facts) -> Result<NewOrder, String> {
+        let Action::Order {
+            stock_id,
+            side,
+            quantity,
+        } = *self;
+
+        let quantity = match quantity {
+            Quantity::Shares(shares) => shares,
+            Quantity::All => {
+                let held = facts.position(stock_id).map_or(0, |(shares, _)| shares);
+
+                if held <= 0 {
+                    return Err("there are no shares to sell".into());
+                }
+
+                i32::try_from(held).map_err(|_| "too many shares to sell at once".to_string())?
+            }
+        };
+
+        Ok(NewOrder {
+            stock_id,
+            side,
+            kind: OrderKind::Market,
+            venue: OrderVenue::Market,
+            quantity,
+            limit_price: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct TestFacts {
+        prices: HashMap<i32, Money>,
+        positions: HashMap<i32, (i64, Money)>,
+        balance: Money,
+    }
+
+    impl Facts for TestFacts {
+        fn price(&self, stock_id: i32) -> Option<Money> {
+            self.prices.get(&stock_id).copied()
+        }
+
+        fn position(&self, stock_id: i32) -> Option<(i64, Money)> {
+            self.positions.get(&stock_id).copied()
+        }
+
+        fn balance(&self) -> Money {
+            self.balance
+        }
+    }
+
+    fn stop_loss() -> Definition {
+        serde_json::from_value(serde_json::json!({
+            "trigger": {"type": "tick", "stock_id": 1},
+            "condition": {
+                "type": "compare",
+                "left": {"type": "price", "stock_id": 1},
+                "op": "le",
+                "right": {"type": "scaled", "value": {"type": "cost_basis", "stock_id": 1}, "factor": "0.95"}
+            },
+            "action": {"type": "order", "stock_id": 1, "side": "sell", "quantity": "all"}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_stop_loss() {
+        let rule = stop_loss();
+        assert_eq!(rule.validate(), Ok(()));
+        assert_eq!(rule.stock_ids(), BTreeSet::from([1]));
+
+        let mut facts = TestFacts {
+            prices: HashMap::from([(1, Money::from(96))]),
+            positions: HashMap::from([(1, (10, Money::from(100)))]),
+            ..Default::default()
+        };
+
+        assert!(!rule.matches(&facts));
+
+        facts.prices.insert(1, Money::from(95));
+        assert!(rule.matches(&facts));
+
+        let order = rule.action.order(&facts).unwrap();
+        assert_eq!((order.side, order.quantity), (OrderSide::Sell, 10));
+        assert_eq!(order.kind, OrderKind::Market);
+
+        // Nothing to compare against, or to sell, once the shares are gone.
+        facts.positions.insert(1, (0, Money::ZERO));
+        assert!(!rule.matches(&facts));
+        assert!(rule.action.order(&facts).is_err());
+    }
+
+    #[test]
+    fn test_logical_conditions() {
+        let price_above = |value: i64| Condition::Compare {
+            left: Value::Price { stock_id: 1 },
+            op: Comparison::Gt,
+            right: Value::Constant {
+                value: Money::from(value),
+            },
+        };
+        let facts = TestFacts {
+            prices: HashMap::from([(1, Money::from(10))]),
+            balance: Money::from(50),
+            ..Default::default()
+        };
+
+        assert!(
+            Condition::All {
+                conditions: vec![price_above(5), price_above(9)]
+            }
+            .evaluate(&facts)
+        );
+        assert!(
+            !Condition::All {
+                conditions: vec![price_above(5), price_above(10)]
+            }
+            .evaluate(&facts)
+        );
+        assert!(
+            Condition::Any {
+                conditions: vec![price_above(10), price_above(5)]
+            }
+            .evaluate(&facts)
+        );
+        assert!(
+            Condition::Not {
+                condition: Box::new(price_above(10))
+            }
+            .evaluate(&facts)
+        );
+
+        // The unknown stock does not compare.
+        let unknown = Condition::Compare {
+            left: Value::Price { stock_id: 2 },
+            op: Comparison::Ne,
+            right: Value::Balance,
+        };
+        assert!(!unknown.evaluate(&facts));
+
+        let no_shares = Condition::Compare {
+            left: Value::Shares { stock_id: 1 },
+            op: Comparison::Eq,
+            right: Value::Constant { value: Money::ZERO },
+        };
+        assert!(no_shares.evaluate(&facts));
+    }
+
+    #[test]
+    fn test_validate() {
+        let rule = stop_loss();
+
+        let invalid = [
+            Definition {
+                condition: None,
+                ..rule.clone()
+            },
+            Definition {
+                condition: Some(Condition::Any { conditions: vec![] }),
+                ..rule.clone()
+            },
+            Definition {
+                action: Action::Order {
+                    stock_id: 1,
+                    side: OrderSide::Buy,
+                    quantity: Quantity::All,
+                },
+                ..rule.clone()
+            },
+            Definition {
+                action: Action::Order {
+                    stock_id: 1,
+                    side: OrderSide::Sell,
+                    quantity: Quantity::Shares(0),
+                },
+                ..rule.clone()
+            },
+            Definition {
+                condition: Some(Condition::Compare {
+                    left: Value::Scaled {
+                        value: Box::new(Value::Balance),
+                        factor: Money::ZERO,
+                    },
+                    op: Comparison::Lt,
+                    right: Value::Balance,
+                }),
+                ..rule.clone()
+            },
+        ];
+
+        for definition in invalid {
+            assert!(definition.validate().is_err(), "{definition:?}");
+        }
+
+        let mut deep = Condition::Not {
+            condition: Box::new(rule.condition.clone().unwrap()),
+        };
+        for _ in 0..MAX_DEPTH {
+            deep = Condition::Not {
+                condition: Box::new(deep),
+            };
+        }
+        assert!(
+            Definition {
+                condition: Some(deep),
+                ..rule
+            }
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_schedule() {
+        let every_monday: Definition = serde_json::from_value(serde_json::json!({
+            "trigger": {"type": "schedule", "weekdays": ["mon"], "time": "09:30:00"},
+            "action": {"type": "order", "stock_id": 2, "side": "buy", "quantity": {"shares": 5}}
+        }))
+        .unwrap();
+
+        assert_eq!(every_monday.validate(), Ok(()));
+        assert!(every_monday.matches(&TestFacts::default()));
+
+        // A Wednesday.
+        let at = chrono::NaiveDate::from_ymd_opt(2026, 10, 14)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let next = every_monday.trigger.next_run(at).unwrap();
+        assert_eq!(next.date().weekday(), chrono::Weekday::Mon);
+        assert_eq!(next.to_string(), "2026-10-19 09:30:00");
+
+        // Not the same run again.
+        assert_eq!(
+            every_monday.trigger.next_run(next).unwrap().to_string(),
+            "2026-10-26 09:30:00"
+        );
+
+        let daily = Trigger::Schedule {
+            weekdays: vec![],
+            time: chrono::NaiveTime::from_hms_opt(9, 30, 0).unwrap(),
+        };
+        assert_eq!(
+            daily.next_run(at).unwrap().to_string(),
+            "2026-10-15 09:30:00"
+        );
+        assert_eq!(Trigger::Tick { stock_id: 1 }.next_run(at), None);
+    }
+}
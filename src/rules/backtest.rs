@@ -0,0 +1,648 @@
+//! Backtesting of the rules, the rule is replayed over the price history of its stocks in the simulated account.
+//!
+//! The rule is decided by `Definition::decide`, the same as the live engine does, so only the account differs:
+//! the orders fill right away at the last price of the history, the same as the market orders do, and nothing
+//! is ever written to the database.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::{Decision, Definition, Error, Facts, RuleOutcome, Trigger};
+use crate::{
+    database::types::Money,
+    trading::{self, NewOrder, OrderSide},
+};
+
+/// The cash of the simulated account when not told otherwise.
+pub const DEFAULT_INITIAL_BALANCE: i64 = 10_000;
+
+pub const MAX_RANGE: chrono::Duration = chrono::Duration::days(366);
+
+/// The prices the single backtest can replay, the longer history has to be split into the shorter ranges.
+pub const MAX_PRICE_POINTS: i64 = 500_000;
+
+/// The failures past that many are only counted.
+pub const MAX_FAILURES: usize = 100;
+
+/// The equity curve is sampled down to that many points, the drawdown is still computed over every one of them.
+pub const MAX_EQUITY_POINTS: i64 = 500;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BacktestRequest {
+    pub from: chrono::NaiveDateTime,
+    pub to: chrono::NaiveDateTime,
+    /// `DEFAULT_INITIAL_BALANCE` when not given.
+    #[serde(default)]
+    pub initial_balance: Option<Money>,
+    /// The shares held from the start by the stock id, their cost is the price at the start.
+    #[serde(default)]
+    pub initial_shares: BTreeMap<i32, i32>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Backtest {
+    pub rule_id: i64,
+    pub from: chrono::NaiveDateTime,
+    pub to: chrono::NaiveDateTime,
+    /// The cash and the shares at the start, valued at the prices at the start.
+    pub initial_value: Money,
+    /// The cash at the end.
+    pub final_balance: Money,
+    /// The cash and the shares at the end, valued at the last prices.
+    pub final_value: Money,
+    /// The fraction, `0.05` is 5%.
+    pub total_return: f64,
+    /// The largest fall of the value from its peak, the fraction as well.
+    pub max_drawdown: f64,
+    pub evaluations: Evaluations,
+    pub trades: Vec<Trade>,
+    /// Why the rule could not act, the first `MAX_FAILURES` of those.
+    pub failures: Vec<Failure>,
+    pub equity_curve: Vec<EquityPoint>,
+}
+
+/// How many evaluations ended with the outcome.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Evaluations {
+    pub unmatched: u64,
+    pub held: u64,
+    pub fired: u64,
+    pub failed: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Trade {
+    pub at: chrono::NaiveDateTime,
+    pub stock_id: i32,
+    pub side: OrderSide,
+    pub quantity: i32,
+    pub price: Money,
+    /// What the shares cost, in the whole cents.
+    pub value: Money,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Failure {
+    pub at: chrono::NaiveDateTime,
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EquityPoint {
+    pub at: chrono::NaiveDateTime,
+    pub value: Money,
+}
+
+/// The price of the stock at the time, the point of the history.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PricePoint {
+    pub stock_id: i32,
+    pub price: Money,
+    pub at: chrono::NaiveDateTime,
+}
+
+impl BacktestRequest {
+    pub fn validate(&self) -> super::Result<()> {
+        if self.from >= self.to || self.to - self.from > MAX_RANGE {
+            return Err(Error::InvalidBacktest(format!(
+                "from must be before to, at most {} days apart",
+                MAX_RANGE.num_days()
+            )));
+        }
+
+        if let Some(balance) = self.initial_balance
+            && (balance.is_negative() || balance.round(Money::CASH_SCALE) != balance)
+        {
+            return Err(Error::InvalidBacktest(
+                "initial_balance cannot be negative, in the whole cents".into(),
+            ));
+        }
+
+        if self.initial_shares.values().any(|shares| *shares <= 0) {
+            return Err(Error::InvalidBacktest(
+                "initial_shares must be positive".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The simulated account, what the rule knows about while replayed.
+#[derive(Debug, Default)]
+struct Account {
+    balance: Money,
+    positions: HashMap<i32, (i64, Money)>,
+    prices: HashMap<i32, Money>,
+}
+
+impl Facts for Account {
+    fn price(&self, stock_id: i32) -> Option<Money> {
+        self.prices.get(&stock_id).copied()
+    }
+
+    fn position(&self, stock_id: i32) -> Option<(i64, Money)> {
+        self.positions.get(&stock_id).copied()
+    }
+
+    fn balance(&self) -> Money {
+        self.balance
+    }
+}
+
+impl Account {
+    /// Fills the market order at the current price, the failure is what the live order would fail with.
+    fn fill(
+        &mut self,
+        order: &NewOrder,
+        at: chrono::NaiveDateTime,
+    ) -> std::result::Result<Trade, String> {
+        let price = self
+            .price(order.stock_id)
+            .ok_or_else(|| format!("No price of the stock {} yet", order.stock_id))?;
+        let value = price
+            .checked_mul(order.quantity as i64)
+            .map(|value| value.round(Money::CASH_SCALE))
+            .ok_or_else(|| format!("{} shares at {price} overflow", order.quantity))?;
+
+        let (shares, average_cost) = self.position(order.stock_id).unwrap_or_default();
+        let quantity = order.quantity as i64;
+
+        match order.side {
+            OrderSide::Buy => {
+                if value > self.balance {
+                    return Err(trading::Error::InsufficientFunds {
+                        required: value,
+                        available: self.balance.max(Money::ZERO),
+                    }
+                    .to_string());
+                }
+
+                // The same average cost method as the positions use.
+                let average_cost = average_cost
+                    .checked_mul(shares)
+                    .and_then(|cost| cost.checked_add(price.checked_mul(quantity)?))
+                    .and_then(|cost| cost.decimal().checked_div((shares + quantity).into()))
+                    .map(|cost| Money::from(cost).round(Money::PRICE_SCALE))
+                    .ok_or_else(|| "the cost of the position overflows".to_string())?;
+
+                self.balance = self
+                    .balance
+                    .checked_sub(value)
+                    .ok_or_else(|| "the balance overflows".to_string())?;
+                self.positions
+                    .insert(order.stock_id, (shares + quantity, average_cost));
+            }
+            OrderSide::Sell => {
+                if quantity > shares {
+                    return Err(trading::Error::InsufficientShares {
+                        requested: quantity,
+                        available: shares,
+                    }
+                    .to_string());
+                }
+
+                let average_cost = match shares == quantity {
+                    true => Money::ZERO,
+                    false => average_cost,
+                };
+
+                self.balance = self
+                    .balance
+                    .checked_add(value)
+                    .ok_or_else(|| "the balance overflows".to_string())?;
+                self.positions
+                    .insert(order.stock_id, (shares - quantity, average_cost));
+            }
+        }
+
+        Ok(Trade {
+            at,
+            stock_id: order.stock_id,
+            side: order.side,
+            quantity: order.quantity,
+            price,
+            value,
+        })
+    }
+
+    /// The cash and the shares at the current prices, the shares without the price yet are not counted.
+    fn value(&self) -> Option<Money> {
+        self.positions
+            .iter()
+            .try_fold(self.balance, |value, (stock_id, (shares, _))| {
+                let price = self.price(*stock_id).unwrap_or(Money::ZERO);
+                value.checked_add(price.checked_mul(*shares)?.round(Money::CASH_SCALE))
+            })
+    }
+}
+
+/// The replay of the rule over the history, the state between the events.
+struct Replay<'a> {
+    definition: &'a Definition,
+    account: Account,
+    matched: bool,
+    evaluations: Evaluations,
+    trades: Vec<Trade>,
+    failures: Vec<Failure>,
+    equity_curve: Vec<EquityPoint>,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+    peak: Money,
+    max_drawdown: f64,
+}
+
+impl Replay<'_> {
+    fn evaluate(&mut self, at: chrono::NaiveDateTime) {
+        let decision = self.definition.decide(&self.account, self.matched);
+        self.matched = decision.matched();
+
+        let outcome = match decision {
+            Decision::Unmatched => RuleOutcome::Unmatched,
+            Decision::Held => RuleOutcome::Held,
+            Decision::Place(order) => match self.account.fill(&order, at) {
+                Ok(trade) => {
+                    self.trades.push(trade);
+                    RuleOutcome::Fired
+                }
+                Err(detail) => self.fail(at, detail),
+            },
+            Decision::Fail(detail) => self.fail(at, detail),
+        };
+
+        match outcome {
+            RuleOutcome::Unmatched => self.evaluations.unmatched += 1,
+            RuleOutcome::Held => self.evaluations.held += 1,
+            RuleOutcome::Fired => self.evaluations.fired += 1,
+            RuleOutcome::Failed => self.evaluations.failed += 1,
+        }
+    }
+
+    fn fail(&mut self, at: chrono::NaiveDateTime, detail: String) -> RuleOutcome {
+        if self.failures.len() < MAX_FAILURES {
+            self.failures.push(Failure { at, detail });
+        }
+
+        RuleOutcome::Failed
+    }
+
+    /// Values the account after the event, the curve keeps the last value of every one of its buckets.
+    fn record(&mut self, at: chrono::NaiveDateTime) -> super::Result<()> {
+        let value = self.account.value().ok_or_else(|| {
+            Error::InvalidBacktest("the value of the account is out of range".into())
+        })?;
+
+        self.peak = self.peak.max(value);
+
+        if self.peak.is_positive() {
+            let drawdown = self.peak.to_f64() - value.to_f64();
+            self.max_drawdown = self.max_drawdown.max(drawdown / self.peak.to_f64());
+        }
+
+        let bucket = |at: chrono::NaiveDateTime| {
+            (at - self.from).num_milliseconds() as i128 * MAX_EQUITY_POINTS as i128
+                / (self.to - self.from).num_milliseconds().max(1) as i128
+        };
+
+        match self.equity_curve.last_mut() {
+            Some(last) if bucket(last.at) == bucket(at) => {
+                *last = EquityPoint { at, value };
+            }
+            _ => self.equity_curve.push(EquityPoint { at, value }),
+        }
+
+        Ok(())
+    }
+}
+
+/// Replays the rule over the points of the history between `from` and `to`, in their order.
+///
+/// The `seed` are the last prices of the stocks before `from`, the rule knows those from the start.
+/// The tick rule is evaluated on every point of its stock, the scheduled one at every time of its schedule.
+pub fn replay(
+    rule_id: i64,
+    definition: &Definition,
+    request: &BacktestRequest,
+    seed: &[PricePoint],
+    points: &[PricePoint],
+) -> super::Result<Backtest> {
+    let mut account = Account {
+        balance: request
+            .initial_balance
+            .unwrap_or(Money::from(DEFAULT_INITIAL_BALANCE)),
+        ..Default::default()
+    };
+
+    for point in seed {
+        account.prices.insert(point.stock_id, point.price);
+    }
+
+    for (&stock_id, &shares) in &request.initial_shares {
+        let Some(price) = account.price(stock_id).or_else(|| {
+            points
+                .iter()
+                .find(|point| point.stock_id == stock_id)
+                .map(|point| point.price)
+        }) else {
+            return Err(Error::InvalidBacktest(format!(
+                "no price history of the stock {stock_id} to start with"
+            )));
+        };
+
+        account.positions.insert(stock_id, (shares as i64, price));
+    }
+
+    // The value at the start counts the initial shares at their cost, even those without the price yet.
+    let initial_value = account
+        .positions
+        .values()
+        .try_fold(account.balance, |value, (shares, cost)| {
+            value.checked_add(cost.checked_mul(*shares)?.round(Money::CASH_SCALE))
+        })
+        .ok_or_else(|| Error::InvalidBacktest("the initial value is out of range".into()))?;
+
+    let mut replay = Replay {
+        definition,
+        account,
+        matched: false,
+        evaluations: Evaluations::default(),
+        trades: Vec::new(),
+        failures: Vec::new(),
+        equity_curve: vec![EquityPoint {
+            at: request.from,
+            value: initial_value,
+        }],
+        from: request.from,
+        to: request.to,
+        peak: initial_value,
+        max_drawdown: 0.0,
+    };
+
+    let tick_stock_id = match definition.trigger {
+        Trigger::Tick { stock_id } => Some(stock_id),
+        Trigger::Schedule { .. } => None,
+    };
+
+    // The run exactly at `from` counts as well.
+    let mut next_run = definition
+        .trigger
+        .next_run(request.from - chrono::Duration::nanoseconds(1));
+
+    for point in points {
+        while let Some(run) = next_run.filter(|run| *run < point.at) {
+            replay.evaluate(run);
+            replay.record(run)?;
+            next_run = definition.trigger.next_run(run);
+        }
+
+        replay.account.prices.insert(point.stock_id, point.price);
+
+        if tick_stock_id == Some(point.stock_id) {
+            replay.evaluate(point.at);
+        }
+
+        replay.record(point.at)?;
+    }
+
+    while let Some(run) = next_run.filter(|run| *run <= request.to) {
+        replay.evaluate(run);
+        replay.record(run)?;
+        next_run = definition.trigger.next_run(run);
+    }
+
+    let final_value = replay
+        .equity_curve
+        .last()
+        .map_or(initial_value, |point| point.value);
+    let total_return = match initial_value.is_positive() {
+        true => final_value.to_f64() / initial_value.to_f64() - 1.0,
+        false => 0.0,
+    };
+
+    Ok(Backtest {
+        rule_id,
+        from: request.from,
+        to: request.to,
+        initial_value,
+        final_balance: replay.account.balance,
+        final_value,
+        total_return,
+        max_drawdown: replay.max_drawdown,
+        evaluations: replay.evaluations,
+        trades: replay.trades,
+        failures: replay.failures,
+        equity_curve: replay.equity_curve,
+    })
+}
+
+/// Backtests the rule of the user over the price history of the stocks it refers to.
+pub async fn backtest(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    request: &BacktestRequest,
+) -> super::Result<Backtest> {
+    request.validate()?;
+
+    let Some(rule) = super::find_rule(conn, user_id, id).await? else {
+        return Err(Error::RuleNotFound(id));
+    };
+
+    let stock_ids = rule
+        .definition
+        .stock_ids()
+        .into_iter()
+        .chain(request.initial_shares.keys().copied())
+        .collect::<Vec<_>>();
+
+    let seed = sqlx::query_as!(
+        PricePoint,
+        r#"SELECT DISTINCT ON (stock_id) stock_id, price AS "price: Money", recorded_at AS at
+        FROM stock_prices
+        WHERE stock_id = ANY($1) AND recorded_at < $2
+        ORDER BY stock_id, recorded_at DESC, id DESC"#,
+        &stock_ids,
+        request.from
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let points = sqlx::query_as!(
+        PricePoint,
+        r#"SELECT stock_id, price AS "price: Money", recorded_at AS at
+        FROM stock_prices
+        WHERE stock_id = ANY($1) AND recorded_at BETWEEN $2 AND $3
+        ORDER BY recorded_at, id
+        LIMIT $4"#,
+        &stock_ids,
+        request.from,
+        request.to,
+        MAX_PRICE_POINTS + 1
+    )
+    .fetch_all(conn)
+    .await?;
+
+    if points.len() as i64 > MAX_PRICE_POINTS {
+        return Err(Error::InvalidBacktest(format!(
+            "the range has more than {MAX_PRICE_POINTS} prices, backtest the shorter one"
+        )));
+    }
+
+    self::replay(rule.id, &rule.definition, request, &seed, &points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 10, 12)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap()
+            + chrono::Duration::minutes(minutes)
+    }
+
+    fn points(prices: &[i64]) -> Vec<PricePoint> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(minute, price)| PricePoint {
+                stock_id: 1,
+                price: Money::from(*price),
+                at: at(minute as i64 + 1),
+            })
+            .collect()
+    }
+
+    fn request(initial_shares: BTreeMap<i32, i32>) -> BacktestRequest {
+        BacktestRequest {
+            from: at(0),
+            to: at(60),
+            initial_balance: Some(Money::from(1000)),
+            initial_shares,
+        }
+    }
+
+    #[test]
+    fn test_replay_stop_loss() {
+        let definition: Definition = serde_json::from_value(serde_json::json!({
+            "trigger": {"type": "tick", "stock_id": 1},
+            "condition": {
+                "type": "compare",
+                "left": {"type": "price", "stock_id": 1},
+                "op": "le",
+                "right": {"type": "scaled", "value": {"type": "cost_basis", "stock_id": 1}, "factor": "0.95"}
+            },
+            "action": {"type": "order", "stock_id": 1, "side": "sell", "quantity": "all"}
+        }))
+        .unwrap();
+
+        let seed = [PricePoint {
+            stock_id: 1,
+            price: Money::from(100),
+            at: at(-1),
+        }];
+
+        let backtest = replay(
+            7,
+            &definition,
+            &request(BTreeMap::from([(1, 10)])),
+            &seed,
+            &points(&[98, 94, 90, 97]),
+        )
+        .unwrap();
+
+        assert_eq!(backtest.initial_value, Money::from(2000));
+        assert_eq!(backtest.trades.len(), 1);
+        assert_eq!(
+            (backtest.trades[0].side, backtest.trades[0].quantity),
+            (OrderSide::Sell, 10)
+        );
+        assert_eq!(backtest.trades[0].value, Money::from(940));
+        assert_eq!(backtest.final_balance, Money::from(1940));
+        assert_eq!(backtest.final_value, Money::from(1940));
+        assert!((backtest.total_return + 0.03).abs() < 1e-9);
+        // From 2000 down to 1940.
+        assert!((backtest.max_drawdown - 0.03).abs() < 1e-9);
+        // Sold everything, nothing to compare the price to afterwards.
+        assert_eq!(
+            backtest.evaluations,
+            Evaluations {
+                unmatched: 3,
+                held: 0,
+                fired: 1,
+                failed: 0,
+            }
+        );
+        assert_eq!(backtest.equity_curve.len(), 5);
+    }
+
+    #[test]
+    fn test_replay_schedule() {
+        // Every 09:30, while there is the cash for it.
+        let definition: Definition = serde_json::from_value(serde_json::json!({
+            "trigger": {"type": "schedule", "time": "09:30:00"},
+            "action": {"type": "order", "stock_id": 1, "side": "buy", "quantity": {"shares": 4}}
+        }))
+        .unwrap();
+
+        let request = BacktestRequest {
+            from: at(0),
+            to: at(3 * 24 * 60),
+            ..request(BTreeMap::new())
+        };
+
+        let backtest = replay(7, &definition, &request, &[], &points(&[100, 110])).unwrap();
+
+        // Both days at the last price of 110, the third one cannot afford the 440 anymore.
+        assert_eq!(
+            backtest
+                .trades
+                .iter()
+                .map(|trade| (trade.at, trade.value))
+                .collect::<Vec<_>>(),
+            [
+                (at(30), Money::from(440)),
+                (at(24 * 60 + 30), Money::from(440)),
+            ]
+        );
+        assert_eq!(backtest.evaluations.failed, 1);
+        assert!(
+            backtest.failures[0]
+                .detail
+                .starts_with("Insufficient funds")
+        );
+        assert_eq!(backtest.final_balance, Money::from(120));
+        assert_eq!(backtest.final_value, Money::from(1000));
+    }
+
+    #[test]
+    fn test_validate_request() {
+        let valid = request(BTreeMap::new());
+        assert!(valid.validate().is_ok());
+
+        for invalid in [
+            BacktestRequest {
+                to: valid.from,
+                ..valid.clone()
+            },
+            BacktestRequest {
+                to: valid.from + MAX_RANGE + chrono::Duration::seconds(1),
+                ..valid.clone()
+            },
+            BacktestRequest {
+                initial_balance: Some(Money::from(-1)),
+                ..valid.clone()
+            },
+            BacktestRequest {
+                initial_shares: BTreeMap::from([(1, 0)]),
+                ..valid.clone()
+            },
+        ] {
+            assert!(
+                matches!(invalid.validate(), Err(Error::InvalidBacktest(_))),
+                "{invalid:?}"
+            );
+        }
+    }
+}
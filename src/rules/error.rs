@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+#[derive(thiserror::Error, Debug, Clone)]
+// NOTE: Those are shown to the client by the controllers, except the database one, so keep them free of anything sensitive.
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    #[error("Invalid rule: {0}")]
+    InvalidRule(String),
+    #[error("Rule not found: {0}")]
+    RuleNotFound(i64),
+    #[error("Invalid backtest: {0}")]
+    InvalidBacktest(String),
+    #[error("Stock not found: {0}")]
+    StockNotFound(i32),
+    #[error(transparent)]
+    Other(#[from] Arc<anyhow::Error>),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
@@ -0,0 +1,588 @@
+//! Automated trading rules of the users, see `ast` for what those are made of.
+//!
+//! The tick rules are evaluated by the market after every batch of the prices, for the stocks in it, and the
+//! scheduled ones by their own task once their time comes, see `spawn`. The rule that acts places the market order
+//! the same as `trading::place_order` would for the user. Every evaluation is recorded with its outcome, together with
+//! the order placed and the new state of the rule.
+//!
+//! NOTE: The tick rule fires once its condition starts to hold, not on every tick while it holds, otherwise the rule
+//! buying some shares below the price would keep buying them on every tick, those evaluations are `held`.
+
+pub mod ast;
+pub mod backtest;
+mod error;
+
+pub use ast::{
+    Action, Comparison, Condition, Decision, Definition, Facts, Quantity, Trigger, Value,
+};
+pub use backtest::{Backtest, BacktestRequest};
+pub use error::Error;
+
+use std::collections::{BTreeSet, HashMap};
+
+use sqlx::Acquire;
+
+use crate::{
+    database::{DatabaseConnection, types::Money},
+    trading,
+};
+
+pub(crate) type Result<T> = std::result::Result<T, self::Error>;
+
+/// How many rules the user can have, disabled ones included.
+pub const MAX_RULES: i64 = 100;
+
+pub const MAX_NAME_LENGTH: usize = 100;
+
+/// How often the scheduled rules whose time came are evaluated.
+pub const SCHEDULE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "rule_outcome", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RuleOutcome {
+    /// The condition did not hold.
+    Unmatched,
+    /// The condition still holds since the tick rule fired.
+    Held,
+    /// The order was placed.
+    Fired,
+    /// The order could not be placed, the detail tells why.
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Rule {
+    pub id: i64,
+    pub user_id: i32,
+    pub name: String,
+    pub definition: Definition,
+    pub enabled: bool,
+    /// Whether the condition held at the last evaluation.
+    pub matched: bool,
+    /// When the scheduled rule is evaluated next, None for the tick rules.
+    pub next_run_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NewRule {
+    pub name: String,
+    pub definition: Definition,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Evaluation {
+    pub id: i64,
+    pub rule_id: i64,
+    pub outcome: RuleOutcome,
+    /// The order placed, when the rule fired.
+    pub order_id: Option<i64>,
+    pub detail: Option<String>,
+    pub evaluated_at: chrono::NaiveDateTime,
+}
+
+impl NewRule {
+    pub fn validate(&self) -> self::Result<()> {
+        if self.name.trim().is_empty() || self.name.chars().count() > MAX_NAME_LENGTH {
+            return Err(Error::InvalidRule(format!(
+                "name must be between 1 and {MAX_NAME_LENGTH} characters"
+            )));
+        }
+
+        self.definition.validate().map_err(Error::InvalidRule)
+    }
+}
+
+/// Creates the rule of the user, enabled right away, every stock it refers to has to exist.
+pub async fn create_rule(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    rule: &NewRule,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Rule> {
+    rule.validate()?;
+
+    let stock_ids = rule.definition.stock_ids().into_iter().collect::<Vec<_>>();
+
+    let existing = sqlx::query_scalar!("SELECT id FROM stocks WHERE id = ANY($1)", &stock_ids)
+        .fetch_all(conn)
+        .await?;
+
+    if let Some(&missing) = stock_ids.iter().find(|id| !existing.contains(id)) {
+        return Err(Error::StockNotFound(missing));
+    }
+
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM rules WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    if count >= MAX_RULES {
+        return Err(Error::InvalidRule(format!(
+            "cannot have more than {MAX_RULES} rules"
+        )));
+    }
+
+    let (trigger, stock_id) = match rule.definition.trigger {
+        Trigger::Tick { stock_id } => ("tick", Some(stock_id)),
+        Trigger::Schedule { .. } => ("schedule", None),
+    };
+
+    Ok(sqlx::query_as!(
+        Rule,
+        r#"INSERT INTO rules (user_id, name, definition, trigger, stock_id, next_run_at, created_at, updated_at)
+        VALUES ($1, $2, $3, $4::text::rule_trigger, $5, $6, $7, $7)
+        RETURNING id, user_id, name, definition AS "definition: sqlx::types::Json<Definition>", enabled, matched,
+            next_run_at, created_at, updated_at"#,
+        user_id,
+        rule.name.trim(),
+        sqlx::types::Json(&rule.definition) as _,
+        trigger,
+        stock_id,
+        rule.definition.trigger.next_run(at),
+        at
+    )
+    .fetch_one(conn)
+    .await?)
+}
+
+/// The rules of the user, newest first.
+pub async fn list_rules(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+) -> self::Result<Vec<Rule>> {
+    Ok(sqlx::query_as!(
+        Rule,
+        r#"SELECT id, user_id, name, definition AS "definition: sqlx::types::Json<Definition>", enabled, matched,
+            next_run_at, created_at, updated_at
+        FROM rules
+        WHERE user_id = $1
+        ORDER BY id DESC"#,
+        user_id
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// The rule of the user, None when there is no such rule of that user.
+pub async fn find_rule(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+) -> self::Result<Option<Rule>> {
+    Ok(sqlx::query_as!(
+        Rule,
+        r#"SELECT id, user_id, name, definition AS "definition: sqlx::types::Json<Definition>", enabled, matched,
+            next_run_at, created_at, updated_at
+        FROM rules
+        WHERE id = $1 AND user_id = $2"#,
+        id,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+/// Enables or disables the rule of the user.
+///
+/// NOTE: The rule enabled again starts over, the tick rule fires the next time its condition holds, even if it
+/// held before, and the scheduled rule waits for its next time instead of catching up on the missed ones.
+pub async fn set_enabled(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    enabled: bool,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Rule> {
+    let mut tx = conn.begin().await?;
+
+    let Some(rule) = sqlx::query_as!(
+        Rule,
+        r#"SELECT id, user_id, name, definition AS "definition: sqlx::types::Json<Definition>", enabled, matched,
+            next_run_at, created_at, updated_at
+        FROM rules
+        WHERE id = $1 AND user_id = $2
+        FOR UPDATE"#,
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(Error::RuleNotFound(id));
+    };
+
+    if rule.enabled == enabled {
+        return Ok(rule);
+    }
+
+    let next_run_at = match enabled {
+        true => rule.definition.trigger.next_run(at),
+        false => rule.next_run_at,
+    };
+
+    let updated = sqlx::query_as!(
+        Rule,
+        r#"UPDATE rules SET enabled = $2, matched = FALSE, next_run_at = $3, updated_at = $4
+        WHERE id = $1
+        RETURNING id, user_id, name, definition AS "definition: sqlx::types::Json<Definition>", enabled, matched,
+            next_run_at, created_at, updated_at"#,
+        id,
+        enabled,
+        next_run_at,
+        at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(updated)
+}
+
+/// The evaluations of the rule of the user, newest first.
+pub async fn list_evaluations(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    limit: i64,
+) -> self::Result<Vec<Evaluation>> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM rules WHERE id = $1 AND user_id = $2) AS "exists!""#,
+        id,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    if !exists {
+        return Err(Error::RuleNotFound(id));
+    }
+
+    Ok(sqlx::query_as!(
+        Evaluation,
+        r#"SELECT id, rule_id, outcome AS "outcome: RuleOutcome", order_id, detail, evaluated_at
+        FROM rule_evaluations
+        WHERE rule_id = $1
+        ORDER BY id DESC
+        LIMIT $2"#,
+        id,
+        limit
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Evaluates the enabled tick rules of the stocks that just got their new prices.
+pub async fn evaluate_ticks(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    stock_ids: &[i32],
+    at: chrono::NaiveDateTime,
+) -> self::Result<Vec<Evaluation>> {
+    let rules = sqlx::query_as!(
+        Rule,
+        r#"SELECT id, user_id, name, definition AS "definition: sqlx::types::Json<Definition>", enabled, matched,
+            next_run_at, created_at, updated_at
+        FROM rules
+        WHERE enabled AND trigger = 'tick' AND stock_id = ANY($1)
+        ORDER BY id"#,
+        stock_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    self::evaluate(conn, &rules, at).await
+}
+
+/// Evaluates the enabled scheduled rules whose time came by `at`, each is then scheduled for its next time after it.
+///
+/// NOTE: The rule that missed several of its times, e.g. while the server was down, is evaluated once.
+pub async fn evaluate_schedules(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Vec<Evaluation>> {
+    let rules = sqlx::query_as!(
+        Rule,
+        r#"SELECT id, user_id, name, definition AS "definition: sqlx::types::Json<Definition>", enabled, matched,
+            next_run_at, created_at, updated_at
+        FROM rules
+        WHERE enabled AND trigger = 'schedule' AND next_run_at <= $1
+        ORDER BY next_run_at, id"#,
+        at
+    )
+    .fetch_all(conn)
+    .await?;
+
+    self::evaluate(conn, &rules, at).await
+}
+
+/// The facts of the users of the evaluated rules, loaded for all of them at once.
+#[derive(Debug, Default)]
+struct Snapshot {
+    prices: HashMap<i32, Money>,
+    positions: HashMap<(i32, i32), (i64, Money)>,
+    balances: HashMap<i32, Money>,
+}
+
+/// The facts of the single user in the snapshot.
+struct UserFacts<'a> {
+    snapshot: &'a Snapshot,
+    user_id: i32,
+}
+
+impl Facts for UserFacts<'_> {
+    fn price(&self, stock_id: i32) -> Option<Money> {
+        self.snapshot.prices.get(&stock_id).copied()
+    }
+
+    fn position(&self, stock_id: i32) -> Option<(i64, Money)> {
+        self.snapshot
+            .positions
+            .get(&(self.user_id, stock_id))
+            .copied()
+    }
+
+    fn balance(&self) -> Money {
+        self.snapshot
+            .balances
+            .get(&self.user_id)
+            .copied()
+            .unwrap_or(Money::ZERO)
+    }
+}
+
+impl Snapshot {
+    async fn load(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        user_ids: &[i32],
+        stock_ids: &[i32],
+    ) -> self::Result<Self> {
+        let mut conn = conn.acquire().await?;
+
+        let mut snapshot = Self {
+            prices: sqlx::query!(
+                r#"SELECT id, price AS "price: Money" FROM stocks WHERE id = ANY($1)"#,
+                stock_ids
+            )
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(|stock| (stock.id, stock.price))
+            .collect(),
+            ..Default::default()
+        };
+
+        snapshot.load_users(&mut conn, user_ids, stock_ids).await?;
+
+        Ok(snapshot)
+    }
+
+    /// Loads the balances and the positions of the users, over whatever was loaded for them before.
+    async fn load_users(
+        &mut self,
+        conn: &mut sqlx::PgConnection,
+        user_ids: &[i32],
+        stock_ids: &[i32],
+    ) -> self::Result<()> {
+        self.balances.extend(
+            sqlx::query!(
+                r#"SELECT id, balance AS "balance: Money" FROM users WHERE id = ANY($1)"#,
+                user_ids
+            )
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(|user| (user.id, user.balance)),
+        );
+
+        self.positions
+            .retain(|(user_id, _), _| !user_ids.contains(user_id));
+        self.positions.extend(
+            sqlx::query!(
+                r#"SELECT user_id, stock_id, quantity, average_cost AS "average_cost: Money"
+                FROM positions
+                WHERE user_id = ANY($1) AND stock_id = ANY($2)"#,
+                user_ids,
+                stock_ids
+            )
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(|position| {
+                (
+                    (position.user_id, position.stock_id),
+                    (position.quantity as i64, position.average_cost),
+                )
+            }),
+        );
+
+        Ok(())
+    }
+
+    fn of(&self, user_id: i32) -> UserFacts<'_> {
+        UserFacts {
+            snapshot: self,
+            user_id,
+        }
+    }
+}
+
+/// Evaluates the rules in their order, places the orders of the ones that act and records every evaluation.
+///
+/// NOTE: Every rule is claimed, evaluated and recorded in its own transaction, together with the order it placed,
+/// the rule evaluated elsewhere in the meantime, e.g. by the other instance, is skipped.
+async fn evaluate(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    rules: &[Rule],
+    at: chrono::NaiveDateTime,
+) -> self::Result<Vec<Evaluation>> {
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let user_ids = rules
+        .iter()
+        .map(|rule| rule.user_id)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let stock_ids = rules
+        .iter()
+        .flat_map(|rule| rule.definition.stock_ids())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let mut snapshot = Snapshot::load(conn, &user_ids, &stock_ids).await?;
+
+    let mut evaluations = Vec::with_capacity(rules.len());
+
+    for rule in rules {
+        let mut tx = conn.begin().await?;
+
+        // The rule as it is now, it is gone once disabled or evaluated for its time already.
+        let Some(rule) = sqlx::query_as!(
+            Rule,
+            r#"SELECT id, user_id, name, definition AS "definition: sqlx::types::Json<Definition>", enabled, matched,
+                next_run_at, created_at, updated_at
+            FROM rules
+            WHERE id = $1 AND enabled AND (trigger = 'tick' OR next_run_at <= $2)
+            FOR UPDATE SKIP LOCKED"#,
+            rule.id,
+            at
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            continue;
+        };
+
+        let decision = rule
+            .definition
+            .decide(&snapshot.of(rule.user_id), rule.matched);
+        let matched = decision.matched();
+
+        let (outcome, order_id, detail) = match decision {
+            Decision::Unmatched => (RuleOutcome::Unmatched, None, None),
+            Decision::Held => (RuleOutcome::Held, None, None),
+            Decision::Fail(detail) => (RuleOutcome::Failed, None, Some(detail)),
+            Decision::Place(order) => match self::place(&mut tx, &rule, &order).await? {
+                Ok(order_id) => {
+                    // The rules after it see what the order changed.
+                    snapshot
+                        .load_users(&mut tx, &[rule.user_id], &stock_ids)
+                        .await?;
+                    (RuleOutcome::Fired, Some(order_id), None)
+                }
+                Err(detail) => (RuleOutcome::Failed, None, Some(detail)),
+            },
+        };
+
+        sqlx::query!(
+            "UPDATE rules SET matched = $2, next_run_at = COALESCE($3, next_run_at) WHERE id = $1",
+            rule.id,
+            matched,
+            rule.definition.trigger.next_run(at)
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let evaluation = sqlx::query_as!(
+            Evaluation,
+            r#"INSERT INTO rule_evaluations (rule_id, outcome, order_id, detail, evaluated_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, rule_id, outcome AS "outcome: RuleOutcome", order_id, detail, evaluated_at"#,
+            rule.id,
+            outcome as RuleOutcome,
+            order_id,
+            detail,
+            at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        evaluations.push(evaluation);
+    }
+
+    Ok(evaluations)
+}
+
+/// Places the order of the rule within the transaction of its evaluation, returns its id, or why it could not be
+/// placed, the order that could not be placed leaves nothing behind.
+async fn place(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    rule: &Rule,
+    order: &trading::NewOrder,
+) -> self::Result<std::result::Result<i64, String>> {
+    let mut savepoint = tx.begin().await?;
+
+    match trading::place_market_order(&mut savepoint, rule.user_id, order).await {
+        Ok(order) => {
+            savepoint.commit().await?;
+            Ok(Ok(order.id))
+        }
+        // NOTE: The detail is shown to the user, those are the only trading errors that are not meant for that.
+        Err(
+            err @ (trading::Error::DatabaseError(_)
+            | trading::Error::Ledger(_)
+            | trading::Error::Other(_)),
+        ) => {
+            tracing::error!(
+                ?err,
+                rule_id = rule.id,
+                "Placing the order of the rule failed"
+            );
+            Ok(Err("Internal error".into()))
+        }
+        Err(err) => Ok(Err(err.to_string())),
+    }
+}
+
+/// Evaluates the scheduled rules every `interval` on its own task, for as long as the runtime lives.
+pub fn spawn(
+    DatabaseConnection(conn): DatabaseConnection,
+    interval: std::time::Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticks.tick().await;
+
+            match self::evaluate_schedules(&conn, chrono::Utc::now().naive_utc()).await {
+                Ok(evaluations) if !evaluations.is_empty() => {
+                    tracing::debug!("Evaluated {} scheduled rules", evaluations.len())
+                }
+                Ok(_) => {}
+                Err(err) => tracing::error!(?err, "Evaluating the scheduled rules failed"),
+            }
+        }
+    })
+}
@@ -0,0 +1,375 @@
+//! The order books of the stocks, kept in the memory of the process, the orders themselves live in the database.
+//!
+//! Every book holds the open book orders of its stock with what is left of them to fill, the bids from the highest
+//! price and the asks from the lowest, the older one first at the same price. That is the priority they are matched
+//! in, see `matching`. The book is only changed once the transaction of the database committed, so the two agree.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use super::OrderSide;
+use crate::database::types::Money;
+
+/// The order resting in the book.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BookOrder {
+    pub id: i64,
+    pub user_id: i32,
+    pub side: OrderSide,
+    pub price: Money,
+    /// What is left of the order to fill.
+    pub remaining: i32,
+}
+
+/// The resting orders at the price.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Level {
+    pub price: Money,
+    pub quantity: i64,
+    pub orders: usize,
+}
+
+/// The best levels of both sides, the best price first.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Depth {
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OrderBook {
+    bids: BTreeMap<(Reverse<Money>, i64), BookOrder>,
+    asks: BTreeMap<(Money, i64), BookOrder>,
+    /// Where the order is, by its id.
+    index: HashMap<i64, (OrderSide, Money)>,
+}
+
+impl OrderBook {
+    pub fn insert(&mut self, order: BookOrder) {
+        self.index.insert(order.id, (order.side, order.price));
+
+        match order.side {
+            OrderSide::Buy => self.bids.insert((Reverse(order.price), order.id), order),
+            OrderSide::Sell => self.asks.insert((order.price, order.id), order),
+        };
+    }
+
+    pub fn get(&self, id: i64) -> Option<&BookOrder> {
+        let &(side, price) = self.index.get(&id)?;
+
+        match side {
+            OrderSide::Buy => self.bids.get(&(Reverse(price), id)),
+            OrderSide::Sell => self.asks.get(&(price, id)),
+        }
+    }
+
+    pub fn remove(&mut self, id: i64) -> Option<BookOrder> {
+        let (side, price) = self.index.remove(&id)?;
+
+        match side {
+            OrderSide::Buy => self.bids.remove(&(Reverse(price), id)),
+            OrderSide::Sell => self.asks.remove(&(price, id)),
+        }
+    }
+
+    /// Takes the quantity off the order, which leaves the book once nothing is left of it.
+    pub fn fill(&mut self, id: i64, quantity: i32) {
+        let Some(&(side, price)) = self.index.get(&id) else {
+            return;
+        };
+
+        let order = match side {
+            OrderSide::Buy => self.bids.get_mut(&(Reverse(price), id)),
+            OrderSide::Sell => self.asks.get_mut(&(price, id)),
+        };
+
+        if let Some(order) = order {
+            order.remaining -= quantity;
+
+            if order.remaining <= 0 {
+                self.remove(id);
+            }
+        }
+    }
+
+    /// The resting orders the incoming order of the side crosses at the limit, in the priority those match in.
+    pub fn crossed_by(
+        &self,
+        side: OrderSide,
+        limit: Money,
+    ) -> Box<dyn Iterator<Item = &BookOrder> + Send + '_> {
+        match side {
+            OrderSide::Buy => {
+                Box::new(self.asks.values().take_while(move |ask| ask.price <= limit))
+            }
+            OrderSide::Sell => {
+                Box::new(self.bids.values().take_while(move |bid| bid.price >= limit))
+            }
+        }
+    }
+
+    /// The orders aggregated by the price, at most `levels` of them on each side.
+    pub fn depth(&self, levels: usize) -> Depth {
+        fn aggregate<'a>(orders: impl Iterator<Item = &'a BookOrder>, levels: usize) -> Vec<Level> {
+            let mut aggregated: Vec<Level> = Vec::new();
+
+            for order in orders {
+                if let Some(level) = aggregated.last_mut()
+                    && level.price == order.price
+                {
+                    level.quantity += order.remaining as i64;
+                    level.orders += 1;
+                } else if aggregated.len() == levels {
+                    break;
+                } else {
+                    aggregated.push(Level {
+                        price: order.price,
+                        quantity: order.remaining as i64,
+                        orders: 1,
+                    });
+                }
+            }
+
+            aggregated
+        }
+
+        Depth {
+            bids: aggregate(self.bids.values(), levels),
+            asks: aggregate(self.asks.values(), levels),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
+type Slot = Arc<tokio::sync::Mutex<Option<OrderBook>>>;
+
+/// The books of every stock, each behind its own lock, so the stocks are matched independently.
+///
+/// NOTE: The book of the stock is loaded out of the database the first time it is locked,
+/// unless it was already by `load` on startup.
+#[derive(Clone, Debug, Default)]
+pub struct OrderBooks(Arc<std::sync::Mutex<HashMap<i32, Slot>>>);
+
+/// The locked book of the stock, the matching of the stock waits for it to drop.
+pub struct BookGuard(tokio::sync::OwnedMutexGuard<Option<OrderBook>>);
+
+impl std::ops::Deref for BookGuard {
+    type Target = OrderBook;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+            .as_ref()
+            .expect("the book is loaded before it is locked")
+    }
+}
+
+impl std::ops::DerefMut for BookGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+            .as_mut()
+            .expect("the book is loaded before it is locked")
+    }
+}
+
+impl OrderBooks {
+    /// Rebuilds the books of every stock out of the open book orders.
+    pub async fn load(conn: &sqlx::Pool<sqlx::Postgres>) -> super::Result<Self> {
+        let mut books: HashMap<i32, OrderBook> = HashMap::new();
+
+        for (stock_id, order) in self::open_orders(conn, None).await? {
+            books.entry(stock_id).or_default().insert(order);
+        }
+
+        let slots = books
+            .into_iter()
+            .map(|(stock_id, book)| (stock_id, Arc::new(tokio::sync::Mutex::new(Some(book)))))
+            .collect();
+
+        Ok(Self(Arc::new(std::sync::Mutex::new(slots))))
+    }
+
+    /// Locks the book of the stock, loading it first when it was not yet.
+    pub async fn lock(
+        &self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        stock_id: i32,
+    ) -> super::Result<BookGuard> {
+        let slot = self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .entry(stock_id)
+            .or_default()
+            .clone();
+
+        let mut guard = slot.lock_owned().await;
+
+        if guard.is_none() {
+            let mut book = OrderBook::default();
+
+            for (_, order) in self::open_orders(conn, Some(stock_id)).await? {
+                book.insert(order);
+            }
+
+            *guard = Some(book);
+        }
+
+        Ok(BookGuard(guard))
+    }
+}
+
+/// The open book orders with their stock, of the one stock or of all of them.
+async fn open_orders(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    stock_id: Option<i32>,
+) -> super::Result<Vec<(i32, BookOrder)>> {
+    let rows = sqlx::query!(
+        r#"SELECT id, user_id, stock_id, side AS "side: OrderSide",
+            limit_price AS "price!: Money", quantity - filled_quantity AS "remaining!"
+        FROM orders
+        WHERE status = 'open' AND venue = 'book' AND ($1::int4 IS NULL OR stock_id = $1)
+        ORDER BY id"#,
+        stock_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.stock_id,
+                BookOrder {
+                    id: row.id,
+                    user_id: row.user_id,
+                    side: row.side,
+                    price: row.price,
+                    remaining: row.remaining,
+                },
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: i64, side: OrderSide, price: i64, remaining: i32) -> BookOrder {
+        BookOrder {
+            id,
+            user_id: 1,
+            side,
+            price: Money::from(price),
+            remaining,
+        }
+    }
+
+    fn ids<'a>(orders: impl Iterator<Item = &'a BookOrder>) -> Vec<i64> {
+        orders.map(|order| order.id).collect()
+    }
+
+    #[test]
+    fn test_price_time_priority() {
+        let mut book = OrderBook::default();
+
+        book.insert(order(1, OrderSide::Sell, 11, 5));
+        book.insert(order(2, OrderSide::Sell, 10, 5));
+        book.insert(order(3, OrderSide::Sell, 10, 5));
+        book.insert(order(4, OrderSide::Buy, 9, 5));
+        book.insert(order(5, OrderSide::Buy, 8, 5));
+        book.insert(order(6, OrderSide::Buy, 9, 5));
+
+        // The lowest ask first, the older one at the same price.
+        assert_eq!(
+            ids(book.crossed_by(OrderSide::Buy, Money::from(11))),
+            [2, 3, 1]
+        );
+        assert_eq!(
+            ids(book.crossed_by(OrderSide::Buy, Money::from(10))),
+            [2, 3]
+        );
+        assert!(
+            book.crossed_by(OrderSide::Buy, Money::from(9))
+                .next()
+                .is_none()
+        );
+
+        // The highest bid first.
+        assert_eq!(
+            ids(book.crossed_by(OrderSide::Sell, Money::from(8))),
+            [4, 6, 5]
+        );
+        assert_eq!(
+            ids(book.crossed_by(OrderSide::Sell, Money::from(9))),
+            [4, 6]
+        );
+    }
+
+    #[test]
+    fn test_partial_fills() {
+        let mut book = OrderBook::default();
+
+        book.insert(order(1, OrderSide::Sell, 10, 5));
+        book.fill(1, 2);
+        assert_eq!(book.get(1).map(|order| order.remaining), Some(3));
+
+        book.fill(1, 3);
+        assert_eq!(book.get(1), None);
+        assert!(book.is_empty());
+
+        // The unknown orders are nothing to fill nor remove.
+        book.fill(1, 1);
+        assert_eq!(book.remove(1), None);
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut book = OrderBook::default();
+
+        book.insert(order(1, OrderSide::Buy, 9, 5));
+        book.insert(order(2, OrderSide::Buy, 9, 3));
+        book.insert(order(3, OrderSide::Buy, 8, 1));
+        book.insert(order(4, OrderSide::Buy, 7, 1));
+        book.insert(order(5, OrderSide::Sell, 12, 2));
+
+        let depth = book.depth(2);
+        assert_eq!(
+            depth.bids,
+            [
+                Level {
+                    price: Money::from(9),
+                    quantity: 8,
+                    orders: 2
+                },
+                Level {
+                    price: Money::from(8),
+                    quantity: 1,
+                    orders: 1
+                },
+            ]
+        );
+        assert_eq!(
+            depth.asks,
+            [Level {
+                price: Money::from(12),
+                quantity: 2,
+                orders: 1
+            }]
+        );
+
+        book.remove(5);
+        assert_eq!(book.depth(2).asks, []);
+        assert_eq!(book.len(), 4);
+    }
+}
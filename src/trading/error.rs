@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use crate::database::types::Money;
+
+#[derive(thiserror::Error, Debug, Clone)]
+// NOTE: Those are shown to the client by the controllers, except the database one, so keep them free of anything sensitive.
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    #[error("Invalid order: {0}")]
+    InvalidOrder(String),
+    #[error("Stock not found: {0}")]
+    StockNotFound(i32),
+    #[error("Order not found: {0}")]
+    OrderNotFound(i64),
+    #[error("Order {0} is not open anymore")]
+    OrderNotOpen(i64),
+    #[error("User not found: {0}")]
+    UserNotFound(i32),
+    #[error("Invalid offer: {0}")]
+    InvalidOffer(String),
+    #[error("Offer not found: {0}")]
+    OfferNotFound(i64),
+    #[error("Offer {0} is not pending anymore")]
+    OfferNotPending(i64),
+    #[error("Offer {0} has expired")]
+    OfferExpired(i64),
+    #[error("Offer {0} is not covered by the user who made it anymore")]
+    OfferNotCovered(i64),
+    #[error("Insufficient funds, the order needs {required} but only {available} is available")]
+    InsufficientFunds { required: Money, available: Money },
+    #[error("Insufficient shares, the order sells {requested} but only {available} are available")]
+    InsufficientShares { requested: i64, available: i64 },
+    #[error(transparent)]
+    Ledger(#[from] crate::ledger::Error),
+    #[error(transparent)]
+    Other(#[from] Arc<anyhow::Error>),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
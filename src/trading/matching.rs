@@ -0,0 +1,240 @@
+//! Matching of the book orders against the book of their stock.
+//!
+//! The incoming order takes the resting orders it crosses in their priority, each at the price of the resting one,
+//! until it is filled or nothing crosses anymore, whatever is left of it rests in the book at its limit.
+//! Every match is the trade, settled for both users in the same transaction as the order was placed in.
+//!
+//! NOTE: The self-trade prevention cancels the newest: once the incoming order gets to the resting order of the
+//! same user, what is left of it is cancelled and the resting one stays, so the book is never left crossed.
+
+use super::{
+    BookOrder, Error, NewOrder, Order, OrderBooks, OrderKind, OrderSide, OrderStatus, OrderVenue,
+    Transaction,
+};
+use crate::database::types::Money;
+
+/// What happens to the book once the transaction commits.
+enum Effect {
+    Fill { id: i64, quantity: i32 },
+    Remove(i64),
+}
+
+/// Records the fill of the part of the order, the fill price becomes the average of the fills.
+async fn record_fill(
+    tx: &mut Transaction<'_>,
+    id: i64,
+    quantity: i32,
+    price: Money,
+) -> super::Result<Order> {
+    Ok(sqlx::query_as!(
+        Order,
+        r#"UPDATE orders SET
+            fill_price = ROUND(
+                (COALESCE(fill_price, 0) * filled_quantity + $3::numeric * $2::int4) / (filled_quantity + $2), 4
+            ),
+            filled_quantity = filled_quantity + $2,
+            status = CASE WHEN filled_quantity + $2 = quantity THEN 'filled' ELSE status END,
+            closed_at = CASE WHEN filled_quantity + $2 = quantity THEN CURRENT_TIMESTAMP ELSE closed_at END
+        WHERE id = $1
+        RETURNING id, user_id, stock_id, side AS "side: OrderSide", kind AS "kind: OrderKind",
+            venue AS "venue: OrderVenue", quantity, filled_quantity, limit_price AS "limit_price: Money",
+            status AS "status: OrderStatus", fill_price AS "fill_price: Money", created_at, closed_at"#,
+        id,
+        quantity,
+        price as Money
+    )
+    .fetch_one(&mut **tx)
+    .await?)
+}
+
+/// Places the book order of the user and matches it against the book of the stock.
+///
+/// The order has to be affordable at its limit, same as the open limit order. The resting order whose user cannot
+/// afford it anymore is rejected and skipped, the same as `fill_crossed_orders` does.
+pub async fn place_book_order(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    books: &OrderBooks,
+    user_id: i32,
+    order: &NewOrder,
+) -> super::Result<Order> {
+    order.validate()?;
+
+    let Some(limit) = order
+        .limit_price
+        .filter(|_| order.venue == OrderVenue::Book)
+    else {
+        return Err(Error::InvalidOrder(
+            "market order cannot be placed in the order book".into(),
+        ));
+    };
+
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM stocks WHERE id = $1) AS "exists!""#,
+        order.stock_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    if !exists {
+        return Err(Error::StockNotFound(order.stock_id));
+    }
+
+    // NOTE: The book is locked before anything in the database, otherwise the user rows locked by the
+    // transaction could wait on the book the other transaction holds.
+    let mut book = books.lock(conn, order.stock_id).await?;
+
+    let mut tx = conn.begin().await?;
+
+    let holdings = super::lock_holdings(&mut tx, user_id, order.stock_id).await?;
+    holdings.check(
+        order.side,
+        order.quantity,
+        super::value(limit, order.quantity)?,
+    )?;
+
+    let mut placed = sqlx::query_as!(
+        Order,
+        r#"INSERT INTO orders (user_id, stock_id, side, kind, venue, quantity, limit_price)
+        VALUES ($1, $2, $3, $4, 'book', $5, $6)
+        RETURNING id, user_id, stock_id, side AS "side: OrderSide", kind AS "kind: OrderKind",
+            venue AS "venue: OrderVenue", quantity, filled_quantity, limit_price AS "limit_price: Money",
+            status AS "status: OrderStatus", fill_price AS "fill_price: Money", created_at, closed_at"#,
+        user_id,
+        order.stock_id,
+        order.side as OrderSide,
+        OrderKind::Limit as OrderKind,
+        order.quantity,
+        limit as Money
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut remaining = order.quantity;
+    let mut effects = Vec::new();
+    let mut self_trade = false;
+
+    for maker in book.crossed_by(order.side, limit) {
+        if remaining == 0 {
+            break;
+        }
+
+        if maker.user_id == user_id {
+            self_trade = true;
+            break;
+        }
+
+        // The book trusts the database, the order closed in there is only dropped from the book.
+        let Some(maker_remaining) = sqlx::query_scalar!(
+            r#"SELECT quantity - filled_quantity AS "remaining!" FROM orders
+            WHERE id = $1 AND status = 'open' FOR UPDATE"#,
+            maker.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            effects.push(Effect::Remove(maker.id));
+            continue;
+        };
+
+        let quantity = remaining.min(maker_remaining);
+        let value = super::value(maker.price, quantity)?;
+
+        let maker_holdings = super::lock_holdings(&mut tx, maker.user_id, order.stock_id).await?;
+        let affordable = match maker.side {
+            OrderSide::Buy => value <= maker_holdings.balance,
+            OrderSide::Sell => quantity as i64 <= maker_holdings.shares,
+        };
+
+        if !affordable {
+            super::close(&mut tx, maker.id, OrderStatus::Rejected, None).await?;
+            effects.push(Effect::Remove(maker.id));
+            continue;
+        }
+
+        self::trade(&mut tx, &placed, maker, quantity).await?;
+
+        placed = self::record_fill(&mut tx, placed.id, quantity, maker.price).await?;
+        self::record_fill(&mut tx, maker.id, quantity, maker.price).await?;
+
+        remaining -= quantity;
+        effects.push(Effect::Fill {
+            id: maker.id,
+            quantity,
+        });
+    }
+
+    if self_trade && remaining > 0 {
+        placed = super::close(&mut tx, placed.id, OrderStatus::Cancelled, None).await?;
+    }
+
+    tx.commit().await?;
+
+    for effect in effects {
+        match effect {
+            Effect::Fill { id, quantity } => book.fill(id, quantity),
+            Effect::Remove(id) => {
+                book.remove(id);
+            }
+        }
+    }
+
+    if placed.status == OrderStatus::Open {
+        book.insert(BookOrder {
+            id: placed.id,
+            user_id,
+            side: order.side,
+            price: limit,
+            remaining,
+        });
+    }
+
+    Ok(placed)
+}
+
+/// Settles the match of the incoming order with the resting one for both users, at the price of the resting one.
+async fn trade(
+    tx: &mut Transaction<'_>,
+    taker: &Order,
+    maker: &BookOrder,
+    quantity: i32,
+) -> super::Result<()> {
+    super::settle(
+        tx,
+        taker.id,
+        taker.user_id,
+        taker.stock_id,
+        taker.side,
+        quantity,
+        maker.price,
+    )
+    .await?;
+    super::settle(
+        tx,
+        maker.id,
+        maker.user_id,
+        taker.stock_id,
+        maker.side,
+        quantity,
+        maker.price,
+    )
+    .await?;
+
+    let (buy_order_id, sell_order_id) = match taker.side {
+        OrderSide::Buy => (taker.id, maker.id),
+        OrderSide::Sell => (maker.id, taker.id),
+    };
+
+    sqlx::query!(
+        "INSERT INTO trades (stock_id, buy_order_id, sell_order_id, price, quantity)
+        VALUES ($1, $2, $3, $4, $5)",
+        taker.stock_id,
+        buy_order_id,
+        sell_order_id,
+        maker.price as Money,
+        quantity
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
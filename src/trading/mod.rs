@@ -0,0 +1,617 @@
+//! Orders of the users and their execution against the prices of the market.
+//!
+//! The market orders are filled right away at the current price of the stock. The limit orders wait in the
+//! `open` status until the price engine moves the price across their limit, see `fill_crossed_orders`.
+//! The book orders are the limit orders that trade with the other users instead, those rest in the order book
+//! of the stock and are matched with the price-time priority, see `book` and `matching`.
+//!
+//! NOTE: The funds of the open buy orders and the shares of the open sell orders are reserved, so the user
+//! cannot place more orders than it could fill. Those are not taken from the balance until the order fills.
+
+pub mod book;
+mod error;
+mod matching;
+pub mod offers;
+mod positions;
+
+pub use book::{BookOrder, Depth, Level, OrderBook, OrderBooks};
+pub use error::Error;
+pub use matching::place_book_order;
+pub use offers::{NewOffer, Offer, OfferFeed, OfferStatus};
+pub use positions::{Position, list_positions};
+
+use crate::{
+    database::types::Money,
+    ledger::{self, Account, TransactionKind},
+    market::Quote,
+};
+
+pub(crate) type Result<T> = std::result::Result<T, self::Error>;
+
+type Transaction<'a> = sqlx::Transaction<'a, sqlx::Postgres>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "order_side", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "order_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OrderKind {
+    Market,
+    Limit,
+}
+
+/// Where the order is executed, at the prices of the market, or against the orders of the other users in the book.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "order_venue", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OrderVenue {
+    #[default]
+    Market,
+    Book,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "order_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Open,
+    Filled,
+    Cancelled,
+    Rejected,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Order {
+    pub id: i64,
+    pub user_id: i32,
+    pub stock_id: i32,
+    pub side: OrderSide,
+    #[serde(rename = "type")]
+    pub kind: OrderKind,
+    pub venue: OrderVenue,
+    pub quantity: i32,
+    /// The shares filled so far, the book orders may fill in parts.
+    pub filled_quantity: i32,
+    pub limit_price: Option<Money>,
+    pub status: OrderStatus,
+    /// The average price of the fills, None until the first one.
+    pub fill_price: Option<Money>,
+    pub created_at: chrono::NaiveDateTime,
+    pub closed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NewOrder {
+    pub stock_id: i32,
+    pub side: OrderSide,
+    #[serde(rename = "type")]
+    pub kind: OrderKind,
+    #[serde(default)]
+    pub venue: OrderVenue,
+    pub quantity: i32,
+    /// Required for the limit orders, the market ones fill at whatever the price is.
+    #[serde(default)]
+    pub limit_price: Option<Money>,
+}
+
+impl NewOrder {
+    pub fn validate(&self) -> self::Result<()> {
+        if self.quantity <= 0 {
+            return Err(Error::InvalidOrder(format!(
+                "quantity must be positive, got {}",
+                self.quantity
+            )));
+        }
+
+        match (self.kind, self.limit_price) {
+            (OrderKind::Market, Some(_)) => Err(Error::InvalidOrder(
+                "market order cannot have the limit price".into(),
+            )),
+            (OrderKind::Limit, None) => Err(Error::InvalidOrder(
+                "limit order requires the limit price".into(),
+            )),
+            (OrderKind::Limit, Some(limit)) if !limit.is_positive() => Err(Error::InvalidOrder(
+                format!("limit price must be positive, got {limit}"),
+            )),
+            (OrderKind::Limit, Some(limit)) if limit.round(Money::PRICE_SCALE) != limit => {
+                Err(Error::InvalidOrder(format!(
+                    "limit price has more than {} decimal places, got {limit}",
+                    Money::PRICE_SCALE
+                )))
+            }
+            _ if self.venue == OrderVenue::Book && self.kind != OrderKind::Limit => Err(
+                Error::InvalidOrder("book order has to be the limit order".into()),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the order fills right away at the price.
+    pub fn is_crossed_at(&self, price: Money) -> bool {
+        self::is_crossed(self.side, self.limit_price, price)
+    }
+}
+
+/// The buy limit crosses when the price falls to it, the sell one when the price rises to it.
+/// The market orders are always crossed.
+pub fn is_crossed(side: OrderSide, limit_price: Option<Money>, price: Money) -> bool {
+    match (side, limit_price) {
+        (_, None) => true,
+        (OrderSide::Buy, Some(limit)) => price <= limit,
+        (OrderSide::Sell, Some(limit)) => price >= limit,
+    }
+}
+
+/// What the user can still spend and sell, with the reservations of the open orders subtracted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Holdings {
+    balance: Money,
+    available_funds: Money,
+    /// Shares of the stock in the position of the user.
+    shares: i64,
+    /// Shares left after the open sell orders.
+    available_shares: i64,
+}
+
+impl Holdings {
+    /// Whether the new order fits into what is available, `required` is what the buy order reserves.
+    fn check(&self, side: OrderSide, quantity: i32, required: Money) -> self::Result<()> {
+        match side {
+            OrderSide::Buy if required > self.available_funds => Err(Error::InsufficientFunds {
+                required,
+                available: self.available_funds.max(Money::ZERO),
+            }),
+            OrderSide::Sell if quantity as i64 > self.available_shares => {
+                Err(Error::InsufficientShares {
+                    requested: quantity as i64,
+                    available: self.available_shares.max(0),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Locks the user row, so the orders of the same user are placed and filled one at a time.
+async fn lock_holdings(
+    tx: &mut Transaction<'_>,
+    user_id: i32,
+    stock_id: i32,
+) -> self::Result<Holdings> {
+    let Some(balance) = sqlx::query_scalar!(
+        r#"SELECT balance AS "balance: Money" FROM users WHERE id = $1 FOR UPDATE"#,
+        user_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    else {
+        return Err(Error::Other(std::sync::Arc::new(anyhow::anyhow!(
+            "User {user_id} not found"
+        ))));
+    };
+
+    let reserved = sqlx::query!(
+        r#"SELECT
+            COALESCE(SUM(limit_price * (quantity - filled_quantity)) FILTER (
+                WHERE status = 'open' AND side = 'buy'
+            ), 0) AS "funds!: Money",
+            COALESCE(SUM(quantity - filled_quantity) FILTER (
+                WHERE status = 'open' AND side = 'sell' AND stock_id = $2
+            ), 0) AS "selling!"
+        FROM orders WHERE user_id = $1"#,
+        user_id,
+        stock_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    let shares = positions::held_shares(tx, user_id, stock_id).await?;
+
+    Ok(Holdings {
+        balance,
+        available_funds: balance
+            .checked_sub(reserved.funds)
+            .ok_or_else(|| Error::InvalidOrder("reserved funds overflow".into()))?,
+        shares,
+        available_shares: shares - reserved.selling,
+    })
+}
+
+/// What the shares cost at the price, in the whole cents, the half cent goes away from zero.
+fn value(price: Money, quantity: i32) -> self::Result<Money> {
+    price
+        .checked_mul(quantity as i64)
+        .map(|value| value.round(Money::CASH_SCALE))
+        .ok_or_else(|| Error::InvalidOrder(format!("{quantity} shares at {price} overflow")))
+}
+
+/// Moves the money between the balance and the position for the shares of the order filled at the price.
+async fn settle(
+    tx: &mut Transaction<'_>,
+    id: i64,
+    user_id: i32,
+    stock_id: i32,
+    side: OrderSide,
+    quantity: i32,
+    price: Money,
+) -> self::Result<()> {
+    let value = self::value(price, quantity)?;
+    let change = match side {
+        OrderSide::Buy => -value,
+        OrderSide::Sell => value,
+    };
+
+    ledger::record(
+        tx,
+        user_id,
+        TransactionKind::Fill,
+        Some(id),
+        change,
+        Account::Market,
+        &format!("{side:?} {quantity} shares at {price}"),
+    )
+    .await?;
+
+    positions::apply_fill(tx, user_id, stock_id, side, quantity, price).await
+}
+
+/// Fills the whole order at the price.
+async fn fill(
+    tx: &mut Transaction<'_>,
+    id: i64,
+    user_id: i32,
+    stock_id: i32,
+    side: OrderSide,
+    quantity: i32,
+    price: Money,
+) -> self::Result<Order> {
+    self::settle(tx, id, user_id, stock_id, side, quantity, price).await?;
+
+    self::close(tx, id, OrderStatus::Filled, Some(price)).await
+}
+
+/// Closes the order with the status, the filled part of the cancelled or rejected book order keeps its fill price.
+async fn close(
+    tx: &mut Transaction<'_>,
+    id: i64,
+    status: OrderStatus,
+    fill_price: Option<Money>,
+) -> self::Result<Order> {
+    Ok(sqlx::query_as!(
+        Order,
+        r#"UPDATE orders SET
+            status = $2,
+            fill_price = COALESCE($3, fill_price),
+            filled_quantity = CASE WHEN $2::order_status = 'filled' THEN quantity ELSE filled_quantity END,
+            closed_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        RETURNING id, user_id, stock_id, side AS "side: OrderSide", kind AS "kind: OrderKind",
+            venue AS "venue: OrderVenue", quantity, filled_quantity, limit_price AS "limit_price: Money",
+            status AS "status: OrderStatus", fill_price AS "fill_price: Money", created_at, closed_at"#,
+        id,
+        status as OrderStatus,
+        fill_price as Option<Money>
+    )
+    .fetch_one(&mut **tx)
+    .await?)
+}
+
+/// Places the order of the user, the market order and the limit order that is already crossed are filled right away.
+/// The book orders go through `place_book_order`.
+pub async fn place_order(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    order: &NewOrder,
+) -> self::Result<Order> {
+    let mut tx = conn.begin().await?;
+
+    let placed = self::place_market_order(&mut tx, user_id, order).await?;
+
+    tx.commit().await?;
+
+    Ok(placed)
+}
+
+/// Places the order the same as `place_order`, within the transaction of the caller, which commits it together with
+/// whatever else it does.
+pub(crate) async fn place_market_order(
+    tx: &mut Transaction<'_>,
+    user_id: i32,
+    order: &NewOrder,
+) -> self::Result<Order> {
+    order.validate()?;
+
+    if order.venue != OrderVenue::Market {
+        return Err(Error::InvalidOrder(
+            "book order has to be matched by the order book".into(),
+        ));
+    }
+
+    let holdings = self::lock_holdings(tx, user_id, order.stock_id).await?;
+
+    let Some(price) = sqlx::query_scalar!(
+        r#"SELECT price AS "price: Money" FROM stocks WHERE id = $1"#,
+        order.stock_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    else {
+        return Err(Error::StockNotFound(order.stock_id));
+    };
+
+    let crossed = order.is_crossed_at(price);
+
+    // The open order reserves its limit, the price it fills at is never above that.
+    let required = if crossed {
+        self::value(price, order.quantity)?
+    } else {
+        self::value(order.limit_price.unwrap_or(price), order.quantity)?
+    };
+
+    holdings.check(order.side, order.quantity, required)?;
+
+    let placed = sqlx::query_as!(
+        Order,
+        r#"INSERT INTO orders (user_id, stock_id, side, kind, quantity, limit_price)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, user_id, stock_id, side AS "side: OrderSide", kind AS "kind: OrderKind",
+            venue AS "venue: OrderVenue", quantity, filled_quantity, limit_price AS "limit_price: Money",
+            status AS "status: OrderStatus", fill_price AS "fill_price: Money", created_at, closed_at"#,
+        user_id,
+        order.stock_id,
+        order.side as OrderSide,
+        order.kind as OrderKind,
+        order.quantity,
+        order.limit_price as Option<Money>
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    if crossed {
+        self::fill(
+            tx,
+            placed.id,
+            user_id,
+            order.stock_id,
+            order.side,
+            order.quantity,
+            price,
+        )
+        .await
+    } else {
+        Ok(placed)
+    }
+}
+
+/// Cancels the open order of the user, which releases whatever it reserved, the book order leaves the book.
+pub async fn cancel_order(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    books: &OrderBooks,
+    user_id: i32,
+    id: i64,
+) -> self::Result<Order> {
+    let Some(placed) = sqlx::query!(
+        r#"SELECT stock_id, venue AS "venue: OrderVenue" FROM orders WHERE id = $1 AND user_id = $2"#,
+        id,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?
+    else {
+        return Err(Error::OrderNotFound(id));
+    };
+
+    // NOTE: The book is locked before anything in the database, the same as when matching.
+    let mut book = match placed.venue {
+        OrderVenue::Book => Some(books.lock(conn, placed.stock_id).await?),
+        OrderVenue::Market => None,
+    };
+
+    let mut tx = conn.begin().await?;
+
+    let Some(status) = sqlx::query_scalar!(
+        r#"SELECT status AS "status: OrderStatus" FROM orders WHERE id = $1 AND user_id = $2 FOR UPDATE"#,
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(Error::OrderNotFound(id));
+    };
+
+    if status != OrderStatus::Open {
+        return Err(Error::OrderNotOpen(id));
+    }
+
+    let order = self::close(&mut tx, id, OrderStatus::Cancelled, None).await?;
+
+    tx.commit().await?;
+
+    if let Some(book) = book.as_mut() {
+        book.remove(id);
+    }
+
+    Ok(order)
+}
+
+/// Orders of the user, newest first, optionally only the ones with the status.
+pub async fn list_orders(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    status: Option<OrderStatus>,
+    limit: i64,
+) -> self::Result<Vec<Order>> {
+    Ok(sqlx::query_as!(
+        Order,
+        r#"SELECT id, user_id, stock_id, side AS "side: OrderSide", kind AS "kind: OrderKind",
+            venue AS "venue: OrderVenue", quantity, filled_quantity, limit_price AS "limit_price: Money",
+            status AS "status: OrderStatus", fill_price AS "fill_price: Money", created_at, closed_at
+        FROM orders
+        WHERE user_id = $1 AND ($2::order_status IS NULL OR status = $2)
+        ORDER BY id DESC
+        LIMIT $3"#,
+        user_id,
+        status as Option<OrderStatus>,
+        limit
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Fills the open limit orders crossed by the quotes, at the price of the quote, which is the limit or better.
+/// The book orders are left alone, those only trade with the other users.
+///
+/// The orders the user cannot afford anymore are rejected, e.g. when the balance went down since placing it.
+/// Returns every order that was closed, in the order they were placed.
+pub async fn fill_crossed_orders(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    quotes: &[Quote],
+) -> self::Result<Vec<Order>> {
+    let (stock_ids, prices): (Vec<i32>, Vec<Money>) = quotes
+        .iter()
+        .map(|quote| (quote.stock_id, quote.price))
+        .unzip();
+
+    let mut tx = conn.begin().await?;
+
+    // NOTE: SKIP LOCKED, the orders being cancelled right now are not worth waiting for, the next tick gets them.
+    let crossed = sqlx::query!(
+        r#"SELECT orders.id, orders.user_id, orders.stock_id, orders.side AS "side: OrderSide",
+            orders.quantity, quotes.price AS "price!: Money"
+        FROM orders
+        JOIN UNNEST($1::int4[], $2::numeric[]) AS quotes(stock_id, price) ON quotes.stock_id = orders.stock_id
+        WHERE orders.status = 'open' AND orders.kind = 'limit' AND orders.venue = 'market' AND (
+            (orders.side = 'buy' AND quotes.price <= orders.limit_price)
+            OR (orders.side = 'sell' AND quotes.price >= orders.limit_price)
+        )
+        ORDER BY orders.id
+        FOR UPDATE OF orders SKIP LOCKED"#,
+        &stock_ids,
+        &prices as &[Money]
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut closed = Vec::with_capacity(crossed.len());
+
+    for order in crossed {
+        let holdings = self::lock_holdings(&mut tx, order.user_id, order.stock_id).await?;
+        let value = self::value(order.price, order.quantity)?;
+
+        // The order itself is among the reservations, so those are checked against what the user holds.
+        let affordable = match order.side {
+            OrderSide::Buy => value <= holdings.balance,
+            OrderSide::Sell => order.quantity as i64 <= holdings.shares,
+        };
+
+        closed.push(if affordable {
+            self::fill(
+                &mut tx,
+                order.id,
+                order.user_id,
+                order.stock_id,
+                order.side,
+                order.quantity,
+                order.price,
+            )
+            .await?
+        } else {
+            self::close(&mut tx, order.id, OrderStatus::Rejected, None).await?
+        });
+    }
+
+    tx.commit().await?;
+
+    Ok(closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(value: &str) -> Money {
+        value.parse().unwrap()
+    }
+
+    fn order(kind: OrderKind, limit_price: Option<&str>) -> NewOrder {
+        NewOrder {
+            stock_id: 1,
+            side: OrderSide::Buy,
+            kind,
+            venue: OrderVenue::Market,
+            quantity: 1,
+            limit_price: limit_price.map(money),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(order(OrderKind::Market, None).validate().is_ok());
+        assert!(order(OrderKind::Limit, Some("10.0")).validate().is_ok());
+        assert!(
+            NewOrder {
+                venue: OrderVenue::Book,
+                ..order(OrderKind::Limit, Some("10.0"))
+            }
+            .validate()
+            .is_ok()
+        );
+
+        for invalid in [
+            order(OrderKind::Market, Some("10.0")),
+            order(OrderKind::Limit, None),
+            order(OrderKind::Limit, Some("0.0")),
+            order(OrderKind::Limit, Some("-1")),
+            order(OrderKind::Limit, Some("10.00001")),
+            NewOrder {
+                quantity: 0,
+                ..order(OrderKind::Market, None)
+            },
+            NewOrder {
+                venue: OrderVenue::Book,
+                ..order(OrderKind::Market, None)
+            },
+        ] {
+            assert!(matches!(invalid.validate(), Err(Error::InvalidOrder(_))));
+        }
+    }
+
+    #[test]
+    fn test_is_crossed() {
+        assert!(is_crossed(OrderSide::Buy, None, money("100.0")));
+        assert!(is_crossed(
+            OrderSide::Buy,
+            Some(money("10.0")),
+            money("10.0")
+        ));
+        assert!(is_crossed(
+            OrderSide::Buy,
+            Some(money("10.0")),
+            money("9.0")
+        ));
+        assert!(!is_crossed(
+            OrderSide::Buy,
+            Some(money("10.0")),
+            money("10.5")
+        ));
+        assert!(is_crossed(
+            OrderSide::Sell,
+            Some(money("10.0")),
+            money("10.5")
+        ));
+        assert!(!is_crossed(
+            OrderSide::Sell,
+            Some(money("10.0")),
+            money("9.0")
+        ));
+    }
+}
@@ -0,0 +1,497 @@
+//! Trade offers, the user proposes the trade of the shares for the money directly to another user.
+//!
+//! The offer is pending until the other user accepts or rejects it, the user who made it expires it, or it runs out
+//! of time, see `expire_offers`. Every change of the offer is published to the `OfferFeed`, that is how the other
+//! user gets notified.
+//!
+//! NOTE: Nothing is reserved while the offer is pending, so both sides are checked again once it is accepted.
+//! The shares and the money of both users then move in the single transaction, or not at all.
+
+use super::{Error, OrderSide, Transaction};
+use crate::{
+    database::{DatabaseConnection, types::Money},
+    ledger::{self, Account, TransactionKind},
+};
+
+/// How long the offer is pending when not told otherwise.
+pub const DEFAULT_EXPIRES_IN: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+pub const MAX_EXPIRES_IN: std::time::Duration = std::time::Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often the offers that ran out of time are expired.
+pub const EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[sqlx(type_name = "offer_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OfferStatus {
+    Pending,
+    Accepted,
+    Rejected,
+    Expired,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Offer {
+    pub id: i64,
+    pub from_user_id: i32,
+    pub to_user_id: i32,
+    pub stock_id: i32,
+    /// The side of the user who made the offer, the sell gives the shares for the money.
+    pub side: OrderSide,
+    pub quantity: i32,
+    /// The money paid for all of the shares.
+    pub amount: Money,
+    pub status: OfferStatus,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub closed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NewOffer {
+    pub to_user_id: i32,
+    pub stock_id: i32,
+    pub side: OrderSide,
+    pub quantity: i32,
+    pub amount: Money,
+    /// Seconds until the offer expires, `DEFAULT_EXPIRES_IN` when not given.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+impl NewOffer {
+    pub fn validate(&self) -> super::Result<()> {
+        if self.quantity <= 0 {
+            return Err(Error::InvalidOffer("quantity must be positive".into()));
+        }
+
+        if !self.amount.is_positive() || self.amount.round(Money::CASH_SCALE) != self.amount {
+            return Err(Error::InvalidOffer(
+                "amount must be positive, in the whole cents".into(),
+            ));
+        }
+
+        if let Some(expires_in) = self.expires_in
+            && !(1..=MAX_EXPIRES_IN.as_secs()).contains(&expires_in)
+        {
+            return Err(Error::InvalidOffer(format!(
+                "expires_in must be between 1 and {} seconds",
+                MAX_EXPIRES_IN.as_secs()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn expires_in(&self) -> chrono::Duration {
+        chrono::Duration::seconds(
+            self.expires_in
+                .unwrap_or(DEFAULT_EXPIRES_IN.as_secs())
+                .min(MAX_EXPIRES_IN.as_secs()) as i64,
+        )
+    }
+}
+
+/// The changes of the offers, the clients of both users of the offer are notified of those.
+#[derive(Clone, Debug)]
+pub struct OfferFeed(pub tokio::sync::broadcast::Sender<Offer>);
+
+impl OfferFeed {
+    pub const CAPACITY: usize = 256;
+
+    pub fn new(capacity: usize) -> Self {
+        Self(tokio::sync::broadcast::channel(capacity).0)
+    }
+
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Offer> {
+        self.0.subscribe()
+    }
+
+    pub fn publish(&self, offers: &[Offer]) {
+        for offer in offers {
+            // NOTE: That only fails when nobody is listening, which is fine.
+            let _ = self.0.send(offer.clone());
+        }
+    }
+}
+
+impl Default for OfferFeed {
+    fn default() -> Self {
+        Self::new(Self::CAPACITY)
+    }
+}
+
+/// Makes the offer of the user to the other one, the user has to hold what it gives at the time.
+pub async fn create_offer(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    offer: &NewOffer,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Offer> {
+    offer.validate()?;
+
+    if offer.to_user_id == user_id {
+        return Err(Error::InvalidOffer("cannot trade with yourself".into()));
+    }
+
+    let mut tx = conn.begin().await?;
+
+    let holdings = super::lock_holdings(&mut tx, user_id, offer.stock_id).await?;
+
+    let exists = sqlx::query!(
+        r#"SELECT
+            EXISTS(SELECT 1 FROM users WHERE id = $1) AS "user!",
+            EXISTS(SELECT 1 FROM stocks WHERE id = $2) AS "stock!""#,
+        offer.to_user_id,
+        offer.stock_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if !exists.user {
+        return Err(Error::UserNotFound(offer.to_user_id));
+    }
+
+    if !exists.stock {
+        return Err(Error::StockNotFound(offer.stock_id));
+    }
+
+    holdings.check(offer.side, offer.quantity, offer.amount)?;
+
+    let created = sqlx::query_as!(
+        Offer,
+        r#"INSERT INTO trade_offers (from_user_id, to_user_id, stock_id, side, quantity, amount, expires_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id, from_user_id, to_user_id, stock_id, side AS "side: OrderSide", quantity, amount,
+            status AS "status: OfferStatus", expires_at, created_at, closed_at"#,
+        user_id,
+        offer.to_user_id,
+        offer.stock_id,
+        offer.side as OrderSide,
+        offer.quantity,
+        offer.amount as Money,
+        at + offer.expires_in(),
+        at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(created)
+}
+
+async fn close(
+    tx: &mut Transaction<'_>,
+    id: i64,
+    status: OfferStatus,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Offer> {
+    Ok(sqlx::query_as!(
+        Offer,
+        r#"UPDATE trade_offers SET status = $2, closed_at = $3
+        WHERE id = $1
+        RETURNING id, from_user_id, to_user_id, stock_id, side AS "side: OrderSide", quantity, amount,
+            status AS "status: OfferStatus", expires_at, created_at, closed_at"#,
+        id,
+        status as OfferStatus,
+        at
+    )
+    .fetch_one(&mut **tx)
+    .await?)
+}
+
+/// Locks the pending offer, `made` tells whether the user has to be the one who made it or the one it was made to.
+///
+/// NOTE: The offer that ran out of time is refused even when the job did not get to expiring it yet,
+/// the job does that and notifies the users.
+async fn lock_pending(
+    tx: &mut Transaction<'_>,
+    user_id: i32,
+    id: i64,
+    made: bool,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Offer> {
+    let Some(offer) = sqlx::query_as!(
+        Offer,
+        r#"SELECT id, from_user_id, to_user_id, stock_id, side AS "side: OrderSide", quantity, amount,
+            status AS "status: OfferStatus", expires_at, created_at, closed_at
+        FROM trade_offers
+        WHERE id = $1 AND (CASE WHEN $3 THEN from_user_id ELSE to_user_id END) = $2
+        FOR UPDATE"#,
+        id,
+        user_id,
+        made
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    else {
+        return Err(Error::OfferNotFound(id));
+    };
+
+    if offer.status != OfferStatus::Pending {
+        return Err(Error::OfferNotPending(id));
+    }
+
+    if offer.expires_at <= at {
+        return Err(Error::OfferExpired(id));
+    }
+
+    Ok(offer)
+}
+
+/// Accepts the offer made to the user, settling both sides, fails when either of them cannot cover it anymore.
+pub async fn accept_offer(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Offer> {
+    let mut tx = conn.begin().await?;
+
+    let offer = self::lock_pending(&mut tx, user_id, id, false, at).await?;
+    self::transfer(&mut tx, &offer).await?;
+    let accepted = self::close(&mut tx, id, OfferStatus::Accepted, at).await?;
+
+    tx.commit().await?;
+
+    Ok(accepted)
+}
+
+/// Moves the shares from the seller to the buyer of the offer, and the money the other way.
+async fn transfer(tx: &mut Transaction<'_>, offer: &Offer) -> super::Result<()> {
+    let (buyer_id, seller_id) = match offer.side {
+        OrderSide::Buy => (offer.from_user_id, offer.to_user_id),
+        OrderSide::Sell => (offer.to_user_id, offer.from_user_id),
+    };
+
+    // NOTE: The users are locked in the order of their ids, so the two offers accepted between the same users
+    // at the same time cannot wait on each other.
+    let (buyer, seller) = if buyer_id < seller_id {
+        let buyer = super::lock_holdings(tx, buyer_id, offer.stock_id).await?;
+        (
+            buyer,
+            super::lock_holdings(tx, seller_id, offer.stock_id).await?,
+        )
+    } else {
+        let seller = super::lock_holdings(tx, seller_id, offer.stock_id).await?;
+        (
+            super::lock_holdings(tx, buyer_id, offer.stock_id).await?,
+            seller,
+        )
+    };
+
+    for (user_id, holdings, side) in [
+        (buyer_id, &buyer, OrderSide::Buy),
+        (seller_id, &seller, OrderSide::Sell),
+    ] {
+        match holdings.check(side, offer.quantity, offer.amount) {
+            // The one who made the offer cannot cover it anymore, that is not up to the one accepting it.
+            Err(_) if user_id == offer.from_user_id => {
+                return Err(Error::OfferNotCovered(offer.id));
+            }
+            result => result?,
+        }
+    }
+
+    // The price the positions are moved at, the amount is what actually changes hands.
+    let price = offer
+        .amount
+        .decimal()
+        .checked_div(offer.quantity.into())
+        .map(|price| Money::from(price).round(Money::PRICE_SCALE))
+        .ok_or_else(|| Error::InvalidOffer("amount per share overflows".into()))?;
+
+    let description = format!(
+        "{} shares of the offer {}, for {}",
+        offer.quantity, offer.id, offer.amount
+    );
+
+    ledger::record(
+        tx,
+        buyer_id,
+        TransactionKind::Offer,
+        None,
+        -offer.amount,
+        Account::Peer,
+        &format!("Bought {description}"),
+    )
+    .await?;
+    ledger::record(
+        tx,
+        seller_id,
+        TransactionKind::Offer,
+        None,
+        offer.amount,
+        Account::Peer,
+        &format!("Sold {description}"),
+    )
+    .await?;
+
+    super::positions::apply_fill(
+        tx,
+        buyer_id,
+        offer.stock_id,
+        OrderSide::Buy,
+        offer.quantity,
+        price,
+    )
+    .await?;
+    super::positions::apply_fill(
+        tx,
+        seller_id,
+        offer.stock_id,
+        OrderSide::Sell,
+        offer.quantity,
+        price,
+    )
+    .await
+}
+
+/// Rejects the offer made to the user.
+pub async fn reject_offer(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Offer> {
+    self::close_pending(conn, user_id, id, false, OfferStatus::Rejected, at).await
+}
+
+/// Expires the offer the user made right away, that is how it takes the offer back.
+pub async fn expire_offer(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Offer> {
+    self::close_pending(conn, user_id, id, true, OfferStatus::Expired, at).await
+}
+
+async fn close_pending(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    made: bool,
+    status: OfferStatus,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Offer> {
+    let mut tx = conn.begin().await?;
+
+    self::lock_pending(&mut tx, user_id, id, made, at).await?;
+    let closed = self::close(&mut tx, id, status, at).await?;
+
+    tx.commit().await?;
+
+    Ok(closed)
+}
+
+/// Expires the pending offers that ran out of time by `at`, returns those.
+pub async fn expire_offers(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    at: chrono::NaiveDateTime,
+) -> super::Result<Vec<Offer>> {
+    Ok(sqlx::query_as!(
+        Offer,
+        r#"UPDATE trade_offers SET status = 'expired', closed_at = $1
+        WHERE status = 'pending' AND expires_at <= $1
+        RETURNING id, from_user_id, to_user_id, stock_id, side AS "side: OrderSide", quantity, amount,
+            status AS "status: OfferStatus", expires_at, created_at, closed_at"#,
+        at
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// The offers the user made or got, newest first, optionally only the ones with the status.
+pub async fn list_offers(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    status: Option<OfferStatus>,
+    limit: i64,
+) -> super::Result<Vec<Offer>> {
+    Ok(sqlx::query_as!(
+        Offer,
+        r#"SELECT id, from_user_id, to_user_id, stock_id, side AS "side: OrderSide", quantity, amount,
+            status AS "status: OfferStatus", expires_at, created_at, closed_at
+        FROM trade_offers
+        WHERE (from_user_id = $1 OR to_user_id = $1) AND ($2::offer_status IS NULL OR status = $2)
+        ORDER BY id DESC
+        LIMIT $3"#,
+        user_id,
+        status as Option<OfferStatus>,
+        limit
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Expires the offers every `interval` on its own task and publishes those, for as long as the runtime lives.
+pub fn spawn(
+    DatabaseConnection(conn): DatabaseConnection,
+    feed: OfferFeed,
+    interval: std::time::Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticks.tick().await;
+
+            match self::expire_offers(&conn, chrono::Utc::now().naive_utc()).await {
+                Ok(expired) => feed.publish(&expired),
+                Err(err) => tracing::error!(?err, "Expiring the trade offers failed"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_offer() {
+        let offer = NewOffer {
+            to_user_id: 2,
+            stock_id: 1,
+            side: OrderSide::Sell,
+            quantity: 100,
+            amount: Money::from(5000),
+            expires_in: None,
+        };
+
+        assert!(offer.validate().is_ok());
+        assert_eq!(offer.expires_in(), chrono::Duration::days(1));
+
+        for invalid in [
+            NewOffer {
+                quantity: 0,
+                ..offer.clone()
+            },
+            NewOffer {
+                amount: Money::ZERO,
+                ..offer.clone()
+            },
+            NewOffer {
+                amount: "0.001".parse().unwrap(),
+                ..offer.clone()
+            },
+            NewOffer {
+                expires_in: Some(0),
+                ..offer.clone()
+            },
+            NewOffer {
+                expires_in: Some(MAX_EXPIRES_IN.as_secs() + 1),
+                ..offer.clone()
+            },
+        ] {
+            assert!(
+                matches!(invalid.validate(), Err(Error::InvalidOffer(_))),
+                "{invalid:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Positions of the users, how many shares of the stock those hold and what they paid for them.
+//!
+//! Updated only by the order fills, in the same transaction, with the average cost method.
+
+use super::{OrderSide, Transaction};
+use crate::database::types::Money;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Position {
+    pub stock_id: i32,
+    pub abbreviation: String,
+    pub company: String,
+    pub quantity: i32,
+    /// Average price paid per share of the shares still held, 0 once the position is closed.
+    pub average_cost: Money,
+    /// Sum of the differences between the sell price and the average cost of the shares sold.
+    pub realized_pnl: Money,
+    /// The current price of the stock.
+    pub price: Money,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// Moves the position of the user by the filled order.
+///
+/// The average cost is rounded to the scale of the prices and the realized P&L to the cents, every fill on its own.
+///
+/// NOTE: The sell is checked against the position before it fills, the CHECK on the quantity
+/// is only the last line of defense.
+pub(super) async fn apply_fill(
+    tx: &mut Transaction<'_>,
+    user_id: i32,
+    stock_id: i32,
+    side: OrderSide,
+    quantity: i32,
+    price: Money,
+) -> super::Result<()> {
+    match side {
+        OrderSide::Buy => {
+            sqlx::query!(
+                "INSERT INTO positions (user_id, stock_id, quantity, average_cost)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id, stock_id) DO UPDATE SET
+                    average_cost = ROUND((
+                        positions.average_cost * positions.quantity
+                        + EXCLUDED.average_cost * EXCLUDED.quantity
+                    ) / (positions.quantity + EXCLUDED.quantity), 4),
+                    quantity = positions.quantity + EXCLUDED.quantity,
+                    updated_at = CURRENT_TIMESTAMP",
+                user_id,
+                stock_id,
+                quantity,
+                price as Money
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        OrderSide::Sell => {
+            sqlx::query!(
+                "UPDATE positions SET
+                    realized_pnl = realized_pnl + ROUND(($4::numeric - average_cost) * $3::int4, 2),
+                    quantity = quantity - $3::int4,
+                    average_cost = CASE WHEN quantity = $3::int4 THEN 0 ELSE average_cost END,
+                    updated_at = CURRENT_TIMESTAMP
+                WHERE user_id = $1 AND stock_id = $2",
+                user_id,
+                stock_id,
+                quantity,
+                price as Money
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shares of the stock the user holds, 0 with no position.
+pub(super) async fn held_shares(
+    tx: &mut Transaction<'_>,
+    user_id: i32,
+    stock_id: i32,
+) -> super::Result<i64> {
+    Ok(sqlx::query_scalar!(
+        "SELECT quantity FROM positions WHERE user_id = $1 AND stock_id = $2",
+        user_id,
+        stock_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .unwrap_or_default() as i64)
+}
+
+/// Positions of the user by the abbreviation of the stock, the closed ones are kept for their realized P&L.
+pub async fn list_positions(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+) -> super::Result<Vec<Position>> {
+    Ok(sqlx::query_as!(
+        Position,
+        "SELECT positions.stock_id, stocks.abbreviation, stocks.company, positions.quantity,
+            positions.average_cost, positions.realized_pnl, stocks.price, positions.updated_at
+        FROM positions
+        JOIN stocks ON stocks.id = positions.stock_id
+        WHERE positions.user_id = $1
+        ORDER BY stocks.abbreviation",
+        user_id
+    )
+    .fetch_all(conn)
+    .await?)
+}
@@ -0,0 +1,24 @@
+#[derive(thiserror::Error, Debug, Clone)]
+// NOTE: Those are shown to the client by the controllers, except the database one, so keep them free of anything sensitive.
+pub enum Error {
+    #[error(transparent)]
+    DatabaseError(#[from] crate::database::Error),
+    #[error("Invalid watchlist: {0}")]
+    InvalidWatchlist(String),
+    #[error("Watchlist not found: {0}")]
+    WatchlistNotFound(i64),
+    #[error("Watchlist named {0} already exists")]
+    DuplicateName(String),
+    #[error("Stock not found: {0}")]
+    StockNotFound(String),
+    #[error("Stock {0} is already in the watchlist")]
+    AlreadyWatched(String),
+    #[error("Stock {0} is not in the watchlist")]
+    NotWatched(String),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(crate::database::Error::from(err))
+    }
+}
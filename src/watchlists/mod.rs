@@ -0,0 +1,600 @@
+//! Watchlists of the users, the named lists of the stocks those track without owning them.
+//!
+//! Both the watchlists of the user and the stocks of the watchlist keep the order the user put them in, the positions
+//! are dense from 0, the new ones go at the end and the reorder takes the whole new order at once.
+//! The watchlist is always read with the current quotes of its stocks.
+
+mod error;
+
+pub use error::Error;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::database::types::Money;
+
+pub(crate) type Result<T> = std::result::Result<T, self::Error>;
+
+type Transaction<'a> = sqlx::Transaction<'a, sqlx::Postgres>;
+
+pub const MAX_WATCHLISTS: i64 = 50;
+
+pub const MAX_STOCKS: usize = 100;
+
+pub const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Watchlist {
+    pub id: i64,
+    pub name: String,
+    /// The place among the watchlists of the user, from 0.
+    pub position: i32,
+    /// In the order of the watchlist.
+    pub stocks: Vec<WatchedStock>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// The stock of the watchlist with its current quote.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WatchedStock {
+    pub stock_id: i32,
+    pub abbreviation: String,
+    pub company: String,
+    pub price: Money,
+    /// Percent change since the previous price.
+    pub delta: Money,
+    pub last_update: chrono::NaiveDateTime,
+    pub added_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NewWatchlist {
+    pub name: String,
+    /// The stocks to start with, in that order.
+    #[serde(default)]
+    pub tickers: Vec<String>,
+}
+
+struct WatchlistRow {
+    id: i64,
+    name: String,
+    position: i32,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+}
+
+fn validate_name(name: &str) -> self::Result<&str> {
+    let name = name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(Error::InvalidWatchlist(format!(
+            "name must be between 1 and {MAX_NAME_LENGTH} characters"
+        )));
+    }
+
+    Ok(name)
+}
+
+/// Locks the user row, so the watchlists of the same user are created and reordered one at a time.
+async fn lock_user(tx: &mut Transaction<'_>, user_id: i32) -> self::Result<()> {
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_one(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Locks the watchlist of the user, the changes of its stocks go one at a time.
+async fn lock_watchlist(tx: &mut Transaction<'_>, user_id: i32, id: i64) -> self::Result<()> {
+    sqlx::query!(
+        "SELECT id FROM watchlists WHERE id = $1 AND user_id = $2 FOR UPDATE",
+        id,
+        user_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or(Error::WatchlistNotFound(id))?;
+
+    Ok(())
+}
+
+/// The tickers are matched the way the stream subscriptions match them.
+fn normalize(ticker: &str) -> String {
+    ticker.trim().to_uppercase()
+}
+
+/// The ids of the stocks by their tickers, in the same order, each ticker at most once.
+async fn resolve_tickers(tx: &mut Transaction<'_>, tickers: &[String]) -> self::Result<Vec<i32>> {
+    let tickers = tickers
+        .iter()
+        .map(|ticker| self::normalize(ticker))
+        .collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+
+    if let Some(duplicate) = tickers.iter().find(|ticker| !seen.insert(*ticker)) {
+        return Err(Error::InvalidWatchlist(format!(
+            "{duplicate} is listed more than once"
+        )));
+    }
+
+    let stocks = sqlx::query!(
+        "SELECT id, abbreviation FROM stocks WHERE abbreviation = ANY($1)",
+        &tickers
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|stock| (stock.abbreviation, stock.id))
+    .collect::<HashMap<_, _>>();
+
+    tickers
+        .iter()
+        .map(|ticker| {
+            stocks
+                .get(ticker)
+                .copied()
+                .ok_or_else(|| Error::StockNotFound(ticker.clone()))
+        })
+        .collect()
+}
+
+async fn touch(tx: &mut Transaction<'_>, id: i64, at: chrono::NaiveDateTime) -> self::Result<()> {
+    sqlx::query!(
+        "UPDATE watchlists SET updated_at = $2 WHERE id = $1",
+        id,
+        at
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// The watchlists of the user with the quotes of their stocks, in their order, only the ones with the ids if given.
+async fn load(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    ids: Option<&[i64]>,
+) -> self::Result<Vec<Watchlist>> {
+    let rows = sqlx::query_as!(
+        WatchlistRow,
+        "SELECT id, name, position, created_at, updated_at
+        FROM watchlists
+        WHERE user_id = $1 AND ($2::int8[] IS NULL OR id = ANY($2))
+        ORDER BY position",
+        user_id,
+        ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let ids = rows.iter().map(|row| row.id).collect::<Vec<_>>();
+
+    let mut stocks = HashMap::<i64, Vec<WatchedStock>>::new();
+
+    for stock in sqlx::query!(
+        r#"SELECT
+            watchlist_stocks.watchlist_id,
+            stocks.id AS stock_id,
+            stocks.abbreviation,
+            stocks.company,
+            stocks.price AS "price: Money",
+            stocks.delta AS "delta: Money",
+            stocks.last_update,
+            watchlist_stocks.added_at
+        FROM watchlist_stocks
+        JOIN stocks ON stocks.id = watchlist_stocks.stock_id
+        WHERE watchlist_stocks.watchlist_id = ANY($1)
+        ORDER BY watchlist_stocks.watchlist_id, watchlist_stocks.position"#,
+        &ids
+    )
+    .fetch_all(conn)
+    .await?
+    {
+        stocks
+            .entry(stock.watchlist_id)
+            .or_default()
+            .push(WatchedStock {
+                stock_id: stock.stock_id,
+                abbreviation: stock.abbreviation,
+                company: stock.company,
+                price: stock.price,
+                delta: stock.delta,
+                last_update: stock.last_update,
+                added_at: stock.added_at,
+            });
+    }
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Watchlist {
+            stocks: stocks.remove(&row.id).unwrap_or_default(),
+            id: row.id,
+            name: row.name,
+            position: row.position,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+        .collect())
+}
+
+/// The watchlists of the user, in their order.
+pub async fn list_watchlists(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+) -> self::Result<Vec<Watchlist>> {
+    self::load(conn, user_id, None).await
+}
+
+pub async fn get_watchlist(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+) -> self::Result<Watchlist> {
+    self::load(conn, user_id, Some(&[id]))
+        .await?
+        .pop()
+        .ok_or(Error::WatchlistNotFound(id))
+}
+
+/// Creates the watchlist of the user after its other ones.
+pub async fn create_watchlist(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    watchlist: &NewWatchlist,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Watchlist> {
+    let name = self::validate_name(&watchlist.name)?;
+
+    if watchlist.tickers.len() > MAX_STOCKS {
+        return Err(Error::InvalidWatchlist(format!(
+            "cannot have more than {MAX_STOCKS} stocks"
+        )));
+    }
+
+    let mut tx = conn.begin().await?;
+
+    self::lock_user(&mut tx, user_id).await?;
+
+    let existing = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!", COALESCE(BOOL_OR(name = $2), FALSE) AS "named!"
+        FROM watchlists WHERE user_id = $1"#,
+        user_id,
+        name
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if existing.named {
+        return Err(Error::DuplicateName(name.to_string()));
+    }
+
+    if existing.count >= MAX_WATCHLISTS {
+        return Err(Error::InvalidWatchlist(format!(
+            "cannot have more than {MAX_WATCHLISTS} watchlists"
+        )));
+    }
+
+    let stock_ids = self::resolve_tickers(&mut tx, &watchlist.tickers).await?;
+
+    let id = sqlx::query_scalar!(
+        "INSERT INTO watchlists (user_id, name, position, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $4)
+        RETURNING id",
+        user_id,
+        name,
+        existing.count as i32,
+        at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO watchlist_stocks (watchlist_id, stock_id, position, added_at)
+        SELECT $1, stock_id, position - 1, $3
+        FROM UNNEST($2::int4[]) WITH ORDINALITY AS added(stock_id, position)",
+        id,
+        &stock_ids,
+        at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    self::get_watchlist(conn, user_id, id).await
+}
+
+pub async fn rename_watchlist(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    name: &str,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Watchlist> {
+    let name = self::validate_name(name)?;
+
+    let mut tx = conn.begin().await?;
+
+    self::lock_user(&mut tx, user_id).await?;
+    self::lock_watchlist(&mut tx, user_id, id).await?;
+
+    let taken = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM watchlists WHERE user_id = $1 AND name = $2 AND id <> $3) AS "taken!""#,
+        user_id,
+        name,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if taken {
+        return Err(Error::DuplicateName(name.to_string()));
+    }
+
+    sqlx::query!(
+        "UPDATE watchlists SET name = $2, updated_at = $3 WHERE id = $1",
+        id,
+        name,
+        at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    self::get_watchlist(conn, user_id, id).await
+}
+
+/// Deletes the watchlist of the user, the ones after it move up.
+pub async fn delete_watchlist(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+) -> self::Result<()> {
+    let mut tx = conn.begin().await?;
+
+    self::lock_user(&mut tx, user_id).await?;
+
+    let Some(position) = sqlx::query_scalar!(
+        "DELETE FROM watchlists WHERE id = $1 AND user_id = $2 RETURNING position",
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(Error::WatchlistNotFound(id));
+    };
+
+    sqlx::query!(
+        "UPDATE watchlists SET position = position - 1 WHERE user_id = $1 AND position > $2",
+        user_id,
+        position
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Puts the watchlists of the user in the order of the ids, those have to be all of its watchlists.
+pub async fn reorder_watchlists(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    ids: &[i64],
+) -> self::Result<Vec<Watchlist>> {
+    let mut tx = conn.begin().await?;
+
+    self::lock_user(&mut tx, user_id).await?;
+
+    let existing = sqlx::query_scalar!("SELECT id FROM watchlists WHERE user_id = $1", user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    if !self::is_permutation(ids, &existing) {
+        return Err(Error::InvalidWatchlist(
+            "the order must list every watchlist exactly once".into(),
+        ));
+    }
+
+    sqlx::query!(
+        "UPDATE watchlists SET position = reordered.position - 1
+        FROM UNNEST($2::int8[]) WITH ORDINALITY AS reordered(id, position)
+        WHERE watchlists.id = reordered.id AND watchlists.user_id = $1",
+        user_id,
+        ids
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    self::list_watchlists(conn, user_id).await
+}
+
+/// Adds the stock at the end of the watchlist.
+pub async fn add_ticker(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    ticker: &str,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Watchlist> {
+    let mut tx = conn.begin().await?;
+
+    self::lock_watchlist(&mut tx, user_id, id).await?;
+
+    let ticker = self::normalize(ticker);
+    let stock_id = self::resolve_tickers(&mut tx, std::slice::from_ref(&ticker)).await?[0];
+
+    let stocks = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!", COALESCE(BOOL_OR(stock_id = $2), FALSE) AS "watched!"
+        FROM watchlist_stocks WHERE watchlist_id = $1"#,
+        id,
+        stock_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if stocks.watched {
+        return Err(Error::AlreadyWatched(ticker));
+    }
+
+    if stocks.count >= MAX_STOCKS as i64 {
+        return Err(Error::InvalidWatchlist(format!(
+            "cannot have more than {MAX_STOCKS} stocks"
+        )));
+    }
+
+    sqlx::query!(
+        "INSERT INTO watchlist_stocks (watchlist_id, stock_id, position, added_at)
+        VALUES ($1, $2, $3, $4)",
+        id,
+        stock_id,
+        stocks.count as i32,
+        at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    self::touch(&mut tx, id, at).await?;
+
+    tx.commit().await?;
+
+    self::get_watchlist(conn, user_id, id).await
+}
+
+/// Removes the stock from the watchlist, the ones after it move up.
+pub async fn remove_ticker(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    ticker: &str,
+    at: chrono::NaiveDateTime,
+) -> self::Result<Watchlist> {
+    let ticker = self::normalize(ticker);
+    let mut tx = conn.begin().await?;
+
+    self::lock_watchlist(&mut tx, user_id, id).await?;
+
+    let Some(position) = sqlx::query_scalar!(
+        "DELETE FROM watchlist_stocks
+        USING stocks
+        WHERE watchlist_stocks.watchlist_id = $1
+            AND watchlist_stocks.stock_id = stocks.id
+            AND stocks.abbreviation = $2
+        RETURNING watchlist_stocks.position",
+        id,
+        ticker
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(Error::NotWatched(ticker));
+    };
+
+    sqlx::query!(
+        "UPDATE watchlist_stocks SET position = position - 1 WHERE watchlist_id = $1 AND position > $2",
+        id,
+        position
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    self::touch(&mut tx, id, at).await?;
+
+    tx.commit().await?;
+
+    self::get_watchlist(conn, user_id, id).await
+}
+
+/// Puts the stocks of the watchlist in the order of the tickers, those have to be all of its stocks.
+pub async fn reorder_tickers(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    id: i64,
+    tickers: &[String],
+    at: chrono::NaiveDateTime,
+) -> self::Result<Watchlist> {
+    let mut tx = conn.begin().await?;
+
+    self::lock_watchlist(&mut tx, user_id, id).await?;
+
+    let existing = sqlx::query_scalar!(
+        "SELECT stocks.abbreviation
+        FROM watchlist_stocks JOIN stocks ON stocks.id = watchlist_stocks.stock_id
+        WHERE watchlist_stocks.watchlist_id = $1",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let tickers = tickers
+        .iter()
+        .map(|ticker| self::normalize(ticker))
+        .collect::<Vec<_>>();
+
+    if !self::is_permutation(&tickers, &existing) {
+        return Err(Error::InvalidWatchlist(
+            "the order must list every stock of the watchlist exactly once".into(),
+        ));
+    }
+
+    let stock_ids = self::resolve_tickers(&mut tx, &tickers).await?;
+
+    sqlx::query!(
+        "UPDATE watchlist_stocks SET position = reordered.position - 1
+        FROM UNNEST($2::int4[]) WITH ORDINALITY AS reordered(stock_id, position)
+        WHERE watchlist_stocks.watchlist_id = $1 AND watchlist_stocks.stock_id = reordered.stock_id",
+        id,
+        &stock_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    self::touch(&mut tx, id, at).await?;
+
+    tx.commit().await?;
+
+    self::get_watchlist(conn, user_id, id).await
+}
+
+/// Whether the order has every one of the existing items exactly once, and nothing else.
+fn is_permutation<T: Eq + std::hash::Hash>(order: &[T], existing: &[T]) -> bool {
+    let unique = order.iter().collect::<HashSet<_>>();
+
+    order.len() == existing.len()
+        && unique.len() == order.len()
+        && existing.iter().all(|item| unique.contains(item))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_permutation() {
+        assert!(is_permutation(&[3, 1, 2], &[1, 2, 3]));
+        assert!(is_permutation::<i64>(&[], &[]));
+
+        assert!(!is_permutation(&[1, 2], &[1, 2, 3]));
+        assert!(!is_permutation(&[1, 2, 3, 4], &[1, 2, 3]));
+        assert!(!is_permutation(&[1, 1, 2], &[1, 2, 3]));
+        assert!(!is_permutation(&[1, 2, 4], &[1, 2, 3]));
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert_eq!(validate_name("  Tech  ").unwrap(), "Tech");
+
+        for invalid in ["", "   ", &"x".repeat(MAX_NAME_LENGTH + 1)] {
+            assert!(matches!(
+                validate_name(invalid),
+                Err(Error::InvalidWatchlist(_))
+            ));
+        }
+    }
+}
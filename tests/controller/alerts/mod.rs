@@ -0,0 +1,304 @@
+use axum::http::{Method, Request, StatusCode, header};
+use http_body_util::BodyExt;
+use rust_web_app::{
+    alerts,
+    database::types::Money,
+    market::{self, PriceUpdate},
+};
+
+use crate::controller::{TestRequest, TestResponse, create_session};
+
+async fn seed_stock(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('ALA', 'Alert A Inc.', '2020-01-01', 100, 0)"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn send(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    method: Method,
+    uri: &str,
+    cookie: Option<&str>,
+    body: serde_json::Value,
+) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json");
+
+    if let Some(cookie) = cookie {
+        builder = builder.header(header::COOKIE, cookie);
+    }
+
+    let TestResponse { response, .. } = TestRequest::new(pool.clone(), builder).send(body).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+
+    // The deletes answer with no content at all.
+    if body.is_empty() {
+        return Ok((status, serde_json::Value::Null));
+    }
+
+    Ok((status, serde_json::from_slice(&body)?))
+}
+
+/// Writes the price and evaluates the alerts against it, as the background task does with the published quotes.
+async fn tick(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    price: i64,
+    at: chrono::NaiveDateTime,
+) -> anyhow::Result<Vec<alerts::Notification>> {
+    let update = PriceUpdate {
+        abbreviation: "ALA".into(),
+        price: Money::from(price),
+    };
+    let quotes = market::apply_prices(pool, &[update], at).await?;
+
+    Ok(alerts::evaluate_quotes(pool, &quotes, at).await?)
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_alerts_fire_into_inbox(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stock(&pool).await?;
+    let (user_id, cookie) = create_session(&pool, "alerts@email.com").await?;
+    let cookie = Some(cookie.as_str());
+
+    let (status, once) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/alerts",
+        cookie,
+        serde_json::json!({"ticker": "ala", "metric": "price", "direction": "above", "threshold": "200"}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(once["abbreviation"], "ALA");
+    assert_eq!(once["threshold"], "200.0000");
+    assert_eq!(once["rearm"], false);
+
+    let (status, rearming) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/alerts",
+        cookie,
+        serde_json::json!({
+            "ticker": "ALA",
+            "metric": "delta",
+            "direction": "below",
+            "threshold": "-3",
+            "rearm": true,
+            "cooldown_seconds": 60,
+        }),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let now = chrono::Utc::now().naive_utc();
+
+    assert!(tick(&pool, 150, now).await?.is_empty());
+
+    let fired = tick(&pool, 210, now).await?;
+    assert_eq!(fired.len(), 1);
+    assert_eq!(fired[0].user_id, user_id);
+    assert_eq!(fired[0].alert_id, once["id"].as_i64());
+    assert_eq!(
+        fired[0].message,
+        "ALA price is above 200.0000, now at 210.0000"
+    );
+
+    // The one-shot alert is done.
+    let (_, once) = send(
+        &pool,
+        Method::GET,
+        &format!("/api/v1/me/alerts/{}", once["id"]),
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(once["enabled"], false);
+
+    // Flapping within the cooldown notifies only once.
+    let fired = tick(&pool, 100, now).await?;
+    assert_eq!(fired.len(), 1);
+    assert_eq!(fired[0].alert_id, rearming["id"].as_i64());
+    assert!(tick(&pool, 105, now).await?.is_empty());
+    assert!(tick(&pool, 50, now).await?.is_empty());
+
+    let later = now + chrono::Duration::seconds(60);
+    assert!(tick(&pool, 55, later).await?.is_empty());
+    assert_eq!(tick(&pool, 40, later).await?.len(), 1);
+
+    let (status, page) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/me/notifications?limit=2",
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["unread"], 3);
+    assert_eq!(page["notifications"].as_array().unwrap().len(), 2);
+
+    let newest = page["notifications"][0]["id"].as_i64().unwrap();
+    let cursor = page["next_cursor"].as_str().unwrap();
+
+    let (_, rest) = send(
+        &pool,
+        Method::GET,
+        &format!("/api/v1/me/notifications?limit=2&cursor={cursor}"),
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(rest["notifications"].as_array().unwrap().len(), 1);
+    assert_eq!(rest["next_cursor"], serde_json::Value::Null);
+
+    let (status, read) = send(
+        &pool,
+        Method::POST,
+        &format!("/api/v1/me/notifications/{newest}/read"),
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(read["read_at"], serde_json::Value::Null);
+
+    let (status, marked) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/notifications/read",
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(marked["marked"], 2);
+
+    let (_, page) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/me/notifications?unread=true",
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(page["unread"], 0);
+    assert_eq!(page["notifications"], serde_json::json!([]));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_alerts_are_private(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stock(&pool).await?;
+    let (_, owner) = create_session(&pool, "owner@email.com").await?;
+    let (_, other) = create_session(&pool, "other@email.com").await?;
+
+    let (status, _) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/alerts",
+        Some(&owner),
+        serde_json::json!({"ticker": "ALA", "metric": "price", "direction": "above", "threshold": "0"}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/alerts",
+        Some(&owner),
+        serde_json::json!({"ticker": "NOPE", "metric": "price", "direction": "above", "threshold": "1"}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, alert) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/alerts",
+        Some(&owner),
+        serde_json::json!({"ticker": "ALA", "metric": "price", "direction": "below", "threshold": "90"}),
+    )
+    .await?;
+    let uri = format!("/api/v1/me/alerts/{}", alert["id"]);
+
+    let (status, _) = send(
+        &pool,
+        Method::PATCH,
+        &uri,
+        Some(&other),
+        serde_json::json!({"enabled": false}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    tick(&pool, 80, chrono::Utc::now().naive_utc()).await?;
+
+    let (_, page) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/me/notifications",
+        Some(&other),
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(page["unread"], 0);
+
+    let (_, page) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/me/notifications",
+        Some(&owner),
+        serde_json::Value::Null,
+    )
+    .await?;
+    let id = page["notifications"][0]["id"].as_i64().unwrap();
+
+    let (status, _) = send(
+        &pool,
+        Method::POST,
+        &format!("/api/v1/me/notifications/{id}/read"),
+        Some(&other),
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The notification outlives the alert.
+    let (status, _) = send(
+        &pool,
+        Method::DELETE,
+        &uri,
+        Some(&owner),
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (_, page) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/me/notifications",
+        Some(&owner),
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(page["unread"], 1);
+    assert_eq!(
+        page["notifications"][0]["alert_id"],
+        serde_json::Value::Null
+    );
+
+    Ok(())
+}
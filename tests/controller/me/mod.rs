@@ -0,0 +1,342 @@
+use axum::http::{Method, Request, StatusCode, header};
+use http_body_util::BodyExt;
+use rust_web_app::{
+    database::types::Money,
+    ledger,
+    market::{self, PriceUpdate},
+    portfolio,
+    trading::{self, NewOrder, OrderKind, OrderSide, OrderVenue},
+};
+
+use crate::controller::{TestRequest, TestResponse, create_session};
+
+async fn get(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    uri: &str,
+    cookie: Option<&str>,
+) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+    let mut builder = Request::builder().method(Method::GET).uri(uri);
+
+    if let Some(cookie) = cookie {
+        builder = builder.header(header::COOKIE, cookie);
+    }
+
+    let TestResponse { response, .. } = TestRequest::new(pool.clone(), builder).send(()).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+
+    Ok((status, serde_json::from_slice(&body)?))
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_positions_follow_fills(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let stock_id = sqlx::query_scalar!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('POS', 'Positions Inc.', '2020-01-01', 100, 0) RETURNING id"
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let (user_id, cookie) = create_session(&pool, "positions@email.com").await?;
+
+    ledger::deposit(&pool, user_id, Money::from(10000)).await?;
+
+    let (status, positions) = get(&pool, "/api/v1/me/positions", Some(&cookie)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(positions, serde_json::json!([]));
+
+    let order = |side, quantity| NewOrder {
+        stock_id,
+        side,
+        kind: OrderKind::Market,
+        venue: OrderVenue::Market,
+        quantity,
+        limit_price: None,
+    };
+    let move_price = async |price| {
+        market::apply_prices(
+            &pool,
+            &[PriceUpdate {
+                abbreviation: "POS".into(),
+                price,
+            }],
+            chrono::Utc::now().naive_utc(),
+        )
+        .await
+    };
+
+    trading::place_order(&pool, user_id, &order(OrderSide::Buy, 10)).await?;
+    move_price(Money::from(120)).await?;
+    trading::place_order(&pool, user_id, &order(OrderSide::Buy, 10)).await?;
+    move_price(Money::from(130)).await?;
+    trading::place_order(&pool, user_id, &order(OrderSide::Sell, 5)).await?;
+
+    let (_, positions) = get(&pool, "/api/v1/me/positions", Some(&cookie)).await?;
+    let position = &positions[0];
+
+    assert_eq!(positions.as_array().unwrap().len(), 1);
+    assert_eq!(position["abbreviation"], "POS");
+    assert_eq!(position["quantity"], 15);
+    assert_eq!(position["average_cost"], "110.0000");
+    assert_eq!(position["realized_pnl"], "100.00");
+    assert_eq!(position["price"], "130.0000");
+
+    // The closed position stays, with what it realized.
+    move_price(Money::from(100)).await?;
+    trading::place_order(&pool, user_id, &order(OrderSide::Sell, 15)).await?;
+
+    let (_, positions) = get(&pool, "/api/v1/me/positions", Some(&cookie)).await?;
+
+    assert_eq!(positions[0]["quantity"], 0);
+    assert_eq!(positions[0]["average_cost"], "0");
+    assert_eq!(positions[0]["realized_pnl"], "-50.00");
+
+    // Cannot sell the shares of the closed position.
+    assert!(matches!(
+        trading::place_order(&pool, user_id, &order(OrderSide::Sell, 1)).await,
+        Err(trading::Error::InsufficientShares { available: 0, .. })
+    ));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_positions_require_session(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let (status, _) = get(&pool, "/api/v1/me/positions", None).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = get(&pool, "/api/v1/me/portfolio", None).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_portfolio_valuation(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let stock_id = sqlx::query_scalar!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('VAL', 'Valuation Inc.', '2020-01-01', 100, 0) RETURNING id"
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    // The price the day started with, that is the reference.
+    sqlx::query!(
+        "INSERT INTO stock_prices (stock_id, price, recorded_at)
+        VALUES ($1, 80, NOW() AT TIME ZONE 'UTC' - INTERVAL '1 day')",
+        stock_id
+    )
+    .execute(&pool)
+    .await?;
+
+    let (user_id, cookie) = create_session(&pool, "portfolio@email.com").await?;
+    let delta = async || {
+        sqlx::query_scalar!(
+            r#"SELECT delta AS "delta: Money" FROM users WHERE id = $1"#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+    };
+
+    ledger::deposit(&pool, user_id, Money::from(5000)).await?;
+
+    let (status, portfolio) = get(&pool, "/api/v1/me/portfolio", Some(&cookie)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(portfolio["total_value"], "5000.00");
+    assert_eq!(portfolio["market_value"], "0.00");
+    assert_eq!(portfolio["delta"], "0.00");
+
+    trading::place_order(
+        &pool,
+        user_id,
+        &NewOrder {
+            stock_id,
+            side: OrderSide::Buy,
+            kind: OrderKind::Market,
+            venue: OrderVenue::Market,
+            quantity: 10,
+            limit_price: None,
+        },
+    )
+    .await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let quotes = market::apply_prices(
+        &pool,
+        &[PriceUpdate {
+            abbreviation: "VAL".into(),
+            price: Money::from(110),
+        }],
+        now,
+    )
+    .await?;
+
+    assert_eq!(delta().await?, Money::ZERO);
+    assert_eq!(
+        portfolio::refresh_deltas(&pool, &[quotes[0].stock_id], now).await?,
+        1
+    );
+    // The 10 shares were worth 800 when the day started.
+    assert_eq!(delta().await?, "37.50".parse()?);
+
+    let (_, portfolio) = get(&pool, "/api/v1/me/portfolio", Some(&cookie)).await?;
+    assert_eq!(portfolio["cash"], "4000.00");
+    assert_eq!(portfolio["market_value"], "1100.00");
+    assert_eq!(portfolio["total_value"], "5100.00");
+    assert_eq!(portfolio["cost_basis"], "1000.00");
+    assert_eq!(portfolio["unrealized_pnl"], "100.00");
+    assert_eq!(portfolio["reference_value"], "800.00");
+    assert_eq!(portfolio["delta"], "37.50");
+
+    // The money coming in is not the performance, nothing changes.
+    ledger::deposit(&pool, user_id, Money::from(1000)).await?;
+    assert_eq!(portfolio::refresh_deltas(&pool, &[stock_id], now).await?, 0);
+
+    let (_, portfolio) = get(&pool, "/api/v1/me/portfolio", Some(&cookie)).await?;
+    assert_eq!(portfolio["total_value"], "6100.00");
+    assert_eq!(portfolio["delta"], "37.50");
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_performance_windows(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let (user_id, cookie) = create_session(&pool, "performance@email.com").await?;
+
+    // Doubled long ago, then up 10%, down 10% and up 10% again with the deposit on the last day.
+    for (days, total_value, net_flow) in [
+        (100, 500, 500),
+        (3, 1000, 0),
+        (2, 1100, 0),
+        (1, 990, 0),
+        (0, 1589, 500),
+    ] {
+        sqlx::query!(
+            "INSERT INTO portfolio_snapshots (user_id, day, total_value, market_value, net_flow)
+            VALUES ($1, CURRENT_DATE - $2::int4, $3, 0, $4)",
+            user_id,
+            days,
+            Money::from(total_value) as Money,
+            Money::from(net_flow) as Money
+        )
+        .execute(&pool)
+        .await?;
+    }
+
+    let close = |value: &serde_json::Value, expected: f64| {
+        (value.as_f64().unwrap() - expected).abs() < 1e-9
+    };
+
+    let (status, performance) =
+        get(&pool, "/api/v1/me/performance?window=1m", Some(&cookie)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(performance["window"], "1m");
+    assert_eq!(performance["snapshots"].as_array().unwrap().len(), 4);
+    assert!(close(
+        &performance["time_weighted_return"],
+        1.1 * 0.9 * 1.1 - 1.0
+    ));
+    assert!(close(&performance["max_drawdown"], 0.1));
+    assert!(performance["volatility"].as_f64().unwrap() > 0.0);
+    assert!(performance["sharpe_ratio"].as_f64().unwrap() > 0.0);
+
+    let (_, performance) = get(&pool, "/api/v1/me/performance?window=all", Some(&cookie)).await?;
+    assert_eq!(performance["snapshots"].as_array().unwrap().len(), 5);
+    assert!(close(
+        &performance["time_weighted_return"],
+        2.0 * 1.1 * 0.9 * 1.1 - 1.0
+    ));
+
+    let (status, _) = get(&pool, "/api/v1/me/performance?window=2w", Some(&cookie)).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Nothing to measure yet.
+    let (_, other) = create_session(&pool, "empty@email.com").await?;
+    let (_, performance) = get(&pool, "/api/v1/me/performance", Some(&other)).await?;
+    assert_eq!(performance["window"], "1y");
+    assert_eq!(performance["time_weighted_return"], 0.0);
+    assert_eq!(performance["sharpe_ratio"], serde_json::Value::Null);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_transactions_pages(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let stock_id = sqlx::query_scalar!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('TXN', 'Transactions Inc.', '2020-01-01', 50, 0) RETURNING id"
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let (user_id, cookie) = create_session(&pool, "transactions@email.com").await?;
+    let (_, other) = create_session(&pool, "other@email.com").await?;
+
+    ledger::deposit(&pool, user_id, Money::from(1000)).await?;
+    ledger::adjust(&pool, user_id, Money::from(-100), "Correction").await?;
+    let order = trading::place_order(
+        &pool,
+        user_id,
+        &NewOrder {
+            stock_id,
+            side: OrderSide::Buy,
+            kind: OrderKind::Market,
+            venue: OrderVenue::Market,
+            quantity: 4,
+            limit_price: None,
+        },
+    )
+    .await?;
+
+    let (status, page) = get(&pool, "/api/v1/me/transactions?limit=2", Some(&cookie)).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let transactions = page["transactions"].as_array().unwrap();
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0]["type"], "fill");
+    assert_eq!(transactions[0]["order_id"], order.id);
+    assert_eq!(transactions[0]["amount"], "-200.00");
+    assert_eq!(transactions[0]["account"], "market");
+    assert_eq!(transactions[1]["type"], "adjustment");
+    assert_eq!(transactions[1]["amount"], "-100.00");
+
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let (_, page) = get(
+        &pool,
+        &format!("/api/v1/me/transactions?limit=2&cursor={cursor}"),
+        Some(&cookie),
+    )
+    .await?;
+
+    assert_eq!(page["transactions"][0]["type"], "deposit");
+    assert_eq!(page["transactions"][0]["amount"], "1000.00");
+    assert_eq!(page["transactions"][0]["account"], "external");
+    assert!(page["next_cursor"].is_null());
+
+    // Only the transactions of the user.
+    let (_, page) = get(&pool, "/api/v1/me/transactions", Some(&other)).await?;
+    assert_eq!(page["transactions"], serde_json::json!([]));
+
+    for uri in [
+        "/api/v1/me/transactions?limit=0",
+        "/api/v1/me/transactions?cursor=abc",
+    ] {
+        assert_eq!(
+            get(&pool, uri, Some(&cookie)).await?.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    assert_eq!(
+        get(&pool, "/api/v1/me/transactions", None).await?.0,
+        StatusCode::UNAUTHORIZED
+    );
+
+    Ok(())
+}
@@ -1,3 +1,136 @@
 //! Controller module tests.
 
+mod alerts;
 mod auth;
+mod me;
+mod offers;
+mod orders;
+mod rules;
+mod stocks;
+mod stream;
+mod watchlists;
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{self, request::Builder},
+};
+use rust_web_app::{AppState, controller::auth::csrf};
+use tower::ServiceExt;
+
+/// Request shared across the controller tests, the builder is public so each test can modify it.
+#[derive(Debug)]
+pub(crate) struct TestRequest {
+    pub(crate) pool: sqlx::Pool<sqlx::Postgres>,
+    pub(crate) builder: Builder,
+}
+
+#[derive(Debug)]
+pub(crate) struct TestResponse {
+    // pool: sqlx::Pool<sqlx::Postgres>,
+    // app: Router,
+    pub(crate) response: http::Response<Body>,
+    pub(crate) error: Option<rust_web_app::Error>,
+}
+
+impl TestRequest {
+    pub(crate) fn new(pool: sqlx::Pool<sqlx::Postgres>, builder: Builder) -> Self {
+        Self { pool, builder }
+    }
+
+    pub(crate) async fn send<T>(self, payload: T) -> anyhow::Result<TestResponse>
+    where
+        T: serde::Serialize,
+    {
+        // NOTE: Maybe we should return that router.
+        let app = rust_web_app::app(AppState::new(self.pool.clone())).await?;
+
+        let mut builder = self.builder;
+
+        // Sends the CSRF token of the session along, as the frontend does, unless the test set its own.
+        if let Some(headers) = builder.headers_ref()
+            && !headers.contains_key(csrf::HEADER)
+            && let Some(cookie) = headers.get(http::header::COOKIE)
+            && let Some(token) = csrf_token(&self.pool, cookie.to_str()?).await?
+        {
+            builder = builder.header(csrf::HEADER, token);
+        }
+
+        let request = builder.body(Body::from(serde_json::to_string(&payload)?))?;
+
+        // This would give you the response after serialization.
+        let response = app.oneshot(request).await?;
+        let error = response
+            .extensions()
+            .get::<Arc<rust_web_app::Error>>()
+            // We can afford that clone when testing.
+            .map(|e| e.as_ref().clone());
+
+        Ok(TestResponse { response, error })
+    }
+}
+
+/// The CSRF token of the session in the `Cookie` header, None when there is no such session.
+pub(crate) async fn csrf_token(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cookie: &str,
+) -> anyhow::Result<Option<String>> {
+    let ssid = cookie
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == rust_web_app::controller::cookies::SSID)
+        .and_then(|(_, ssid)| sqlx::types::Uuid::parse_str(ssid).ok());
+
+    let Some(ssid) = ssid else {
+        return Ok(None);
+    };
+
+    Ok(
+        sqlx::query_scalar!("SELECT csrf_token FROM sessions WHERE id = $1", ssid)
+            .fetch_optional(pool)
+            .await?
+            .map(|token| token.to_string()),
+    )
+}
+
+/// Serves the app on the random local port, for the tests that need the real connection like the WebSocket ones.
+pub(crate) async fn serve(state: AppState) -> anyhow::Result<std::net::SocketAddr> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let app = rust_web_app::app(state).await?;
+
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    Ok(addr)
+}
+
+/// Registers the user with the session directly in the database, returns the user id and the `SSID` cookie.
+pub(crate) async fn create_session(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    email: &str,
+) -> anyhow::Result<(i32, String)> {
+    let account_id = sqlx::query_scalar!("INSERT INTO accounts DEFAULT VALUES RETURNING id")
+        .fetch_one(pool)
+        .await?;
+
+    let user_id = sqlx::query_scalar!(
+        "INSERT INTO users (email, password_hash, account_id) VALUES ($1, $2, $3) RETURNING id",
+        email,
+        rust_web_app::controller::auth::hash_password("Password1!")?,
+        account_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let ssid = sqlx::query_scalar!(
+        "INSERT INTO sessions (user_id) VALUES ($1) RETURNING id",
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let cookie = rust_web_app::controller::auth::create_ssid_cookie(ssid)?;
+
+    Ok((user_id, format!("{}={}", cookie.name(), cookie.value())))
+}
@@ -0,0 +1,323 @@
+use axum::http::{Method, Request, StatusCode, header};
+use http_body_util::BodyExt;
+use rust_web_app::{
+    database::types::Money,
+    ledger,
+    trading::{self, Error, OfferStatus, offers},
+};
+
+use crate::controller::{TestRequest, TestResponse, create_session};
+
+async fn seed_stock(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('OFR', 'Offers Inc.', '2020-01-01', 100, 0) RETURNING id"
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+async fn balance(pool: &sqlx::Pool<sqlx::Postgres>, user_id: i32) -> anyhow::Result<String> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT balance::text AS "balance!" FROM users WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+async fn shares(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    stock_id: i32,
+) -> anyhow::Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "SELECT quantity FROM positions WHERE user_id = $1 AND stock_id = $2",
+        user_id,
+        stock_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or_default())
+}
+
+async fn send(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    method: Method,
+    uri: &str,
+    cookie: &str,
+    body: serde_json::Value,
+) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+    let request = TestRequest::new(
+        pool.clone(),
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, cookie)
+            .header(header::CONTENT_TYPE, "application/json"),
+    );
+
+    let TestResponse { response, .. } = request.send(body).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+
+    Ok((status, serde_json::from_slice(&body)?))
+}
+
+/// Acts on the offer, e.g. accepts it.
+async fn act(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cookie: &str,
+    offer: &serde_json::Value,
+    action: &str,
+) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+    let uri = format!("/api/v1/offers/{}/{action}", offer["id"]);
+
+    send(pool, Method::POST, &uri, cookie, serde_json::Value::Null).await
+}
+
+/// The seller with 10 shares bought at 100 and nothing else, the buyer with 1000 of the balance.
+async fn seed_users(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    stock_id: i32,
+) -> anyhow::Result<((i32, String), (i32, String))> {
+    let (seller_id, seller) = create_session(pool, "seller@email.com").await?;
+    let (buyer_id, buyer) = create_session(pool, "buyer@email.com").await?;
+    ledger::deposit(pool, seller_id, Money::from(1000)).await?;
+    ledger::deposit(pool, buyer_id, Money::from(1000)).await?;
+
+    let (status, _) = send(
+        pool,
+        Method::POST,
+        "/api/v1/orders",
+        &seller,
+        serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "market", "quantity": 10}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+
+    Ok(((seller_id, seller), (buyer_id, buyer)))
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_accepted_offer_settles_both_sides(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let ((seller_id, seller), (buyer_id, buyer)) = seed_users(&pool, stock_id).await?;
+
+    let new_offer = |to_user_id: i32, quantity: i32, amount: &str| {
+        serde_json::json!({
+            "to_user_id": to_user_id,
+            "stock_id": stock_id,
+            "side": "sell",
+            "quantity": quantity,
+            "amount": amount,
+        })
+    };
+
+    for (offer, expected) in [
+        (new_offer(seller_id, 5, "600"), StatusCode::BAD_REQUEST),
+        (new_offer(buyer_id, 5, "0.001"), StatusCode::BAD_REQUEST),
+        (new_offer(999999, 5, "600"), StatusCode::NOT_FOUND),
+        // Cannot offer more shares than it holds.
+        (
+            new_offer(buyer_id, 11, "600"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+    ] {
+        let (status, _) = send(&pool, Method::POST, "/api/v1/offers", &seller, offer).await?;
+        assert_eq!(status, expected);
+    }
+
+    let (status, offer) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/offers",
+        &seller,
+        new_offer(buyer_id, 5, "600"),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(offer["status"], "pending");
+    assert_eq!(offer["amount"], "600.00");
+
+    // Both users see it.
+    for cookie in [&seller, &buyer] {
+        let (status, list) = send(
+            &pool,
+            Method::GET,
+            "/api/v1/offers?status=pending",
+            cookie,
+            serde_json::Value::Null,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list[0]["id"], offer["id"]);
+    }
+
+    // Only the user it was made to accepts it.
+    let (status, _) = act(&pool, &seller, &offer, "accept").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, accepted) = act(&pool, &buyer, &offer, "accept").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(accepted["status"], "accepted");
+    assert!(!accepted["closed_at"].is_null());
+
+    assert_eq!(balance(&pool, seller_id).await?, "600.00");
+    assert_eq!(balance(&pool, buyer_id).await?, "400.00");
+    assert_eq!(shares(&pool, seller_id, stock_id).await?, 5);
+    assert_eq!(shares(&pool, buyer_id, stock_id).await?, 5);
+
+    let realized_pnl = sqlx::query_scalar!(
+        r#"SELECT realized_pnl AS "realized_pnl: Money" FROM positions WHERE user_id = $1"#,
+        seller_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(realized_pnl, Money::from(100));
+
+    let (status, _) = act(&pool, &buyer, &offer, "accept").await?;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // The money only moved between the two users.
+    let peer = sqlx::query_scalar!(
+        r#"SELECT SUM(amount) AS "sum: Money" FROM ledger_entries WHERE account = 'peer'"#
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(peer.map(|sum| sum.is_zero()), Some(true));
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_offer_fails_cleanly_when_not_covered(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let ((seller_id, seller), (buyer_id, buyer)) = seed_users(&pool, stock_id).await?;
+
+    let offer_of = async |cookie: &str, to_user_id: i32, side: &str, amount: &str| {
+        let (status, offer) = send(
+            &pool,
+            Method::POST,
+            "/api/v1/offers",
+            cookie,
+            serde_json::json!({
+                "to_user_id": to_user_id,
+                "stock_id": stock_id,
+                "side": side,
+                "quantity": 5,
+                "amount": amount,
+            }),
+        )
+        .await?;
+        assert_eq!(status, StatusCode::CREATED);
+
+        anyhow::Ok(offer)
+    };
+
+    // More than the buyer has.
+    let expensive = offer_of(&seller, buyer_id, "sell", "5000").await?;
+    let (status, body) = act(&pool, &buyer, &expensive, "accept").await?;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(
+        body["message"]
+            .as_str()
+            .unwrap()
+            .contains("Insufficient funds")
+    );
+
+    // The shares of the offer are not reserved, the seller sells them in the meantime.
+    let offer = offer_of(&seller, buyer_id, "sell", "600").await?;
+    let (status, _) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/orders",
+        &seller,
+        serde_json::json!({"stock_id": stock_id, "side": "sell", "type": "market", "quantity": 10}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, body) = act(&pool, &buyer, &offer, "accept").await?;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body["message"].as_str().unwrap().contains("not covered"));
+
+    // Nothing moved, the offer is still there to reject.
+    assert_eq!(balance(&pool, buyer_id).await?, "1000.00");
+    assert_eq!(balance(&pool, seller_id).await?, "1000.00");
+    assert_eq!(shares(&pool, buyer_id, stock_id).await?, 0);
+
+    let (status, _) = act(&pool, &seller, &offer, "reject").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, rejected) = act(&pool, &buyer, &offer, "reject").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(rejected["status"], "rejected");
+
+    // The one who made the offer takes it back by expiring it.
+    let bid = offer_of(&buyer, seller_id, "buy", "400").await?;
+    let (status, _) = act(&pool, &seller, &bid, "expire").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, expired) = act(&pool, &buyer, &bid, "expire").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(expired["status"], "expired");
+    let (status, _) = act(&pool, &seller, &bid, "accept").await?;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_offers_run_out_of_time(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let ((seller_id, _), (buyer_id, _)) = seed_users(&pool, stock_id).await?;
+
+    // The database keeps the microseconds.
+    let now = chrono::SubsecRound::trunc_subsecs(chrono::Utc::now().naive_utc(), 6);
+    let offer = offers::create_offer(
+        &pool,
+        buyer_id,
+        &trading::NewOffer {
+            to_user_id: seller_id,
+            stock_id,
+            side: trading::OrderSide::Buy,
+            quantity: 1,
+            amount: Money::from(100),
+            expires_in: Some(60),
+        },
+        now,
+    )
+    .await?;
+    assert_eq!(offer.expires_at, now + chrono::Duration::seconds(60));
+
+    let later = now + chrono::Duration::seconds(60);
+
+    // Refused once the time is up, even before the job expires it.
+    assert!(matches!(
+        offers::accept_offer(&pool, seller_id, offer.id, later).await,
+        Err(Error::OfferExpired(id)) if id == offer.id
+    ));
+
+    assert!(offers::expire_offers(&pool, now).await?.is_empty());
+
+    let expired = offers::expire_offers(&pool, later).await?;
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].status, OfferStatus::Expired);
+    assert_eq!(expired[0].closed_at, Some(later));
+
+    assert!(matches!(
+        offers::accept_offer(&pool, seller_id, offer.id, now).await,
+        Err(Error::OfferNotPending(_))
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,526 @@
+use axum::http::{Method, Request, StatusCode, header};
+use http_body_util::BodyExt;
+use rust_web_app::{
+    database::types::Money,
+    ledger,
+    market::{self, PriceUpdate},
+    trading::{self, NewOrder, OrderBooks, OrderKind, OrderSide, OrderStatus, OrderVenue},
+};
+
+use crate::controller::{TestRequest, TestResponse, create_session};
+
+/// Stock with the round price, so the expected balances are easy to follow.
+async fn seed_stock(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('ORD', 'Orders Inc.', '2020-01-01', 100, 0) RETURNING id"
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// As the text, so the scale of the column is asserted as well.
+async fn balance(pool: &sqlx::Pool<sqlx::Postgres>, user_id: i32) -> anyhow::Result<String> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT balance::text AS "balance!" FROM users WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+async fn send(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    method: Method,
+    uri: &str,
+    cookie: &str,
+    body: serde_json::Value,
+) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+    let request = TestRequest::new(
+        pool.clone(),
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, cookie)
+            .header(header::CONTENT_TYPE, "application/json"),
+    );
+
+    let TestResponse { response, .. } = request.send(body).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+
+    Ok((status, serde_json::from_slice(&body)?))
+}
+
+async fn place(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cookie: &str,
+    order: serde_json::Value,
+) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+    send(pool, Method::POST, "/api/v1/orders", cookie, order).await
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_market_orders_move_balance(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let (user_id, cookie) = create_session(&pool, "orders@email.com").await?;
+
+    let buy =
+        serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "market", "quantity": 10});
+
+    // The new user has nothing to spend.
+    let (status, body) = place(&pool, &cookie, buy.clone()).await?;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(
+        body["message"]
+            .as_str()
+            .unwrap()
+            .contains("Insufficient funds")
+    );
+
+    ledger::deposit(&pool, user_id, Money::from(1500)).await?;
+
+    let (status, order) = place(&pool, &cookie, buy).await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(order["status"], "filled");
+    assert_eq!(order["fill_price"], "100.0000");
+    assert_eq!(order["type"], "market");
+    assert_eq!(balance(&pool, user_id).await?, "500.00");
+
+    // Cannot sell more than was bought.
+    let (status, body) = place(
+        &pool,
+        &cookie,
+        serde_json::json!({"stock_id": stock_id, "side": "sell", "type": "market", "quantity": 11}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(
+        body["message"]
+            .as_str()
+            .unwrap()
+            .contains("Insufficient shares")
+    );
+
+    let (status, order) = place(
+        &pool,
+        &cookie,
+        serde_json::json!({"stock_id": stock_id, "side": "sell", "type": "market", "quantity": 4}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(order["status"], "filled");
+    assert_eq!(balance(&pool, user_id).await?, "900.00");
+
+    // Invalid orders and unknown stocks.
+    for (order, expected) in [
+        (
+            serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "limit", "quantity": 1}),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "market", "quantity": 0}),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            serde_json::json!({"stock_id": stock_id, "side": "hold", "type": "market", "quantity": 1}),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            serde_json::json!({"stock_id": i32::MAX, "side": "buy", "type": "market", "quantity": 1}),
+            StatusCode::NOT_FOUND,
+        ),
+    ] {
+        assert_eq!(place(&pool, &cookie, order).await?.0, expected);
+    }
+
+    // Without the session.
+    let TestResponse { response, .. } = TestRequest::new(
+        pool.clone(),
+        Request::builder().method(Method::GET).uri("/api/v1/orders"),
+    )
+    .send(())
+    .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let (status, orders) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/orders",
+        &cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        orders
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|order| order["side"].as_str().unwrap())
+            .collect::<Vec<_>>(),
+        ["sell", "buy"]
+    );
+
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_limit_orders_fill_when_crossed(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let (user_id, cookie) = create_session(&pool, "limit@email.com").await?;
+    ledger::deposit(&pool, user_id, Money::from(1000)).await?;
+
+    let (status, order) = place(
+        &pool,
+        &cookie,
+        serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "limit", "quantity": 10, "limit_price": 90.0}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(order["status"], "open");
+
+    // The open order reserves 900 of the 1000.
+    let (status, _) = place(
+        &pool,
+        &cookie,
+        serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "market", "quantity": 2}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(balance(&pool, user_id).await?, "1000.00");
+
+    let (status, orders) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/orders?status=open",
+        &cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(orders.as_array().unwrap().len(), 1);
+
+    let update = |price: &str| PriceUpdate {
+        abbreviation: "ORD".into(),
+        price: price.parse().unwrap(),
+    };
+    let now = chrono::Utc::now().naive_utc();
+
+    // Not crossed yet.
+    let quotes = market::apply_prices(&pool, &[update("95")], now).await?;
+    assert!(
+        trading::fill_crossed_orders(&pool, &quotes)
+            .await?
+            .is_empty()
+    );
+
+    // Crossed, filled at the price of the quote rather than the limit.
+    let quotes = market::apply_prices(&pool, &[update("85")], now).await?;
+    let filled = trading::fill_crossed_orders(&pool, &quotes).await?;
+
+    assert_eq!(filled.len(), 1);
+    assert_eq!(filled[0].id, order["id"].as_i64().unwrap());
+    assert_eq!(filled[0].status, OrderStatus::Filled);
+    assert_eq!(filled[0].fill_price, Some(Money::from(85)));
+    assert_eq!(balance(&pool, user_id).await?, "150.00");
+
+    // The limit sell above the price waits, and is rejected if the shares are gone by the time it crosses.
+    let (_, sell) = place(
+        &pool,
+        &cookie,
+        serde_json::json!({"stock_id": stock_id, "side": "sell", "type": "limit", "quantity": 10, "limit_price": 120.0}),
+    )
+    .await?;
+    assert_eq!(sell["status"], "open");
+
+    sqlx::query!(
+        "UPDATE positions SET quantity = 1 WHERE user_id = $1",
+        user_id
+    )
+    .execute(&pool)
+    .await?;
+
+    let quotes = market::apply_prices(&pool, &[update("130")], now).await?;
+    let closed = trading::fill_crossed_orders(&pool, &quotes).await?;
+
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].status, OrderStatus::Rejected);
+    assert_eq!(balance(&pool, user_id).await?, "150.00");
+
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_cancel_order(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let (user_id, cookie) = create_session(&pool, "cancel@email.com").await?;
+    let (_, other) = create_session(&pool, "other@email.com").await?;
+    ledger::deposit(&pool, user_id, Money::from(1000)).await?;
+
+    let (_, order) = place(
+        &pool,
+        &cookie,
+        serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "limit", "quantity": 10, "limit_price": 50.0}),
+    )
+    .await?;
+    let uri = format!("/api/v1/orders/{}", order["id"]);
+
+    // Someone else's order does not exist for them.
+    let (status, _) = send(&pool, Method::DELETE, &uri, &other, serde_json::Value::Null).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, cancelled) = send(
+        &pool,
+        Method::DELETE,
+        &uri,
+        &cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cancelled["status"], "cancelled");
+    assert!(!cancelled["closed_at"].is_null());
+
+    let (status, _) = send(
+        &pool,
+        Method::DELETE,
+        &uri,
+        &cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = send(
+        &pool,
+        Method::DELETE,
+        "/api/v1/orders/abc",
+        &cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The reservation is released, the whole balance can be spent again.
+    let (status, _) = place(
+        &pool,
+        &cookie,
+        serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "market", "quantity": 10}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(balance(&pool, user_id).await?, "0.00");
+
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_book_orders_match_between_users(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let (seller_id, seller) = create_session(&pool, "seller@email.com").await?;
+    let (buyer_id, buyer) = create_session(&pool, "buyer@email.com").await?;
+    ledger::deposit(&pool, seller_id, Money::from(1000)).await?;
+    ledger::deposit(&pool, buyer_id, Money::from(2000)).await?;
+
+    let (status, _) = place(
+        &pool,
+        &seller,
+        serde_json::json!({"stock_id": stock_id, "side": "buy", "type": "market", "quantity": 10}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let book_order = |side: &str, quantity: i32, limit_price: &str| {
+        serde_json::json!({
+            "stock_id": stock_id,
+            "side": side,
+            "type": "limit",
+            "venue": "book",
+            "quantity": quantity,
+            "limit_price": limit_price,
+        })
+    };
+
+    // The book order has to be the limit one.
+    let (status, _) = place(
+        &pool,
+        &seller,
+        serde_json::json!({"stock_id": stock_id, "side": "sell", "type": "market", "venue": "book", "quantity": 1}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, first) = place(&pool, &seller, book_order("sell", 4, "101")).await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(first["status"], "open");
+    assert_eq!(first["venue"], "book");
+    let (_, second) = place(&pool, &seller, book_order("sell", 4, "102")).await?;
+
+    // The market does not fill the book orders, whatever the price.
+    let quotes = market::apply_prices(
+        &pool,
+        &[PriceUpdate {
+            abbreviation: "ORD".into(),
+            price: Money::from(150),
+        }],
+        chrono::Utc::now().naive_utc(),
+    )
+    .await?;
+    assert!(
+        trading::fill_crossed_orders(&pool, &quotes)
+            .await?
+            .is_empty()
+    );
+
+    // Crosses both asks, the cheaper one first, each at its own price.
+    let (status, bought) = place(&pool, &buyer, book_order("buy", 6, "102")).await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(bought["status"], "filled");
+    assert_eq!(bought["filled_quantity"], 6);
+    assert_eq!(bought["fill_price"], "101.3333");
+    assert_eq!(balance(&pool, buyer_id).await?, "1392.00");
+    assert_eq!(balance(&pool, seller_id).await?, "608.00");
+
+    let trades = sqlx::query!(
+        r#"SELECT sell_order_id, price AS "price: Money", quantity FROM trades ORDER BY id"#
+    )
+    .fetch_all(&pool)
+    .await?;
+    assert_eq!(trades.len(), 2);
+    assert_eq!(trades[0].sell_order_id, first["id"].as_i64().unwrap());
+    assert_eq!((trades[0].price, trades[0].quantity), (Money::from(101), 4));
+    assert_eq!((trades[1].price, trades[1].quantity), (Money::from(102), 2));
+
+    // The second ask is left in the book with the rest of it.
+    let uri = format!("/api/v1/stocks/{stock_id}/book");
+    let (status, depth) = send(&pool, Method::GET, &uri, &buyer, serde_json::Value::Null).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        depth,
+        serde_json::json!({
+            "bids": [],
+            "asks": [{"price": "102.0000", "quantity": 2, "orders": 1}],
+        })
+    );
+
+    // The seller does not trade with itself, what would cross its own ask is cancelled.
+    let (status, own) = place(&pool, &seller, book_order("buy", 3, "103")).await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(own["status"], "cancelled");
+    assert_eq!(own["filled_quantity"], 0);
+
+    // The partly filled order keeps its fill once cancelled, the rest leaves the book.
+    let (status, cancelled) = send(
+        &pool,
+        Method::DELETE,
+        &format!("/api/v1/orders/{}", second["id"]),
+        &seller,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cancelled["status"], "cancelled");
+    assert_eq!(cancelled["filled_quantity"], 2);
+    assert_eq!(cancelled["fill_price"], "102.0000");
+
+    let (_, depth) = send(&pool, Method::GET, &uri, &buyer, serde_json::Value::Null).await?;
+    assert_eq!(depth, serde_json::json!({"bids": [], "asks": []}));
+
+    let (status, _) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/stocks/999999/book",
+        &buyer,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_book_partial_fills_and_rebuild(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let (maker_id, _) = create_session(&pool, "maker@email.com").await?;
+    let (broke_id, _) = create_session(&pool, "broke@email.com").await?;
+    let (taker_id, _) = create_session(&pool, "taker@email.com").await?;
+    ledger::deposit(&pool, maker_id, Money::from(1000)).await?;
+    ledger::deposit(&pool, broke_id, Money::from(1000)).await?;
+
+    let books = OrderBooks::default();
+    let order = |side, quantity, limit_price: i64| NewOrder {
+        stock_id,
+        side,
+        kind: OrderKind::Limit,
+        venue: OrderVenue::Book,
+        quantity,
+        limit_price: Some(Money::from(limit_price)),
+    };
+
+    let broke =
+        trading::place_book_order(&pool, &books, broke_id, &order(OrderSide::Buy, 5, 100)).await?;
+    let maker =
+        trading::place_book_order(&pool, &books, maker_id, &order(OrderSide::Buy, 5, 100)).await?;
+    assert_eq!(broke.status, OrderStatus::Open);
+
+    // The older bid cannot be afforded anymore by the time it is crossed, it is rejected and skipped.
+    ledger::adjust(&pool, broke_id, Money::from(-1000), "Correction").await?;
+
+    ledger::deposit(&pool, taker_id, Money::from(1000)).await?;
+    trading::place_order(
+        &pool,
+        taker_id,
+        &NewOrder {
+            kind: OrderKind::Market,
+            venue: OrderVenue::Market,
+            limit_price: None,
+            ..order(OrderSide::Buy, 10, 100)
+        },
+    )
+    .await?;
+
+    let sold =
+        trading::place_book_order(&pool, &books, taker_id, &order(OrderSide::Sell, 3, 90)).await?;
+    assert_eq!(sold.status, OrderStatus::Filled);
+    assert_eq!(sold.fill_price, Some(Money::from(100)));
+
+    let orders = trading::list_orders(&pool, broke_id, None, 10).await?;
+    assert_eq!(orders[0].status, OrderStatus::Rejected);
+
+    let orders = trading::list_orders(&pool, maker_id, Some(OrderStatus::Open), 10).await?;
+    assert_eq!(orders[0].id, maker.id);
+    assert_eq!(orders[0].filled_quantity, 3);
+
+    // The book in the memory is the same as the one rebuilt out of the database.
+    let depth = books.lock(&pool, stock_id).await?.depth(10);
+    let rebuilt = OrderBooks::load(&pool).await?;
+    assert_eq!(rebuilt.lock(&pool, stock_id).await?.depth(10), depth);
+    assert_eq!(depth.bids.len(), 1);
+    assert_eq!(depth.bids[0].quantity, 2);
+    assert!(depth.asks.is_empty());
+
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    Ok(())
+}
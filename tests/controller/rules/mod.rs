@@ -0,0 +1,255 @@
+use axum::http::{Method, Request, StatusCode, header};
+use http_body_util::BodyExt;
+
+use crate::controller::{TestRequest, TestResponse, create_session};
+
+async fn seed_stock(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('RLS', 'Rules Inc.', '2020-01-01', 100, 0) RETURNING id"
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+async fn send(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    method: Method,
+    uri: &str,
+    cookie: &str,
+    body: serde_json::Value,
+) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+    let request = TestRequest::new(
+        pool.clone(),
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, cookie)
+            .header(header::CONTENT_TYPE, "application/json"),
+    );
+
+    let TestResponse { response, .. } = request.send(body).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+
+    Ok((status, serde_json::from_slice(&body)?))
+}
+
+fn stop_loss(stock_id: i32) -> serde_json::Value {
+    serde_json::json!({
+        "trigger": {"type": "tick", "stock_id": stock_id},
+        "condition": {
+            "type": "compare",
+            "left": {"type": "price", "stock_id": stock_id},
+            "op": "le",
+            "right": {"type": "scaled", "value": {"type": "cost_basis", "stock_id": stock_id}, "factor": "0.95"}
+        },
+        "action": {"type": "order", "stock_id": stock_id, "side": "sell", "quantity": "all"}
+    })
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_create_and_list_rules(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let (user_id, cookie) = create_session(&pool, "rules@email.com").await?;
+
+    let (status, rule) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/rules",
+        &cookie,
+        serde_json::json!({"name": "Stop loss", "definition": stop_loss(stock_id)}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(rule["user_id"], user_id);
+    assert_eq!(rule["enabled"], true);
+    assert_eq!(rule["definition"], stop_loss(stock_id));
+
+    let mut without_condition = stop_loss(stock_id);
+    without_condition["condition"] = serde_json::Value::Null;
+    let mut buy_all = stop_loss(stock_id);
+    buy_all["action"]["side"] = "buy".into();
+    let mut unknown_value = stop_loss(stock_id);
+    unknown_value["condition"]["left"]["type"] = "volume".into();
+
+    for (name, definition, expected) in [
+        ("", stop_loss(stock_id), StatusCode::BAD_REQUEST),
+        ("No condition", without_condition, StatusCode::BAD_REQUEST),
+        ("Buy all", buy_all, StatusCode::BAD_REQUEST),
+        ("Unknown value", unknown_value, StatusCode::BAD_REQUEST),
+        (
+            "Unknown stock",
+            stop_loss(stock_id + 1),
+            StatusCode::NOT_FOUND,
+        ),
+    ] {
+        let (status, _) = send(
+            &pool,
+            Method::POST,
+            "/api/v1/rules",
+            &cookie,
+            serde_json::json!({"name": name, "definition": definition}),
+        )
+        .await?;
+        assert_eq!(status, expected, "{name}");
+    }
+
+    let (status, rules) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/rules",
+        &cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(rules, serde_json::json!([rule]));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_rules_of_other_users_are_not_found(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let (_, owner) = create_session(&pool, "owner@email.com").await?;
+    let (_, other) = create_session(&pool, "other@email.com").await?;
+
+    let (_, rule) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/rules",
+        &owner,
+        serde_json::json!({"name": "Stop loss", "definition": stop_loss(stock_id)}),
+    )
+    .await?;
+    let uri = format!("/api/v1/rules/{}", rule["id"]);
+
+    let (status, _) = send(
+        &pool,
+        Method::PATCH,
+        &uri,
+        &other,
+        serde_json::json!({"enabled": false}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(
+        &pool,
+        Method::GET,
+        &format!("{uri}/evaluations"),
+        &other,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, disabled) = send(
+        &pool,
+        Method::PATCH,
+        &uri,
+        &owner,
+        serde_json::json!({"enabled": false}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(disabled["enabled"], false);
+
+    let (status, evaluations) = send(
+        &pool,
+        Method::GET,
+        &format!("{uri}/evaluations?limit=10"),
+        &owner,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(evaluations, serde_json::json!([]));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_backtest_rule(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let (_, owner) = create_session(&pool, "owner@email.com").await?;
+    let (_, other) = create_session(&pool, "other@email.com").await?;
+
+    sqlx::query!(
+        "INSERT INTO stock_prices (stock_id, price, recorded_at)
+        SELECT $1, price, '2026-10-12 09:00:00'::timestamp + minute * INTERVAL '1 minute'
+        FROM UNNEST(ARRAY[100, 98, 94, 90, 97]::numeric[]) WITH ORDINALITY AS prices(price, minute)",
+        stock_id
+    )
+    .execute(&pool)
+    .await?;
+
+    let (_, rule) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/rules",
+        &owner,
+        serde_json::json!({"name": "Stop loss", "definition": stop_loss(stock_id)}),
+    )
+    .await?;
+    let uri = format!("/api/v1/rules/{}/backtest", rule["id"]);
+
+    let (status, backtest) = send(
+        &pool,
+        Method::POST,
+        &uri,
+        &owner,
+        serde_json::json!({
+            "from": "2026-10-12T09:00:00",
+            "to": "2026-10-12T10:00:00",
+            "initial_balance": "1000",
+            "initial_shares": {stock_id.to_string(): 10},
+        }),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(backtest["initial_value"], "2000.00");
+    assert_eq!(
+        backtest["trades"],
+        serde_json::json!([{
+            "at": "2026-10-12T09:03:00",
+            "stock_id": stock_id,
+            "side": "sell",
+            "quantity": 10,
+            "price": "94.0000",
+            "value": "940.00",
+        }])
+    );
+    assert_eq!(backtest["final_balance"], "1940.00");
+    assert_eq!(backtest["evaluations"]["fired"], 1);
+    assert!((backtest["max_drawdown"].as_f64().unwrap() - 0.03).abs() < 1e-9);
+
+    for (cookie, body, expected) in [
+        (
+            &other,
+            serde_json::json!({"from": "2026-10-12T09:00:00", "to": "2026-10-12T10:00:00"}),
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            &owner,
+            serde_json::json!({"from": "2026-10-12T10:00:00", "to": "2026-10-12T09:00:00"}),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            &owner,
+            serde_json::json!({"from": "2020-01-01T00:00:00", "to": "2026-10-12T09:00:00"}),
+            StatusCode::BAD_REQUEST,
+        ),
+    ] {
+        let (status, _) = send(&pool, Method::POST, &uri, cookie, body.clone()).await?;
+        assert_eq!(status, expected, "{body}");
+    }
+
+    Ok(())
+}
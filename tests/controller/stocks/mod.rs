@@ -0,0 +1,469 @@
+use axum::http::{Method, Request, StatusCode};
+use http_body_util::BodyExt;
+use rust_web_app::{
+    Error,
+    controller::{self, stocks},
+    database::types::Money,
+};
+
+use crate::controller::{TestRequest, TestResponse};
+
+/// Replaces the dummy stocks from the migrations with the predictable set.
+async fn seed_stocks(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM stocks").execute(pool).await?;
+
+    let stocks = [
+        ("AAPL", "Apple Inc.", 150.0, 1.5),
+        ("AMZN", "Amazon.com Inc.", 120.0, -2.0),
+        ("GOOGL", "Alphabet Inc.", 2800.0, -0.3),
+        ("MSFT", "Microsoft Corp.", 300.0, 0.7),
+        ("AMD", "Advanced Micro Devices", 120.0, 3.1),
+        ("ABNB", "Airbnb Inc.", 90.0, 0.0),
+    ];
+
+    for (abbreviation, company, price, delta) in stocks {
+        sqlx::query!(
+            "INSERT INTO stocks (abbreviation, company, since, price, delta)
+            VALUES ($1, $2, '2004-08-19', $3, $4)",
+            abbreviation,
+            company,
+            Money::from_f64(price, Money::PRICE_SCALE) as Option<Money>,
+            Money::from_f64(delta, Money::PERCENT_SCALE) as Option<Money>,
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+fn get(pool: sqlx::Pool<sqlx::Postgres>, uri: &str) -> TestRequest {
+    TestRequest::new(pool, Request::builder().method(Method::GET).uri(uri))
+}
+
+async fn json(
+    response: axum::http::Response<axum::body::Body>,
+) -> anyhow::Result<serde_json::Value> {
+    let body = response.into_body().collect().await?.to_bytes();
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stocks_pages_cover_every_stock(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    seed_stocks(&pool).await?;
+
+    let mut cursor: Option<String> = None;
+    let mut seen = Vec::new();
+
+    loop {
+        let uri = match &cursor {
+            Some(cursor) => format!(
+                "/api/v1/stocks?limit=4&sort=price&order=desc&cursor={}",
+                urlencode(cursor)
+            ),
+            None => "/api/v1/stocks?limit=4&sort=price&order=desc".to_string(),
+        };
+
+        let TestResponse { response, error } = get(pool.clone(), &uri).send(()).await?;
+
+        assert!(error.is_none());
+        assert!(response.status().is_success());
+
+        let page = json(response).await?;
+        assert_eq!(page["total"], 6);
+
+        for stock in page["stocks"].as_array().unwrap() {
+            seen.push((
+                stock["abbreviation"].as_str().unwrap().to_string(),
+                stock["price"].as_str().unwrap().parse::<f64>()?,
+            ));
+        }
+
+        match page["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+
+    let abbreviations = seen.iter().map(|(a, _)| a.as_str()).collect::<Vec<_>>();
+
+    // AMZN and AMD share the price, the id breaks the tie.
+    assert_eq!(
+        abbreviations,
+        ["GOOGL", "MSFT", "AAPL", "AMD", "AMZN", "ABNB"]
+    );
+    assert!(seen.windows(2).all(|w| w[0].1 >= w[1].1));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stocks_filters(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stocks(&pool).await?;
+
+    let TestResponse { response, error } = get(
+        pool.clone(),
+        "/api/v1/stocks?min_price=100&max_price=300&min_delta=0&company=a",
+    )
+    .send(())
+    .await?;
+
+    assert!(error.is_none());
+
+    let page = json(response).await?;
+    let abbreviations = page["stocks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["abbreviation"].as_str().unwrap())
+        .collect::<Vec<_>>();
+
+    // Sorted by abbreviation by default, the company prefix is case-insensitive.
+    assert_eq!(abbreviations, ["AAPL", "AMD"]);
+    assert_eq!(page["total"], 2);
+    assert!(page["next_cursor"].is_null());
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stocks_invalid_query(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    for uri in [
+        "/api/v1/stocks?limit=0",
+        "/api/v1/stocks?sort=volume",
+        "/api/v1/stocks?min_price=10&max_price=1",
+    ] {
+        let TestResponse {
+            response,
+            error: Some(error),
+        } = get(pool.clone(), uri).send(()).await?
+        else {
+            panic!("Expected error in response extensions for {uri}");
+        };
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            error,
+            Error::Controller(controller::Error::Stocks(stocks::Error::InvalidQuery(_)))
+        ));
+    }
+
+    // The cursor of the ascending price sorting, used with the other sorting and with the other order.
+    for uri in [
+        "/api/v1/stocks?sort=delta&cursor=price:asc:1:10",
+        "/api/v1/stocks?sort=price&order=desc&cursor=price:asc:1:10",
+    ] {
+        let TestResponse {
+            response,
+            error: Some(error),
+        } = get(pool.clone(), uri).send(()).await?
+        else {
+            panic!("Expected error in response extensions for {uri}");
+        };
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            error,
+            Error::Controller(controller::Error::Stocks(stocks::Error::InvalidCursor(_)))
+        ));
+    }
+
+    Ok(())
+}
+
+/// Percent-encodes the cursor, it may contain spaces, colons and whatever the abbreviation contains.
+fn urlencode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stock_by_id(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stocks(&pool).await?;
+
+    let id = sqlx::query_scalar!("SELECT id FROM stocks WHERE abbreviation = 'MSFT'")
+        .fetch_one(&pool)
+        .await?;
+
+    let TestResponse { response, error } = get(pool.clone(), &format!("/api/v1/stocks/{id}"))
+        .send(())
+        .await?;
+
+    assert!(error.is_none());
+    assert_eq!(json(response).await?["abbreviation"], "MSFT");
+
+    // Unknown, but valid id responds with 404 instead of panicking the handler.
+    let TestResponse {
+        response,
+        error: Some(error),
+    } = get(pool.clone(), "/api/v1/stocks/2147483647")
+        .send(())
+        .await?
+    else {
+        panic!("Expected error in response extensions");
+    };
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(matches!(
+        error,
+        Error::Controller(controller::Error::Stocks(stocks::Error::StockNotFound(_)))
+    ));
+
+    for id in ["0", "-1", "abc", "2147483648"] {
+        let TestResponse { response, error } = get(pool.clone(), &format!("/api/v1/stocks/{id}"))
+            .send(())
+            .await?;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            error,
+            Some(Error::Controller(controller::Error::Stocks(
+                stocks::Error::GenericControllerError(_)
+            )))
+        ));
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stock_by_symbol(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stocks(&pool).await?;
+
+    let TestResponse { response, error } = get(pool.clone(), "/api/v1/stocks/by-symbol/AAPL")
+        .send(())
+        .await?;
+
+    assert!(error.is_none());
+    assert_eq!(json(response).await?["company"], "Apple Inc.");
+
+    let TestResponse {
+        response,
+        error: Some(error),
+    } = get(pool.clone(), "/api/v1/stocks/by-symbol/NOPE")
+        .send(())
+        .await?
+    else {
+        panic!("Expected error in response extensions");
+    };
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(matches!(
+        error,
+        Error::Controller(controller::Error::Stocks(stocks::Error::StockNotFound(_)))
+    ));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stock_history(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stocks(&pool).await?;
+
+    let id = sqlx::query_scalar!("SELECT id FROM stocks WHERE abbreviation = 'AAPL'")
+        .fetch_one(&pool)
+        .await?;
+
+    // One point per day going 10 days back, the newest one is the most expensive.
+    for days in 0..10 {
+        sqlx::query!(
+            "INSERT INTO stock_prices (stock_id, price, recorded_at)
+            VALUES ($1, $2, NOW() AT TIME ZONE 'UTC' - $3 * INTERVAL '1 day' - INTERVAL '1 minute')",
+            id,
+            Money::from(200 - days) as Money,
+            days as f64,
+        )
+        .execute(&pool)
+        .await?;
+    }
+
+    let TestResponse { response, error } = get(
+        pool.clone(),
+        &format!("/api/v1/stocks/{id}/history?range=1w"),
+    )
+    .send(())
+    .await?;
+
+    assert!(error.is_none());
+
+    let history = json(response).await?;
+    let prices = history["prices"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["price"].as_str().unwrap())
+        .collect::<Vec<_>>();
+
+    // Days 0..=6 are within the week, ordered from the oldest.
+    assert_eq!(
+        prices,
+        [
+            "194.0000", "195.0000", "196.0000", "197.0000", "198.0000", "199.0000", "200.0000"
+        ]
+    );
+
+    let TestResponse { response, .. } = get(
+        pool.clone(),
+        &format!("/api/v1/stocks/{id}/history?range=custom"),
+    )
+    .send(())
+    .await?;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let TestResponse { response, .. } = get(
+        pool.clone(),
+        &format!("/api/v1/stocks/{id}/history?from=1900-01-01T00:00:00"),
+    )
+    .send(())
+    .await?;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let TestResponse { response, error } = get(pool.clone(), "/api/v1/stocks/2147483647/history")
+        .send(())
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(matches!(
+        error,
+        Some(Error::Controller(controller::Error::Stocks(
+            stocks::Error::StockNotFound(_)
+        )))
+    ));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stock_candles(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stocks(&pool).await?;
+
+    let id = sqlx::query_scalar!("SELECT id FROM stocks WHERE abbreviation = 'AAPL'")
+        .fetch_one(&pool)
+        .await?;
+
+    for (price, minute) in [(10, 0), (14, 1), (8, 2), (12, 3), (20, 7)] {
+        sqlx::query!(
+            "INSERT INTO stock_prices (stock_id, price, recorded_at)
+            VALUES ($1, $2, '2025-06-15 10:00:00'::timestamp + $3 * INTERVAL '1 minute')",
+            id,
+            Money::from(price) as Money,
+            minute as f64,
+        )
+        .execute(&pool)
+        .await?;
+    }
+
+    let TestResponse { response, error } = get(
+        pool.clone(),
+        &format!(
+            "/api/v1/stocks/{id}/candles?interval=5m&from=2025-06-15T10:00:00&to=2025-06-15T10:19:59"
+        ),
+    )
+    .send(())
+    .await?;
+
+    assert!(error.is_none());
+
+    let candles = json(response).await?["candles"].clone();
+    let candles = candles
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            (
+                c["open"].as_str().unwrap(),
+                c["high"].as_str().unwrap(),
+                c["low"].as_str().unwrap(),
+                c["close"].as_str().unwrap(),
+                c["ticks"].as_i64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        candles,
+        [
+            ("10.0000", "14.0000", "8.0000", "12.0000", 4),
+            ("20.0000", "20.0000", "20.0000", "20.0000", 1),
+            // No ticks, previous close carried forward.
+            ("20.0000", "20.0000", "20.0000", "20.0000", 0),
+            ("20.0000", "20.0000", "20.0000", "20.0000", 0),
+        ]
+    );
+
+    // A year of minute candles is over the limit.
+    let TestResponse { response, .. } = get(
+        pool.clone(),
+        &format!("/api/v1/stocks/{id}/candles?interval=1m&range=1y"),
+    )
+    .send(())
+    .await?;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stock_history_sampled(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stocks(&pool).await?;
+
+    let id = sqlx::query_scalar!("SELECT id FROM stocks WHERE abbreviation = 'AAPL'")
+        .fetch_one(&pool)
+        .await?;
+
+    // A tick every ten seconds over the last day, far more than a single response holds.
+    sqlx::query!(
+        "INSERT INTO stock_prices (stock_id, price, recorded_at)
+        SELECT $1, 100 + n, NOW() AT TIME ZONE 'UTC' - INTERVAL '1 minute' - n * INTERVAL '10 seconds'
+        FROM generate_series(0, 8000) AS n",
+        id
+    )
+    .execute(&pool)
+    .await?;
+
+    let TestResponse { response, error } = get(
+        pool.clone(),
+        &format!("/api/v1/stocks/{id}/history?range=1d"),
+    )
+    .send(())
+    .await?;
+
+    assert!(error.is_none());
+
+    let history = json(response).await?;
+    let prices = history["prices"].as_array().unwrap();
+
+    assert!(prices.len() <= stocks::history::MAX_PRICE_POINTS as usize);
+    assert!(prices.len() > stocks::history::MAX_PRICE_POINTS as usize / 2);
+
+    // Still ordered from the oldest, and the newest tick is kept.
+    assert!(
+        prices
+            .windows(2)
+            .all(|pair| { pair[0]["recorded_at"].as_str() < pair[1]["recorded_at"].as_str() })
+    );
+    assert_eq!(prices.last().unwrap()["price"], "100.0000");
+
+    Ok(())
+}
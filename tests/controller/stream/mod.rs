@@ -0,0 +1,547 @@
+//! Stream controller tests, those run against the real server as the WebSocket needs the upgraded connection.
+
+use futures::{SinkExt, StreamExt};
+use rust_web_app::{
+    AppState,
+    controller::stream::websocket::{ClientMessage, ServerMessage},
+    database::types::Money,
+    market::{self, PriceUpdate, Quote},
+};
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http::header};
+
+use crate::controller::{create_session, csrf_token, serve};
+
+type Socket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn connect(
+    addr: std::net::SocketAddr,
+    query: &str,
+    cookie: Option<&str>,
+) -> Result<Socket, tungstenite::Error> {
+    let mut request = format!("ws://{addr}/api/v1/stream/quotes{query}").into_client_request()?;
+
+    if let Some(cookie) = cookie {
+        request
+            .headers_mut()
+            .insert(header::COOKIE, cookie.parse().unwrap());
+    }
+
+    Ok(tokio_tungstenite::connect_async(request).await?.0)
+}
+
+async fn next(socket: &mut Socket) -> anyhow::Result<ServerMessage> {
+    loop {
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("socket closed"))??;
+
+        if let tungstenite::Message::Text(text) = message {
+            return Ok(serde_json::from_str(text.as_str())?);
+        }
+    }
+}
+
+async fn send(socket: &mut Socket, message: ClientMessage) -> anyhow::Result<()> {
+    socket
+        .send(tungstenite::Message::text(serde_json::to_string(&message)?))
+        .await?;
+
+    Ok(())
+}
+
+fn quote(abbreviation: &str, price: i64) -> Quote {
+    Quote {
+        id: 1,
+        stock_id: 1,
+        abbreviation: abbreviation.into(),
+        price: Money::from(price),
+        delta: Money::ZERO,
+        at: chrono::Utc::now().naive_utc(),
+    }
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stream_quotes_subscription(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let tickers = sqlx::query_scalar!("SELECT abbreviation FROM stocks ORDER BY id LIMIT 2")
+        .fetch_all(&pool)
+        .await?;
+
+    let state = AppState::new(pool);
+    let feed = state.quotes.clone();
+    let addr = serve(state).await?;
+
+    let query = format!("?tickers={},nope", tickers[0].to_lowercase());
+    let mut socket = connect(addr, &query, None).await?;
+
+    assert_eq!(
+        next(&mut socket).await?,
+        ServerMessage::Welcome { user_id: None }
+    );
+    assert_eq!(
+        next(&mut socket).await?,
+        ServerMessage::Subscribed {
+            tickers: [tickers[0].clone()].into(),
+            unknown: ["NOPE".to_string()].into(),
+        }
+    );
+
+    // Only the subscribed tickers are forwarded.
+    let (ignored, forwarded) = (quote(&tickers[1], 2), quote(&tickers[0], 1));
+    feed.publish(&[ignored, forwarded.clone()]);
+
+    assert_eq!(next(&mut socket).await?, ServerMessage::Quote(forwarded));
+
+    send(
+        &mut socket,
+        ClientMessage::Subscribe {
+            tickers: vec![tickers[1].clone()],
+        },
+    )
+    .await?;
+    assert_eq!(
+        next(&mut socket).await?,
+        ServerMessage::Subscribed {
+            tickers: tickers.iter().cloned().collect(),
+            unknown: Default::default(),
+        }
+    );
+
+    send(
+        &mut socket,
+        ClientMessage::Unsubscribe {
+            tickers: vec![tickers[0].clone()],
+        },
+    )
+    .await?;
+    assert_eq!(
+        next(&mut socket).await?,
+        ServerMessage::Subscribed {
+            tickers: [tickers[1].clone()].into(),
+            unknown: Default::default(),
+        }
+    );
+
+    let (ignored, forwarded) = (quote(&tickers[0], 3), quote(&tickers[1], 4));
+    feed.publish(&[ignored, forwarded.clone()]);
+
+    assert_eq!(next(&mut socket).await?, ServerMessage::Quote(forwarded));
+
+    // Garbage is answered, the connection stays open.
+    socket.send(tungstenite::Message::text("{}")).await?;
+    assert!(matches!(
+        next(&mut socket).await?,
+        ServerMessage::Error { .. }
+    ));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stream_quotes_optional_session(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let (user_id, cookie) = create_session(&pool, "stream@email.com").await?;
+    let addr = serve(AppState::new(pool)).await?;
+
+    let mut socket = connect(addr, "", Some(&cookie)).await?;
+    assert_eq!(
+        next(&mut socket).await?,
+        ServerMessage::Welcome {
+            user_id: Some(user_id)
+        }
+    );
+
+    // The session that does not exist is rejected before the upgrade.
+    let cookie = format!("SSID={}", sqlx::types::Uuid::new_v4());
+    let Err(tungstenite::Error::Http(response)) = connect(addr, "", Some(&cookie)).await else {
+        panic!("Expected the upgrade to be rejected");
+    };
+    assert_eq!(response.status(), 401);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stream_quotes_drops_lagging_client(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let ticker = sqlx::query_scalar!("SELECT abbreviation FROM stocks ORDER BY id LIMIT 1")
+        .fetch_one(&pool)
+        .await?;
+
+    // Tiny channel, so the client falls behind as soon as the quotes are published faster than forwarded.
+    let state = AppState {
+        quotes: rust_web_app::market::QuoteFeed::new(1),
+        ..AppState::new(pool)
+    };
+    let feed = state.quotes.clone();
+    let addr = serve(state).await?;
+
+    let mut socket = connect(addr, &format!("?tickers={ticker}"), None).await?;
+    next(&mut socket).await?;
+    next(&mut socket).await?;
+
+    // NOTE: The server forwards the quotes as they come, publishing a burst of ticks at once overflows the channel
+    // before the connection task gets to it.
+    for i in 0..100 {
+        feed.publish(&[quote(&ticker, i + 1)]);
+    }
+
+    let close = loop {
+        match tokio::time::timeout(std::time::Duration::from_secs(5), socket.next()).await? {
+            Some(Ok(tungstenite::Message::Close(frame))) => break frame,
+            Some(Ok(_)) => continue,
+            other => panic!("Expected the close frame, got {other:?}"),
+        }
+    };
+
+    assert_eq!(
+        close.map(|frame| u16::from(frame.code)),
+        Some(tungstenite::protocol::frame::coding::CloseCode::Again.into())
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stream_quotes_large_tick_does_not_lag(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let ticker = sqlx::query_scalar!("SELECT abbreviation FROM stocks ORDER BY id LIMIT 1")
+        .fetch_one(&pool)
+        .await?;
+
+    let state = AppState {
+        quotes: rust_web_app::market::QuoteFeed::new(1),
+        ..AppState::new(pool)
+    };
+    let feed = state.quotes.clone();
+    let addr = serve(state).await?;
+
+    let mut socket = connect(addr, &format!("?tickers={ticker}"), None).await?;
+    next(&mut socket).await?;
+    next(&mut socket).await?;
+
+    // A single tick of far more stocks than the capacity of the channel.
+    let mut tick = (0..5000)
+        .map(|i| quote(&format!("T{i}"), i + 1))
+        .collect::<Vec<_>>();
+    let forwarded = quote(&ticker, 5001);
+    tick.push(forwarded.clone());
+    feed.publish(&tick);
+
+    assert_eq!(next(&mut socket).await?, ServerMessage::Quote(forwarded));
+
+    Ok(())
+}
+
+/// Minimal reader of the `text/event-stream`, the keep-alive comments are skipped.
+struct EventReader {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+#[derive(Debug)]
+struct SseEvent {
+    event: String,
+    id: Option<String>,
+    data: serde_json::Value,
+}
+
+impl EventReader {
+    async fn connect(
+        addr: std::net::SocketAddr,
+        query: &str,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Self> {
+        let mut request =
+            reqwest::Client::new().get(format!("http://{addr}/api/v1/stream/events{query}"));
+
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let response = request.send().await?.error_for_status()?;
+
+        Ok(Self {
+            response,
+            buffer: String::new(),
+        })
+    }
+
+    async fn next(&mut self) -> anyhow::Result<SseEvent> {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let block = self.buffer[..end].to_string();
+                self.buffer.drain(..end + 2);
+
+                let (mut event, mut id, mut data) = (None, None, String::new());
+
+                for line in block.lines() {
+                    match line.split_once(':') {
+                        Some(("event", value)) => event = Some(value.trim().to_string()),
+                        Some(("id", value)) => id = Some(value.trim().to_string()),
+                        Some(("data", value)) => data.push_str(value.trim()),
+                        _ => {}
+                    }
+                }
+
+                if let Some(event) = event {
+                    return Ok(SseEvent {
+                        event,
+                        id,
+                        data: serde_json::from_str(&data)?,
+                    });
+                }
+
+                continue;
+            }
+
+            let chunk =
+                tokio::time::timeout(std::time::Duration::from_secs(10), self.response.chunk())
+                    .await??
+                    .ok_or_else(|| anyhow::anyhow!("event stream ended"))?;
+
+            self.buffer.push_str(std::str::from_utf8(&chunk)?);
+        }
+    }
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stream_events_quotes_and_resume(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let ticker = sqlx::query_scalar!("SELECT abbreviation FROM stocks ORDER BY id LIMIT 1")
+        .fetch_one(&pool)
+        .await?;
+
+    let state = AppState::new(pool.clone());
+    let feed = state.quotes.clone();
+    let addr = serve(state).await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let update = |price: i64| PriceUpdate {
+        abbreviation: ticker.clone(),
+        price: Money::from(price),
+    };
+
+    let mut events = EventReader::connect(addr, &format!("?tickers={ticker},nope"), &[]).await?;
+
+    let subscribed = events.next().await?;
+    assert_eq!(subscribed.event, "subscribed");
+    assert_eq!(subscribed.data["tickers"], serde_json::json!([ticker]));
+    assert_eq!(subscribed.data["unknown"], serde_json::json!(["NOPE"]));
+    assert_eq!(subscribed.data["user_id"], serde_json::Value::Null);
+
+    let first = market::apply_prices(&pool, &[update(10)], now).await?;
+    feed.publish(&first);
+
+    let event = events.next().await?;
+    assert_eq!(event.event, "quote");
+    assert_eq!(event.id, Some(first[0].id.to_string()));
+    assert_eq!(event.data["price"], "10.0000");
+
+    // Quotes written while the client was away.
+    drop(events);
+    let second = market::apply_prices(&pool, &[update(12)], now).await?;
+    let third = market::apply_prices(&pool, &[update(6)], now).await?;
+
+    let last_event_id = first[0].id.to_string();
+    let mut events = EventReader::connect(
+        addr,
+        &format!("?tickers={ticker}"),
+        &[("Last-Event-ID", &last_event_id)],
+    )
+    .await?;
+
+    assert_eq!(events.next().await?.event, "subscribed");
+
+    for (quote, delta) in [(&second[0], "20.00"), (&third[0], "-50.00")] {
+        let event = events.next().await?;
+
+        assert_eq!(event.id, Some(quote.id.to_string()));
+        assert_eq!(event.data["price"], quote.price.to_string());
+        assert_eq!(event.data["delta"], delta);
+    }
+
+    // The replayed quote published late is not sent twice, the live one goes through.
+    let fourth = market::apply_prices(&pool, &[update(7)], now).await?;
+    feed.publish(&third);
+    feed.publish(&fourth);
+
+    assert_eq!(events.next().await?.id, Some(fourth[0].id.to_string()));
+
+    // Garbage id is rejected.
+    let response = reqwest::Client::new()
+        .get(format!("http://{addr}/api/v1/stream/events"))
+        .header("Last-Event-ID", "abc")
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stream_events_balance_of_session(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let (user_id, cookie) = create_session(&pool, "events@email.com").await?;
+    let addr = serve(AppState::new(pool.clone())).await?;
+
+    let mut events = EventReader::connect(addr, "", &[("Cookie", &cookie)]).await?;
+
+    let subscribed = events.next().await?;
+    assert_eq!(subscribed.data["user_id"], user_id);
+
+    let balance = events.next().await?;
+    assert_eq!(balance.event, "balance");
+    assert_eq!(balance.id, None);
+    assert_eq!(
+        balance.data,
+        serde_json::json!({"balance": "0", "delta": "0"})
+    );
+
+    sqlx::query!(
+        "UPDATE users SET balance = 150.5, delta = 1.5 WHERE id = $1",
+        user_id
+    )
+    .execute(&pool)
+    .await?;
+
+    let balance = events.next().await?;
+    assert_eq!(balance.event, "balance");
+    assert_eq!(
+        balance.data,
+        serde_json::json!({"balance": "150.50", "delta": "1.50"})
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stream_events_offers_of_session(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = sqlx::query_scalar!("SELECT id FROM stocks ORDER BY id LIMIT 1")
+        .fetch_one(&pool)
+        .await?;
+    let (maker_id, maker) = create_session(&pool, "maker@email.com").await?;
+    let (taker_id, taker) = create_session(&pool, "taker@email.com").await?;
+    let (_, other) = create_session(&pool, "other@email.com").await?;
+    rust_web_app::ledger::deposit(&pool, maker_id, Money::from(1000)).await?;
+
+    let addr = serve(AppState::new(pool.clone())).await?;
+
+    let mut events = EventReader::connect(addr, "", &[("Cookie", &taker)]).await?;
+    let mut unrelated = EventReader::connect(addr, "", &[("Cookie", &other)]).await?;
+
+    for events in [&mut events, &mut unrelated] {
+        assert_eq!(events.next().await?.event, "subscribed");
+        assert_eq!(events.next().await?.event, "balance");
+    }
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{addr}/api/v1/offers"))
+        .header("Cookie", &maker)
+        .header(
+            rust_web_app::controller::auth::csrf::HEADER,
+            csrf_token(&pool, &maker).await?.unwrap_or_default(),
+        )
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "to_user_id": taker_id,
+                "stock_id": stock_id,
+                "side": "buy",
+                "quantity": 1,
+                "amount": "100",
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::CREATED);
+    let offer = serde_json::from_slice::<serde_json::Value>(&response.bytes().await?)?;
+
+    // The user the offer was made to is notified of it.
+    let event = events.next().await?;
+    assert_eq!(event.event, "offer");
+    assert_eq!(event.id, None);
+    assert_eq!(event.data["id"], offer["id"]);
+    assert_eq!(event.data["status"], "pending");
+    assert_eq!(event.data["from_user_id"], maker_id);
+
+    // Nobody else is.
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(500), unrelated.next())
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_stream_events_notifications_of_session(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let (user_id, cookie) = create_session(&pool, "inbox@email.com").await?;
+    let (_, other) = create_session(&pool, "other@email.com").await?;
+    let ticker = sqlx::query_scalar!("SELECT abbreviation FROM stocks ORDER BY id LIMIT 1")
+        .fetch_one(&pool)
+        .await?;
+
+    let state = AppState::new(pool.clone());
+    let (feed, notifications) = (state.quotes.clone(), state.notifications.clone());
+    let addr = serve(state).await?;
+    rust_web_app::alerts::spawn(pool.clone().into(), &feed, notifications);
+
+    let alert = rust_web_app::alerts::NewAlert {
+        ticker: ticker.clone(),
+        metric: rust_web_app::alerts::AlertMetric::Price,
+        direction: rust_web_app::alerts::AlertDirection::Above,
+        threshold: Money::from(1_000_000),
+        rearm: false,
+        cooldown_seconds: None,
+    };
+    let now = chrono::Utc::now().naive_utc();
+    rust_web_app::alerts::create_alert(&pool, user_id, &alert, now).await?;
+
+    let mut events = EventReader::connect(addr, "", &[("Cookie", &cookie)]).await?;
+    let mut unrelated = EventReader::connect(addr, "", &[("Cookie", &other)]).await?;
+
+    for events in [&mut events, &mut unrelated] {
+        assert_eq!(events.next().await?.event, "subscribed");
+        assert_eq!(events.next().await?.event, "balance");
+    }
+
+    let update = PriceUpdate {
+        abbreviation: ticker,
+        price: Money::from(1_000_001),
+    };
+    feed.publish(&market::apply_prices(&pool, &[update], now).await?);
+
+    let event = events.next().await?;
+    assert_eq!(event.event, "notification");
+    assert_eq!(event.id, None);
+    assert_eq!(event.data["user_id"], user_id);
+    assert_eq!(event.data["read_at"], serde_json::Value::Null);
+
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(500), unrelated.next())
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,303 @@
+use axum::http::{Method, Request, StatusCode, header};
+use http_body_util::BodyExt;
+
+use crate::controller::{TestRequest, TestResponse, create_session};
+
+async fn seed_stocks(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('WLA', 'Watch A Inc.', '2020-01-01', 10, 1),
+            ('WLB', 'Watch B Inc.', '2020-01-01', 20, -2),
+            ('WLC', 'Watch C Inc.', '2020-01-01', 30, 0)"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn send(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    method: Method,
+    uri: &str,
+    cookie: Option<&str>,
+    body: serde_json::Value,
+) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json");
+
+    if let Some(cookie) = cookie {
+        builder = builder.header(header::COOKIE, cookie);
+    }
+
+    let TestResponse { response, .. } = TestRequest::new(pool.clone(), builder).send(body).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+
+    // The deletes answer with no content at all.
+    if body.is_empty() {
+        return Ok((status, serde_json::Value::Null));
+    }
+
+    Ok((status, serde_json::from_slice(&body)?))
+}
+
+fn tickers(watchlist: &serde_json::Value) -> Vec<&str> {
+    watchlist["stocks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|stock| stock["abbreviation"].as_str().unwrap())
+        .collect()
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_watchlist_tickers(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    seed_stocks(&pool).await?;
+    let (_, cookie) = create_session(&pool, "watchlists@email.com").await?;
+    let cookie = Some(cookie.as_str());
+
+    let (status, watchlist) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/watchlists",
+        cookie,
+        serde_json::json!({"name": "Tech", "tickers": ["WLB", "wla"]}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(tickers(&watchlist), ["WLB", "WLA"]);
+
+    let id = watchlist["id"].as_i64().unwrap();
+    let uri = format!("/api/v1/me/watchlists/{id}");
+
+    // The quotes are joined in as they are now.
+    sqlx::query!("UPDATE stocks SET price = 25, delta = 5 WHERE abbreviation = 'WLB'")
+        .execute(&pool)
+        .await?;
+
+    let (status, watchlist) =
+        send(&pool, Method::GET, &uri, cookie, serde_json::Value::Null).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(watchlist["name"], "Tech");
+    assert_eq!(watchlist["stocks"][0]["price"], "25.0000");
+    assert_eq!(watchlist["stocks"][0]["delta"], "5.00");
+
+    let (status, watchlist) = send(
+        &pool,
+        Method::POST,
+        &format!("{uri}/tickers"),
+        cookie,
+        serde_json::json!({"ticker": "WLC"}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(tickers(&watchlist), ["WLB", "WLA", "WLC"]);
+
+    let (status, _) = send(
+        &pool,
+        Method::POST,
+        &format!("{uri}/tickers"),
+        cookie,
+        serde_json::json!({"ticker": "WLC"}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = send(
+        &pool,
+        Method::POST,
+        &format!("{uri}/tickers"),
+        cookie,
+        serde_json::json!({"ticker": "NOPE"}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, watchlist) = send(
+        &pool,
+        Method::PUT,
+        &format!("{uri}/tickers"),
+        cookie,
+        serde_json::json!({"tickers": ["WLC", "WLA", "WLB"]}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(tickers(&watchlist), ["WLC", "WLA", "WLB"]);
+
+    // Not a permutation of the watched stocks.
+    let (status, _) = send(
+        &pool,
+        Method::PUT,
+        &format!("{uri}/tickers"),
+        cookie,
+        serde_json::json!({"tickers": ["WLC", "WLA"]}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, watchlist) = send(
+        &pool,
+        Method::DELETE,
+        &format!("{uri}/tickers/WLA"),
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(tickers(&watchlist), ["WLC", "WLB"]);
+
+    let (status, _) = send(
+        &pool,
+        Method::DELETE,
+        &format!("{uri}/tickers/WLA"),
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_watchlists_order(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let (_, cookie) = create_session(&pool, "order@email.com").await?;
+    let cookie = Some(cookie.as_str());
+
+    let mut ids = vec![];
+    for name in ["First", "Second", "Third"] {
+        let (status, watchlist) = send(
+            &pool,
+            Method::POST,
+            "/api/v1/me/watchlists",
+            cookie,
+            serde_json::json!({"name": name}),
+        )
+        .await?;
+        assert_eq!(status, StatusCode::CREATED);
+        ids.push(watchlist["id"].as_i64().unwrap());
+    }
+
+    let (status, _) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/watchlists",
+        cookie,
+        serde_json::json!({"name": "First"}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, watchlists) = send(
+        &pool,
+        Method::PUT,
+        "/api/v1/me/watchlists/order",
+        cookie,
+        serde_json::json!({"ids": [ids[2], ids[0], ids[1]]}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    let names = |watchlists: &serde_json::Value| -> Vec<String> {
+        watchlists
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|watchlist| watchlist["name"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(names(&watchlists), ["Third", "First", "Second"]);
+
+    let (status, _) = send(
+        &pool,
+        Method::DELETE,
+        &format!("/api/v1/me/watchlists/{}", ids[0]),
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, watchlist) = send(
+        &pool,
+        Method::PATCH,
+        &format!("/api/v1/me/watchlists/{}", ids[1]),
+        cookie,
+        serde_json::json!({"name": "Renamed"}),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(watchlist["name"], "Renamed");
+
+    let (status, watchlists) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/me/watchlists",
+        cookie,
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(names(&watchlists), ["Third", "Renamed"]);
+    assert_eq!(watchlists[0]["position"], 0);
+    assert_eq!(watchlists[1]["position"], 1);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_watchlists_are_private(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let (_, owner) = create_session(&pool, "owner@email.com").await?;
+    let (_, other) = create_session(&pool, "other@email.com").await?;
+
+    let (_, watchlist) = send(
+        &pool,
+        Method::POST,
+        "/api/v1/me/watchlists",
+        Some(&owner),
+        serde_json::json!({"name": "Mine"}),
+    )
+    .await?;
+    let uri = format!("/api/v1/me/watchlists/{}", watchlist["id"]);
+
+    let (status, _) = send(
+        &pool,
+        Method::GET,
+        &uri,
+        Some(&other),
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(
+        &pool,
+        Method::DELETE,
+        &uri,
+        Some(&other),
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(&pool, Method::GET, &uri, None, serde_json::Value::Null).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, watchlists) = send(
+        &pool,
+        Method::GET,
+        "/api/v1/me/watchlists",
+        Some(&other),
+        serde_json::Value::Null,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(watchlists, serde_json::json!([]));
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use rust_web_app::{
+    database::types::Money,
+    ledger::{self, Account, TransactionKind},
+};
+
+async fn create_user(pool: &sqlx::Pool<sqlx::Postgres>, email: &str) -> anyhow::Result<i32> {
+    let account_id = sqlx::query_scalar!("INSERT INTO accounts DEFAULT VALUES RETURNING id")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO users (account_id, email, password_hash) VALUES ($1, $2, '') RETURNING id",
+        account_id,
+        email
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+#[sqlx::test]
+async fn test_balance_follows_ledger(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let user_id = create_user(&pool, "ledger@email.com").await?;
+
+    ledger::deposit(&pool, user_id, "250.50".parse()?).await?;
+    ledger::adjust(&pool, user_id, "-0.50".parse()?, "Rounding").await?;
+
+    let mut conn = pool.acquire().await?;
+    ledger::record(
+        &mut conn,
+        user_id,
+        TransactionKind::Fee,
+        None,
+        Money::from(-10),
+        Account::Fees,
+        "Fee",
+    )
+    .await?;
+
+    let balance = sqlx::query_scalar!(
+        r#"SELECT balance AS "balance: Money" FROM users WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(balance, Money::from(240));
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    // The balance changed behind the back of the ledger.
+    sqlx::query!("UPDATE users SET balance = 1000 WHERE id = $1", user_id)
+        .execute(&pool)
+        .await?;
+
+    let report = ledger::check_consistency(&pool).await?;
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(report.mismatches[0].user_id, user_id);
+    assert_eq!(report.mismatches[0].balance, Money::from(1000));
+    assert_eq!(report.mismatches[0].ledger, Money::from(240));
+
+    assert_eq!(ledger::rebuild_balances(&pool).await?, 1);
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    // Fractions of a cent would drift away from what the users see.
+    for amount in [Money::ZERO, "0.001".parse()?, Money::from(-5)] {
+        assert!(matches!(
+            ledger::deposit(&pool, user_id, amount).await,
+            Err(ledger::Error::InvalidAmount(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn test_fee_charged_into_fees(pool: sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let user_id = create_user(&pool, "fees@email.com").await?;
+    ledger::deposit(&pool, user_id, Money::from(100)).await?;
+
+    let mut tx = pool.begin().await?;
+    let id = ledger::charge_fee(&mut tx, user_id, None, "1.25".parse()?).await?;
+    tx.commit().await?;
+
+    let transactions = ledger::list_transactions(&pool, user_id, None, 10).await?;
+    assert_eq!(transactions[0].id, id);
+    assert_eq!(transactions[0].kind, TransactionKind::Fee);
+    assert_eq!(transactions[0].account, Account::Fees);
+    assert_eq!(transactions[0].amount, "-1.25".parse()?);
+
+    let balance = sqlx::query_scalar!(
+        r#"SELECT balance AS "balance: Money" FROM users WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(balance, "98.75".parse()?);
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    // The fee is always charged, never paid out.
+    let mut conn = pool.acquire().await?;
+    for fee in [Money::ZERO, Money::from(-1)] {
+        assert!(matches!(
+            ledger::charge_fee(&mut conn, user_id, None, fee).await,
+            Err(ledger::Error::InvalidAmount(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn test_ledger_rejects_unbalanced_and_updates(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let user_id = create_user(&pool, "append@email.com").await?;
+    let id = ledger::deposit(&pool, user_id, Money::from(100)).await?;
+
+    // The single entry does not balance, which fails once the transaction commits.
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO ledger_entries (transaction_id, account, amount) VALUES ($1, 'cash', 5)",
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    assert!(tx.commit().await.is_err());
+
+    assert!(
+        sqlx::query!(
+            "UPDATE ledger_entries SET amount = 5 WHERE transaction_id = $1",
+            id
+        )
+        .execute(&pool)
+        .await
+        .is_err()
+    );
+
+    assert!(ledger::check_consistency(&pool).await?.is_consistent());
+
+    Ok(())
+}
@@ -1,5 +1,9 @@
 mod config;
 mod controller;
+mod ledger;
+mod market;
+mod portfolio;
+mod rules;
 
 // Alias for constructing app with state and given connection pool.
 // pub(crate) fn app(
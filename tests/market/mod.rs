@@ -0,0 +1,294 @@
+//! Market module tests, those are running against the database, the pure parts are unit tested in the module.
+
+use std::io::Write;
+
+use rust_web_app::{
+    database::{DatabaseConnection, types::Money},
+    market::{
+        self, MarketDataProvider, PriceModel, PriceUpdate, ReplayConfig, ReplayProvider,
+        SimulationConfig, Simulator, import,
+    },
+};
+
+fn update(abbreviation: &str, price: i64) -> PriceUpdate {
+    PriceUpdate {
+        abbreviation: abbreviation.into(),
+        price: Money::from(price),
+    }
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_simulator_tick_updates_stocks_and_history(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let before = sqlx::query!(r#"SELECT id, price AS "price: Money" FROM stocks ORDER BY id"#)
+        .fetch_all(&pool)
+        .await?;
+
+    let config = SimulationConfig {
+        model: PriceModel::RandomWalk { step: 1.0 },
+        seed: Some(1),
+        ..Default::default()
+    };
+
+    let updates = Simulator::new(config.clone())?
+        .next_updates(&pool)
+        .await?
+        .expect("simulation never runs out");
+
+    let quotes = market::apply_prices(&pool, &updates, chrono::Utc::now().naive_utc()).await?;
+
+    assert_eq!(quotes.len(), before.len());
+
+    // The same seed draws the same prices.
+    let mut expected = Simulator::new(config)?;
+
+    for (quote, stock) in quotes.iter().zip(before.iter()) {
+        assert_eq!(quote.stock_id, stock.id);
+        assert_eq!(quote.price, expected.next_price(stock.price));
+
+        let delta = (quote.price.decimal() - stock.price.decimal()) / stock.price.decimal()
+            * rust_decimal::Decimal::ONE_HUNDRED;
+        assert_eq!(quote.delta, Money::from(delta).round(Money::PERCENT_SCALE));
+
+        let after = sqlx::query!(
+            r#"SELECT price AS "price: Money", delta AS "delta: Money", last_update FROM stocks WHERE id = $1"#,
+            stock.id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        assert_eq!(after.price, quote.price);
+        assert_eq!(after.delta, quote.delta);
+        assert_eq!(after.last_update, quote.at);
+
+        // The history has the tick appended as the newest point.
+        let latest = sqlx::query!(
+            r#"SELECT price AS "price: Money", recorded_at FROM stock_prices WHERE stock_id = $1
+            ORDER BY recorded_at DESC, id DESC LIMIT 1"#,
+            stock.id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        assert_eq!(latest.price, quote.price);
+        assert_eq!(latest.recorded_at, quote.at);
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_apply_prices_skips_invalid_updates(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let abbreviation = sqlx::query_scalar!("SELECT abbreviation FROM stocks ORDER BY id LIMIT 1")
+        .fetch_one(&pool)
+        .await?;
+
+    let history = sqlx::query_scalar!("SELECT COUNT(*) FROM stock_prices")
+        .fetch_one(&pool)
+        .await?;
+
+    let now = chrono::Utc::now().naive_utc();
+
+    // Unknown stock and non-positive price are skipped, the huge move is clamped into the delta constraint.
+    let quotes = market::apply_prices(
+        &pool,
+        &[
+            update("NOT-A-TICKER", 10),
+            update(&abbreviation, -1),
+            update(&abbreviation, 1_000_000),
+        ],
+        now,
+    )
+    .await?;
+
+    assert_eq!(quotes.len(), 1);
+    assert_eq!(quotes[0].price, Money::from(1_000_000));
+    assert!(quotes[0].delta < Money::from(100));
+
+    let after = sqlx::query_scalar!("SELECT COUNT(*) FROM stock_prices")
+        .fetch_one(&pool)
+        .await?;
+
+    assert_eq!(after, history.map(|count| count + 1));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_replay_is_written_until_exhausted(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stocks = sqlx::query!("SELECT id, abbreviation FROM stocks ORDER BY id LIMIT 2")
+        .fetch_all(&pool)
+        .await?;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("quotes.csv");
+
+    let mut file = std::fs::File::create(&path)?;
+    writeln!(file, "timestamp,abbreviation,price")?;
+    writeln!(file, "2025-01-02 10:00:00,{},101.5", stocks[0].abbreviation)?;
+    writeln!(file, "2025-01-02 10:00:00,{},55.25", stocks[1].abbreviation)?;
+    writeln!(file, "2025-01-02 10:00:01,{},102", stocks[0].abbreviation)?;
+
+    let provider = ReplayProvider::from_file(ReplayConfig {
+        path,
+        // A second of the recorded time takes a millisecond.
+        speed: 1000.0,
+        repeat: false,
+    })?;
+
+    // The loop ends on its own once the provider is exhausted.
+    tokio::time::timeout(
+        std::time::Duration::from_secs(10),
+        market::spawn(
+            DatabaseConnection(pool.clone()),
+            Default::default(),
+            provider,
+        ),
+    )
+    .await??;
+
+    let money = |price: &str| price.parse::<Money>().unwrap();
+
+    for (stock, expected) in stocks
+        .iter()
+        .zip([vec![money("101.5"), money("102")], vec![money("55.25")]])
+    {
+        let price = sqlx::query_scalar!(
+            r#"SELECT price AS "price: Money" FROM stocks WHERE id = $1"#,
+            stock.id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        assert_eq!(Some(&price), expected.last());
+
+        // The points written by the replay are the newest ones, after the seeded history.
+        let mut history = sqlx::query_scalar!(
+            r#"SELECT price AS "price: Money" FROM stock_prices WHERE stock_id = $1
+            ORDER BY recorded_at DESC, id DESC LIMIT $2"#,
+            stock.id,
+            expected.len() as i64
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        history.reverse();
+        assert_eq!(history, expected);
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_import_creates_stock_and_reports_rows(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let csv = "\
+Date,Open,High,Low,Close,Volume,Dividends,Stock Splits
+2025-01-02 00:00:00-05:00,10,11,9,10,100,0,0
+2025-01-03 00:00:00-05:00,10,11,9,0,100,0,0
+2025-01-06 00:00:00-05:00,10,13,9,12.5,100,0,0
+";
+
+    let report =
+        import::import_history(&pool, "zzimp", Some("Import Inc."), csv.as_bytes()).await?;
+
+    assert!(report.created);
+    assert_eq!(report.abbreviation, "ZZIMP");
+    assert_eq!(report.imported, 2);
+    assert_eq!(report.errors.len(), 1);
+    assert_eq!(report.errors[0].line, 3);
+
+    let stock = sqlx::query!(
+        r#"SELECT company, since, price AS "price: Money", delta AS "delta: Money", last_update
+        FROM stocks WHERE id = $1"#,
+        report.stock_id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    assert_eq!(stock.company, "Import Inc.");
+    assert_eq!(stock.since.to_string(), "2025-01-02");
+    assert_eq!(stock.price, "12.5".parse()?);
+    assert_eq!(stock.delta, Money::from(25));
+    assert_eq!(stock.last_update.to_string(), "2025-01-06 05:00:00");
+
+    let history = sqlx::query_scalar!(
+        r#"SELECT price AS "price: Money" FROM stock_prices WHERE stock_id = $1 ORDER BY recorded_at"#,
+        report.stock_id
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    assert_eq!(history, [Money::from(10), "12.5".parse()?]);
+
+    // Importing again updates in place, nothing is duplicated.
+    let report = import::import_history(&pool, "ZZIMP", None, csv.as_bytes()).await?;
+    assert!(!report.created);
+
+    let count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM stock_prices WHERE stock_id = $1",
+        report.stock_id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    assert_eq!(count, Some(2));
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_import_of_old_history_keeps_current_price(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let before = sqlx::query!(
+        "SELECT id, abbreviation, company, price, delta, last_update FROM stocks ORDER BY id LIMIT 1"
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let csv = "\
+Date,Open,High,Low,Close,Volume
+2001-01-02,10,11,9,1000,100
+2001-01-03,10,11,9,-1,100
+";
+
+    let report = import::import_history(&pool, &before.abbreviation, None, csv.as_bytes()).await?;
+
+    assert!(!report.created);
+    assert_eq!(report.stock_id, before.id);
+    assert_eq!(report.imported, 1);
+
+    let after = sqlx::query!(
+        "SELECT company, since, price, delta, last_update FROM stocks WHERE id = $1",
+        before.id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    assert_eq!(after.company, before.company);
+    assert_eq!(after.price, before.price);
+    assert_eq!(after.delta, before.delta);
+    assert_eq!(after.last_update, before.last_update);
+    // The history now reaches further back than the stock was listed with.
+    assert_eq!(after.since.to_string(), "2001-01-02");
+
+    // Nothing valid to import fails the whole file.
+    assert!(matches!(
+        import::import_history(&pool, "ZZIMP", None, "Date,Close\nnope,1\n".as_bytes()).await,
+        Err(market::Error::InvalidImport(_))
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use rust_web_app::{
+    database::types::Money,
+    ledger,
+    market::{self, PriceUpdate},
+    portfolio::snapshots,
+    trading::{self, NewOrder, OrderKind, OrderSide, OrderVenue},
+};
+
+async fn create_user(pool: &sqlx::Pool<sqlx::Postgres>, email: &str) -> anyhow::Result<i32> {
+    let account_id = sqlx::query_scalar!("INSERT INTO accounts DEFAULT VALUES RETURNING id")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO users (account_id, email, password_hash) VALUES ($1, $2, '') RETURNING id",
+        account_id,
+        email
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_snapshot_of_the_day_is_rewritten(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = sqlx::query_scalar!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('SNAP', 'Snapshot Inc.', '2020-01-01', 100, 0) RETURNING id"
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let user_id = create_user(&pool, "snapshots@email.com").await?;
+    let idle_id = create_user(&pool, "idle@email.com").await?;
+
+    ledger::deposit(&pool, user_id, Money::from(1500)).await?;
+    trading::place_order(
+        &pool,
+        user_id,
+        &NewOrder {
+            stock_id,
+            side: OrderSide::Buy,
+            kind: OrderKind::Market,
+            venue: OrderVenue::Market,
+            quantity: 10,
+            limit_price: None,
+        },
+    )
+    .await?;
+
+    let users = sqlx::query_scalar!("SELECT COUNT(*) FROM users")
+        .fetch_one(&pool)
+        .await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    assert_eq!(
+        Some(snapshots::take_snapshots(&pool, now).await? as i64),
+        users
+    );
+
+    let list = snapshots::list_snapshots(&pool, user_id, None).await?;
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].day, now.date());
+    assert_eq!(list[0].total_value, Money::from(1500));
+    assert_eq!(list[0].market_value, Money::from(1000));
+    // The fill is not the flow, only the deposit is.
+    assert_eq!(list[0].net_flow, Money::from(1500));
+
+    // Users without anything are still snapshotted, with nothing.
+    let idle = snapshots::list_snapshots(&pool, idle_id, None).await?;
+    assert_eq!(idle[0].total_value, Money::ZERO);
+
+    market::apply_prices(
+        &pool,
+        &[PriceUpdate {
+            abbreviation: "SNAP".into(),
+            price: Money::from(90),
+        }],
+        now,
+    )
+    .await?;
+    snapshots::take_snapshots(&pool, now).await?;
+
+    let list = snapshots::list_snapshots(&pool, user_id, None).await?;
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].total_value, Money::from(1400));
+    assert_eq!(list[0].market_value, Money::from(900));
+
+    // The next day starts the new row, without the flows of the previous one.
+    snapshots::take_snapshots(&pool, now + chrono::Duration::days(1)).await?;
+
+    let list = snapshots::list_snapshots(&pool, user_id, Some(now.date())).await?;
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[1].net_flow, Money::ZERO);
+
+    Ok(())
+}
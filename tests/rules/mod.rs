@@ -0,0 +1,432 @@
+use rust_web_app::{
+    database::types::Money,
+    ledger,
+    market::{self, PriceUpdate},
+    rules::{self, BacktestRequest, Definition, NewRule, RuleOutcome},
+    trading::{self, NewOrder, OrderKind, OrderSide, OrderStatus, OrderVenue},
+};
+
+async fn create_user(pool: &sqlx::Pool<sqlx::Postgres>, email: &str) -> anyhow::Result<i32> {
+    let account_id = sqlx::query_scalar!("INSERT INTO accounts DEFAULT VALUES RETURNING id")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO users (account_id, email, password_hash) VALUES ($1, $2, '') RETURNING id",
+        account_id,
+        email
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+async fn seed_stock(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO stocks (abbreviation, company, since, price, delta)
+        VALUES ('RULE', 'Rules Inc.', '2020-01-01', 100, 0) RETURNING id"
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+async fn shares(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    stock_id: i32,
+) -> anyhow::Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "SELECT quantity FROM positions WHERE user_id = $1 AND stock_id = $2",
+        user_id,
+        stock_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or_default())
+}
+
+/// Moves the price of the stock and evaluates its tick rules, the same as the market does.
+async fn tick(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    stock_id: i32,
+    price: i64,
+) -> anyhow::Result<Vec<RuleOutcome>> {
+    tick_at(pool, stock_id, price, chrono::Utc::now().naive_utc()).await
+}
+
+async fn tick_at(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    stock_id: i32,
+    price: i64,
+    at: chrono::NaiveDateTime,
+) -> anyhow::Result<Vec<RuleOutcome>> {
+    market::apply_prices(
+        pool,
+        &[PriceUpdate {
+            abbreviation: "RULE".into(),
+            price: Money::from(price),
+        }],
+        at,
+    )
+    .await?;
+
+    Ok(rules::evaluate_ticks(pool, &[stock_id], at)
+        .await?
+        .into_iter()
+        .map(|evaluation| evaluation.outcome)
+        .collect())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_tick_rule_fires_once_its_condition_starts_to_hold(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let user_id = create_user(&pool, "rules@email.com").await?;
+
+    ledger::deposit(&pool, user_id, Money::from(2000)).await?;
+    trading::place_order(
+        &pool,
+        user_id,
+        &NewOrder {
+            stock_id,
+            side: OrderSide::Buy,
+            kind: OrderKind::Market,
+            venue: OrderVenue::Market,
+            quantity: 10,
+            limit_price: None,
+        },
+    )
+    .await?;
+
+    // Sells 4 shares once the price drops 5% below the cost basis.
+    let definition: Definition = serde_json::from_value(serde_json::json!({
+        "trigger": {"type": "tick", "stock_id": stock_id},
+        "condition": {
+            "type": "compare",
+            "left": {"type": "price", "stock_id": stock_id},
+            "op": "le",
+            "right": {"type": "scaled", "value": {"type": "cost_basis", "stock_id": stock_id}, "factor": "0.95"}
+        },
+        "action": {"type": "order", "stock_id": stock_id, "side": "sell", "quantity": {"shares": 4}}
+    }))?;
+
+    let rule = rules::create_rule(
+        &pool,
+        user_id,
+        &NewRule {
+            name: "Stop loss".into(),
+            definition,
+        },
+        chrono::Utc::now().naive_utc(),
+    )
+    .await?;
+    assert_eq!(rule.next_run_at, None);
+
+    assert_eq!(tick(&pool, stock_id, 96).await?, [RuleOutcome::Unmatched]);
+    assert_eq!(shares(&pool, user_id, stock_id).await?, 10);
+
+    assert_eq!(tick(&pool, stock_id, 95).await?, [RuleOutcome::Fired]);
+    assert_eq!(shares(&pool, user_id, stock_id).await?, 6);
+
+    // Still below, but it already fired.
+    assert_eq!(tick(&pool, stock_id, 90).await?, [RuleOutcome::Held]);
+    assert_eq!(shares(&pool, user_id, stock_id).await?, 6);
+
+    assert_eq!(tick(&pool, stock_id, 100).await?, [RuleOutcome::Unmatched]);
+    assert_eq!(tick(&pool, stock_id, 94).await?, [RuleOutcome::Fired]);
+    assert_eq!(shares(&pool, user_id, stock_id).await?, 2);
+
+    // Not enough shares left for the next one, that is recorded as the failure.
+    assert_eq!(tick(&pool, stock_id, 100).await?, [RuleOutcome::Unmatched]);
+    assert_eq!(tick(&pool, stock_id, 90).await?, [RuleOutcome::Failed]);
+    assert_eq!(shares(&pool, user_id, stock_id).await?, 2);
+
+    let evaluations = rules::list_evaluations(&pool, user_id, rule.id, 100).await?;
+    assert_eq!(evaluations.len(), 7);
+    assert!(
+        evaluations[0]
+            .detail
+            .as_deref()
+            .is_some_and(|detail| detail.starts_with("Insufficient shares"))
+    );
+    assert_eq!(
+        evaluations
+            .iter()
+            .filter(|evaluation| evaluation.order_id.is_some())
+            .count(),
+        2
+    );
+
+    // The disabled rule is not evaluated at all.
+    rules::set_enabled(
+        &pool,
+        user_id,
+        rule.id,
+        false,
+        chrono::Utc::now().naive_utc(),
+    )
+    .await?;
+    assert_eq!(tick(&pool, stock_id, 100).await?, []);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_scheduled_rule_runs_at_its_time(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let user_id = create_user(&pool, "schedule@email.com").await?;
+    ledger::deposit(&pool, user_id, Money::from(250)).await?;
+
+    // Buys 2 shares every Monday at 09:30, while the balance lasts.
+    let definition: Definition = serde_json::from_value(serde_json::json!({
+        "trigger": {"type": "schedule", "weekdays": ["mon"], "time": "09:30:00"},
+        "condition": {
+            "type": "compare",
+            "left": {"type": "balance"},
+            "op": "ge",
+            "right": {"type": "constant", "value": "100"}
+        },
+        "action": {"type": "order", "stock_id": stock_id, "side": "buy", "quantity": {"shares": 2}}
+    }))?;
+
+    // A Wednesday.
+    let created_at = chrono::NaiveDate::from_ymd_opt(2026, 10, 14)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    let monday = chrono::NaiveDate::from_ymd_opt(2026, 10, 19)
+        .unwrap()
+        .and_hms_opt(9, 30, 0)
+        .unwrap();
+
+    let rule = rules::create_rule(
+        &pool,
+        user_id,
+        &NewRule {
+            name: "Every Monday".into(),
+            definition,
+        },
+        created_at,
+    )
+    .await?;
+    assert_eq!(rule.next_run_at, Some(monday));
+
+    // Not yet.
+    assert!(
+        rules::evaluate_schedules(&pool, monday - chrono::Duration::seconds(1))
+            .await?
+            .is_empty()
+    );
+
+    let evaluations = rules::evaluate_schedules(&pool, monday).await?;
+    assert_eq!(evaluations.len(), 1);
+    assert_eq!(evaluations[0].outcome, RuleOutcome::Fired);
+    assert_eq!(shares(&pool, user_id, stock_id).await?, 2);
+
+    // Ran already, the next time is the next Monday.
+    assert!(rules::evaluate_schedules(&pool, monday).await?.is_empty());
+
+    let next_monday = monday + chrono::Duration::days(7);
+    let rule = rules::list_rules(&pool, user_id).await?.remove(0);
+    assert_eq!(rule.next_run_at, Some(next_monday));
+
+    // 50 left, below the condition.
+    let evaluations = rules::evaluate_schedules(&pool, next_monday).await?;
+    assert_eq!(evaluations[0].outcome, RuleOutcome::Unmatched);
+    assert_eq!(shares(&pool, user_id, stock_id).await?, 2);
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_scheduled_rule_runs_once_when_evaluated_concurrently(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let user_id = create_user(&pool, "concurrent@email.com").await?;
+    ledger::deposit(&pool, user_id, Money::from(1000)).await?;
+
+    let definition: Definition = serde_json::from_value(serde_json::json!({
+        "trigger": {"type": "schedule", "weekdays": ["mon"], "time": "09:30:00"},
+        "condition": {
+            "type": "compare",
+            "left": {"type": "balance"},
+            "op": "ge",
+            "right": {"type": "constant", "value": "100"}
+        },
+        "action": {"type": "order", "stock_id": stock_id, "side": "buy", "quantity": {"shares": 2}}
+    }))?;
+
+    let created_at = chrono::NaiveDate::from_ymd_opt(2026, 10, 14)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    let monday = chrono::NaiveDate::from_ymd_opt(2026, 10, 19)
+        .unwrap()
+        .and_hms_opt(9, 30, 0)
+        .unwrap();
+
+    let rule = rules::create_rule(
+        &pool,
+        user_id,
+        &NewRule {
+            name: "Every Monday".into(),
+            definition,
+        },
+        created_at,
+    )
+    .await?;
+
+    // Every instance finds the rule whose time came, only the one that claims it evaluates it.
+    let evaluated =
+        futures::future::try_join_all((0..4).map(|_| rules::evaluate_schedules(&pool, monday)))
+            .await?;
+    assert_eq!(evaluated.iter().map(Vec::len).sum::<usize>(), 1);
+    assert_eq!(shares(&pool, user_id, stock_id).await?, 2);
+
+    let evaluations = rules::list_evaluations(&pool, user_id, rule.id, 100).await?;
+    assert_eq!(evaluations.len(), 1);
+    assert_eq!(evaluations[0].outcome, RuleOutcome::Fired);
+    assert_eq!(
+        trading::list_orders(&pool, user_id, None, 100).await?.len(),
+        1
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+#[tracing_test::traced_test]
+async fn test_backtest_matches_the_live_rule(
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<()> {
+    let stock_id = seed_stock(&pool).await?;
+    let user_id = create_user(&pool, "backtest@email.com").await?;
+
+    let start = chrono::NaiveDate::from_ymd_opt(2026, 10, 12)
+        .unwrap()
+        .and_hms_opt(9, 0, 0)
+        .unwrap();
+    let minute = |minutes: i64| start + chrono::Duration::minutes(minutes);
+
+    // The price at the start, the cost of the shares bought live and of the initial shares of the backtest.
+    tick_at(&pool, stock_id, 100, start).await?;
+
+    ledger::deposit(&pool, user_id, Money::from(1000)).await?;
+    trading::place_order(
+        &pool,
+        user_id,
+        &NewOrder {
+            stock_id,
+            side: OrderSide::Buy,
+            kind: OrderKind::Market,
+            venue: OrderVenue::Market,
+            quantity: 10,
+            limit_price: None,
+        },
+    )
+    .await?;
+
+    let definition: Definition = serde_json::from_value(serde_json::json!({
+        "trigger": {"type": "tick", "stock_id": stock_id},
+        "condition": {
+            "type": "compare",
+            "left": {"type": "price", "stock_id": stock_id},
+            "op": "le",
+            "right": {"type": "scaled", "value": {"type": "cost_basis", "stock_id": stock_id}, "factor": "0.95"}
+        },
+        "action": {"type": "order", "stock_id": stock_id, "side": "sell", "quantity": {"shares": 4}}
+    }))?;
+
+    let rule = rules::create_rule(
+        &pool,
+        user_id,
+        &NewRule {
+            name: "Stop loss".into(),
+            definition,
+        },
+        start,
+    )
+    .await?;
+
+    for (minutes, price) in [(1, 96), (2, 95), (3, 90), (4, 100), (5, 94), (6, 91)] {
+        tick_at(&pool, stock_id, price, minute(minutes)).await?;
+    }
+
+    let live = trading::list_orders(&pool, user_id, Some(OrderStatus::Filled), 100)
+        .await?
+        .into_iter()
+        .filter(|order| order.side == OrderSide::Sell)
+        .map(|order| (order.quantity, order.fill_price))
+        .rev()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        live,
+        [(4, Some(Money::from(95))), (4, Some(Money::from(94)))]
+    );
+
+    let backtest = rules::backtest::backtest(
+        &pool,
+        user_id,
+        rule.id,
+        &BacktestRequest {
+            from: minute(0) + chrono::Duration::seconds(1),
+            to: minute(10),
+            initial_balance: Some(Money::ZERO),
+            initial_shares: [(stock_id, 10)].into(),
+        },
+    )
+    .await?;
+
+    assert_eq!(
+        backtest
+            .trades
+            .iter()
+            .map(|trade| (trade.quantity, Some(trade.price)))
+            .collect::<Vec<_>>(),
+        live
+    );
+    assert_eq!(
+        backtest
+            .trades
+            .iter()
+            .map(|trade| trade.at)
+            .collect::<Vec<_>>(),
+        [minute(2), minute(5)]
+    );
+    assert_eq!(backtest.initial_value, Money::from(1000));
+    assert_eq!(backtest.final_balance, Money::from(756));
+    // The 2 shares left at 91.
+    assert_eq!(backtest.final_value, Money::from(938));
+
+    // The evaluations match the recorded ones, outcome by outcome.
+    let recorded = rules::list_evaluations(&pool, user_id, rule.id, 100).await?;
+    assert_eq!(
+        (
+            backtest.evaluations.unmatched,
+            backtest.evaluations.held,
+            backtest.evaluations.fired
+        ),
+        (
+            recorded
+                .iter()
+                .filter(|e| e.outcome == RuleOutcome::Unmatched)
+                .count() as u64,
+            recorded
+                .iter()
+                .filter(|e| e.outcome == RuleOutcome::Held)
+                .count() as u64,
+            recorded
+                .iter()
+                .filter(|e| e.outcome == RuleOutcome::Fired)
+                .count() as u64,
+        )
+    );
+
+    Ok(())
+}